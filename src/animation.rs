@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+// Playback position within a sequence of frames with individual delays.
+// Time is passed in explicitly so that the main loop can drive it from its wake-up logic.
+pub struct AnimationPlayer {
+    frame_delays: Vec<Duration>,
+    current_frame: usize,
+    playing: bool,
+    frame_start: Instant,
+}
+
+impl AnimationPlayer {
+    pub fn new(frame_delays: Vec<Duration>, now: Instant) -> Self {
+        assert!(!frame_delays.is_empty());
+        Self {
+            frame_delays,
+            current_frame: 0,
+            playing: true,
+            frame_start: now,
+        }
+    }
+
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_delays.len()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn set_playing(&mut self, playing: bool, now: Instant) {
+        if playing && !self.playing {
+            self.frame_start = now;
+        }
+        self.playing = playing;
+    }
+
+    // Moves by `delta` frames with wrap-around and pauses playback
+    pub fn step(&mut self, delta: i32) {
+        let n = self.frame_count() as i64;
+        let frame = (self.current_frame as i64 + delta as i64).rem_euclid(n);
+        self.current_frame = frame as usize;
+        self.playing = false;
+    }
+
+    // Selects the frame at `position` in [0, 1] across the sequence and pauses playback
+    pub fn seek(&mut self, position: f32) {
+        let n = self.frame_count();
+        let frame = (position.max(0.0) * n as f32) as usize;
+        self.current_frame = frame.min(n - 1);
+        self.playing = false;
+    }
+
+    // Advances past every frame whose delay has elapsed. Returns true if the frame changed.
+    pub fn update(&mut self, now: Instant) -> bool {
+        if !self.playing || self.frame_count() < 2 {
+            return false;
+        }
+        let total: Duration = self.frame_delays.iter().sum();
+        if now.saturating_duration_since(self.frame_start) > total {
+            // Skip whole loops after a long stall instead of replaying every frame
+            self.frame_start = now - self.frame_delays[self.current_frame];
+        }
+        let mut changed = false;
+        while now >= self.frame_start + self.frame_delays[self.current_frame] {
+            self.frame_start += self.frame_delays[self.current_frame];
+            self.current_frame = (self.current_frame + 1) % self.frame_count();
+            changed = true;
+        }
+        changed
+    }
+
+    // Time at which the next frame should be shown, or None when paused
+    pub fn next_frame_time(&self) -> Option<Instant> {
+        if self.playing && self.frame_count() > 1 {
+            Some(self.frame_start + self.frame_delays[self.current_frame])
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_animation_player() {
+    let ms = Duration::from_millis;
+    let t0 = Instant::now();
+    let mut player = AnimationPlayer::new(vec![ms(100), ms(50), ms(200)], t0);
+
+    assert_eq!(player.next_frame_time(), Some(t0 + ms(100)));
+    assert!(!player.update(t0 + ms(99)));
+    assert_eq!(player.current_frame(), 0);
+    assert!(player.update(t0 + ms(100)));
+    assert_eq!(player.current_frame(), 1);
+    assert_eq!(player.next_frame_time(), Some(t0 + ms(150)));
+
+    // Late wake-up skips frames but keeps the original schedule
+    assert!(player.update(t0 + ms(360)));
+    assert_eq!(player.current_frame(), 0);
+    assert_eq!(player.next_frame_time(), Some(t0 + ms(450)));
+
+    // Stalls longer than a loop don't replay every frame
+    assert!(player.update(t0 + ms(5000)));
+    assert!(player.next_frame_time().unwrap() > t0 + ms(5000));
+
+    player.set_playing(false, t0 + ms(5000));
+    assert_eq!(player.next_frame_time(), None);
+    assert!(!player.update(t0 + ms(9000)));
+
+    player.set_playing(true, t0 + ms(9000));
+    let frame = player.current_frame();
+    assert_eq!(
+        player.next_frame_time(),
+        Some(t0 + ms(9000) + [ms(100), ms(50), ms(200)][frame])
+    );
+}
+
+#[test]
+fn test_animation_player_navigation() {
+    let t0 = Instant::now();
+    let mut player = AnimationPlayer::new(vec![Duration::from_millis(10); 4], t0);
+
+    player.step(-1);
+    assert_eq!(player.current_frame(), 3);
+    assert!(!player.is_playing());
+    player.step(2);
+    assert_eq!(player.current_frame(), 1);
+
+    player.seek(0.0);
+    assert_eq!(player.current_frame(), 0);
+    player.seek(0.6);
+    assert_eq!(player.current_frame(), 2);
+    player.seek(1.0);
+    assert_eq!(player.current_frame(), 3);
+    player.seek(-3.0);
+    assert_eq!(player.current_frame(), 0);
+
+    let mut still = AnimationPlayer::new(vec![Duration::from_millis(10)], t0);
+    assert_eq!(still.next_frame_time(), None);
+    assert!(!still.update(t0 + Duration::from_secs(1)));
+}
@@ -1,9 +1,47 @@
+use crate::loader::read_exif_info;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum StepDirection {
     Backward,
     Forward,
+    BackwardBy(usize),
+    ForwardBy(usize),
+    First,
+    Last,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SortOrder {
+    Name,
+    ModifiedTime,
+    FileSize,
+    // EXIF DateTimeOriginal, files without it come last
+    DateTaken,
+}
+
+impl SortOrder {
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg.to_ascii_lowercase().as_str() {
+            "name" => Some(SortOrder::Name),
+            "mtime" | "modified" => Some(SortOrder::ModifiedTime),
+            "size" => Some(SortOrder::FileSize),
+            "date" | "date-taken" => Some(SortOrder::DateTaken),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Name => SortOrder::ModifiedTime,
+            SortOrder::ModifiedTime => SortOrder::FileSize,
+            SortOrder::FileSize => SortOrder::DateTaken,
+            SortOrder::DateTaken => SortOrder::Name,
+        }
+    }
 }
 
 pub fn is_compatible_file(path: &Path) -> bool {
@@ -22,23 +60,668 @@ pub fn is_compatible_file(path: &Path) -> bool {
     false
 }
 
-pub fn get_next_file(path: &Path, direction: StepDirection) -> Option<PathBuf> {
-    let file_dir = path.parent().unwrap();
-    let file_name = path.file_name().unwrap();
-    let dir = std::fs::read_dir(file_dir);
-    if let Ok(dir) = dir {
-        let files: Vec<_> = dir
-            .filter_map(|f| f.ok().map(|f| f.path()))
-            .filter(|f| is_compatible_file(f))
-            .map(|f| f.file_name().unwrap().to_owned())
-            .collect();
-        if let Some(i) = files.iter().position(|f| f == file_name) {
-            return match direction {
-                StepDirection::Backward if i > 0 => Some(files[i - 1].clone().into()),
-                StepDirection::Forward if i + 1 < files.len() => Some(files[i + 1].clone().into()),
-                _ => None,
+// Orders runs of digits by their numeric value so that "frame_2" comes before "frame_10".
+// Other characters are compared case-insensitively, exact ties fall back to the raw strings.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+                        digits.push(c);
+                        chars.next();
+                    }
+                    digits
+                };
+                let x_digits = take_number(&mut a_chars);
+                let y_digits = take_number(&mut b_chars);
+                let x_value = x_digits.trim_start_matches('0');
+                let y_value = y_digits.trim_start_matches('0');
+                let ord = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.to_lowercase().cmp(y.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+// Files given without a folder are in the working directory
+fn listing_dir(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }
+}
+
+// SplitMix64, small and stable so that a shuffle seed gives the same order on every machine
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    for i in (1..items.len()).rev() {
+        let j = (next_random(&mut state) % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+struct DirectoryEntry {
+    path: PathBuf,
+    name: String,
+    modified: Option<SystemTime>,
+    size: u64,
+    // Read off the UI thread since it requires parsing every file, None until it arrives
+    date_taken: Option<Option<String>>,
+    date_requested: bool,
+}
+
+// Reads the EXIF DateTimeOriginal of each file for sorting by date taken
+pub fn read_dates_taken(paths: Vec<PathBuf>) -> Vec<(PathBuf, Option<String>)> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let date = read_exif_info(&path).and_then(|exif| exif.datetime_original);
+            (path, date)
+        })
+        .collect()
+}
+
+// Sorted list of the viewable files in one folder with a current position. The listing is only
+// read again when the folder's modification time changes, and file metadata is kept across
+// refreshes for files whose size and modification time are unchanged.
+pub struct DirectoryIndex {
+    sort_order: SortOrder,
+    dir: Option<PathBuf>,
+    dir_modified: Option<SystemTime>,
+    // Sorted, or shuffled when a seed is set
+    entries: Vec<DirectoryEntry>,
+    current: Option<usize>,
+    // Name of the selected file when it is not in the listing, such as a file that failed to load
+    // because of an unknown extension or that was deleted. Steps continue from where it would be
+    // sorted by name.
+    unlisted_name: Option<String>,
+    wrap: bool,
+    shuffle_seed: Option<u64>,
+}
+
+impl DirectoryIndex {
+    pub fn new(sort_order: SortOrder) -> Self {
+        Self {
+            sort_order,
+            dir: None,
+            dir_modified: None,
+            entries: Vec::new(),
+            current: None,
+            unlisted_name: None,
+            wrap: true,
+            shuffle_seed: None,
+        }
+    }
+
+    pub fn wrap(&self) -> bool {
+        self.wrap
+    }
+
+    // Stepping past either end continues at the other one
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    pub fn shuffle_seed(&self) -> Option<u64> {
+        self.shuffle_seed
+    }
+
+    // Visits the files in a random order that only depends on the seed and the sorted listing
+    pub fn set_shuffle_seed(&mut self, seed: Option<u64>) {
+        self.shuffle_seed = seed;
+        self.sort();
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
+    }
+
+    pub fn set_sort_order(&mut self, sort_order: SortOrder) {
+        self.sort_order = sort_order;
+        self.sort();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().map(|e| e.path.as_path())
+    }
+
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.map(|i| self.entries[i].path.as_path())
+    }
+
+    // Makes `path` the current file, indexing its folder first if needed
+    pub fn select(&mut self, path: &Path) -> std::io::Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        if self.dir.as_deref() != Some(dir) {
+            self.dir = Some(dir.to_path_buf());
+            self.current = None;
+            self.entries.clear();
+            self.rescan()?;
+        } else {
+            self.refresh()?;
+        }
+        self.current = self.entries.iter().position(|e| e.path == path);
+        self.unlisted_name = match self.current {
+            Some(_) => None,
+            None => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        };
+        Ok(())
+    }
+
+    // Reads the folder again if it changed since the last scan
+    pub fn refresh(&mut self) -> std::io::Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let modified = std::fs::metadata(listing_dir(dir))?.modified().ok();
+        if modified.is_none() || modified != self.dir_modified {
+            self.rescan()?;
+        }
+        Ok(())
+    }
+
+    fn rescan(&mut self) -> std::io::Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => return Ok(()),
+        };
+        self.dir_modified = std::fs::metadata(listing_dir(&dir))?.modified().ok();
+        let current_path = self.current_path().map(Path::to_path_buf);
+
+        let mut previous: Vec<DirectoryEntry> = std::mem::take(&mut self.entries);
+        previous.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in std::fs::read_dir(listing_dir(&dir))?.filter_map(Result::ok) {
+            // Joined with the folder as given so paths compare equal to the selected one
+            let path = dir.join(entry.file_name());
+            if !is_compatible_file(&path) {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.file_type().is_symlink() => std::fs::metadata(&path),
+                metadata => metadata,
             };
+            let metadata = match metadata {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let modified = metadata.modified().ok();
+            let size = metadata.len();
+            let unchanged = previous
+                .binary_search_by(|e| e.path.cmp(&path))
+                .ok()
+                .map(|i| &previous[i])
+                .filter(|e| e.modified == modified && e.size == size);
+            self.entries.push(DirectoryEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path,
+                modified,
+                size,
+                date_taken: unchanged.and_then(|e| e.date_taken.clone()),
+                date_requested: unchanged.is_some_and(|e| e.date_requested),
+            });
+        }
+
+        self.current = None;
+        self.sort();
+        if let Some(current_path) = current_path {
+            self.current = self.entries.iter().position(|e| e.path == current_path);
+        }
+        Ok(())
+    }
+
+    // Files whose date taken is needed for sorting and hasn't been asked for yet. The caller reads
+    // them with `read_dates_taken` in the background and passes them to `set_dates_taken`.
+    pub fn take_missing_dates(&mut self) -> Vec<PathBuf> {
+        if self.sort_order != SortOrder::DateTaken {
+            return Vec::new();
         }
+        self.entries
+            .iter_mut()
+            .filter(|e| e.date_taken.is_none() && !e.date_requested)
+            .map(|e| {
+                e.date_requested = true;
+                e.path.clone()
+            })
+            .collect()
     }
-    None
+
+    // Stores dates read in the background and sorts again. Files that are no longer listed are
+    // ignored.
+    pub fn set_dates_taken(&mut self, dates: Vec<(PathBuf, Option<String>)>) {
+        let mut dates: HashMap<PathBuf, Option<String>> = dates.into_iter().collect();
+        for entry in &mut self.entries {
+            if let Some(date) = dates.remove(&entry.path) {
+                entry.date_taken = Some(date);
+            }
+        }
+        if self.sort_order == SortOrder::DateTaken {
+            self.sort();
+        }
+    }
+
+    // Sorts the entries and keeps the current file selected. Files whose date taken hasn't been
+    // read yet sort like files without one.
+    fn sort(&mut self) {
+        let current_path = self.current_path().map(Path::to_path_buf);
+        let by_name = |a: &DirectoryEntry, b: &DirectoryEntry| natural_cmp(&a.name, &b.name);
+        match self.sort_order {
+            SortOrder::Name => self.entries.sort_by(by_name),
+            SortOrder::ModifiedTime => self
+                .entries
+                .sort_by(|a, b| a.modified.cmp(&b.modified).then_with(|| by_name(a, b))),
+            SortOrder::FileSize => self
+                .entries
+                .sort_by(|a, b| a.size.cmp(&b.size).then_with(|| by_name(a, b))),
+            SortOrder::DateTaken => self.entries.sort_by(|a, b| {
+                let a_date = a.date_taken.as_ref().and_then(Option::as_ref);
+                let b_date = b.date_taken.as_ref().and_then(Option::as_ref);
+                match (a_date, b_date) {
+                    (Some(x), Some(y)) => x.cmp(y),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+                .then_with(|| by_name(a, b))
+            }),
+        }
+        if let Some(seed) = self.shuffle_seed {
+            shuffle(&mut self.entries, seed);
+        }
+        if let Some(current_path) = current_path {
+            self.current = self.entries.iter().position(|e| e.path == current_path);
+        }
+    }
+
+    // Index `delta` files away from the current one, before wrapping or clamping. None when there
+    // is no current file, or when an unlisted file is already past the end without wrap-around.
+    fn offset_index(&self, delta: i64) -> Option<i64> {
+        if let Some(current) = self.current {
+            return Some(current as i64 + delta);
+        }
+        let name = self.unlisted_name.as_deref()?;
+        let next = self
+            .entries
+            .iter()
+            .filter(|e| natural_cmp(&e.name, name) == Ordering::Less)
+            .count() as i64;
+        let first = if delta > 0 { next } else { next - 1 };
+        if !self.wrap && !(0..self.entries.len() as i64).contains(&first) {
+            return None;
+        }
+        Some(first + delta - delta.signum())
+    }
+
+    // Moves the current position and returns the new current file. Returns None when the
+    // position doesn't change.
+    pub fn step(&mut self, direction: StepDirection) -> Option<PathBuf> {
+        let len = self.entries.len() as i64;
+        if len == 0 {
+            return None;
+        }
+        let target = match direction {
+            StepDirection::First => 0,
+            StepDirection::Last => len - 1,
+            _ => {
+                let delta = match direction {
+                    StepDirection::Backward => -1,
+                    StepDirection::Forward => 1,
+                    StepDirection::BackwardBy(n) => -(n as i64),
+                    StepDirection::ForwardBy(n) => n as i64,
+                    _ => unreachable!(),
+                };
+                let target = self.offset_index(delta)?;
+                if self.wrap {
+                    target.rem_euclid(len)
+                } else {
+                    target.clamp(0, len - 1)
+                }
+            }
+        } as usize;
+        if self.current == Some(target) {
+            return None;
+        }
+        self.current = Some(target);
+        self.unlisted_name = None;
+        Some(self.entries[target].path.clone())
+    }
+
+    // Files around the current one in the order they are likely to be viewed next:
+    // next, previous, second next, second previous and so on
+    pub fn neighbors(&self, count: usize) -> Vec<PathBuf> {
+        let len = self.entries.len() as i64;
+        let mut result: Vec<PathBuf> = Vec::new();
+        if len == 0 {
+            return result;
+        }
+        for distance in 1..=count as i64 {
+            for delta in [distance, -distance] {
+                let index = match self.offset_index(delta) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                let index = if self.wrap {
+                    index.rem_euclid(len)
+                } else if (0..len).contains(&index) {
+                    index
+                } else {
+                    continue;
+                };
+                let path = &self.entries[index as usize].path;
+                if self.current != Some(index as usize) && !result.contains(path) {
+                    result.push(path.clone());
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+fn make_test_dir(name: &str, files: &[(&str, usize)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imgv_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, size) in files {
+        std::fs::write(dir.join(file), vec![0u8; *size]).unwrap();
+    }
+    dir
+}
+
+#[cfg(test)]
+fn file_names(index: &DirectoryIndex) -> Vec<String> {
+    index
+        .paths()
+        .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn test_natural_cmp() {
+    let mut names = vec![
+        "frame_10.png",
+        "Frame_2.png",
+        "frame_1.png",
+        "frame_002b.png",
+        "a.png",
+        "frame_.png",
+        "B.png",
+    ];
+    names.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(
+        names,
+        [
+            "a.png",
+            "B.png",
+            "frame_.png",
+            "frame_1.png",
+            "Frame_2.png",
+            "frame_002b.png",
+            "frame_10.png",
+        ]
+    );
+    assert_eq!(natural_cmp("x01", "x1"), "x01".cmp("x1"));
+    assert_eq!(natural_cmp("x", "x"), Ordering::Equal);
+}
+
+#[test]
+fn test_directory_index() {
+    let dir = make_test_dir(
+        "index",
+        &[
+            ("frame_10.png", 30),
+            ("frame_2.png", 10),
+            ("frame_1.png", 20),
+            ("notes.txt", 1),
+        ],
+    );
+    std::fs::create_dir(dir.join("nested.png")).unwrap();
+
+    let mut index = DirectoryIndex::new(SortOrder::Name);
+    index.set_wrap(false);
+    index.select(&dir.join("frame_2.png")).unwrap();
+    assert_eq!(
+        file_names(&index),
+        ["frame_1.png", "frame_2.png", "frame_10.png"]
+    );
+    assert_eq!(
+        index.step(StepDirection::Forward),
+        Some(dir.join("frame_10.png"))
+    );
+    assert_eq!(index.step(StepDirection::Forward), None);
+    assert_eq!(
+        index.current_path(),
+        Some(dir.join("frame_10.png").as_path())
+    );
+
+    index.set_sort_order(SortOrder::FileSize);
+    assert_eq!(
+        file_names(&index),
+        ["frame_2.png", "frame_1.png", "frame_10.png"]
+    );
+    assert_eq!(
+        index.current_path(),
+        Some(dir.join("frame_10.png").as_path())
+    );
+    assert_eq!(
+        index.step(StepDirection::Backward),
+        Some(dir.join("frame_1.png"))
+    );
+
+    // New files show up after a rescan and the current file stays selected
+    std::fs::write(dir.join("frame_3.png"), vec![0u8; 15]).unwrap();
+    index.rescan().unwrap();
+    assert_eq!(index.len(), 4);
+    assert_eq!(
+        index.current_path(),
+        Some(dir.join("frame_1.png").as_path())
+    );
+    assert_eq!(
+        index.step(StepDirection::Backward),
+        Some(dir.join("frame_3.png"))
+    );
+
+    // Files that are not in the listing step to the files next to them by name
+    index.set_sort_order(SortOrder::Name);
+    index.select(&dir.join("notes.txt")).unwrap();
+    assert_eq!(index.current_path(), None);
+    assert_eq!(index.step(StepDirection::Forward), None);
+    index.select(&dir.join("notes.txt")).unwrap();
+    assert_eq!(
+        index.step(StepDirection::Backward),
+        Some(dir.join("frame_10.png"))
+    );
+    index.select(&dir.join("frame_2b.png")).unwrap();
+    assert_eq!(
+        index.step(StepDirection::Forward),
+        Some(dir.join("frame_3.png"))
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_directory_index_modified_time() {
+    let dir = make_test_dir("mtime", &[("b.png", 1), ("a.png", 1), ("c.png", 1)]);
+    let base = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+    for (file, seconds) in [("b.png", 10), ("a.png", 30), ("c.png", 20)] {
+        let f = std::fs::File::options()
+            .write(true)
+            .open(dir.join(file))
+            .unwrap();
+        f.set_modified(base + std::time::Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    let mut index = DirectoryIndex::new(SortOrder::ModifiedTime);
+    index.select(&dir.join("a.png")).unwrap();
+    assert_eq!(file_names(&index), ["b.png", "c.png", "a.png"]);
+
+    // Files without EXIF data keep their name order
+    index.set_sort_order(SortOrder::DateTaken);
+    assert_eq!(file_names(&index), ["a.png", "b.png", "c.png"]);
+    let missing = index.take_missing_dates();
+    assert_eq!(missing.len(), 3);
+    assert!(index.take_missing_dates().is_empty());
+    let dates = read_dates_taken(missing);
+    assert!(dates.iter().all(|(_, date)| date.is_none()));
+
+    // Dates arrive later and reorder the files, the current file stays selected
+    index.set_dates_taken(vec![
+        (dir.join("c.png"), Some("2020-01-01T10:00:00".into())),
+        (dir.join("b.png"), Some("2021-01-01T10:00:00".into())),
+        (dir.join("gone.png"), Some("2019-01-01T10:00:00".into())),
+    ]);
+    assert_eq!(file_names(&index), ["c.png", "b.png", "a.png"]);
+    assert_eq!(index.current_path(), Some(dir.join("a.png").as_path()));
+
+    // Known dates are kept across rescans of unchanged files
+    index.rescan().unwrap();
+    assert_eq!(file_names(&index), ["c.png", "b.png", "a.png"]);
+    assert!(index.take_missing_dates().is_empty());
+
+    assert_eq!(SortOrder::parse("MTIME"), Some(SortOrder::ModifiedTime));
+    assert_eq!(SortOrder::parse("bogus"), None);
+    assert_eq!(SortOrder::DateTaken.next(), SortOrder::Name);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_directory_navigation() {
+    let names: Vec<String> = (1..=12).map(|i| format!("img_{i}.png")).collect();
+    let files: Vec<(&str, usize)> = names.iter().map(|name| (name.as_str(), 1)).collect();
+    let dir = make_test_dir("navigation", &files);
+    let path = |i: usize| dir.join(format!("img_{i}.png"));
+
+    let mut index = DirectoryIndex::new(SortOrder::Name);
+    assert!(index.wrap());
+    index.select(&path(1)).unwrap();
+
+    // Wrap-around at both ends
+    assert_eq!(index.step(StepDirection::Backward), Some(path(12)));
+    assert_eq!(index.step(StepDirection::Forward), Some(path(1)));
+    assert_eq!(index.step(StepDirection::BackwardBy(5)), Some(path(8)));
+    assert_eq!(index.step(StepDirection::ForwardBy(10)), Some(path(6)));
+
+    assert_eq!(index.step(StepDirection::Last), Some(path(12)));
+    assert_eq!(index.step(StepDirection::Last), None);
+    assert_eq!(index.step(StepDirection::First), Some(path(1)));
+
+    // Prefetch order alternates around the current file
+    assert_eq!(
+        index.neighbors(2),
+        vec![path(2), path(12), path(3), path(11)]
+    );
+
+    // Without wrap-around, jumps stop at the ends
+    index.set_wrap(false);
+    assert_eq!(index.step(StepDirection::Backward), None);
+    assert_eq!(index.neighbors(2), vec![path(2), path(3)]);
+    assert_eq!(index.step(StepDirection::ForwardBy(10)), Some(path(11)));
+    assert_eq!(index.step(StepDirection::ForwardBy(10)), Some(path(12)));
+    assert_eq!(index.step(StepDirection::ForwardBy(10)), None);
+    assert_eq!(index.step(StepDirection::BackwardBy(100)), Some(path(1)));
+
+    // A file that is not in the listing sits between its neighbors by name
+    index.select(&dir.join("img_4b.png")).unwrap();
+    assert_eq!(index.current_path(), None);
+    assert_eq!(index.neighbors(2), vec![path(5), path(4), path(6), path(3)]);
+    assert_eq!(index.step(StepDirection::ForwardBy(2)), Some(path(6)));
+    index.select(&dir.join("img_4b.png")).unwrap();
+    assert_eq!(index.step(StepDirection::Backward), Some(path(4)));
+    index.select(&dir.join("missing.png")).unwrap();
+    assert_eq!(index.step(StepDirection::Forward), None);
+    assert_eq!(index.neighbors(2), vec![path(12), path(11)]);
+    assert_eq!(index.step(StepDirection::Last), Some(path(12)));
+
+    index.set_wrap(true);
+    index.select(&dir.join("missing.png")).unwrap();
+    assert_eq!(index.neighbors(1), vec![path(1), path(12)]);
+    assert_eq!(index.step(StepDirection::Forward), Some(path(1)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_directory_shuffle() {
+    let names: Vec<String> = (1..=20).map(|i| format!("img_{i}.png")).collect();
+    let files: Vec<(&str, usize)> = names.iter().map(|name| (name.as_str(), 1)).collect();
+    let dir = make_test_dir("shuffle", &files);
+
+    let mut index = DirectoryIndex::new(SortOrder::Name);
+    index.select(&dir.join("img_7.png")).unwrap();
+    let sorted = file_names(&index);
+
+    index.set_shuffle_seed(Some(42));
+    let shuffled = file_names(&index);
+    assert_ne!(shuffled, sorted);
+    let mut resorted = shuffled.clone();
+    resorted.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(resorted, sorted);
+    assert_eq!(index.current_path(), Some(dir.join("img_7.png").as_path()));
+
+    // Same seed gives the same order, also for a fresh index and after a rescan
+    let mut other = DirectoryIndex::new(SortOrder::Name);
+    other.set_shuffle_seed(Some(42));
+    other.select(&dir.join("img_1.png")).unwrap();
+    assert_eq!(file_names(&other), shuffled);
+    other.rescan().unwrap();
+    assert_eq!(file_names(&other), shuffled);
+
+    other.set_shuffle_seed(Some(43));
+    assert_ne!(file_names(&other), shuffled);
+    other.set_shuffle_seed(None);
+    assert_eq!(file_names(&other), sorted);
+
+    // Every file is visited once before the order repeats
+    let start = index.current_path().unwrap().to_path_buf();
+    let mut visited: Vec<PathBuf> = (0..20)
+        .filter_map(|_| index.step(StepDirection::Forward))
+        .collect();
+    assert_eq!(visited.last(), Some(&start));
+    visited.sort();
+    visited.dedup();
+    assert_eq!(visited.len(), 20);
+
+    std::fs::remove_dir_all(&dir).unwrap();
 }
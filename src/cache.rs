@@ -0,0 +1,229 @@
+use crate::loader::LoadedImage;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Least recently used cache of decoded images, bounded by an approximate memory budget.
+// Entries are shared so that the viewer can keep displaying an image after it is evicted.
+pub struct ImageCache<K, T> {
+    // Most recently used entry last
+    entries: Vec<(K, Arc<T>, usize)>,
+    budget: usize,
+    used: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: PartialEq, T> ImageCache<K, T> {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            budget,
+            used: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // Looks up an entry for display, marking it as most recently used and counting the hit or miss
+    pub fn get(&mut self, key: &K) -> Option<Arc<T>> {
+        match self.entries.iter().position(|(k, _, _)| k == key) {
+            Some(index) => {
+                let entry = self.entries.remove(index);
+                let value = entry.1.clone();
+                self.entries.push(entry);
+                self.hits += 1;
+                Some(value)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    // Checks for an entry without affecting eviction order or statistics
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.iter().any(|(k, _, _)| k == key)
+    }
+
+    // Inserts or replaces an entry, evicting the least recently used ones to stay within budget.
+    // Items larger than the whole budget are not cached.
+    pub fn insert(&mut self, key: K, value: Arc<T>, size: usize) {
+        if let Some(index) = self.entries.iter().position(|(k, _, _)| *k == key) {
+            let (_, _, old_size) = self.entries.remove(index);
+            self.used -= old_size;
+        }
+        if size > self.budget {
+            return;
+        }
+        while self.used + size > self.budget {
+            let (_, _, evicted_size) = self.entries.remove(0);
+            self.used -= evicted_size;
+        }
+        self.used += size;
+        self.entries.push((key, value, size));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    // Fraction of display lookups served from the cache
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f32 / total as f32
+        }
+    }
+}
+
+pub enum LoadRequest {
+    // Image to show next, superseding any earlier display request
+    Display(PathBuf),
+    // Neighbouring images to decode ahead of time, nearest first
+    Prefetch(Vec<PathBuf>),
+    // Page of a multi-page TIFF that was navigated to, superseding any earlier page request
+    Page(Arc<LoadedImage>, usize),
+}
+
+// Pending work of the loader thread. Only the latest display and page requests are kept, so
+// images and pages that were skipped over while navigating quickly are never decoded. Display
+// requests always run first, then pages, then prefetches.
+#[derive(Default)]
+pub struct LoadQueue {
+    display: Option<PathBuf>,
+    page: Option<(Arc<LoadedImage>, usize)>,
+    prefetch: VecDeque<PathBuf>,
+}
+
+impl LoadQueue {
+    // Returns the display request that was superseded, if any
+    pub fn push(&mut self, request: LoadRequest) -> Option<PathBuf> {
+        match request {
+            LoadRequest::Display(path) => {
+                // Pages belong to the image that is being replaced
+                self.page = None;
+                self.prefetch.retain(|p| *p != path);
+                self.display.replace(path)
+            }
+            LoadRequest::Prefetch(paths) => {
+                self.prefetch = paths
+                    .into_iter()
+                    .filter(|p| Some(p) != self.display.as_ref())
+                    .collect();
+                None
+            }
+            LoadRequest::Page(image, page) => {
+                self.page = Some((image, page));
+                None
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<LoadRequest> {
+        if let Some(path) = self.display.take() {
+            Some(LoadRequest::Display(path))
+        } else if let Some((image, page)) = self.page.take() {
+            Some(LoadRequest::Page(image, page))
+        } else {
+            self.prefetch
+                .pop_front()
+                .map(|path| LoadRequest::Prefetch(vec![path]))
+        }
+    }
+
+    pub fn has_display(&self) -> bool {
+        self.display.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.display.is_none() && self.page.is_none() && self.prefetch.is_empty()
+    }
+}
+
+#[test]
+fn test_image_cache() {
+    let mut cache: ImageCache<u32, &str> = ImageCache::new(100);
+    cache.insert(1, Arc::new("a"), 40);
+    cache.insert(2, Arc::new("b"), 40);
+    assert_eq!(cache.used(), 80);
+
+    // Touching 1 makes 2 the eviction candidate
+    assert_eq!(cache.get(&1).as_deref(), Some(&"a"));
+    cache.insert(3, Arc::new("c"), 40);
+    assert!(cache.contains(&1));
+    assert!(!cache.contains(&2));
+    assert!(cache.contains(&3));
+    assert_eq!(cache.used(), 80);
+
+    assert!(cache.get(&2).is_none());
+    assert_eq!(cache.hit_rate(), 0.5);
+
+    // Replacing an entry updates its size
+    cache.insert(3, Arc::new("c"), 10);
+    assert_eq!(cache.used(), 50);
+
+    // Oversized items are not cached and don't evict anything
+    cache.insert(4, Arc::new("d"), 101);
+    assert!(!cache.contains(&4));
+    assert_eq!(cache.len(), 2);
+
+    cache.insert(5, Arc::new("e"), 100);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.used(), 100);
+}
+
+#[test]
+fn test_load_queue() {
+    let path = |name: &str| PathBuf::from(name);
+    let mut queue = LoadQueue::default();
+    assert!(queue.pop().is_none());
+
+    assert_eq!(queue.push(LoadRequest::Display(path("a"))), None);
+    queue.push(LoadRequest::Prefetch(vec![path("b"), path("a"), path("c")]));
+    assert_eq!(queue.push(LoadRequest::Display(path("b"))), Some(path("a")));
+    assert!(queue.has_display());
+
+    match queue.pop() {
+        Some(LoadRequest::Display(p)) => assert_eq!(p, path("b")),
+        _ => panic!("expected display request"),
+    }
+    match queue.pop() {
+        Some(LoadRequest::Prefetch(p)) => assert_eq!(p, vec![path("c")]),
+        _ => panic!("expected prefetch request"),
+    }
+    assert!(queue.is_empty());
+
+    // New prefetch lists replace stale ones
+    queue.push(LoadRequest::Prefetch(vec![path("x"), path("y")]));
+    queue.push(LoadRequest::Prefetch(vec![path("z")]));
+    match queue.pop() {
+        Some(LoadRequest::Prefetch(p)) => assert_eq!(p, vec![path("z")]),
+        _ => panic!("expected prefetch request"),
+    }
+    assert!(queue.pop().is_none());
+
+    // Pages run before prefetches, only the latest one is kept and a new image drops it
+    let image = Arc::new(LoadedImage::from_image(image::DynamicImage::new_luma8(
+        1, 1,
+    )));
+    queue.push(LoadRequest::Prefetch(vec![path("p")]));
+    queue.push(LoadRequest::Page(image.clone(), 1));
+    queue.push(LoadRequest::Page(image.clone(), 2));
+    match queue.pop() {
+        Some(LoadRequest::Page(_, page)) => assert_eq!(page, 2),
+        _ => panic!("expected page request"),
+    }
+    queue.push(LoadRequest::Page(image, 3));
+    queue.push(LoadRequest::Display(path("d")));
+    assert!(matches!(queue.pop(), Some(LoadRequest::Display(_))));
+    assert!(matches!(queue.pop(), Some(LoadRequest::Prefetch(_))));
+    assert!(queue.is_empty());
+}
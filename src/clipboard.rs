@@ -1,6 +1,6 @@
+use crate::loader::{load_image_from_bytes, LoadOptions, LoadedImage};
 use anyhow::{anyhow, Result};
 use clipboard_win::formats::Format;
-use image::DynamicImage;
 use std::convert::TryInto;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -8,6 +8,7 @@ use std::time::Duration;
 fn dib_to_bmp(dib_data: &[u8]) -> Result<Vec<u8>> {
     const BMP_FILE_HEADER_SIZE: usize = 14;
     const BITMAPINFOHEADER_SIZE: usize = 40;
+    const BI_BITFIELDS: u32 = 3;
     if dib_data.len() < BITMAPINFOHEADER_SIZE {
         return Err(anyhow!("DIB data too small"));
     }
@@ -18,6 +19,7 @@ fn dib_to_bmp(dib_data: &[u8]) -> Result<Vec<u8>> {
     }
 
     let bit_count = u16::from_le_bytes(dib_data[14..16].try_into().unwrap());
+    let compression = u32::from_le_bytes(dib_data[16..20].try_into().unwrap());
     let clr_used = u32::from_le_bytes(dib_data[32..36].try_into().unwrap());
     let color_count = if clr_used != 0 {
         clr_used as usize
@@ -26,15 +28,29 @@ fn dib_to_bmp(dib_data: &[u8]) -> Result<Vec<u8>> {
     } else {
         0
     };
+    // A plain BITMAPINFOHEADER is followed by the three channel masks
+    let mask_size = if header_size == BITMAPINFOHEADER_SIZE && compression == BI_BITFIELDS {
+        12
+    } else {
+        0
+    };
 
-    let bf_off_bits = BMP_FILE_HEADER_SIZE + header_size + color_count * 4;
-    let bf_size = BMP_FILE_HEADER_SIZE + dib_data.len();
-
-    let mut bmp_data = Vec::with_capacity(bf_size);
+    // Clipboard data comes from other processes, so the header is not trusted to describe it
+    let pixels_offset = color_count
+        .checked_mul(4)
+        .and_then(|size| size.checked_add(header_size + mask_size))
+        .filter(|&offset| offset < dib_data.len())
+        .ok_or_else(|| anyhow!("DIB color table extends past the data"))?;
+    let bf_off_bits = (BMP_FILE_HEADER_SIZE + pixels_offset) as u32;
+    let bf_size: u32 = (BMP_FILE_HEADER_SIZE + dib_data.len())
+        .try_into()
+        .map_err(|_| anyhow!("DIB data too large"))?;
+
+    let mut bmp_data = Vec::with_capacity(bf_size as usize);
     bmp_data.extend_from_slice(&[0x42, 0x4d]);
-    bmp_data.extend_from_slice(&(bf_size as u32).to_le_bytes());
+    bmp_data.extend_from_slice(&bf_size.to_le_bytes());
     bmp_data.extend_from_slice(&0u32.to_le_bytes());
-    bmp_data.extend_from_slice(&(bf_off_bits as u32).to_le_bytes());
+    bmp_data.extend_from_slice(&bf_off_bits.to_le_bytes());
     bmp_data.extend_from_slice(dib_data);
 
     Ok(bmp_data)
@@ -66,6 +82,11 @@ pub fn write_bitmap_to_clipboard(hwnd: isize, bmp_data: &[u8]) -> Result<()> {
     }
 }
 
+pub fn write_text_to_clipboard(text: &str) -> Result<()> {
+    clipboard_win::set_clipboard_string(text)
+        .map_err(|err| anyhow!("Failed to set clipboard text: {err:?}"))
+}
+
 pub fn get_clipboard_file_path() -> Result<Option<PathBuf>> {
     let file_list = clipboard_win::formats::FileList;
     if !file_list.is_format_avail() {
@@ -105,11 +126,24 @@ pub fn get_clipboard_file_path() -> Result<Option<PathBuf>> {
     Ok(paths.into_iter().next().map(PathBuf::from))
 }
 
-pub fn get_clipboard_image() -> Result<Option<DynamicImage>> {
+pub fn get_clipboard_image(options: &LoadOptions) -> Result<Option<LoadedImage>> {
+    // Browsers and image editors also place the encoded PNG, which keeps the ICC profile and
+    // other metadata that the bitmap formats lose
+    if let Some(png_id) = clipboard_win::raw::register_format("PNG") {
+        let png_format = clipboard_win::formats::RawData(png_id.get());
+        if png_format.is_format_avail() {
+            if let Ok(data) = clipboard_win::get_clipboard(png_format) {
+                if let Ok(image) = load_image_from_bytes(&data, options) {
+                    return Ok(Some(image));
+                }
+            }
+        }
+    }
+
     let bitmap_format = clipboard_win::formats::Bitmap;
     if bitmap_format.is_format_avail() {
         if let Ok(data) = clipboard_win::get_clipboard(bitmap_format) {
-            if let Ok(image) = image::load_from_memory(&data) {
+            if let Ok(image) = load_image_from_bytes(&data, options) {
                 return Ok(Some(image));
             }
         }
@@ -119,7 +153,7 @@ pub fn get_clipboard_image() -> Result<Option<DynamicImage>> {
     if dib_format.is_format_avail() {
         if let Ok(dib_data) = clipboard_win::get_clipboard(dib_format) {
             let bmp_data = dib_to_bmp(&dib_data)?;
-            if let Ok(image) = image::load_from_memory(&bmp_data) {
+            if let Ok(image) = load_image_from_bytes(&bmp_data, options) {
                 return Ok(Some(image));
             }
         }
@@ -127,3 +161,61 @@ pub fn get_clipboard_image() -> Result<Option<DynamicImage>> {
 
     Ok(None)
 }
+
+#[cfg(test)]
+fn make_test_dib(width: i32, height: i32) -> Vec<u8> {
+    // Bottom-up 24-bit rows, padded to 4 bytes
+    let stride = ((width * 3 + 3) & !3) as usize;
+    let mut dib = Vec::new();
+    dib.extend_from_slice(&40u32.to_le_bytes());
+    dib.extend_from_slice(&width.to_le_bytes());
+    dib.extend_from_slice(&height.to_le_bytes());
+    dib.extend_from_slice(&1u16.to_le_bytes());
+    dib.extend_from_slice(&24u16.to_le_bytes());
+    dib.extend_from_slice(&[0; 4]);
+    dib.extend_from_slice(&((stride * height as usize) as u32).to_le_bytes());
+    dib.extend_from_slice(&[0; 16]);
+    for y in 0..height {
+        let mut row = vec![0u8; stride];
+        for x in 0..width as usize {
+            row[x * 3..x * 3 + 3].copy_from_slice(&[x as u8 * 100, y as u8 * 100, 50]);
+        }
+        dib.extend_from_slice(&row);
+    }
+    dib
+}
+
+#[test]
+fn test_dib_to_bmp() {
+    let dib = make_test_dib(2, 2);
+    let bmp = dib_to_bmp(&dib).unwrap();
+    assert_eq!(&bmp[0..2], b"BM");
+    assert_eq!(u32::from_le_bytes(bmp[10..14].try_into().unwrap()), 54);
+    let image = image::load_from_memory(&bmp).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (2, 2));
+    // BGR order, and the first stored row is the bottom one
+    assert_eq!(image.get_pixel(0, 1).0, [50, 0, 0]);
+    assert_eq!(image.get_pixel(1, 0).0, [50, 100, 100]);
+
+    // Palette that claims more entries than the data holds
+    let mut bad_palette = dib.clone();
+    bad_palette[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(dib_to_bmp(&bad_palette).is_err());
+    let mut bad_header = dib.clone();
+    bad_header[0..4].copy_from_slice(&1000u32.to_le_bytes());
+    assert!(dib_to_bmp(&bad_header).is_err());
+
+    // Corrupt headers must fail to convert or decode, never panic
+    for len in 0..dib.len() {
+        if let Ok(bmp) = dib_to_bmp(&dib[..len]) {
+            let _ = image::load_from_memory(&bmp);
+        }
+    }
+    for offset in 0..40 {
+        let mut corrupt = dib.clone();
+        corrupt[offset] ^= 0xff;
+        if let Ok(bmp) = dib_to_bmp(&corrupt) {
+            let _ = image::load_from_memory(&bmp);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageBuffer, Pixel};
+use moxcms::{CmsError, ColorProfile, Layout, TransformExecutor, TransformOptions};
+use std::path::Path;
+
+// Color space that decoded images are converted to for display
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum OutputProfile {
+    #[default]
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    Icc(Vec<u8>),
+}
+
+impl OutputProfile {
+    // Accepts one of the built-in profile names or a path to an ICC file
+    pub fn parse(arg: &str) -> Result<Self> {
+        match arg.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(OutputProfile::Srgb),
+            "display-p3" | "p3" => Ok(OutputProfile::DisplayP3),
+            "adobe-rgb" => Ok(OutputProfile::AdobeRgb),
+            _ => {
+                let data = std::fs::read(Path::new(arg))
+                    .map_err(|err| anyhow!("Failed to read output profile {arg:?}: {err}"))?;
+                ColorProfile::new_from_slice(&data)
+                    .map_err(|err| anyhow!("Failed to parse output profile {arg:?}: {err}"))?;
+                Ok(OutputProfile::Icc(data))
+            }
+        }
+    }
+
+    pub fn to_color_profile(&self) -> Result<ColorProfile> {
+        match self {
+            OutputProfile::Srgb => Ok(ColorProfile::new_srgb()),
+            OutputProfile::DisplayP3 => Ok(ColorProfile::new_display_p3()),
+            OutputProfile::AdobeRgb => Ok(ColorProfile::new_adobe_rgb()),
+            OutputProfile::Icc(data) => ColorProfile::new_from_slice(data)
+                .map_err(|err| anyhow!("Failed to parse output profile: {err}")),
+        }
+    }
+}
+
+// Converts pixels from the embedded ICC profile to the output profile, using the rendering intent
+// from the embedded profile header. Grayscale images are expanded to RGB.
+pub fn convert_to_output_profile(
+    image: &DynamicImage,
+    icc: &[u8],
+    output: &OutputProfile,
+) -> Result<DynamicImage> {
+    let src_profile = ColorProfile::new_from_slice(icc)
+        .map_err(|err| anyhow!("Failed to parse embedded ICC profile: {err}"))?;
+    let dst_profile = output.to_color_profile()?;
+    let options = TransformOptions {
+        rendering_intent: src_profile.rendering_intent,
+        ..Default::default()
+    };
+
+    let t8 = |s, d| src_profile.create_transform_8bit(s, &dst_profile, d, options);
+    let t16 = |s, d| src_profile.create_transform_16bit(s, &dst_profile, d, options);
+    let tf32 = |s, d| src_profile.create_transform_f32(s, &dst_profile, d, options);
+    let result = match image {
+        DynamicImage::ImageRgb8(buf) => {
+            DynamicImage::ImageRgb8(transform_buffer(buf, Layout::Rgb, Layout::Rgb, t8)?)
+        }
+        DynamicImage::ImageRgba8(buf) => {
+            DynamicImage::ImageRgba8(transform_buffer(buf, Layout::Rgba, Layout::Rgba, t8)?)
+        }
+        DynamicImage::ImageLuma8(buf) => {
+            DynamicImage::ImageRgb8(transform_buffer(buf, Layout::Gray, Layout::Rgb, t8)?)
+        }
+        DynamicImage::ImageLumaA8(buf) => {
+            DynamicImage::ImageRgba8(transform_buffer(buf, Layout::GrayAlpha, Layout::Rgba, t8)?)
+        }
+        DynamicImage::ImageRgb16(buf) => {
+            DynamicImage::ImageRgb16(transform_buffer(buf, Layout::Rgb, Layout::Rgb, t16)?)
+        }
+        DynamicImage::ImageRgba16(buf) => {
+            DynamicImage::ImageRgba16(transform_buffer(buf, Layout::Rgba, Layout::Rgba, t16)?)
+        }
+        DynamicImage::ImageLuma16(buf) => {
+            DynamicImage::ImageRgb16(transform_buffer(buf, Layout::Gray, Layout::Rgb, t16)?)
+        }
+        DynamicImage::ImageLumaA16(buf) => {
+            DynamicImage::ImageRgba16(transform_buffer(buf, Layout::GrayAlpha, Layout::Rgba, t16)?)
+        }
+        DynamicImage::ImageRgb32F(buf) => {
+            DynamicImage::ImageRgb32F(transform_buffer(buf, Layout::Rgb, Layout::Rgb, tf32)?)
+        }
+        DynamicImage::ImageRgba32F(buf) => {
+            DynamicImage::ImageRgba32F(transform_buffer(buf, Layout::Rgba, Layout::Rgba, tf32)?)
+        }
+        _ => return Err(anyhow!("Unsupported pixel format {:?}", image.color())),
+    };
+    Ok(result)
+}
+
+type TransformBox<T> = Box<dyn TransformExecutor<T> + Send + Sync>;
+
+fn transform_buffer<PIn, POut>(
+    buf: &ImageBuffer<PIn, Vec<PIn::Subpixel>>,
+    src_layout: Layout,
+    dst_layout: Layout,
+    create_transform: impl FnOnce(Layout, Layout) -> Result<TransformBox<PIn::Subpixel>, CmsError>,
+) -> Result<ImageBuffer<POut, Vec<PIn::Subpixel>>>
+where
+    PIn: Pixel,
+    POut: Pixel<Subpixel = PIn::Subpixel>,
+    PIn::Subpixel: Default,
+{
+    let transform = create_transform(src_layout, dst_layout)
+        .map_err(|err| anyhow!("Failed to create color transform: {err}"))?;
+    let (w, h) = buf.dimensions();
+    let num_pixels = w as usize * h as usize;
+    let mut data = vec![PIn::Subpixel::default(); num_pixels * dst_layout.channels()];
+    transform
+        .transform(buf.as_raw(), &mut data)
+        .map_err(|err| anyhow!("Failed to apply color transform: {err}"))?;
+    ImageBuffer::from_raw(w, h, data).ok_or_else(|| anyhow!("Color transform size mismatch"))
+}
+
+#[test]
+fn test_color_conversion() {
+    let convert = |profile: ColorProfile, pixel: [u8; 3]| -> [u8; 3] {
+        let icc = profile.encode().unwrap();
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 3, image::Rgb(pixel)));
+        let result = convert_to_output_profile(&image, &icc, &OutputProfile::Srgb).unwrap();
+        assert_eq!(result.color(), image::ColorType::Rgb8);
+        assert_eq!((result.width(), result.height()), (4, 3));
+        result.into_rgb8().get_pixel(3, 2).0
+    };
+    let assert_near = |a: [u8; 3], b: [u8; 3]| {
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((*x as i32 - *y as i32).abs() <= 2, "{:?} != {:?}", a, b);
+        }
+    };
+
+    // Reference values computed from the primaries and transfer functions of each color space
+    assert_near(
+        convert(ColorProfile::new_display_p3(), [200, 100, 50]),
+        [215, 93, 31],
+    );
+    assert_near(
+        convert(ColorProfile::new_display_p3(), [128, 128, 128]),
+        [128, 128, 128],
+    );
+    assert_near(
+        convert(ColorProfile::new_adobe_rgb(), [200, 100, 50]),
+        [227, 100, 42],
+    );
+    assert_near(
+        convert(ColorProfile::new_srgb(), [12, 34, 56]),
+        [12, 34, 56],
+    );
+}
+
+#[test]
+fn test_color_conversion_formats() {
+    let icc = ColorProfile::new_display_p3().encode().unwrap();
+
+    let image = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(
+        2,
+        2,
+        image::Rgba([51400u16, 25700, 12850, 1234]),
+    ));
+    let result = convert_to_output_profile(&image, &icc, &OutputProfile::Srgb).unwrap();
+    let pixel = result.into_rgba16().get_pixel(1, 1).0;
+    assert!((pixel[0] as i32 - 55229).abs() < 600, "{:?}", pixel);
+    assert_eq!(pixel[3], 1234);
+
+    let gray_icc = ColorProfile::new_gray_with_gamma(2.2).encode().unwrap();
+    let image = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 2, image::Luma([0])));
+    let result = convert_to_output_profile(&image, &gray_icc, &OutputProfile::Srgb).unwrap();
+    assert_eq!(result.color(), image::ColorType::Rgb8);
+    assert_eq!(result.into_rgb8().get_pixel(0, 0).0, [0, 0, 0]);
+
+    // Profile color space must match the pixel data
+    let image = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 2, image::Luma([0])));
+    assert!(convert_to_output_profile(&image, &icc, &OutputProfile::Srgb).is_err());
+    assert!(convert_to_output_profile(&image, b"not a profile", &OutputProfile::Srgb).is_err());
+}
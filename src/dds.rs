@@ -0,0 +1,744 @@
+use crate::subimage::{CubeFace, SubimageInfo, SubimageKind, SubimageLayout, SubimageSet};
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageBuffer, Pixel};
+use std::convert::TryInto;
+
+// DirectDraw Surface reader that keeps every array slice, cube face and mip level.
+// The image crate only decodes the first surface of DXT1/3/5 files.
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_ALPHA: u32 = 0x2;
+pub const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x20000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const DDS_DIMENSION_TEXTURE3D: u32 = 4;
+
+// Guards against allocating for nonsensical headers
+const MAX_SURFACES: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PixelFormat {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    // Packed integer pixels described by R, G, B, A bit masks
+    Masked {
+        bit_count: u32,
+        masks: [u32; 4],
+        luminance: bool,
+    },
+    Unorm16 {
+        channels: u32,
+    },
+    Float16 {
+        channels: u32,
+    },
+    Float32 {
+        channels: u32,
+    },
+}
+
+impl PixelFormat {
+    fn from_dxgi(format: u32) -> Result<Self> {
+        let masked = |bit_count, masks| PixelFormat::Masked {
+            bit_count,
+            masks,
+            luminance: false,
+        };
+        let format = match format {
+            2 => PixelFormat::Float32 { channels: 4 },
+            6 => PixelFormat::Float32 { channels: 3 },
+            10 => PixelFormat::Float16 { channels: 4 },
+            11 => PixelFormat::Unorm16 { channels: 4 },
+            16 => PixelFormat::Float32 { channels: 2 },
+            24 => masked(32, [0x3FF, 0xFFC00, 0x3FF0_0000, 0xC000_0000]),
+            28 | 29 => masked(32, [0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000]),
+            34 => PixelFormat::Float16 { channels: 2 },
+            35 => PixelFormat::Unorm16 { channels: 2 },
+            41 => PixelFormat::Float32 { channels: 1 },
+            49 => masked(16, [0xFF, 0xFF00, 0, 0]),
+            54 => PixelFormat::Float16 { channels: 1 },
+            56 => PixelFormat::Unorm16 { channels: 1 },
+            61 => masked(8, [0xFF, 0, 0, 0]),
+            65 => masked(8, [0, 0, 0, 0xFF]),
+            71 | 72 => PixelFormat::Bc1,
+            74 | 75 => PixelFormat::Bc2,
+            77 | 78 => PixelFormat::Bc3,
+            80 => PixelFormat::Bc4,
+            83 => PixelFormat::Bc5,
+            85 => masked(16, [0xF800, 0x7E0, 0x1F, 0]),
+            86 => masked(16, [0x7C00, 0x3E0, 0x1F, 0x8000]),
+            87 | 91 => masked(32, [0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000]),
+            88 | 93 => masked(32, [0xFF_0000, 0xFF00, 0xFF, 0]),
+            115 => masked(16, [0xF00, 0xF0, 0xF, 0xF000]),
+            _ => return Err(anyhow!("Unsupported DDS DXGI format {format}")),
+        };
+        Ok(format)
+    }
+
+    fn from_legacy(flags: u32, fourcc: [u8; 4], bit_count: u32, masks: [u32; 4]) -> Result<Self> {
+        if flags & DDPF_FOURCC != 0 {
+            let format = match &fourcc {
+                b"DXT1" => PixelFormat::Bc1,
+                b"DXT2" | b"DXT3" => PixelFormat::Bc2,
+                b"DXT4" | b"DXT5" => PixelFormat::Bc3,
+                b"ATI1" | b"BC4U" => PixelFormat::Bc4,
+                b"ATI2" | b"BC5U" => PixelFormat::Bc5,
+                // D3DFORMAT values stored in place of a four character code
+                _ => match u32::from_le_bytes(fourcc) {
+                    36 => PixelFormat::Unorm16 { channels: 4 },
+                    111 => PixelFormat::Float16 { channels: 1 },
+                    112 => PixelFormat::Float16 { channels: 2 },
+                    113 => PixelFormat::Float16 { channels: 4 },
+                    114 => PixelFormat::Float32 { channels: 1 },
+                    115 => PixelFormat::Float32 { channels: 2 },
+                    116 => PixelFormat::Float32 { channels: 4 },
+                    _ => {
+                        return Err(anyhow!(
+                            "Unsupported DDS four character code {:?}",
+                            String::from_utf8_lossy(&fourcc)
+                        ))
+                    }
+                },
+            };
+            return Ok(format);
+        }
+        if flags & (DDPF_RGB | DDPF_LUMINANCE | DDPF_ALPHA) == 0 {
+            return Err(anyhow!("Unsupported DDS pixel format flags {flags:#x}"));
+        }
+        if !matches!(bit_count, 8 | 16 | 24 | 32) {
+            return Err(anyhow!("Unsupported DDS bit count {bit_count}"));
+        }
+        let has_alpha = flags & (DDPF_ALPHAPIXELS | DDPF_ALPHA) != 0;
+        Ok(PixelFormat::Masked {
+            bit_count,
+            masks: [
+                masks[0],
+                masks[1],
+                masks[2],
+                if has_alpha { masks[3] } else { 0 },
+            ],
+            luminance: flags & DDPF_LUMINANCE != 0,
+        })
+    }
+
+    fn block_bytes(self) -> Option<usize> {
+        match self {
+            PixelFormat::Bc1 | PixelFormat::Bc4 => Some(8),
+            PixelFormat::Bc2 | PixelFormat::Bc3 | PixelFormat::Bc5 => Some(16),
+            _ => None,
+        }
+    }
+
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Masked { bit_count, .. } => bit_count as usize / 8,
+            PixelFormat::Unorm16 { channels } | PixelFormat::Float16 { channels } => {
+                2 * channels as usize
+            }
+            PixelFormat::Float32 { channels } => 4 * channels as usize,
+            _ => 0,
+        }
+    }
+
+    // Of the images that `decode_surface` returns
+    fn decoded_bytes_per_pixel(self) -> u64 {
+        match self {
+            PixelFormat::Bc4 => 1,
+            PixelFormat::Bc5 => 3,
+            PixelFormat::Bc1 | PixelFormat::Bc2 | PixelFormat::Bc3 => 4,
+            PixelFormat::Masked { .. } => 4,
+            PixelFormat::Unorm16 { .. } => 8,
+            PixelFormat::Float16 { .. } | PixelFormat::Float32 { .. } => 16,
+        }
+    }
+
+    // None when the size does not fit in usize
+    fn surface_size(self, dim: (u32, u32)) -> Option<usize> {
+        let (w, h) = (dim.0 as usize, dim.1 as usize);
+        match self.block_bytes() {
+            Some(block_bytes) => w
+                .div_ceil(4)
+                .checked_mul(h.div_ceil(4))?
+                .checked_mul(block_bytes),
+            None => w.checked_mul(h)?.checked_mul(self.bytes_per_pixel()),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn is_dds(data: &[u8]) -> bool {
+    data.starts_with(DDS_MAGIC)
+}
+
+// Size of the magic number and the header
+pub const DDS_HEADER_LEN: usize = 4 + HEADER_SIZE;
+
+// Size of everything before the surface data, from the first DDS_HEADER_LEN bytes
+pub fn full_header_len(data: &[u8]) -> usize {
+    let header = &data[4..4 + HEADER_SIZE];
+    if read_u32(header, 76) & DDPF_FOURCC != 0 && &header[80..84] == b"DX10" {
+        DDS_HEADER_LEN + DX10_HEADER_SIZE
+    } else {
+        DDS_HEADER_LEN
+    }
+}
+
+// Format and surface layout of a DDS file. Read before any pixels are decoded, so that the size
+// of the decoded surfaces can be checked first.
+pub struct DdsHeader {
+    format: PixelFormat,
+    dim: (u32, u32),
+    faces: Vec<Option<CubeFace>>,
+    layout: SubimageLayout,
+    // Start of the surface data
+    data_offset: usize,
+}
+
+impl DdsHeader {
+    pub fn read(data: &[u8]) -> Result<Self> {
+        if !is_dds(data) || data.len() < 4 + HEADER_SIZE {
+            return Err(anyhow!("Not a DDS file"));
+        }
+        let header = &data[4..4 + HEADER_SIZE];
+        if read_u32(header, 0) as usize != HEADER_SIZE || read_u32(header, 72) != 32 {
+            return Err(anyhow!("Invalid DDS header size"));
+        }
+        let flags = read_u32(header, 4);
+        let height = read_u32(header, 8);
+        let width = read_u32(header, 12);
+        let mip_count = if flags & DDSD_MIPMAPCOUNT != 0 {
+            read_u32(header, 24).max(1)
+        } else {
+            1
+        };
+        let pf_flags = read_u32(header, 76);
+        let fourcc: [u8; 4] = header[80..84].try_into().unwrap();
+        let bit_count = read_u32(header, 84);
+        let masks = [
+            read_u32(header, 88),
+            read_u32(header, 92),
+            read_u32(header, 96),
+            read_u32(header, 100),
+        ];
+        let caps2 = read_u32(header, 108);
+
+        let mut offset = 4 + HEADER_SIZE;
+        let (format, layer_count, is_cubemap) = if pf_flags & DDPF_FOURCC != 0 && &fourcc == b"DX10"
+        {
+            if data.len() < offset + DX10_HEADER_SIZE {
+                return Err(anyhow!("Truncated DDS DX10 header"));
+            }
+            let dx10 = &data[offset..offset + DX10_HEADER_SIZE];
+            offset += DX10_HEADER_SIZE;
+            if read_u32(dx10, 4) == DDS_DIMENSION_TEXTURE3D {
+                return Err(anyhow!("DDS volume textures are not supported"));
+            }
+            let format = PixelFormat::from_dxgi(read_u32(dx10, 0))?;
+            let is_cubemap = read_u32(dx10, 8) & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+            (format, read_u32(dx10, 12).max(1), is_cubemap)
+        } else {
+            if caps2 & DDSCAPS2_VOLUME != 0 {
+                return Err(anyhow!("DDS volume textures are not supported"));
+            }
+            let is_cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+            if is_cubemap && caps2 & DDSCAPS2_CUBEMAP_ALLFACES != DDSCAPS2_CUBEMAP_ALLFACES {
+                return Err(anyhow!("DDS cubemaps with missing faces are not supported"));
+            }
+            let format = PixelFormat::from_legacy(pf_flags, fourcc, bit_count, masks)?;
+            (format, 1, is_cubemap)
+        };
+
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Invalid DDS dimensions {width}x{height}"));
+        }
+        let mip_count = mip_count.min(32 - width.max(height).leading_zeros());
+        let faces: Vec<Option<CubeFace>> = if is_cubemap {
+            CubeFace::ALL.iter().copied().map(Some).collect()
+        } else {
+            vec![None]
+        };
+        let surface_count = layer_count as usize * faces.len() * mip_count as usize;
+        if surface_count > MAX_SURFACES {
+            return Err(anyhow!("Too many DDS surfaces ({surface_count})"));
+        }
+        Ok(DdsHeader {
+            format,
+            dim: (width, height),
+            layout: SubimageLayout {
+                kind: SubimageKind::Texture,
+                layer_count,
+                face_count: faces.len() as u32,
+                mip_count,
+            },
+            faces,
+            data_offset: offset,
+        })
+    }
+
+    // Dimensions of the first surface
+    pub fn dim(&self) -> (u32, u32) {
+        self.dim
+    }
+
+    // Bytes of every decoded surface, saturates for headers that claim absurd sizes
+    pub fn decoded_bytes(&self) -> u64 {
+        let pixel_bytes = self.format.decoded_bytes_per_pixel();
+        self.surfaces()
+            .iter()
+            .map(|s| (u64::from(s.dim.0) * u64::from(s.dim.1)).saturating_mul(pixel_bytes))
+            .fold(0, u64::saturating_add)
+    }
+
+    // In the order they are stored in the file
+    fn surfaces(&self) -> Vec<SubimageInfo> {
+        let mut surfaces = Vec::new();
+        for layer in 0..self.layout.layer_count {
+            for &face in &self.faces {
+                for mip_level in 0..self.layout.mip_count {
+                    let dim = (
+                        (self.dim.0 >> mip_level).max(1),
+                        (self.dim.1 >> mip_level).max(1),
+                    );
+                    surfaces.push(SubimageInfo {
+                        layer,
+                        face,
+                        mip_level,
+                        dim,
+                    });
+                }
+            }
+        }
+        surfaces
+    }
+}
+
+pub fn decode_dds(data: &[u8], header: &DdsHeader) -> Result<SubimageSet> {
+    let surfaces = header.surfaces();
+    let mut offset = header.data_offset;
+    let mut images = Vec::with_capacity(surfaces.len());
+    for surface in &surfaces {
+        let end = header
+            .format
+            .surface_size(surface.dim)
+            .and_then(|size| offset.checked_add(size))
+            .ok_or_else(|| anyhow!("DDS surface size overflows"))?;
+        let surface_data = data
+            .get(offset..end)
+            .ok_or_else(|| anyhow!("Truncated DDS file"))?;
+        offset = end;
+        images.push(decode_surface(header.format, surface.dim, surface_data)?);
+    }
+
+    Ok(SubimageSet {
+        layout: header.layout,
+        surfaces,
+        images,
+    })
+}
+
+fn decode_surface(format: PixelFormat, dim: (u32, u32), data: &[u8]) -> Result<DynamicImage> {
+    let (w, h) = dim;
+    let image = match format {
+        PixelFormat::Bc1 | PixelFormat::Bc2 | PixelFormat::Bc3 => {
+            let mut pixels = vec![0u8; w as usize * h as usize * 4];
+            decode_blocks(format, dim, data, 4, &mut pixels);
+            DynamicImage::ImageRgba8(from_raw(w, h, pixels)?)
+        }
+        PixelFormat::Bc4 => {
+            let mut pixels = vec![0u8; w as usize * h as usize];
+            decode_blocks(format, dim, data, 1, &mut pixels);
+            DynamicImage::ImageLuma8(from_raw(w, h, pixels)?)
+        }
+        PixelFormat::Bc5 => {
+            let mut pixels = vec![0u8; w as usize * h as usize * 3];
+            decode_blocks(format, dim, data, 3, &mut pixels);
+            DynamicImage::ImageRgb8(from_raw(w, h, pixels)?)
+        }
+        PixelFormat::Masked {
+            bit_count,
+            masks,
+            luminance,
+        } => {
+            let bytes = bit_count as usize / 8;
+            let pixels = data
+                .chunks_exact(bytes)
+                .flat_map(|p| {
+                    let mut value = [0u8; 4];
+                    value[..bytes].copy_from_slice(p);
+                    let value = u32::from_le_bytes(value);
+                    let r = extract_channel(value, masks[0]).unwrap_or(0);
+                    let (g, b) = if luminance {
+                        (r, r)
+                    } else {
+                        (
+                            extract_channel(value, masks[1]).unwrap_or(0),
+                            extract_channel(value, masks[2]).unwrap_or(0),
+                        )
+                    };
+                    [r, g, b, extract_channel(value, masks[3]).unwrap_or(255)]
+                })
+                .collect();
+            DynamicImage::ImageRgba8(from_raw(w, h, pixels)?)
+        }
+        PixelFormat::Unorm16 { channels } => {
+            let values = data
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]));
+            let pixels = expand_channels(values, channels, 0, u16::MAX);
+            DynamicImage::ImageRgba16(from_raw(w, h, pixels)?)
+        }
+        PixelFormat::Float16 { channels } => {
+            let values = data
+                .chunks_exact(2)
+                .map(|c| f16_to_f32(u16::from_le_bytes([c[0], c[1]])));
+            let pixels = expand_channels(values, channels, 0.0, 1.0);
+            DynamicImage::ImageRgba32F(from_raw(w, h, pixels)?)
+        }
+        PixelFormat::Float32 { channels } => {
+            let values = data
+                .chunks_exact(4)
+                .map(|c| f32::from_le_bytes(c.try_into().unwrap()));
+            let pixels = expand_channels(values, channels, 0.0, 1.0);
+            DynamicImage::ImageRgba32F(from_raw(w, h, pixels)?)
+        }
+    };
+    Ok(image)
+}
+
+fn from_raw<P: Pixel>(
+    w: u32,
+    h: u32,
+    pixels: Vec<P::Subpixel>,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+    ImageBuffer::from_raw(w, h, pixels)
+        .ok_or_else(|| anyhow!("DDS surface data does not match its dimensions"))
+}
+
+// Scales the masked bits of a packed pixel to 8 bits, None when the channel is absent
+fn extract_channel(value: u32, mask: u32) -> Option<u8> {
+    if mask == 0 {
+        return None;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    let v = ((value & mask) >> shift) as u64;
+    Some(((v * 255 + max / 2) / max) as u8)
+}
+
+// Pads one to four channel pixels to RGBA, missing color channels are zero and alpha is opaque
+fn expand_channels<T: Copy>(
+    values: impl Iterator<Item = T>,
+    channels: u32,
+    zero: T,
+    one: T,
+) -> Vec<T> {
+    let channels = channels as usize;
+    let values: Vec<T> = values.collect();
+    values
+        .chunks_exact(channels)
+        .flat_map(|p| {
+            let mut rgba = [zero, zero, zero, one];
+            rgba[..channels].copy_from_slice(p);
+            rgba
+        })
+        .collect()
+}
+
+fn f16_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1F) as i32;
+    let mantissa = (h & 0x3FF) as f32;
+    match exponent {
+        0 => sign * mantissa * (-24f32).exp2(),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * ((exponent - 15) as f32).exp2(),
+    }
+}
+
+fn decode_blocks(
+    format: PixelFormat,
+    dim: (u32, u32),
+    data: &[u8],
+    channels: usize,
+    out: &mut [u8],
+) {
+    let (w, h) = (dim.0 as usize, dim.1 as usize);
+    let block_bytes = format.block_bytes().unwrap();
+    let blocks_x = w.div_ceil(4);
+    for (block_index, block) in data.chunks_exact(block_bytes).enumerate() {
+        let texels = decode_block(format, block);
+        let bx = (block_index % blocks_x) * 4;
+        let by = (block_index / blocks_x) * 4;
+        for y in 0..4 {
+            for x in 0..4 {
+                let (px, py) = (bx + x, by + y);
+                if px < w && py < h {
+                    let dst = (py * w + px) * channels;
+                    out[dst..dst + channels].copy_from_slice(&texels[y * 4 + x][..channels]);
+                }
+            }
+        }
+    }
+}
+
+// Returns 16 texels in row order. Single channel formats fill the first component,
+// BC5 fills the first two.
+fn decode_block(format: PixelFormat, block: &[u8]) -> [[u8; 4]; 16] {
+    let mut texels = [[0u8; 4]; 16];
+    match format {
+        PixelFormat::Bc1 => decode_color_block(block, true, &mut texels),
+        PixelFormat::Bc2 => {
+            decode_color_block(&block[8..], false, &mut texels);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let a = (block[i / 2] >> ((i % 2) * 4)) & 0xF;
+                texel[3] = a * 17;
+            }
+        }
+        PixelFormat::Bc3 => {
+            decode_color_block(&block[8..], false, &mut texels);
+            decode_alpha_block(block, 3, &mut texels);
+        }
+        PixelFormat::Bc4 => decode_alpha_block(block, 0, &mut texels),
+        PixelFormat::Bc5 => {
+            decode_alpha_block(block, 0, &mut texels);
+            decode_alpha_block(&block[8..], 1, &mut texels);
+        }
+        _ => unreachable!(),
+    }
+    texels
+}
+
+fn rgb565(c: u16) -> [u32; 3] {
+    let r = ((c >> 11) & 0x1F) as u32;
+    let g = ((c >> 5) & 0x3F) as u32;
+    let b = (c & 0x1F) as u32;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+// BC1 color endpoints select 3 color + transparent mode when c0 <= c1,
+// BC2 and BC3 always interpolate 4 colors
+fn decode_color_block(block: &[u8], is_bc1: bool, texels: &mut [[u8; 4]; 16]) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mut palette = [[0u8; 4]; 4];
+    for i in 0..3 {
+        let (a, b) = (e0[i], e1[i]);
+        palette[0][i] = a as u8;
+        palette[1][i] = b as u8;
+        if c0 > c1 || !is_bc1 {
+            palette[2][i] = ((2 * a + b) / 3) as u8;
+            palette[3][i] = ((a + 2 * b) / 3) as u8;
+        } else {
+            palette[2][i] = ((a + b) / 2) as u8;
+        }
+    }
+    palette[0][3] = 255;
+    palette[1][3] = 255;
+    palette[2][3] = 255;
+    palette[3][3] = if c0 > c1 || !is_bc1 { 255 } else { 0 };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (2 * i)) & 3) as usize];
+    }
+}
+
+// Interpolated 8 bit channel used for BC3 alpha and BC4/BC5 color
+fn decode_alpha_block(block: &[u8], channel: usize, texels: &mut [[u8; 4]; 16]) {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i) as u32 * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i) as u32 * a0 + i as u32 * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[channel] = palette[((indices >> (3 * i)) & 7) as usize];
+    }
+}
+
+#[cfg(test)]
+pub fn make_dds_header(
+    dim: (u32, u32),
+    mip_count: u32,
+    pixel_format: [u32; 8],
+    caps2: u32,
+) -> Vec<u8> {
+    let mut header = [0u32; 31];
+    header[0] = HEADER_SIZE as u32;
+    header[1] = 0x1 | 0x2 | 0x4 | 0x1000 | DDSD_MIPMAPCOUNT;
+    header[2] = dim.1;
+    header[3] = dim.0;
+    header[6] = mip_count;
+    header[18..26].copy_from_slice(&pixel_format);
+    header[27] = caps2;
+    let mut data = DDS_MAGIC.to_vec();
+    data.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+    data
+}
+
+#[cfg(test)]
+fn decode_test_dds(data: &[u8]) -> Result<SubimageSet> {
+    decode_dds(data, &DdsHeader::read(data)?)
+}
+
+#[test]
+fn test_dds_bc_blocks() {
+    // Red and blue endpoints, indices select c0, c1, 2/3 c0 + 1/3 c1, 1/3 c0 + 2/3 c1
+    let red = 0xF800u16.to_le_bytes();
+    let blue = 0x001Fu16.to_le_bytes();
+    let block = [red[0], red[1], blue[0], blue[1], 0b11100100, 0, 0, 0];
+    let texels = decode_block(PixelFormat::Bc1, &block);
+    assert_eq!(texels[0], [255, 0, 0, 255]);
+    assert_eq!(texels[1], [0, 0, 255, 255]);
+    assert_eq!(texels[2], [170, 0, 85, 255]);
+    assert_eq!(texels[3], [85, 0, 170, 255]);
+    assert_eq!(texels[4], [255, 0, 0, 255]);
+
+    // Swapped endpoints select 3 color mode with transparent black
+    let block = [blue[0], blue[1], red[0], red[1], 0b11100100, 0, 0, 0];
+    let texels = decode_block(PixelFormat::Bc1, &block);
+    assert_eq!(texels[2], [127, 0, 127, 255]);
+    assert_eq!(texels[3], [0, 0, 0, 0]);
+
+    // 8 value mode: index 2 is 6/7 a0 + 1/7 a1, 6 value mode: index 6 and 7 are 0 and 255
+    let block = [255, 0, 0b010_001_000, 0, 0, 0, 0, 0];
+    let texels = decode_block(PixelFormat::Bc4, &block);
+    assert_eq!([texels[0][0], texels[1][0], texels[2][0]], [255, 0, 218]);
+    let block = [0, 255, 0b10_111_110, 0, 0, 0, 0, 0];
+    let texels = decode_block(PixelFormat::Bc4, &block);
+    assert_eq!([texels[0][0], texels[1][0], texels[2][0]], [0, 255, 51]);
+    let block = [10, 20, 0b00_111_110, 0, 0, 0, 0, 0];
+    let texels = decode_block(PixelFormat::Bc4, &block);
+    assert_eq!([texels[0][0], texels[1][0]], [0, 255]);
+
+    let mut block = [0u8; 16];
+    block[0] = 0x21;
+    block[8..10].copy_from_slice(&0xFFFFu16.to_le_bytes());
+    let texels = decode_block(PixelFormat::Bc2, &block);
+    assert_eq!(texels[0], [255, 255, 255, 17]);
+    assert_eq!(texels[1], [255, 255, 255, 34]);
+    assert_eq!(texels[2][3], 0);
+}
+
+#[test]
+fn test_dds_cubemap_mips() {
+    let pixel_format = [32, DDPF_FOURCC, u32::from_le_bytes(*b"DXT1"), 0, 0, 0, 0, 0];
+    let mut data = make_dds_header((8, 4), 4, pixel_format, DDSCAPS2_CUBEMAP | 0xFC00);
+    // Each face has 2x1, 1x1, 1x1 and 1x1 blocks, the face index is stored in the first endpoint
+    for face in 0..6u16 {
+        for _ in 0..(2 + 1 + 1 + 1) {
+            let c0 = (face << 11).to_le_bytes();
+            data.extend_from_slice(&[c0[0], c0[1], 0, 0, 0, 0, 0, 0]);
+        }
+    }
+    // RGBA8 pixels of 8x4, 4x2, 2x1 and 1x1 mips on each face
+    let header = DdsHeader::read(&data).unwrap();
+    assert_eq!(header.dim(), (8, 4));
+    assert_eq!(header.decoded_bytes(), 6 * (32 + 8 + 2 + 1) * 4);
+    let set = decode_test_dds(&data).unwrap();
+    assert_eq!(
+        set.layout,
+        SubimageLayout {
+            kind: SubimageKind::Texture,
+            layer_count: 1,
+            face_count: 6,
+            mip_count: 4,
+        }
+    );
+    assert_eq!(set.surfaces.len(), 24);
+    assert_eq!(set.images.len(), 24);
+    let dims: Vec<(u32, u32)> = set.surfaces[..4].iter().map(|s| s.dim).collect();
+    assert_eq!(dims, [(8, 4), (4, 2), (2, 1), (1, 1)]);
+    let surface = set.surfaces[4 * 3 + 2];
+    assert_eq!(surface.face, Some(CubeFace::NegativeY));
+    assert_eq!(surface.mip_level, 2);
+    let image = &set.images[4 * 3 + 2];
+    assert_eq!((image.width(), image.height()), (2, 1));
+    assert_eq!(image.to_rgba8().get_pixel(1, 0).0, [24, 0, 0, 255]);
+
+    data.pop();
+    assert!(decode_test_dds(&data).is_err());
+}
+
+#[test]
+fn test_dds_uncompressed() {
+    // Legacy BGRA with alpha
+    let pixel_format = [
+        32,
+        DDPF_RGB | DDPF_ALPHAPIXELS,
+        0,
+        32,
+        0xFF_0000,
+        0xFF00,
+        0xFF,
+        0xFF00_0000,
+    ];
+    let mut data = make_dds_header((2, 1), 1, pixel_format, 0);
+    data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    let set = decode_test_dds(&data).unwrap();
+    assert_eq!(set.images.len(), 1);
+    assert_eq!(
+        set.images[0].to_rgba8().into_raw(),
+        [3, 2, 1, 4, 7, 6, 5, 8]
+    );
+
+    // DX10 array of two R16G16B16A16_FLOAT slices
+    let pixel_format = [32, DDPF_FOURCC, u32::from_le_bytes(*b"DX10"), 0, 0, 0, 0, 0];
+    let mut data = make_dds_header((1, 1), 1, pixel_format, 0);
+    for v in [10u32, 3, 0, 2, 0] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    for half in [0x3C00u16, 0x4000, 0xC400, 0x3800, 0, 0x7C00, 0x0001, 0x3C00] {
+        data.extend_from_slice(&half.to_le_bytes());
+    }
+    let set = decode_test_dds(&data).unwrap();
+    assert_eq!(set.layout.layer_count, 2);
+    assert_eq!(set.surfaces[1].layer, 1);
+    let pixel = set.images[0].to_rgba32f().get_pixel(0, 0).0;
+    assert_eq!(pixel, [1.0, 2.0, -4.0, 0.5]);
+    let pixel = set.images[1].to_rgba32f().get_pixel(0, 0).0;
+    assert_eq!(pixel[1], f32::INFINITY);
+    assert_eq!(pixel[2], (-24f32).exp2());
+
+    // Header sizes that overflow must fail instead of panicking
+    let pixel_format = [32, DDPF_RGB, 0, 32, 0xFF_0000, 0xFF00, 0xFF, 0];
+    let data = make_dds_header((u32::MAX, u32::MAX), 1, pixel_format, 0);
+    assert!(decode_test_dds(&data).is_err());
+
+    assert!(PixelFormat::from_dxgi(98).is_err());
+    assert!(decode_test_dds(b"DDS ").is_err());
+}
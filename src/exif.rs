@@ -0,0 +1,999 @@
+use anyhow::anyhow;
+use nom_exif::{EntryValue, ExifTag, IRational, URational};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::Cursor;
+
+// Upper bound on entries read from one image file directory
+const MAX_IFD_ENTRIES: usize = 4096;
+// Longer arrays are summarized instead of listing every value
+const MAX_DISPLAYED_VALUES: usize = 16;
+
+const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+const TAG_GPS_IFD_POINTER: u16 = 0x8825;
+const TAG_INTEROP_IFD_POINTER: u16 = 0xA005;
+
+// nom-exif reads in steps of this size, and fails on shorter input than it expects
+const MIN_DECODE_LEN: usize = 4096;
+const EXIF_DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+// Image file directories of an EXIF block, in display order
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExifIfd {
+    Ifd0,
+    Exif,
+    Gps,
+    Interop,
+    // Thumbnail
+    Ifd1,
+}
+
+impl ExifIfd {
+    pub const ALL: [ExifIfd; 5] = [
+        ExifIfd::Ifd0,
+        ExifIfd::Exif,
+        ExifIfd::Gps,
+        ExifIfd::Interop,
+        ExifIfd::Ifd1,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExifIfd::Ifd0 => "IFD0",
+            ExifIfd::Exif => "Exif",
+            ExifIfd::Gps => "GPS",
+            ExifIfd::Interop => "Interop",
+            ExifIfd::Ifd1 => "IFD1",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExifValue {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl ExifValue {
+    // Every element as a number, rationals are divided out
+    pub fn to_f64s(&self) -> Vec<f64> {
+        let ratio = |n: f64, d: f64| if d == 0.0 { f64::NAN } else { n / d };
+        match self {
+            ExifValue::Byte(v) | ExifValue::Undefined(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::Ascii(_) => Vec::new(),
+            ExifValue::Short(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::Long(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::Rational(v) => v.iter().map(|&(n, d)| ratio(n as f64, d as f64)).collect(),
+            ExifValue::SByte(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::SShort(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::SLong(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::SRational(v) => v.iter().map(|&(n, d)| ratio(n as f64, d as f64)).collect(),
+            ExifValue::Float(v) => v.iter().map(|&x| x as f64).collect(),
+            ExifValue::Double(v) => v.clone(),
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.to_f64s().first().copied().filter(|x| x.is_finite())
+    }
+
+    // First element of an integer value
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            ExifValue::Byte(v) | ExifValue::Undefined(v) => v.first().map(|&x| x as u32),
+            ExifValue::Short(v) => v.first().map(|&x| x as u32),
+            ExifValue::Long(v) => v.first().copied(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ExifValue::Ascii(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            ExifValue::Ascii(s) => s.len(),
+            ExifValue::Byte(v) | ExifValue::Undefined(v) => v.len(),
+            other => other.to_f64s().len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExifEntry {
+    pub ifd: ExifIfd,
+    pub tag: u16,
+    pub value: ExifValue,
+}
+
+impl ExifEntry {
+    pub fn name(&self) -> String {
+        match tag_name(self.ifd, self.tag) {
+            Some(name) => name.to_owned(),
+            None => format!("Tag 0x{:04X}", self.tag),
+        }
+    }
+
+    // Value formatted for humans, with units and names of enumerated values
+    pub fn display_value(&self) -> String {
+        format_value(self.ifd, self.tag, &self.value)
+            .unwrap_or_else(|| format_generic_value(&self.value))
+    }
+}
+
+// Every tag of an EXIF block, grouped by image file directory
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExifTags {
+    pub entries: Vec<ExifEntry>,
+}
+
+impl ExifTags {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, ifd: ExifIfd, tag: u16) -> Option<&ExifEntry> {
+        self.entries.iter().find(|e| e.ifd == ifd && e.tag == tag)
+    }
+
+    pub fn group(&self, ifd: ExifIfd) -> impl Iterator<Item = &ExifEntry> {
+        self.entries.iter().filter(move |e| e.ifd == ifd)
+    }
+}
+
+// One section per directory with aligned names, as shown in the metadata panel
+impl fmt::Display for ExifTags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.entries.iter().map(|e| e.name().len()).max();
+        let width = width.unwrap_or(0);
+        let mut first = true;
+        for ifd in ExifIfd::ALL {
+            let mut entries = self.group(ifd).peekable();
+            if entries.peek().is_none() {
+                continue;
+            }
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            writeln!(f, "[{}]", ifd.name())?;
+            for entry in entries {
+                writeln!(f, "{:width$}  {}", entry.name(), entry.display_value())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads the directory structure of an EXIF block. nom-exif decodes the values, but it lists the
+// entries of sub-directories under their parent directory, so the directories are found here.
+struct ExifReader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl ExifReader<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let b: [u8; 2] = self.bytes(offset, 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let b: [u8; 4] = self.bytes(offset, 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    // Tags of the directory at `offset` and the offset of the next directory
+    fn read_ifd_tags(&self, offset: u32) -> Option<(Vec<u16>, u32)> {
+        let offset = offset as usize;
+        let count = self.u16(offset)? as usize;
+        if count > MAX_IFD_ENTRIES {
+            return None;
+        }
+        let tags = (0..count)
+            .map(|i| self.u16(offset + 2 + i * 12))
+            .collect::<Option<Vec<_>>>()?;
+        let next = self.u32(offset + 2 + count * 12).unwrap_or(0);
+        Some((tags, next))
+    }
+
+    // Offset of the sub-directory that `tag` of the directory at `offset` points to
+    fn sub_ifd_offset(&self, offset: u32, tags: &[u16], tag: u16) -> Option<u32> {
+        let index = tags.iter().position(|&t| t == tag)?;
+        self.u32(offset as usize + 2 + index * 12 + 8)
+            .filter(|&offset| offset != 0)
+    }
+
+    // Whether the value of the entry at `entry_offset` has a known type and lies in the block
+    fn has_valid_value(&self, entry_offset: usize) -> bool {
+        let elem_size = match self.u16(entry_offset + 2) {
+            Some(1 | 2 | 6 | 7) => 1,
+            Some(3 | 8) => 2,
+            Some(4 | 9 | 11 | 13) => 4,
+            Some(5 | 10 | 12) => 8,
+            _ => return false,
+        };
+        let len = self
+            .u32(entry_offset + 4)
+            .and_then(|count| (count as usize).checked_mul(elem_size));
+        match len {
+            Some(len) if len <= 4 => true,
+            Some(len) => self
+                .u32(entry_offset + 8)
+                .and_then(|offset| self.bytes(offset as usize, len))
+                .is_some(),
+            None => false,
+        }
+    }
+
+    // Entries of the directory at `offset`, decoded by nom-exif from a copy of the block whose
+    // header points at that directory. nom-exif fails the whole directory when a value lies
+    // outside the block, and lists the entries of sub-directories and the next directory with
+    // it. In the copy, those entries have no value and the link to the next directory is cut.
+    fn decode_ifd(&self, ifd: ExifIfd, offset: u32, tags: &[u16]) -> Vec<ExifEntry> {
+        let mut block = self.data.to_vec();
+        let mut write_u32 = |position: usize, value: u32| {
+            if let Some(bytes) = block.get_mut(position..position + 4) {
+                bytes.copy_from_slice(&if self.big_endian {
+                    value.to_be_bytes()
+                } else {
+                    value.to_le_bytes()
+                });
+            }
+        };
+        write_u32(4, offset);
+        let offset = offset as usize;
+        let mut kept = Vec::with_capacity(tags.len());
+        for (i, &tag) in tags.iter().enumerate() {
+            let entry_offset = offset + 2 + i * 12;
+            let is_pointer = matches!(
+                tag,
+                TAG_EXIF_IFD_POINTER | TAG_GPS_IFD_POINTER | TAG_INTEROP_IFD_POINTER
+            );
+            if is_pointer || !self.has_valid_value(entry_offset) {
+                write_u32(entry_offset + 4, 0);
+                write_u32(entry_offset + 8, 0);
+            } else {
+                kept.push(tag);
+            }
+        }
+        write_u32(offset + 2 + tags.len() * 12, 0);
+        block.resize(block.len().max(MIN_DECODE_LEN), 0);
+        let iter: nom_exif::ExifIter = match nom_exif::MediaSource::seekable(Cursor::new(block))
+            .and_then(|source| nom_exif::MediaParser::new().parse(source))
+        {
+            Ok(iter) => iter,
+            Err(_) => return Vec::new(),
+        };
+        // Malformed entries are skipped, the rest of the directory is still useful
+        iter.filter(|entry| entry.ifd_index() == 0 && kept.contains(&entry.tag_code()))
+            .filter_map(|mut entry| {
+                let value = exif_value(entry.take_value()?)?;
+                Some(ExifEntry {
+                    ifd,
+                    tag: entry.tag_code(),
+                    value,
+                })
+            })
+            .collect()
+    }
+}
+
+// nom-exif values as the field types of the block. Dates that nom-exif parsed are formatted back
+// the way EXIF stores them.
+fn exif_value(value: EntryValue) -> Option<ExifValue> {
+    let rationals = |v: Vec<URational>| v.iter().map(|r| (r.0, r.1)).collect();
+    let signed_rationals = |v: Vec<IRational>| v.iter().map(|r| (r.0, r.1)).collect();
+    Some(match value {
+        EntryValue::Text(s) => ExifValue::Ascii(s.trim_end().to_owned()),
+        EntryValue::Time(t) => ExifValue::Ascii(t.format(EXIF_DATE_FORMAT).to_string()),
+        EntryValue::NaiveDateTime(t) => ExifValue::Ascii(t.format(EXIF_DATE_FORMAT).to_string()),
+        EntryValue::U8(v) => ExifValue::Byte(vec![v]),
+        EntryValue::U8Array(v) => ExifValue::Byte(v),
+        EntryValue::Undefined(v) => ExifValue::Undefined(v),
+        EntryValue::U16(v) => ExifValue::Short(vec![v]),
+        EntryValue::U16Array(v) => ExifValue::Short(v),
+        EntryValue::U32(v) => ExifValue::Long(vec![v]),
+        EntryValue::U32Array(v) => ExifValue::Long(v),
+        EntryValue::URational(r) => ExifValue::Rational(rationals(vec![r])),
+        EntryValue::URationalArray(v) => ExifValue::Rational(rationals(v)),
+        EntryValue::I8(v) => ExifValue::SByte(vec![v]),
+        EntryValue::I16(v) => ExifValue::SShort(vec![v]),
+        EntryValue::I32(v) => ExifValue::SLong(vec![v]),
+        EntryValue::IRational(r) => ExifValue::SRational(signed_rationals(vec![r])),
+        EntryValue::IRationalArray(v) => ExifValue::SRational(signed_rationals(v)),
+        EntryValue::F32(v) => ExifValue::Float(vec![v]),
+        EntryValue::F64(v) => ExifValue::Double(vec![v]),
+        // 64-bit integers only occur in BigTIFF files
+        _ => return None,
+    })
+}
+
+// Parses the TIFF structured EXIF block returned by image decoders, with or without the
+// "Exif\0\0" prefix of JPEG APP1 segments. Unreadable sub-directories and entries are skipped.
+pub fn parse_exif_tags(data: &[u8]) -> anyhow::Result<ExifTags> {
+    let data = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+    let big_endian = match data.get(0..2) {
+        Some(b"II") => false,
+        Some(b"MM") => true,
+        _ => return Err(anyhow!("Invalid EXIF byte order")),
+    };
+    let reader = ExifReader { data, big_endian };
+    if reader.u16(2) != Some(42) {
+        return Err(anyhow!("Invalid EXIF header"));
+    }
+    let ifd0_offset = reader
+        .u32(4)
+        .ok_or_else(|| anyhow!("Truncated EXIF header"))?;
+    let (ifd0_tags, ifd1_offset) = reader
+        .read_ifd_tags(ifd0_offset)
+        .ok_or_else(|| anyhow!("Invalid EXIF IFD0 offset {ifd0_offset}"))?;
+
+    let mut entries = reader.decode_ifd(ExifIfd::Ifd0, ifd0_offset, &ifd0_tags);
+    let mut read_sub_ifd = |ifd: ExifIfd, offset: Option<u32>| {
+        let (tags, _) = reader.read_ifd_tags(offset?)?;
+        entries.extend(reader.decode_ifd(ifd, offset?, &tags));
+        Some((offset?, tags))
+    };
+    let exif_offset = reader.sub_ifd_offset(ifd0_offset, &ifd0_tags, TAG_EXIF_IFD_POINTER);
+    let exif = read_sub_ifd(ExifIfd::Exif, exif_offset);
+    let gps_offset = reader.sub_ifd_offset(ifd0_offset, &ifd0_tags, TAG_GPS_IFD_POINTER);
+    read_sub_ifd(ExifIfd::Gps, gps_offset);
+    let interop_offset = exif
+        .and_then(|(offset, tags)| reader.sub_ifd_offset(offset, &tags, TAG_INTEROP_IFD_POINTER));
+    read_sub_ifd(ExifIfd::Interop, interop_offset);
+    read_sub_ifd(ExifIfd::Ifd1, Some(ifd1_offset).filter(|&o| o != 0));
+
+    Ok(ExifTags { entries })
+}
+
+// Names of nom-exif, except for the Interop tags whose numbers it gives GPS names
+fn tag_name(ifd: ExifIfd, tag: u16) -> Option<&'static str> {
+    match ifd {
+        ExifIfd::Interop => match tag {
+            0x0001 => Some("InteroperabilityIndex"),
+            0x0002 => Some("InteroperabilityVersion"),
+            0x1000 => Some("RelatedImageFileFormat"),
+            0x1001 => Some("RelatedImageWidth"),
+            0x1002 => Some("RelatedImageLength"),
+            _ => None,
+        },
+        // GPS tags are numbered from zero, the TIFF tags of the other directories from 0x00FE
+        ExifIfd::Gps if tag >= 0x00FE => None,
+        ExifIfd::Ifd0 | ExifIfd::Exif | ExifIfd::Ifd1 if tag < 0x00FE => None,
+        _ => ExifTag::try_from(tag).ok().map(<&str>::from),
+    }
+}
+
+// Shortest decimal representation with at most `precision` fractional digits
+pub fn format_decimal(value: f64, precision: usize) -> String {
+    let s = format!("{value:.precision$}");
+    let s = if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &s
+    };
+    if s == "-0" {
+        "0".to_owned()
+    } else {
+        s.to_owned()
+    }
+}
+
+fn format_exposure_time(seconds: f64) -> String {
+    if seconds > 0.0 && seconds < 0.25 {
+        format!("1/{} s", (1.0 / seconds).round())
+    } else {
+        format!("{} s", format_decimal(seconds, 1))
+    }
+}
+
+fn format_f_number(f_number: f64) -> String {
+    format!("f/{}", format_decimal(f_number, 1))
+}
+
+fn format_dms(values: &[f64]) -> Option<String> {
+    match values {
+        [degrees, minutes, seconds] => Some(format!(
+            "{}\u{b0} {}' {}\"",
+            format_decimal(*degrees, 4),
+            format_decimal(*minutes, 4),
+            format_decimal(*seconds, 2)
+        )),
+        _ => None,
+    }
+}
+
+// "2.32" for the four digit "0232" version tags
+fn format_version(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != 4 || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let digits = std::str::from_utf8(bytes).ok()?;
+    let major: u32 = digits[..2].parse().ok()?;
+    Some(format!("{major}.{}", &digits[2..]))
+}
+
+fn format_flash(flash: u32) -> String {
+    let mut parts = vec![if flash & 1 != 0 {
+        "Fired"
+    } else {
+        "Did not fire"
+    }];
+    match (flash >> 3) & 3 {
+        1 => parts.push("compulsory"),
+        2 => parts.push("suppressed"),
+        3 => parts.push("auto mode"),
+        _ => {}
+    }
+    match (flash >> 1) & 3 {
+        2 => parts.push("return not detected"),
+        3 => parts.push("return detected"),
+        _ => {}
+    }
+    if flash & 0x20 != 0 {
+        parts.push("no flash function");
+    }
+    if flash & 0x40 != 0 {
+        parts.push("red-eye reduction");
+    }
+    parts.join(", ")
+}
+
+// Character code prefix followed by the comment text
+fn format_user_comment(bytes: &[u8]) -> Option<String> {
+    let (code, text) = (bytes.get(..8)?, bytes.get(8..)?);
+    let text = if code == b"UNICODE\0" {
+        // Byte order follows the file, guess it from the first character
+        let big_endian = text.first() == Some(&0);
+        let units: Vec<u16> = text
+            .chunks_exact(2)
+            .map(|c| {
+                if big_endian {
+                    u16::from_be_bytes([c[0], c[1]])
+                } else {
+                    u16::from_le_bytes([c[0], c[1]])
+                }
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(text).into_owned()
+    };
+    Some(text.trim_end_matches(['\0', ' ']).to_owned())
+}
+
+fn format_lens_specification(values: &[f64]) -> Option<String> {
+    let [min_focal, max_focal, min_f, max_f] = match values {
+        [a, b, c, d] => [*a, *b, *c, *d],
+        _ => return None,
+    };
+    let range = |min: f64, max: f64, precision: usize| {
+        if !max.is_finite() || max == min {
+            format_decimal(min, precision)
+        } else {
+            format!(
+                "{}-{}",
+                format_decimal(min, precision),
+                format_decimal(max, precision)
+            )
+        }
+    };
+    let mut s = format!("{} mm", range(min_focal, max_focal, 0));
+    if min_f.is_finite() && min_f > 0.0 {
+        s += &format!(" f/{}", range(min_f, max_f, 1));
+    }
+    Some(s)
+}
+
+fn enum_name(value: u32, names: &[(u32, &'static str)]) -> Option<String> {
+    names
+        .iter()
+        .find(|(v, _)| *v == value)
+        .map(|(_, name)| (*name).to_owned())
+}
+
+// Human readable formatting of well known tags. Returns None to fall back to the raw values.
+fn format_value(ifd: ExifIfd, tag: u16, value: &ExifValue) -> Option<String> {
+    let number = value.as_f64();
+    let integer = value.as_u32();
+    let numbers = value.to_f64s();
+    if ifd == ExifIfd::Gps {
+        return match tag {
+            0x0000 => Some(
+                numbers
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join("."),
+            ),
+            0x0002 | 0x0004 | 0x0014 | 0x0016 => format_dms(&numbers),
+            0x0005 => enum_name(integer?, &[(0, "Above sea level"), (1, "Below sea level")]),
+            0x0006 => Some(format!("{} m", format_decimal(number?, 2))),
+            0x0007 => match numbers.as_slice() {
+                [h, m, s] => Some(format!(
+                    "{:02}:{:02}:{}{}",
+                    h,
+                    m,
+                    if *s < 10.0 { "0" } else { "" },
+                    format_decimal(*s, 3)
+                )),
+                _ => None,
+            },
+            0x001B | 0x001C => match value {
+                ExifValue::Undefined(bytes) => format_user_comment(bytes),
+                _ => None,
+            },
+            _ => None,
+        };
+    }
+    if ifd == ExifIfd::Interop {
+        return match (tag, value) {
+            (0x0002, ExifValue::Undefined(bytes)) => format_version(bytes),
+            _ => None,
+        };
+    }
+    match tag {
+        0x0103 => enum_name(
+            integer?,
+            &[
+                (1, "Uncompressed"),
+                (5, "LZW"),
+                (6, "JPEG (old-style)"),
+                (7, "JPEG"),
+                (8, "Deflate"),
+                (32773, "PackBits"),
+            ],
+        ),
+        0x0112 => enum_name(
+            integer?,
+            &[
+                (1, "Horizontal (normal)"),
+                (2, "Mirror horizontal"),
+                (3, "Rotate 180"),
+                (4, "Mirror vertical"),
+                (5, "Mirror horizontal and rotate 270 CW"),
+                (6, "Rotate 90 CW"),
+                (7, "Mirror horizontal and rotate 90 CW"),
+                (8, "Rotate 270 CW"),
+            ],
+        ),
+        0x011A | 0x011B | 0xA20E | 0xA20F => Some(format_decimal(number?, 2)),
+        0x0128 | 0xA210 => enum_name(integer?, &[(1, "None"), (2, "inches"), (3, "cm")]),
+        0x0213 => enum_name(integer?, &[(1, "Centered"), (2, "Co-sited")]),
+        0x829A => Some(format_exposure_time(number?)),
+        0x829D => Some(format_f_number(number?)),
+        0x8822 => enum_name(
+            integer?,
+            &[
+                (0, "Not defined"),
+                (1, "Manual"),
+                (2, "Normal program"),
+                (3, "Aperture priority"),
+                (4, "Shutter priority"),
+                (5, "Creative program"),
+                (6, "Action program"),
+                (7, "Portrait mode"),
+                (8, "Landscape mode"),
+            ],
+        ),
+        0x8827 => Some(format!("ISO {}", integer?)),
+        0x9000 | 0xA000 => match value {
+            ExifValue::Undefined(bytes) => format_version(bytes),
+            _ => None,
+        },
+        0x9101 => match value {
+            ExifValue::Undefined(bytes) => Some(
+                bytes
+                    .iter()
+                    .filter_map(|b| match b {
+                        1 => Some("Y"),
+                        2 => Some("Cb"),
+                        3 => Some("Cr"),
+                        4 => Some("R"),
+                        5 => Some("G"),
+                        6 => Some("B"),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        },
+        // APEX units
+        0x9201 => Some(format_exposure_time(2f64.powf(-number?))),
+        0x9202 | 0x9205 => Some(format_f_number(2f64.powf(number? / 2.0))),
+        0x9203 => Some(format!("{} EV", format_decimal(number?, 2))),
+        0x9204 => {
+            let ev = number?;
+            let sign = if ev > 0.0 { "+" } else { "" };
+            Some(format!("{sign}{} EV", format_decimal(ev, 2)))
+        }
+        0x9206 => match value {
+            ExifValue::Rational(v) if v.first()?.0 == u32::MAX => Some("Infinity".to_owned()),
+            _ if number? == 0.0 => Some("Unknown".to_owned()),
+            _ => Some(format!("{} m", format_decimal(number?, 2))),
+        },
+        0x9207 => enum_name(
+            integer?,
+            &[
+                (0, "Unknown"),
+                (1, "Average"),
+                (2, "Center-weighted average"),
+                (3, "Spot"),
+                (4, "Multi-spot"),
+                (5, "Pattern"),
+                (6, "Partial"),
+                (255, "Other"),
+            ],
+        ),
+        0x9208 => enum_name(
+            integer?,
+            &[
+                (0, "Unknown"),
+                (1, "Daylight"),
+                (2, "Fluorescent"),
+                (3, "Tungsten"),
+                (4, "Flash"),
+                (9, "Fine weather"),
+                (10, "Cloudy"),
+                (11, "Shade"),
+                (12, "Daylight fluorescent"),
+                (13, "Day white fluorescent"),
+                (14, "Cool white fluorescent"),
+                (15, "White fluorescent"),
+                (17, "Standard light A"),
+                (18, "Standard light B"),
+                (19, "Standard light C"),
+                (20, "D55"),
+                (21, "D65"),
+                (22, "D75"),
+                (23, "D50"),
+                (24, "ISO studio tungsten"),
+                (255, "Other"),
+            ],
+        ),
+        0x9209 => Some(format_flash(integer?)),
+        0x920A => Some(format!("{} mm", format_decimal(number?, 1))),
+        0x927C => Some(format!("{} bytes", value.len())),
+        0x9286 => match value {
+            ExifValue::Undefined(bytes) => format_user_comment(bytes),
+            _ => None,
+        },
+        0xA001 => enum_name(
+            integer?,
+            &[(1, "sRGB"), (2, "Adobe RGB"), (0xFFFF, "Uncalibrated")],
+        ),
+        0xA217 => enum_name(
+            integer?,
+            &[
+                (1, "Not defined"),
+                (2, "One-chip color area"),
+                (3, "Two-chip color area"),
+                (4, "Three-chip color area"),
+                (5, "Color sequential area"),
+                (7, "Trilinear"),
+                (8, "Color sequential linear"),
+            ],
+        ),
+        0xA300 => enum_name(
+            integer?,
+            &[
+                (1, "Film scanner"),
+                (2, "Reflection print scanner"),
+                (3, "Digital camera"),
+            ],
+        ),
+        0xA301 => enum_name(integer?, &[(1, "Directly photographed")]),
+        0xA401 => enum_name(integer?, &[(0, "Normal"), (1, "Custom")]),
+        0xA402 => enum_name(integer?, &[(0, "Auto"), (1, "Manual"), (2, "Auto bracket")]),
+        0xA403 => enum_name(integer?, &[(0, "Auto"), (1, "Manual")]),
+        0xA404 => match number {
+            Some(ratio) if ratio > 0.0 => Some(format!("{}x", format_decimal(ratio, 2))),
+            _ => Some("Not used".to_owned()),
+        },
+        0xA405 => Some(format!("{} mm", integer?)),
+        0xA406 => enum_name(
+            integer?,
+            &[
+                (0, "Standard"),
+                (1, "Landscape"),
+                (2, "Portrait"),
+                (3, "Night scene"),
+            ],
+        ),
+        0xA407 => enum_name(
+            integer?,
+            &[
+                (0, "None"),
+                (1, "Low gain up"),
+                (2, "High gain up"),
+                (3, "Low gain down"),
+                (4, "High gain down"),
+            ],
+        ),
+        0xA408 | 0xA409 => enum_name(integer?, &[(0, "Normal"), (1, "Low"), (2, "High")]),
+        0xA40A => enum_name(integer?, &[(0, "Normal"), (1, "Soft"), (2, "Hard")]),
+        0xA40C => enum_name(
+            integer?,
+            &[(0, "Unknown"), (1, "Macro"), (2, "Close"), (3, "Distant")],
+        ),
+        0xA432 => format_lens_specification(&numbers),
+        _ => None,
+    }
+}
+
+fn format_generic_value(value: &ExifValue) -> String {
+    let bytes = match value {
+        ExifValue::Ascii(s) => return s.clone(),
+        ExifValue::Byte(bytes) | ExifValue::Undefined(bytes) => Some(bytes),
+        _ => None,
+    };
+    if let Some(bytes) = bytes {
+        let text = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        if !text.is_empty() && text.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            return String::from_utf8_lossy(text).into_owned();
+        }
+        if bytes.len() > MAX_DISPLAYED_VALUES {
+            return format!("{} bytes", bytes.len());
+        }
+        return bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+    let numbers = value.to_f64s();
+    let mut s = numbers
+        .iter()
+        .take(MAX_DISPLAYED_VALUES)
+        .map(|&v| {
+            if v.is_finite() {
+                format_decimal(v, 4)
+            } else {
+                "n/a".to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    if numbers.len() > MAX_DISPLAYED_VALUES {
+        s += &format!(", ... ({} values)", numbers.len());
+    }
+    s
+}
+
+#[cfg(test)]
+pub enum TestValue {
+    // Field type, count and little endian value bytes
+    Data(u16, u32, Vec<u8>),
+    // Offset of another directory in the list
+    Pointer(usize),
+}
+
+#[cfg(test)]
+pub fn test_ascii(s: &str) -> TestValue {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    TestValue::Data(2, bytes.len() as u32, bytes)
+}
+
+#[cfg(test)]
+pub fn test_short(v: u16) -> TestValue {
+    TestValue::Data(3, 1, v.to_le_bytes().to_vec())
+}
+
+#[cfg(test)]
+pub fn test_rationals(v: &[(u32, u32)]) -> TestValue {
+    let bytes = v
+        .iter()
+        .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat())
+        .collect();
+    TestValue::Data(5, v.len() as u32, bytes)
+}
+
+// Little endian EXIF block with the given directories laid out in order. The first directory is
+// IFD0, `ifd1` selects the directory linked after it.
+#[cfg(test)]
+pub fn make_test_exif(ifds: &[Vec<(u16, TestValue)>], ifd1: Option<usize>) -> Vec<u8> {
+    let data_len = |v: &TestValue| match v {
+        TestValue::Data(_, _, bytes) if bytes.len() > 4 => bytes.len(),
+        _ => 0,
+    };
+    let mut offsets = Vec::new();
+    let mut offset = 8;
+    for ifd in ifds {
+        offsets.push(offset);
+        offset += 2 + ifd.len() * 12 + 4 + ifd.iter().map(|(_, v)| data_len(v)).sum::<usize>();
+    }
+
+    let mut out = b"II\x2a\x00".to_vec();
+    out.extend_from_slice(&8u32.to_le_bytes());
+    for (i, ifd) in ifds.iter().enumerate() {
+        let mut data_offset = offsets[i] + 2 + ifd.len() * 12 + 4;
+        let mut data = Vec::new();
+        out.extend_from_slice(&(ifd.len() as u16).to_le_bytes());
+        for (tag, value) in ifd {
+            out.extend_from_slice(&tag.to_le_bytes());
+            let (field_type, count, bytes) = match value {
+                TestValue::Data(t, c, b) => (*t, *c, b.clone()),
+                TestValue::Pointer(target) => {
+                    (4, 1, (offsets[*target] as u32).to_le_bytes().to_vec())
+                }
+            };
+            out.extend_from_slice(&field_type.to_le_bytes());
+            out.extend_from_slice(&count.to_le_bytes());
+            if bytes.len() > 4 {
+                out.extend_from_slice(&(data_offset as u32).to_le_bytes());
+                data_offset += bytes.len();
+                data.extend_from_slice(&bytes);
+            } else {
+                let mut inline = bytes.clone();
+                inline.resize(4, 0);
+                out.extend_from_slice(&inline);
+            }
+        }
+        let next = match ifd1 {
+            Some(target) if i == 0 => offsets[target] as u32,
+            _ => 0,
+        };
+        out.extend_from_slice(&next.to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+    out
+}
+
+#[test]
+fn test_exif_tags() {
+    let exif = make_test_exif(
+        &[
+            vec![
+                (0x010F, test_ascii("Canon")),
+                (0x0112, test_short(6)),
+                (TAG_EXIF_IFD_POINTER, TestValue::Pointer(1)),
+                (TAG_GPS_IFD_POINTER, TestValue::Pointer(2)),
+            ],
+            vec![
+                (0x829A, test_rationals(&[(1, 250)])),
+                (0x829D, test_rationals(&[(28, 10)])),
+                (0x8827, test_short(400)),
+                (0x9000, TestValue::Data(7, 4, b"0232".to_vec())),
+                (
+                    0x9204,
+                    TestValue::Data(10, 1, [(-2i32).to_le_bytes(), 3i32.to_le_bytes()].concat()),
+                ),
+                (0x9206, test_rationals(&[(35, 10)])),
+                (0x9209, test_short(0x19)),
+                (0x920A, test_rationals(&[(50, 1)])),
+                (0xA403, test_short(0)),
+                (
+                    0xA432,
+                    test_rationals(&[(24, 1), (70, 1), (28, 10), (28, 10)]),
+                ),
+                (0xBEEF, TestValue::Data(7, 3, vec![1, 2, 3])),
+                (TAG_INTEROP_IFD_POINTER, TestValue::Pointer(3)),
+            ],
+            vec![
+                (0x0001, test_ascii("N")),
+                (0x0002, test_rationals(&[(51, 1), (30, 1), (2646, 100)])),
+                (0x0007, test_rationals(&[(14, 1), (5, 1), (9, 1)])),
+            ],
+            vec![(0x0001, test_ascii("R98"))],
+            vec![(0x0103, test_short(6))],
+        ],
+        Some(4),
+    );
+    let tags = parse_exif_tags(&exif).unwrap();
+    let display = |ifd, tag| tags.get(ifd, tag).unwrap().display_value();
+
+    assert_eq!(tags.group(ExifIfd::Ifd0).count(), 2);
+    assert_eq!(
+        tags.get(ExifIfd::Ifd0, 0x010F).unwrap().value.as_str(),
+        Some("Canon")
+    );
+    assert_eq!(display(ExifIfd::Ifd0, 0x0112), "Rotate 90 CW");
+    assert_eq!(display(ExifIfd::Exif, 0x829A), "1/250 s");
+    assert_eq!(display(ExifIfd::Exif, 0x829D), "f/2.8");
+    assert_eq!(display(ExifIfd::Exif, 0x8827), "ISO 400");
+    assert_eq!(display(ExifIfd::Exif, 0x9000), "2.32");
+    assert_eq!(display(ExifIfd::Exif, 0x9204), "-0.67 EV");
+    assert_eq!(display(ExifIfd::Exif, 0x9206), "3.5 m");
+    assert_eq!(display(ExifIfd::Exif, 0x9209), "Fired, auto mode");
+    assert_eq!(display(ExifIfd::Exif, 0x920A), "50 mm");
+    assert_eq!(display(ExifIfd::Exif, 0xA403), "Auto");
+    assert_eq!(display(ExifIfd::Exif, 0xA432), "24-70 mm f/2.8");
+    assert_eq!(display(ExifIfd::Exif, 0xBEEF), "01 02 03");
+    assert_eq!(
+        tags.get(ExifIfd::Exif, 0xBEEF).unwrap().name(),
+        "Tag 0xBEEF"
+    );
+    assert_eq!(display(ExifIfd::Gps, 0x0001), "N");
+    assert_eq!(display(ExifIfd::Gps, 0x0002), "51\u{b0} 30' 26.46\"");
+    assert_eq!(display(ExifIfd::Gps, 0x0007), "14:05:09");
+    assert_eq!(
+        tags.get(ExifIfd::Gps, 0x0002).unwrap().name(),
+        "GPSLatitude"
+    );
+    assert_eq!(display(ExifIfd::Interop, 0x0001), "R98");
+    assert_eq!(display(ExifIfd::Ifd1, 0x0103), "JPEG (old-style)");
+    // Directory pointers are not listed
+    assert!(tags.get(ExifIfd::Ifd0, TAG_EXIF_IFD_POINTER).is_none());
+
+    let text = tags.to_string();
+    assert!(text.starts_with("[IFD0]\nMake "));
+    assert!(text.contains("\n\n[GPS]\n"));
+    assert!(text.ends_with("[IFD1]\nCompression            JPEG (old-style)\n"));
+
+    // JPEG APP1 payloads start with an identifier
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&exif);
+    assert_eq!(parse_exif_tags(&app1).unwrap(), tags);
+}
+
+#[test]
+fn test_exif_tags_malformed() {
+    assert!(parse_exif_tags(b"").is_err());
+    assert!(parse_exif_tags(b"XX\x2a\x00\x08\x00\x00\x00").is_err());
+    assert!(parse_exif_tags(b"II\x2a\x00\xff\x00\x00\x00").is_err());
+
+    // Big endian with an entry pointing outside of the block, which is skipped
+    let mut exif = b"MM\x00\x2a\x00\x00\x00\x08\x00\x02".to_vec();
+    exif.extend_from_slice(&[
+        0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0, 0,
+    ]);
+    exif.extend_from_slice(&[
+        0x01, 0x0F, 0x00, 0x02, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 1, 0,
+    ]);
+    exif.extend_from_slice(&[0, 0, 0, 0]);
+    let tags = parse_exif_tags(&exif).unwrap();
+    assert_eq!(tags.entries.len(), 1);
+    assert_eq!(
+        tags.get(ExifIfd::Ifd0, 0x0112).unwrap().display_value(),
+        "Rotate 180"
+    );
+
+    // Truncated blocks never panic
+    let full = make_test_exif(
+        &[
+            vec![
+                (0x010F, test_ascii("Nikon Corporation")),
+                (TAG_EXIF_IFD_POINTER, TestValue::Pointer(1)),
+            ],
+            vec![(0x829A, test_rationals(&[(1, 0)]))],
+        ],
+        None,
+    );
+    for len in 0..full.len() {
+        let _ = parse_exif_tags(&full[..len]).map(|tags| tags.to_string());
+    }
+    let tags = parse_exif_tags(&full).unwrap();
+    // Zero denominators don't produce numbers
+    assert_eq!(
+        tags.get(ExifIfd::Exif, 0x829A).unwrap().display_value(),
+        "n/a"
+    );
+}
@@ -27,6 +27,10 @@ use winapi::Interface;
 
 use crate::window::get_window_client_rect_dimensions;
 use crate::math::*;
+use crate::mips::ImageMips;
+use crate::orientation::ViewOrientation;
+use crate::tiles::{PixelRect, Tile, TileGrid};
+use crate::upload::{prepare_upload, UploadFormat, UploadImage};
 
 const NUM_BACK_BUFFERS: u32 = 3;
 const BACK_BUFFER_FORMAT: u32 = DXGI_FORMAT_B8G8R8A8_UNORM;
@@ -45,6 +49,15 @@ pub struct Constants {
     pub window_dim: float2,
     pub mouse: float4, // float2 xy pos, uint buttons, uint unused
     pub xfm_viewport_to_image_uv: float4,
+    pub xfm_viewport_to_image_uv_dihedral: float4, // swap xy, negate x, negate y, unused
+    pub image_orientation: float4, // flip x, flip y, transpose, unused
+    pub display: float4, // exposure scale, 1/gamma, tone map operator, image is linear
+    pub tile_uv_rect: float4, // texture uv min xy, max zw
+    pub xfm_texture_uv_to_tile_uv: float4,
+    pub sampling: float4, // minifying, magnification filter, texture is sRGB, unused
+    pub pixel_grid: float4, // draw grid, draw labels, label max value, label text scale
+    pub pixel_grid_color: float4, // rgb color, opacity
+    pub missing_pixels: float4, // first missing pixel xy, pixel after the last zw, in row order
 }
 
 pub struct BackBuffer {
@@ -64,6 +77,7 @@ pub struct GraphicsD3D11 {
     pub constants: ComPtr<ID3D11Buffer>,
     pub smp_linear: ComPtr<ID3D11SamplerState>,
     pub smp_point: ComPtr<ID3D11SamplerState>,
+    pub smp_anisotropic: ComPtr<ID3D11SamplerState>,
     swap_chain_waitable: Option<winapi::shared::ntdef::HANDLE>,
     frame_statistics: DXGI_FRAME_STATISTICS,
 }
@@ -224,6 +238,7 @@ impl GraphicsD3D11 {
 
         let mut smp_linear = null_mut();
         let mut smp_point = null_mut();
+        let mut smp_anisotropic = null_mut();
 
         {
             let smp_desc_base = D3D11_SAMPLER_DESC {
@@ -251,6 +266,14 @@ impl GraphicsD3D11 {
                 let hr = device.CreateSamplerState(&smp_desc, &mut smp_point);
                 assert!(hr == S_OK);
             }
+
+            {
+                let mut smp_desc = smp_desc_base;
+                smp_desc.Filter = D3D11_FILTER_ANISOTROPIC;
+                smp_desc.MaxAnisotropy = 16;
+                let hr = device.CreateSamplerState(&smp_desc, &mut smp_anisotropic);
+                assert!(hr == S_OK);
+            }
         }
 
         let mut result = GraphicsD3D11 {
@@ -268,6 +291,7 @@ impl GraphicsD3D11 {
             constants,
             smp_linear: ComPtr::from_raw(smp_linear),
             smp_point: ComPtr::from_raw(smp_point),
+            smp_anisotropic: ComPtr::from_raw(smp_anisotropic),
             swap_chain_waitable,
             frame_statistics: std::mem::zeroed(),
         };
@@ -357,25 +381,40 @@ impl GraphicsD3D11 {
     }
 }
 
-pub struct Texture {
+// 8-bit images are sampled through sRGB formats so that filtering between pixels and mip levels
+// happens in linear light, the shader encodes the result again
+fn get_dxgi_format(format: UploadFormat) -> DXGI_FORMAT {
+    match format {
+        UploadFormat::Rgba8Unorm => DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+        UploadFormat::Rgba16Unorm => DXGI_FORMAT_R16G16B16A16_UNORM,
+        UploadFormat::Rgba32Float => DXGI_FORMAT_R32G32B32A32_FLOAT,
+    }
+}
+
+pub struct TextureTile {
     pub tex: ComPtr<ID3D11Texture2D>,
     pub srv: ComPtr<ID3D11ShaderResourceView>,
-    pub dim: (u32, u32),
+    pub tile: Tile,
 }
 
-impl Texture {
-    pub fn new(device: &ComPtr<ID3D11Device>, image: image::DynamicImage) -> Self {
+impl TextureTile {
+    // Texture of the pixels in `rect` of `upload`, with `mips` as the rest of its chain
+    fn new(
+        device: &ComPtr<ID3D11Device>,
+        upload: &UploadImage,
+        rect: PixelRect,
+        mips: &[UploadImage],
+        tile: Tile,
+    ) -> Self {
         let mut image_tex: *mut ID3D11Texture2D = null_mut();
         let mut image_srv: *mut ID3D11ShaderResourceView = null_mut();
-        let img_buf = image.into_rgba8();
-        let dim = img_buf.dimensions();
-        let img_container = img_buf.as_raw();
+        let dim = rect.dim();
         let texture_desc = D3D11_TEXTURE2D_DESC {
             Width: dim.0,
             Height: dim.1,
-            MipLevels: 1,
+            MipLevels: 1 + mips.len() as u32,
             ArraySize: 1,
-            Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+            Format: get_dxgi_format(upload.format),
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
                 Quality: 0,
@@ -385,15 +424,25 @@ impl Texture {
             CPUAccessFlags: 0,
             MiscFlags: 0,
         };
-        let image_data = D3D11_SUBRESOURCE_DATA {
-            pSysMem: img_container.as_ptr() as *mut c_void,
-            SysMemPitch: 4 * texture_desc.Width,
+        // Tiles read their pixels straight out of the whole image, the pitch skips the rest of
+        // each row
+        let (x, y) = rect.min;
+        let offset = y as usize * upload.row_pitch() as usize
+            + x as usize * upload.format.bytes_per_pixel() as usize;
+        let mut image_data = vec![D3D11_SUBRESOURCE_DATA {
+            pSysMem: unsafe { upload.pixels.as_bytes_ptr().add(offset) } as *mut c_void,
+            SysMemPitch: upload.row_pitch(),
             SysMemSlicePitch: 0,
-        };
+        }];
+        image_data.extend(mips.iter().map(|mip| D3D11_SUBRESOURCE_DATA {
+            pSysMem: mip.pixels.as_bytes_ptr() as *mut c_void,
+            SysMemPitch: mip.row_pitch(),
+            SysMemSlicePitch: 0,
+        }));
         unsafe {
             device.CreateTexture2D(
                 &texture_desc as *const D3D11_TEXTURE2D_DESC,
-                &image_data as *const D3D11_SUBRESOURCE_DATA,
+                image_data.as_ptr(),
                 &mut image_tex as *mut *mut ID3D11Texture2D,
             );
             device.CreateShaderResourceView(
@@ -405,7 +454,70 @@ impl Texture {
         Self {
             tex: unsafe { ComPtr::from_raw(image_tex) },
             srv: unsafe { ComPtr::from_raw(image_srv) },
-            dim,
+            tile,
+        }
+    }
+}
+
+// Images larger than the Direct3D limit are split into several textures, see `TileGrid`
+pub struct Texture {
+    pub tiles: Vec<TextureTile>,
+    // Whole image drawn in place of the tiles when zoomed out past their mips, see
+    // `ImageMips::overview`
+    pub overview: Option<TextureTile>,
+    pub grid: TileGrid,
+    pub dim: (u32, u32),
+    pub format: UploadFormat,
+}
+
+impl Texture {
+    pub fn new(
+        device: &ComPtr<ID3D11Device>,
+        image: &image::DynamicImage,
+        mips: &ImageMips,
+    ) -> Self {
+        let upload = prepare_upload(image);
+        let grid = TileGrid::new(upload.dim);
+        // Every tile has its own mip chain, built from its pixels including the border
+        debug_assert_eq!(grid.len(), mips.tiles.len());
+        let tiles = grid
+            .tiles()
+            .zip(&mips.tiles)
+            .map(|(tile, mips)| TextureTile::new(device, &upload, tile.texture_rect, mips, tile))
+            .collect();
+        let overview = mips.overview.split_first().map(|(first, rest)| {
+            let rect = PixelRect {
+                min: (0, 0),
+                max: first.dim,
+            };
+            TextureTile::new(device, first, rect, rest, grid.whole_image_tile())
+        });
+        Self {
+            tiles,
+            overview,
+            grid,
+            dim: upload.dim,
+            format: upload.format,
+        }
+    }
+
+    // Tiles that cover part of the viewport, or the overview alone when the view shows more
+    // than `TileGrid::uses_overview` image pixels per viewport pixel
+    pub fn visible_tiles(
+        &self,
+        xfm_viewport_to_image_uv: Transform2D,
+        orientation: ViewOrientation,
+        viewport_dim: float2,
+        image_pixels_per_pixel: f32,
+    ) -> Vec<&TextureTile> {
+        match &self.overview {
+            Some(overview) if self.grid.uses_overview(image_pixels_per_pixel) => vec![overview],
+            _ => self
+                .grid
+                .visible_tiles(xfm_viewport_to_image_uv, orientation, viewport_dim)
+                .into_iter()
+                .map(|index| &self.tiles[index])
+                .collect(),
         }
     }
 }
@@ -0,0 +1,311 @@
+use crate::math::*;
+use crate::upload::UploadFormat;
+
+// Reference implementation of the pixel grid and pixel value labels in `blit_ps`. Both must be
+// kept in sync.
+
+// Glyphs of 0-9, '.' and '-' in 3x5 pixels, bit `y * 3 + x` is set where the glyph is drawn
+pub const GLYPHS: [u16; 12] = [
+    0x7B6F, 0x749A, 0x73E7, 0x79A7, 0x49ED, 0x79CF, 0x7BCF, 0x2527, 0x7BEF, 0x79EF, 0x2000, 0x01C0,
+];
+pub const GLYPH_POINT: u32 = 10;
+pub const GLYPH_MINUS: u32 = 11;
+// Glyphs are 3x5 with one pixel of spacing
+const GLYPH_ADVANCE: f32 = 4.0;
+const LINE_HEIGHT: f32 = 6.0;
+// Float values are clamped so that their labels fit in a pixel
+pub const MAX_LABEL_FLOAT: f32 = 999.999;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelGrid {
+    pub enabled: bool,
+    // Window pixels per image pixel from which the grid is drawn
+    pub min_magnification: f32,
+    // Display encoded, blended over the image with `opacity`
+    pub color: float3,
+    pub opacity: f32,
+    // Window pixels per image pixel from which pixel values are drawn inside the pixels
+    pub label_magnification: f32,
+}
+
+impl PixelGrid {
+    pub fn new_default() -> Self {
+        Self {
+            enabled: true,
+            min_magnification: 8.0,
+            color: float3::new(0.5, 0.5, 0.5),
+            opacity: 0.5,
+            label_magnification: 64.0,
+        }
+    }
+
+    // "RRGGBB" hex digits, optionally prefixed with '#'
+    pub fn parse_color(arg: &str) -> Option<float3> {
+        let hex = arg.strip_prefix('#').unwrap_or(arg);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .ok()
+                .map(|c| c as f32 / 255.0)
+        };
+        Some(float3::new(channel(0)?, channel(1)?, channel(2)?))
+    }
+
+    // x: draw grid, y: draw labels, z: label max value, w: label text scale. Labels show the
+    // stored integer of normalized formats, so the max value is 255 or 65535, and 0 for floats.
+    pub fn to_float4(self, magnification: f32, format: Option<UploadFormat>) -> float4 {
+        let grid = self.enabled && magnification >= self.min_magnification;
+        let labels = self.enabled && magnification >= self.label_magnification;
+        let max_value = match format {
+            Some(UploadFormat::Rgba8Unorm) => 255.0,
+            Some(UploadFormat::Rgba16Unorm) => 65535.0,
+            Some(UploadFormat::Rgba32Float) => 0.0,
+            None => 0.0,
+        };
+        float4::new(
+            grid as u32 as f32,
+            (labels && format.is_some()) as u32 as f32,
+            max_value,
+            label_text_scale(magnification),
+        )
+    }
+
+    pub fn color_float4(self) -> float4 {
+        self.color.extend(self.opacity)
+    }
+}
+
+impl Default for PixelGrid {
+    fn default() -> Self {
+        PixelGrid::new_default()
+    }
+}
+
+// Window pixels per glyph pixel. Four rows of up to eight characters fit in the image pixel.
+pub fn label_text_scale(magnification: f32) -> f32 {
+    (magnification / 32.0).floor().max(1.0)
+}
+
+// The grid is drawn on window pixels whose neighbor to the left or above shows a different image
+// pixel, so every pixel boundary inside the image is one window pixel wide at any zoom
+pub fn on_grid_line(
+    xfm_window_to_image: Transform2D,
+    image_dim: float2,
+    window_pos: float2,
+) -> bool {
+    let pixel = |pos: float2| {
+        let p = xfm_window_to_image.transform_point(pos);
+        let inside = p.x >= 0.0 && p.y >= 0.0 && p.x <= image_dim.x && p.y <= image_dim.y;
+        (float2::new(p.x.floor(), p.y.floor()), inside)
+    };
+    let (center, _) = pixel(window_pos);
+    [float2::new(1.0, 0.0), float2::new(0.0, 1.0)]
+        .iter()
+        .any(|&delta| {
+            let (neighbor, inside) = pixel(window_pos - delta);
+            inside && neighbor != center
+        })
+}
+
+// Characters of the label of one channel as indices into `GLYPHS`
+pub fn label_glyphs(value: f32, max_value: f32) -> Vec<u32> {
+    let mut glyphs = Vec::new();
+    let push_digits = |glyphs: &mut Vec<u32>, n: u32, count: u32| {
+        for d in (0..count).rev() {
+            glyphs.push(n / 10u32.pow(d) % 10);
+        }
+    };
+    let digit_count = |n: u32| n.max(1).ilog10() + 1;
+    if max_value > 0.0 {
+        let n = (value.clamp(0.0, 1.0) * max_value).round() as u32;
+        push_digits(&mut glyphs, n, digit_count(n));
+    } else {
+        let m = (value.abs().min(MAX_LABEL_FLOAT) * 1000.0).round() as u32;
+        if value < 0.0 && m != 0 {
+            glyphs.push(GLYPH_MINUS);
+        }
+        push_digits(&mut glyphs, m / 1000, digit_count(m / 1000));
+        glyphs.push(GLYPH_POINT);
+        push_digits(&mut glyphs, m % 1000, 3);
+    }
+    glyphs
+}
+
+// Whether the labels of a texel cover a window pixel at `local`, relative to the center of the
+// image pixel. Channels are drawn in rows, each row centered.
+pub fn label_covers(texel: float4, max_value: f32, text_scale: f32, local: float2) -> bool {
+    let s = text_scale;
+    let top = -0.5 * (4.0 * LINE_HEIGHT * s - s);
+    let row = ((local.y - top) / (LINE_HEIGHT * s)).floor();
+    if !(0.0..4.0).contains(&row) {
+        return false;
+    }
+    let gy = ((local.y - top - row * LINE_HEIGHT * s) / s).floor() as u32;
+    if gy >= 5 {
+        return false;
+    }
+    let glyphs = label_glyphs(texel[row as usize], max_value);
+    let x = local.x + 0.5 * (glyphs.len() as f32 * GLYPH_ADVANCE * s - s);
+    let column = (x / (GLYPH_ADVANCE * s)).floor();
+    if x < 0.0 || column >= glyphs.len() as f32 {
+        return false;
+    }
+    let gx = ((x - column * GLYPH_ADVANCE * s) / s).floor() as u32;
+    gx < 3 && (GLYPHS[glyphs[column as usize] as usize] >> (gy * 3 + gx)) & 1 != 0
+}
+
+#[test]
+fn test_glyphs() {
+    let art = [
+        "### #.# #.# #.# ###",
+        ".#. ##. .#. .#. ###",
+        "### ..# ### #.. ###",
+        "### ..# .## ..# ###",
+        "#.# #.# ### ..# ..#",
+        "### #.. ### ..# ###",
+        "### #.. ### #.# ###",
+        "### ..# ..# .#. .#.",
+        "### #.# ### #.# ###",
+        "### #.# ### ..# ###",
+        "... ... ... ... .#.",
+        "... ... ### ... ...",
+    ];
+    for (glyph, art) in GLYPHS.iter().zip(art) {
+        let bits = art
+            .split(' ')
+            .flat_map(|row| row.chars())
+            .enumerate()
+            .fold(0, |bits, (i, c)| bits | (((c == '#') as u16) << i));
+        assert_eq!(*glyph, bits, "{}", art);
+    }
+
+    let text = |value: f32, max_value: f32| -> String {
+        label_glyphs(value, max_value)
+            .iter()
+            .map(|&g| "0123456789.-".as_bytes()[g as usize] as char)
+            .collect()
+    };
+    assert_eq!(text(0.0, 255.0), "0");
+    assert_eq!(text(128.0 / 255.0, 255.0), "128");
+    assert_eq!(text(2.0, 255.0), "255");
+    assert_eq!(text(1.0, 65535.0), "65535");
+    for value in [
+        0.0, 1.0, 0.25, -0.5, 2.71, 12.345, -0.0001, 999.999, -42.5,
+    ] {
+        assert_eq!(
+            text(value, 0.0),
+            format!("{:.3}", value).replace("-0.000", "0.000")
+        );
+    }
+    assert_eq!(text(1e6, 0.0), "999.999");
+    assert_eq!(text(-1e6, 0.0), "-999.999");
+}
+
+#[test]
+fn test_grid_lines() {
+    let image_dim = float2::new(100.0, 100.0);
+    // 8x with the offset rounded to whole pixels like the renderer does
+    let xfm = Transform2D {
+        scale: float2::new(0.125, 0.125),
+        offset: float2::new(2.0, 3.0),
+        ..Default::default()
+    };
+    let lines: Vec<i32> = (0..48)
+        .filter(|&x| on_grid_line(xfm, image_dim, float2::new(x as f32 + 0.5, 3.5)))
+        .collect();
+    assert_eq!(lines, vec![0, 8, 16, 24, 32, 40]);
+    // Rows on a boundary are a line all the way across
+    assert!((0..48).all(|x| on_grid_line(xfm, image_dim, float2::new(x as f32 + 0.5, 8.5))));
+
+    // Every boundary is drawn once at fractional zoom and with the view rotated
+    for dihedral in [Dihedral2D::IDENTITY, Dihedral2D::ROTATE_90_CW] {
+        let xfm = Transform2D {
+            scale: float2::new(1.0 / 8.5, 1.0 / 8.5),
+            offset: float2::new(50.0, 50.0),
+            dihedral,
+        };
+        let count = (0..170)
+            .filter(|&x| on_grid_line(xfm, image_dim, float2::new(x as f32 + 0.5, 3.5)))
+            .count();
+        assert_eq!(count, 20);
+    }
+
+    // Nothing outside the image or along its outer edge
+    let xfm = Transform2D {
+        scale: float2::new(0.125, 0.125),
+        offset: float2::new(-1.0, 0.0),
+        ..Default::default()
+    };
+    let lines: Vec<i32> = (0..24)
+        .filter(|&x| on_grid_line(xfm, image_dim, float2::new(x as f32 + 0.5, 3.5)))
+        .collect();
+    assert_eq!(lines, vec![16]);
+
+    let grid = PixelGrid::new_default();
+    let format = Some(UploadFormat::Rgba8Unorm);
+    assert_eq!(
+        grid.to_float4(4.0, format),
+        float4::new(0.0, 0.0, 255.0, 1.0)
+    );
+    assert_eq!(
+        grid.to_float4(8.0, format),
+        float4::new(1.0, 0.0, 255.0, 1.0)
+    );
+    assert_eq!(
+        grid.to_float4(100.0, format),
+        float4::new(1.0, 1.0, 255.0, 3.0)
+    );
+    let disabled = PixelGrid {
+        enabled: false,
+        ..grid
+    };
+    assert_eq!(
+        disabled.to_float4(100.0, format).truncate().truncate(),
+        FLOAT2_ZERO
+    );
+    assert_eq!(
+        PixelGrid::parse_color("#FF8000"),
+        Some(float3::new(1.0, 128.0 / 255.0, 0.0))
+    );
+    assert_eq!(PixelGrid::parse_color("FF80"), None);
+    assert_eq!(PixelGrid::parse_color("GG8000"), None);
+}
+
+#[test]
+fn test_labels() {
+    // The longest labels stay inside the pixel at the smallest zoom they are drawn at
+    for magnification in [64.0, 80.0, 96.0, 200.0] {
+        let s = label_text_scale(magnification);
+        let half = magnification / 2.0;
+        for max_value in [255.0, 65535.0, 0.0] {
+            let texel = if max_value > 0.0 {
+                float4::new(1.0, 1.0, 1.0, 1.0)
+            } else {
+                float4::new(-888.888, -888.888, -888.888, -888.888)
+            };
+            let mut covered = 0;
+            let range = -(magnification as i32)..magnification as i32;
+            for y in range.clone() {
+                for x in range.clone() {
+                    let local = float2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    if label_covers(texel, max_value, s, local) {
+                        assert!(local.x.abs() < half && local.y.abs() < half);
+                        covered += 1;
+                    }
+                }
+            }
+            assert!(covered > 0);
+        }
+    }
+
+    // Rows are R, G, B and A from the top, the "1" of the top row is in the upper half
+    let texel = float4::new(1.0 / 255.0, 0.0, 0.0, 0.0);
+    let lit: Vec<float2> = (-32..32)
+        .flat_map(|y| (-32..32).map(move |x| float2::new(x as f32 + 0.5, y as f32 + 0.5)))
+        .filter(|&p| label_covers(texel, 255.0, 2.0, p) && p.y < -12.0)
+        .collect();
+    // The "1" glyph has 8 set pixels, each covering 2x2 window pixels
+    assert_eq!(lit.len(), 8 * 4);
+}
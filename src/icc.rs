@@ -0,0 +1,679 @@
+use crate::exif::format_decimal;
+use moxcms::{ColorProfile, DataColorSpace, ProfileText, ToneReprCurve, Xyzd};
+use std::convert::TryInto;
+use std::fmt;
+
+const HEADER_SIZE: usize = 128;
+const TAG_ENTRY_SIZE: usize = 12;
+// Far more than any real profile, bounds the work done for corrupt tag counts
+const MAX_TAG_COUNT: usize = 1024;
+
+// Types allowed for the tags that are displayed, moxcms reads these without checking the type
+const TAG_TYPES: &[(&[u8; 4], &[&[u8; 4]])] = &[
+    (b"desc", &[b"desc", b"mluc"]),
+    (b"cprt", &[b"text", b"desc", b"mluc"]),
+    (b"wtpt", &[b"XYZ "]),
+    (b"rXYZ", &[b"XYZ "]),
+    (b"gXYZ", &[b"XYZ "]),
+    (b"bXYZ", &[b"XYZ "]),
+    (b"rTRC", &[b"curv", b"para"]),
+    (b"gTRC", &[b"curv", b"para"]),
+    (b"bTRC", &[b"curv", b"para"]),
+    (b"kTRC", &[b"curv", b"para"]),
+    (b"chad", &[b"sf32"]),
+];
+
+pub fn signature_string(signature: u32) -> String {
+    signature
+        .to_be_bytes()
+        .iter()
+        .map(|&b| {
+            if (0x20..=0x7E).contains(&b) {
+                b as char
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IccCurve {
+    Gamma(f64),
+    // ICC parametric curve function type 0 to 4 with its parameters g, a, b, c, d, e, f
+    Parametric { function: u16, params: Vec<f64> },
+    // Evenly spaced samples of the curve from 0 to 65535
+    Sampled(Vec<u16>),
+}
+
+impl IccCurve {
+    // Gamma that matches the curve at mid gray, to compare sampled curves at a glance
+    pub fn approximate_gamma(&self) -> Option<f64> {
+        let y = match self {
+            IccCurve::Gamma(gamma) => return Some(*gamma),
+            IccCurve::Parametric {
+                function: 0,
+                params,
+            } => return params.first().copied(),
+            IccCurve::Parametric { .. } => return None,
+            IccCurve::Sampled(samples) if samples.len() < 2 => return None,
+            IccCurve::Sampled(samples) => {
+                let x = (samples.len() - 1) as f64 * 0.5;
+                let (i, t) = (x.floor() as usize, x.fract());
+                let next = samples[(i + 1).min(samples.len() - 1)] as f64;
+                (samples[i] as f64 * (1.0 - t) + next * t) / 65535.0
+            }
+        };
+        if y > 0.0 && y < 1.0 {
+            Some(y.ln() / 0.5f64.ln())
+        } else {
+            None
+        }
+    }
+}
+
+impl From<&ToneReprCurve> for IccCurve {
+    fn from(curve: &ToneReprCurve) -> Self {
+        match curve {
+            ToneReprCurve::Lut(samples) => match samples[..] {
+                [] => IccCurve::Gamma(1.0),
+                // u8Fixed8Number
+                [gamma] => IccCurve::Gamma(gamma as f64 / 256.0),
+                _ => IccCurve::Sampled(samples.clone()),
+            },
+            ToneReprCurve::Parametric(params) => IccCurve::Parametric {
+                function: [1, 3, 4, 5, 7]
+                    .iter()
+                    .position(|&count| count == params.len())
+                    .unwrap_or(0) as u16,
+                params: params.iter().map(|&param| param as f64).collect(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for IccCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IccCurve::Gamma(gamma) if *gamma == 1.0 => write!(f, "linear"),
+            IccCurve::Gamma(gamma) => write!(f, "gamma {}", format_decimal(*gamma, 4)),
+            IccCurve::Parametric { function, params } => {
+                write!(f, "parametric type {function}:")?;
+                for (name, value) in ["g", "a", "b", "c", "d", "e", "f"].iter().zip(params) {
+                    write!(f, " {name}={}", format_decimal(*value, 4))?;
+                }
+                Ok(())
+            }
+            IccCurve::Sampled(samples) => {
+                write!(f, "sampled, {} points", samples.len())?;
+                if let Some(gamma) = self.approximate_gamma() {
+                    write!(f, ", approx. gamma {}", format_decimal(gamma, 2))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccTagEntry {
+    pub signature: u32,
+    // Type signature at the start of the tag data, None when the data is out of bounds
+    pub type_signature: Option<u32>,
+    pub offset: u32,
+    pub size: u32,
+}
+
+// Tag table of an ICC profile with the tags that matter for display decoded, and every
+// structural problem found on the way
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IccTags {
+    pub entries: Vec<IccTagEntry>,
+    pub description: Option<String>,
+    pub copyright: Option<String>,
+    // Media white point and colorants in the profile connection space, as XYZ
+    pub white_point: Option<[f64; 3]>,
+    pub red_colorant: Option<[f64; 3]>,
+    pub green_colorant: Option<[f64; 3]>,
+    pub blue_colorant: Option<[f64; 3]>,
+    pub red_trc: Option<IccCurve>,
+    pub green_trc: Option<IccCurve>,
+    pub blue_trc: Option<IccCurve>,
+    pub gray_trc: Option<IccCurve>,
+    // Row major 3x3 matrix from the chad tag
+    pub chromatic_adaptation: Option<[[f64; 3]; 3]>,
+    // Signatures of the A2B/B2A/D2B/B2D lookup table tags that are present
+    pub luts: Vec<String>,
+    pub problems: Vec<String>,
+}
+
+impl IccTags {
+    pub fn is_malformed(&self) -> bool {
+        !self.problems.is_empty()
+    }
+
+    pub fn colorants(&self) -> [Option<[f64; 3]>; 3] {
+        [self.red_colorant, self.green_colorant, self.blue_colorant]
+    }
+
+    pub fn trcs(&self) -> [(&'static str, Option<&IccCurve>); 4] {
+        [
+            ("red", self.red_trc.as_ref()),
+            ("green", self.green_trc.as_ref()),
+            ("blue", self.blue_trc.as_ref()),
+            ("gray", self.gray_trc.as_ref()),
+        ]
+    }
+
+    // Decoded value of a tag for the tag listing, empty for tags that are not decoded
+    fn summary(&self, signature: u32) -> String {
+        let xyz = |xyz: Option<[f64; 3]>| xyz.map(format_xyz);
+        let curve = |curve: Option<&IccCurve>| curve.map(|c| c.to_string());
+        let summary = match &signature.to_be_bytes() {
+            b"desc" => self.description.clone(),
+            b"cprt" => self.copyright.clone(),
+            b"wtpt" => xyz(self.white_point),
+            b"rXYZ" => xyz(self.red_colorant),
+            b"gXYZ" => xyz(self.green_colorant),
+            b"bXYZ" => xyz(self.blue_colorant),
+            b"rTRC" => curve(self.red_trc.as_ref()),
+            b"gTRC" => curve(self.green_trc.as_ref()),
+            b"bTRC" => curve(self.blue_trc.as_ref()),
+            b"kTRC" => curve(self.gray_trc.as_ref()),
+            b"chad" => self.chromatic_adaptation.map(|m| format_matrix(&m)),
+            _ => None,
+        };
+        summary.unwrap_or_default()
+    }
+}
+
+pub fn format_xyz(xyz: [f64; 3]) -> String {
+    let [x, y, z] = xyz;
+    format!(
+        "X={} Y={} Z={}",
+        format_decimal(x, 4),
+        format_decimal(y, 4),
+        format_decimal(z, 4)
+    )
+}
+
+pub fn format_matrix(matrix: &[[f64; 3]; 3]) -> String {
+    let rows: Vec<String> = matrix
+        .iter()
+        .map(|row| {
+            let row: Vec<String> = row.iter().map(|v| format_decimal(*v, 4)).collect();
+            row.join(" ")
+        })
+        .collect();
+    format!("[{}]", rows.join("; "))
+}
+
+// Listing of the tag table in a "[ICC]" section, followed by the problems if there are any
+impl fmt::Display for IccTags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[ICC]")?;
+        for entry in &self.entries {
+            let type_signature = entry
+                .type_signature
+                .map_or_else(|| "----".to_owned(), signature_string);
+            let line = format!(
+                "{}  {}  {:>7}  {:>7}  {}",
+                signature_string(entry.signature),
+                type_signature,
+                entry.offset,
+                entry.size,
+                self.summary(entry.signature)
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        if self.is_malformed() {
+            writeln!(f, "\n[ICC problems]")?;
+            for problem in &self.problems {
+                writeln!(f, "{problem}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+// Text of a description or copyright tag, localized text prefers English
+fn profile_text(text: &ProfileText) -> String {
+    let text = match text {
+        ProfileText::PlainString(text) => text.as_str(),
+        ProfileText::Localizable(records) => records
+            .iter()
+            .find(|record| record.language == "en")
+            .or_else(|| records.first())
+            .map_or("", |record| record.value.as_str()),
+        ProfileText::Description(description) if description.ascii_string.is_empty() => {
+            &description.unicode_string
+        }
+        ProfileText::Description(description) => &description.ascii_string,
+    };
+    text.trim_end_matches('\0').trim().to_owned()
+}
+
+// Fills in the decoded tags from the color management library's reading of the profile
+fn read_profile_values(tags: &mut IccTags, profile: &ColorProfile) {
+    let has_tag = |signature: &[u8; 4]| {
+        tags.entries
+            .iter()
+            .any(|e| &e.signature.to_be_bytes() == signature && e.type_signature.is_some())
+    };
+    let xyz = |xyz: Xyzd| [xyz.x, xyz.y, xyz.z];
+    // The library leaves missing colorants at zero, and only reads them for RGB profiles
+    let rgb = profile.color_space == DataColorSpace::Rgb;
+    let colorant = |signature: &[u8; 4], colorant: Xyzd| {
+        Some(xyz(colorant)).filter(|_| rgb && has_tag(signature))
+    };
+    let (red, green, blue) = (
+        colorant(b"rXYZ", profile.red_colorant),
+        colorant(b"gXYZ", profile.green_colorant),
+        colorant(b"bXYZ", profile.blue_colorant),
+    );
+    tags.red_colorant = red;
+    tags.green_colorant = green;
+    tags.blue_colorant = blue;
+    tags.description = profile.description.as_ref().map(profile_text);
+    tags.copyright = profile.copyright.as_ref().map(profile_text);
+    tags.white_point = profile.media_white_point.map(xyz);
+    tags.red_trc = profile.red_trc.as_ref().map(IccCurve::from);
+    tags.green_trc = profile.green_trc.as_ref().map(IccCurve::from);
+    tags.blue_trc = profile.blue_trc.as_ref().map(IccCurve::from);
+    tags.gray_trc = profile.gray_trc.as_ref().map(IccCurve::from);
+    tags.chromatic_adaptation = profile.chromatic_adaptation.map(|m| m.v);
+    let luts = [
+        ("A2B0", &profile.lut_a_to_b_perceptual),
+        ("A2B1", &profile.lut_a_to_b_colorimetric),
+        ("A2B2", &profile.lut_a_to_b_saturation),
+        ("B2A0", &profile.lut_b_to_a_perceptual),
+        ("B2A1", &profile.lut_b_to_a_colorimetric),
+        ("B2A2", &profile.lut_b_to_a_saturation),
+    ];
+    tags.luts = luts
+        .iter()
+        .filter(|(_, lut)| lut.is_some())
+        .map(|(signature, _)| signature.to_string())
+        .collect();
+}
+
+// Walks the tag table of an ICC profile. Never fails, problems with the structure are collected
+// in `problems`. The values of the tags are decoded by moxcms.
+pub fn parse_icc_tags(icc: &[u8]) -> IccTags {
+    let mut tags = IccTags::default();
+    let problems = &mut tags.problems;
+    if icc.len() < HEADER_SIZE {
+        problems.push(format!(
+            "Profile is {} bytes, shorter than the {HEADER_SIZE} byte header",
+            icc.len()
+        ));
+        return tags;
+    }
+    if &icc[36..40] != b"acsp" {
+        problems.push("Missing 'acsp' profile signature".to_owned());
+    }
+    let declared_size = read_u32(icc, 0).unwrap_or(0) as usize;
+    if declared_size != icc.len() {
+        problems.push(format!(
+            "Header declares {declared_size} bytes but the profile is {} bytes",
+            icc.len()
+        ));
+    }
+    // Tags must lie within both the declared and the actual size
+    let size = declared_size.min(icc.len());
+    let data = &icc[..size];
+
+    let count = match read_u32(data, HEADER_SIZE) {
+        Some(count) => count as usize,
+        None => {
+            problems.push("Missing tag table".to_owned());
+            return tags;
+        }
+    };
+    let table_end = HEADER_SIZE + 4 + count.saturating_mul(TAG_ENTRY_SIZE);
+    let readable = (size - HEADER_SIZE - 4) / TAG_ENTRY_SIZE;
+    if table_end > size || count > MAX_TAG_COUNT {
+        problems.push(format!(
+            "Tag table with {count} entries extends past the end of the profile"
+        ));
+    }
+    for i in 0..count.min(readable).min(MAX_TAG_COUNT) {
+        let entry = HEADER_SIZE + 4 + i * TAG_ENTRY_SIZE;
+        let signature = read_u32(data, entry).unwrap_or(0);
+        let offset = read_u32(data, entry + 4).unwrap_or(0);
+        let tag_size = read_u32(data, entry + 8).unwrap_or(0);
+        let name = signature_string(signature);
+        let start = offset as usize;
+        let end = start.checked_add(tag_size as usize);
+        let type_signature = match end {
+            Some(end) if end <= size && start >= table_end.min(size) && tag_size >= 8 => {
+                read_u32(data, start)
+            }
+            Some(end) if end > size => {
+                problems.push(format!(
+                    "Tag '{name}' at offset {offset} with size {tag_size} extends past the end of the profile"
+                ));
+                None
+            }
+            None => {
+                problems.push(format!("Tag '{name}' has an invalid size {tag_size}"));
+                None
+            }
+            Some(_) if tag_size < 8 => {
+                problems.push(format!("Tag '{name}' is only {tag_size} bytes"));
+                None
+            }
+            Some(_) => {
+                problems.push(format!(
+                    "Tag '{name}' at offset {offset} overlaps the header or tag table"
+                ));
+                None
+            }
+        };
+        if type_signature.is_some() && offset & 3 != 0 {
+            problems.push(format!(
+                "Tag '{name}' at offset {offset} is not 4 byte aligned"
+            ));
+        }
+        let allowed_types = TAG_TYPES
+            .iter()
+            .find(|(tag, _)| **tag == signature.to_be_bytes())
+            .map(|(_, types)| *types);
+        if let (Some(types), Some(type_signature)) = (allowed_types, type_signature) {
+            if !types.contains(&&type_signature.to_be_bytes()) {
+                problems.push(format!(
+                    "Tag '{name}' has type '{}'",
+                    signature_string(type_signature)
+                ));
+            }
+        }
+        if tags.entries.iter().any(|e| e.signature == signature) {
+            problems.push(format!("Duplicate tag '{name}'"));
+        }
+        tags.entries.push(IccTagEntry {
+            signature,
+            type_signature,
+            offset,
+            size: tag_size,
+        });
+    }
+
+    // Tags may share data, which is common for identical TRCs, but must not partially overlap
+    let mut ranges: Vec<&IccTagEntry> = tags
+        .entries
+        .iter()
+        .filter(|e| e.type_signature.is_some())
+        .collect();
+    ranges.sort_by_key(|e| (e.offset, e.size));
+    for (i, a) in ranges.iter().enumerate() {
+        for b in &ranges[i + 1..] {
+            if b.offset >= a.offset + a.size {
+                break;
+            }
+            if (a.offset, a.size) != (b.offset, b.size) {
+                tags.problems.push(format!(
+                    "Tags '{}' and '{}' overlap",
+                    signature_string(a.signature),
+                    signature_string(b.signature)
+                ));
+            }
+        }
+    }
+
+    // moxcms rejects the whole profile when one of the tags it reads is broken
+    match ColorProfile::new_from_slice(data) {
+        Ok(profile) => read_profile_values(&mut tags, &profile),
+        Err(err) => tags
+            .problems
+            .push(format!("Tag values could not be decoded: {err}")),
+    }
+
+    // Without lookup tables, RGB and gray profiles need a complete matrix/TRC model
+    let has_a2b0 = tags.luts.iter().any(|lut| lut == "A2B0");
+    let missing_model = match &icc[16..20] {
+        b"RGB " => {
+            tags.colorants().iter().any(Option::is_none)
+                || tags.trcs()[..3].iter().any(|(_, trc)| trc.is_none())
+        }
+        b"GRAY" => tags.gray_trc.is_none(),
+        _ => false,
+    };
+    let class = &icc[12..16];
+    if missing_model && !has_a2b0 && class != b"link" && class != b"abst" && class != b"nmcl" {
+        tags.problems
+            .push("Neither an A2B0 table nor complete colorant and TRC tags".to_owned());
+    }
+    tags
+}
+
+#[cfg(test)]
+fn make_test_icc(color_space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut header = vec![0u8; HEADER_SIZE];
+    header[8..12].copy_from_slice(&[4, 0x40, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(color_space);
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    header.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    let mut tag_data: Vec<u8> = Vec::new();
+    let data_start = header.len() + tags.len() * TAG_ENTRY_SIZE;
+    for (signature, data) in tags {
+        // Identical data is shared between tags, like profile writers do for TRCs
+        let offset = match tag_data.windows(data.len()).position(|w| w == &data[..]) {
+            Some(offset) if offset % 4 == 0 => offset,
+            _ => {
+                let offset = tag_data.len();
+                tag_data.extend_from_slice(data);
+                tag_data.resize((tag_data.len() + 3) & !3, 0);
+                offset
+            }
+        };
+        header.extend_from_slice(*signature);
+        header.extend_from_slice(&((data_start + offset) as u32).to_be_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    }
+    header.extend_from_slice(&tag_data);
+    let size = header.len() as u32;
+    header[0..4].copy_from_slice(&size.to_be_bytes());
+    header
+}
+
+#[cfg(test)]
+fn test_tag(type_signature: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    [&type_signature[..], &[0; 4], payload].concat()
+}
+
+#[cfg(test)]
+fn test_fixed(values: &[f64]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|v| ((v * 65536.0).round() as i32).to_be_bytes())
+        .collect()
+}
+
+#[test]
+fn test_icc_tags() {
+    let mut mluc = [2u32.to_be_bytes(), 12u32.to_be_bytes()].concat();
+    let (german, english) = (
+        decode_utf16_test("Test DE"),
+        decode_utf16_test("Test profile"),
+    );
+    for (language, text, offset) in [(b"dede", &german, 40), (b"enUS", &english, 54)] {
+        mluc.extend_from_slice(language);
+        mluc.extend_from_slice(&(text.len() as u32).to_be_bytes());
+        mluc.extend_from_slice(&(offset as u32).to_be_bytes());
+    }
+    mluc.extend_from_slice(&german);
+    mluc.extend_from_slice(&english);
+    let srgb_trc = [
+        &3u16.to_be_bytes()[..],
+        &[0, 0],
+        &test_fixed(&[2.4, 0.9479, 0.0521, 0.0774, 0.0405]),
+    ]
+    .concat();
+    // Three channels through identity B curves, without matrix, M curves and CLUT
+    let mut identity_lut = [&[3, 3, 0, 0][..], &32u32.to_be_bytes(), &[0; 16]].concat();
+    for _ in 0..3 {
+        identity_lut.extend_from_slice(&test_tag(b"curv", &[0; 4]));
+    }
+    let icc = make_test_icc(
+        b"RGB ",
+        &[
+            (b"desc", test_tag(b"mluc", &mluc)),
+            (b"cprt", test_tag(b"text", b"No copyright\0")),
+            (
+                b"wtpt",
+                test_tag(b"XYZ ", &test_fixed(&[0.9642, 1.0, 0.8249])),
+            ),
+            (
+                b"rXYZ",
+                test_tag(b"XYZ ", &test_fixed(&[0.4361, 0.2225, 0.0139])),
+            ),
+            (
+                b"gXYZ",
+                test_tag(b"XYZ ", &test_fixed(&[0.3851, 0.7169, 0.0971])),
+            ),
+            (
+                b"bXYZ",
+                test_tag(b"XYZ ", &test_fixed(&[0.1431, 0.0606, 0.7141])),
+            ),
+            (b"rTRC", test_tag(b"para", &srgb_trc)),
+            (b"gTRC", test_tag(b"para", &srgb_trc)),
+            (b"bTRC", test_tag(b"para", &srgb_trc)),
+            (
+                b"chad",
+                test_tag(
+                    b"sf32",
+                    &test_fixed(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]),
+                ),
+            ),
+            (b"A2B0", test_tag(b"mAB ", &identity_lut)),
+        ],
+    );
+    let tags = parse_icc_tags(&icc);
+    assert_eq!(tags.problems, Vec::<String>::new());
+    assert_eq!(tags.description.as_deref(), Some("Test profile"));
+    assert_eq!(tags.copyright.as_deref(), Some("No copyright"));
+    assert_eq!(
+        tags.white_point.map(format_xyz).as_deref(),
+        Some("X=0.9642 Y=1 Z=0.8249")
+    );
+    assert_eq!(tags.entries.len(), 11);
+    assert_eq!(tags.entries[6].offset, tags.entries[8].offset);
+    assert_eq!(
+        tags.red_trc.as_ref().map(|c| c.to_string()).as_deref(),
+        Some("parametric type 3: g=2.4 a=0.9479 b=0.0521 c=0.0774 d=0.0405")
+    );
+    assert_eq!(
+        tags.chromatic_adaptation
+            .map(|m| format_matrix(&m))
+            .as_deref(),
+        Some("[1 0 0; 0 1 0; 0 0 1]")
+    );
+    assert_eq!(tags.luts, vec!["A2B0"]);
+    assert!(tags
+        .to_string()
+        .starts_with("[ICC]\ndesc  mluc      264       78  Test profile\n"));
+
+    // Gamma and sampled curves of a gray profile
+    let samples: Vec<u8> = (0..256u32)
+        .flat_map(|i| (((i as f64 / 255.0).powi(2) * 65535.0).round() as u16).to_be_bytes())
+        .collect();
+    let icc = make_test_icc(
+        b"GRAY",
+        &[(
+            b"kTRC",
+            test_tag(b"curv", &[&256u32.to_be_bytes()[..], &samples].concat()),
+        )],
+    );
+    let tags = parse_icc_tags(&icc);
+    assert_eq!(tags.problems, Vec::<String>::new());
+    assert_eq!(
+        tags.gray_trc.as_ref().map(|c| c.to_string()).as_deref(),
+        Some("sampled, 256 points, approx. gamma 2")
+    );
+    let gray_trc = |curve: &[u8]| {
+        let icc = make_test_icc(b"GRAY", &[(b"kTRC", test_tag(b"curv", curve))]);
+        parse_icc_tags(&icc).gray_trc
+    };
+    assert_eq!(
+        gray_trc(&[0, 0, 0, 1, 2, 51])
+            .map(|c| c.to_string())
+            .as_deref(),
+        Some("gamma 2.1992")
+    );
+    assert_eq!(gray_trc(&[0; 4]), Some(IccCurve::Gamma(1.0)));
+
+    // Profiles written by the color management library decode without problems
+    let icc = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+    let tags = parse_icc_tags(&icc);
+    assert_eq!(tags.problems, Vec::<String>::new());
+    assert!(tags.colorants().iter().all(Option::is_some));
+    assert!(tags.trcs()[..3].iter().all(|(_, trc)| trc.is_some()));
+}
+
+#[cfg(test)]
+fn decode_utf16_test(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+#[test]
+fn test_icc_tags_malformed() {
+    let xyz = test_tag(b"XYZ ", &test_fixed(&[0.9642, 1.0, 0.8249]));
+    let red = test_tag(b"XYZ ", &test_fixed(&[0.4361, 0.2225, 0.0139]));
+    let icc = make_test_icc(b"RGB ", &[(b"wtpt", xyz), (b"rXYZ", red)]);
+    let tags = parse_icc_tags(&icc);
+    assert_eq!(
+        tags.problems,
+        vec!["Neither an A2B0 table nor complete colorant and TRC tags"]
+    );
+
+    // Declared size larger than the data, the second tag is cut off
+    let mut truncated = icc.clone();
+    truncated.truncate(icc.len() - 4);
+    let tags = parse_icc_tags(&truncated);
+    assert!(tags.problems[0].starts_with("Header declares"));
+    assert!(tags.problems[1].contains("'rXYZ' at offset"));
+    assert!(tags.problems[1].ends_with("extends past the end of the profile"));
+    // moxcms doesn't read profiles with tags out of bounds
+    assert_eq!(
+        tags.problems[2],
+        "Tag values could not be decoded: Invalid ICC profile"
+    );
+    assert!(tags.white_point.is_none());
+
+    // Partially overlapping tags, a duplicate and a wrong tag type
+    let mut overlapping = icc.clone();
+    let second_entry = HEADER_SIZE + 4 + TAG_ENTRY_SIZE;
+    let offset = read_u32(&icc, HEADER_SIZE + 8).unwrap() + 4;
+    overlapping[second_entry..second_entry + 4].copy_from_slice(b"wtpt");
+    overlapping[second_entry + 4..second_entry + 8].copy_from_slice(&offset.to_be_bytes());
+    overlapping[second_entry + 8..second_entry + 12].copy_from_slice(&16u32.to_be_bytes());
+    let tags = parse_icc_tags(&overlapping);
+    assert!(tags.problems.contains(&"Duplicate tag 'wtpt'".to_owned()));
+    assert!(tags
+        .problems
+        .contains(&"Tags 'wtpt' and 'wtpt' overlap".to_owned()));
+    assert!(tags
+        .problems
+        .contains(&"Tag 'wtpt' has type '????'".to_owned()));
+
+    // Tag count beyond the end, tag inside the header
+    let mut bad_table = icc.clone();
+    bad_table[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&1000u32.to_be_bytes());
+    bad_table[HEADER_SIZE + 8..HEADER_SIZE + 12].copy_from_slice(&16u32.to_be_bytes());
+    let tags = parse_icc_tags(&bad_table);
+    assert!(tags.problems[0].starts_with("Tag table with 1000 entries"));
+    assert!(tags.problems[1].ends_with("overlaps the header or tag table"));
+
+    assert_eq!(parse_icc_tags(&[0; 16]).problems.len(), 1);
+    for len in 0..icc.len() {
+        let _ = parse_icc_tags(&icc[..len]);
+    }
+}
@@ -0,0 +1,609 @@
+use crate::exif::{ExifIfd, ExifTags};
+use crate::icc::{signature_string, IccTags};
+use crate::loader::{
+    load_image_with_metadata, ExifInfo, IccInfo, LoadError, LoadOptions, LoadedImage,
+};
+use crate::subimage::SubimageKind;
+use crate::xmp::XmpInfo;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Output of the headless --info mode
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InfoFormat {
+    // One JSON document per line and file
+    Json,
+    // Aligned key/value rows per file, for reading in a terminal
+    Table,
+}
+
+impl InfoFormat {
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg {
+            "json" => Some(InfoFormat::Json),
+            "table" => Some(InfoFormat::Table),
+            _ => None,
+        }
+    }
+}
+
+// Minimal JSON document model. Object fields keep their insertion order, so the output is stable
+// and every document has the same keys, with null for missing values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(&'static str, JsonValue)>),
+    // Object with keys that are only known at runtime
+    Map(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    fn from_option<T>(value: Option<T>, f: impl FnOnce(T) -> JsonValue) -> JsonValue {
+        value.map_or(JsonValue::Null, f)
+    }
+
+    fn string_or_null(value: &Option<String>) -> JsonValue {
+        Self::from_option(value.as_ref(), |s| JsonValue::String(s.clone()))
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            JsonValue::Int(value) => {
+                let _ = write!(out, "{value}");
+            }
+            // JSON has no representation for NaN and infinity
+            JsonValue::Float(value) if !value.is_finite() => out.push_str("null"),
+            JsonValue::Float(value) => {
+                let _ = write!(out, "{value}");
+            }
+            JsonValue::String(value) => write_json_string(out, value),
+            JsonValue::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write_json(out);
+                }
+                out.push(']');
+            }
+            JsonValue::Object(_) | JsonValue::Map(_) => {
+                out.push('{');
+                for (i, (key, value)) in self.fields().into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(out, key);
+                    out.push(':');
+                    value.write_json(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn fields(&self) -> Vec<(&str, &JsonValue)> {
+        match self {
+            JsonValue::Object(fields) => fields.iter().map(|(k, v)| (*k, v)).collect(),
+            JsonValue::Map(fields) => fields.iter().map(|(k, v)| (k.as_str(), v)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Leaf values with dotted keys for nested objects, e.g. ("exif.make", "Canon")
+    fn flatten(&self, prefix: &str, rows: &mut Vec<(String, String)>) {
+        match self {
+            JsonValue::Object(_) | JsonValue::Map(_) => {
+                for (key, value) in self.fields() {
+                    let key = if prefix.is_empty() {
+                        key.to_string()
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+                    value.flatten(&key, rows);
+                }
+            }
+            JsonValue::Null => {}
+            JsonValue::String(value) => rows.push((prefix.to_owned(), value.clone())),
+            // Arrays are short lists of strings such as keywords, kept on one row
+            JsonValue::Array(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| match item {
+                        JsonValue::String(value) => value.clone(),
+                        other => other.to_json(),
+                    })
+                    .collect();
+                rows.push((prefix.to_owned(), items.join("; ")));
+            }
+            other => rows.push((prefix.to_owned(), other.to_json())),
+        }
+    }
+
+    pub fn to_table(&self) -> String {
+        let mut rows = Vec::new();
+        self.flatten("", &mut rows);
+        let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+        let mut out = String::new();
+        for (key, value) in rows {
+            let _ = writeln!(out, "{key:width$}  {value}");
+        }
+        out
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn exif_json(exif: &ExifInfo, include_tags: bool) -> JsonValue {
+    let mut fields = vec![
+        ("make", JsonValue::string_or_null(&exif.make)),
+        ("model", JsonValue::string_or_null(&exif.model)),
+        ("software", JsonValue::string_or_null(&exif.software)),
+        ("datetime", JsonValue::string_or_null(&exif.datetime)),
+        (
+            "datetime_original",
+            JsonValue::string_or_null(&exif.datetime_original),
+        ),
+        ("lens_model", JsonValue::string_or_null(&exif.lens_model)),
+        (
+            "orientation",
+            JsonValue::from_option(exif.orientation, |o| JsonValue::Int(o as u64)),
+        ),
+        ("gps", JsonValue::string_or_null(&exif.gps_iso6709)),
+    ];
+    if include_tags {
+        fields.push(("tags", exif_tags_json(&exif.tags)));
+    }
+    JsonValue::Object(fields)
+}
+
+// Formatted values keyed by directory and tag name, e.g. tags.Exif.FNumber = "f/2.8"
+fn exif_tags_json(tags: &ExifTags) -> JsonValue {
+    let groups = ExifIfd::ALL
+        .iter()
+        .filter(|&&ifd| tags.group(ifd).next().is_some())
+        .map(|&ifd| {
+            let entries = tags
+                .group(ifd)
+                .map(|e| (e.name(), JsonValue::String(e.display_value())))
+                .collect();
+            (ifd.name().to_owned(), JsonValue::Map(entries))
+        })
+        .collect();
+    JsonValue::Map(groups)
+}
+
+fn xmp_json(xmp: &XmpInfo, include_properties: bool) -> JsonValue {
+    let strings = |values: &[String]| {
+        JsonValue::Array(values.iter().cloned().map(JsonValue::String).collect())
+    };
+    let mut fields = vec![
+        ("title", JsonValue::string_or_null(&xmp.title)),
+        ("description", JsonValue::string_or_null(&xmp.description)),
+        ("creators", strings(&xmp.creators)),
+        ("rights", JsonValue::string_or_null(&xmp.rights)),
+        ("keywords", strings(&xmp.keywords)),
+        (
+            "rating",
+            JsonValue::from_option(xmp.rating, |r| JsonValue::Float(r as f64)),
+        ),
+        ("label", JsonValue::string_or_null(&xmp.label)),
+        ("create_date", JsonValue::string_or_null(&xmp.create_date)),
+        ("modify_date", JsonValue::string_or_null(&xmp.modify_date)),
+        ("creator_tool", JsonValue::string_or_null(&xmp.creator_tool)),
+        ("headline", JsonValue::string_or_null(&xmp.headline)),
+        ("city", JsonValue::string_or_null(&xmp.city)),
+        ("state", JsonValue::string_or_null(&xmp.state)),
+        ("country", JsonValue::string_or_null(&xmp.country)),
+        ("credit", JsonValue::string_or_null(&xmp.credit)),
+        ("source", JsonValue::string_or_null(&xmp.source)),
+        ("date_created", JsonValue::string_or_null(&xmp.date_created)),
+        (
+            "date_time_original",
+            JsonValue::string_or_null(&xmp.date_time_original),
+        ),
+    ];
+    if include_properties {
+        let properties = xmp
+            .properties
+            .iter()
+            .map(|p| (p.name.clone(), strings(&p.values)))
+            .collect();
+        fields.push(("properties", JsonValue::Map(properties)));
+    }
+    JsonValue::Object(fields)
+}
+
+fn icc_json(icc: &IccInfo, include_tags: bool) -> JsonValue {
+    let string = |s: &str| JsonValue::String(s.to_owned());
+    let mut fields = vec![
+        ("size", JsonValue::Int(icc.size as u64)),
+        ("cmm_type", string(&icc.cmm_type)),
+        ("version", string(&icc.version)),
+        ("profile_class", string(&icc.profile_class)),
+        ("color_space", string(&icc.color_space)),
+        ("pcs", string(&icc.pcs)),
+        ("created", JsonValue::string_or_null(&icc.created)),
+        ("platform", string(&icc.platform)),
+        ("flags", JsonValue::Int(icc.flags as u64)),
+        ("manufacturer", string(&icc.manufacturer)),
+        ("model", string(&icc.model)),
+        (
+            "rendering_intent",
+            JsonValue::Int(icc.rendering_intent as u64),
+        ),
+    ];
+    fields.extend(icc_tags_json(&icc.tags, include_tags));
+    JsonValue::Object(fields)
+}
+
+fn icc_tags_json(tags: &IccTags, include_table: bool) -> Vec<(&'static str, JsonValue)> {
+    let numbers =
+        |values: &[f64]| JsonValue::Array(values.iter().map(|&v| JsonValue::Float(v)).collect());
+    let strings = |values: &[String]| {
+        JsonValue::Array(values.iter().cloned().map(JsonValue::String).collect())
+    };
+    let [red, green, blue] = tags.colorants();
+    let xyz = |xyz: Option<[f64; 3]>| JsonValue::from_option(xyz, |xyz| numbers(&xyz));
+    let trc = tags
+        .trcs()
+        .iter()
+        .map(|(name, curve)| {
+            let curve = JsonValue::from_option(*curve, |c| JsonValue::String(c.to_string()));
+            (*name, curve)
+        })
+        .collect();
+    let chad = JsonValue::from_option(tags.chromatic_adaptation, |m| {
+        JsonValue::Array(m.iter().map(|row| numbers(row)).collect())
+    });
+    let mut fields = vec![
+        ("description", JsonValue::string_or_null(&tags.description)),
+        ("copyright", JsonValue::string_or_null(&tags.copyright)),
+        ("white_point", xyz(tags.white_point)),
+        (
+            "colorants",
+            JsonValue::Object(vec![
+                ("red", xyz(red)),
+                ("green", xyz(green)),
+                ("blue", xyz(blue)),
+            ]),
+        ),
+        ("trc", JsonValue::Object(trc)),
+        ("chromatic_adaptation", chad),
+        ("luts", strings(&tags.luts)),
+        ("problems", strings(&tags.problems)),
+    ];
+    if include_table {
+        // Type, offset and size of every tag, keyed by signature
+        let table = tags
+            .entries
+            .iter()
+            .map(|entry| {
+                let type_signature = JsonValue::from_option(entry.type_signature, |t| {
+                    JsonValue::String(signature_string(t))
+                });
+                let entry_json = JsonValue::Object(vec![
+                    ("type", type_signature),
+                    ("offset", JsonValue::Int(entry.offset as u64)),
+                    ("size", JsonValue::Int(entry.size as u64)),
+                ]);
+                (signature_string(entry.signature), entry_json)
+            })
+            .collect();
+        fields.push(("tags", JsonValue::Map(table)));
+    }
+    fields
+}
+
+// Everything the loader knows about a file. Failed loads produce the same document with an
+// error message and null fields.
+pub fn image_info(path: &Path, result: &Result<LoadedImage, LoadError>) -> JsonValue {
+    match result {
+        Ok(img) => loaded_image_info(path, img, true),
+        Err(err) => load_error_info(path, err),
+    }
+}
+
+fn load_error_info(path: &Path, err: &LoadError) -> JsonValue {
+    JsonValue::Object(vec![
+        (
+            "path",
+            JsonValue::String(path.to_string_lossy().into_owned()),
+        ),
+        ("error", JsonValue::String(err.to_string())),
+        ("error_kind", JsonValue::String(err.kind_name().to_owned())),
+        ("format", JsonValue::Null),
+        ("width", JsonValue::Null),
+        ("height", JsonValue::Null),
+        ("color_type", JsonValue::Null),
+        ("orientation", JsonValue::Null),
+        ("exif", JsonValue::Null),
+        ("icc", JsonValue::Null),
+        ("xmp", JsonValue::Null),
+        ("animation", JsonValue::Null),
+        ("subimages", JsonValue::Null),
+        ("incomplete", JsonValue::Null),
+    ])
+}
+
+fn loaded_image_info(path: &Path, img: &LoadedImage, include_tags: bool) -> JsonValue {
+    // Color type of the decoded file, before conversion to the output profile
+    let decoded = img.unmanaged_image.as_ref().unwrap_or(&img.image);
+    let format = JsonValue::from_option(img.format, |f| {
+        JsonValue::String(format!("{f:?}").to_lowercase())
+    });
+    let orientation =
+        JsonValue::from_option(img.orientation, |o| JsonValue::Int(o.to_exif() as u64));
+    let animation = JsonValue::from_option(img.animation.as_ref(), |animation| {
+        let duration: Duration = animation.frames.iter().map(|f| f.delay).sum();
+        JsonValue::Object(vec![
+            ("frames", JsonValue::Int(animation.frames.len() as u64)),
+            ("duration_ms", JsonValue::Int(duration.as_millis() as u64)),
+        ])
+    });
+    let subimages = JsonValue::from_option(img.subimages.as_ref(), |subimages| {
+        let layout = &subimages.layout;
+        let kind = match layout.kind {
+            SubimageKind::Pages => "pages",
+            SubimageKind::Texture => "texture",
+        };
+        JsonValue::Object(vec![
+            ("kind", JsonValue::String(kind.to_owned())),
+            ("layers", JsonValue::Int(layout.layer_count as u64)),
+            ("faces", JsonValue::Int(layout.face_count as u64)),
+            ("mips", JsonValue::Int(layout.mip_count as u64)),
+            ("surfaces", JsonValue::Int(subimages.surfaces.len() as u64)),
+        ])
+    });
+
+    let incomplete = JsonValue::from_option(img.incomplete.as_ref(), |incomplete| {
+        JsonValue::Object(vec![
+            ("reason", JsonValue::String(incomplete.reason.clone())),
+            ("missing_pixels", JsonValue::Int(incomplete.missing_pixels)),
+        ])
+    });
+
+    JsonValue::Object(vec![
+        (
+            "path",
+            JsonValue::String(path.to_string_lossy().into_owned()),
+        ),
+        ("error", JsonValue::Null),
+        ("error_kind", JsonValue::Null),
+        ("format", format),
+        ("width", JsonValue::Int(decoded.width() as u64)),
+        ("height", JsonValue::Int(decoded.height() as u64)),
+        (
+            "color_type",
+            JsonValue::String(format!("{:?}", decoded.color()).to_lowercase()),
+        ),
+        ("orientation", orientation),
+        (
+            "exif",
+            JsonValue::from_option(img.exif_info.as_ref(), |exif| exif_json(exif, include_tags)),
+        ),
+        (
+            "icc",
+            JsonValue::from_option(img.icc_info.as_ref(), |icc| icc_json(icc, include_tags)),
+        ),
+        (
+            "xmp",
+            JsonValue::from_option(img.xmp_info.as_ref(), |xmp| xmp_json(xmp, include_tags)),
+        ),
+        ("animation", animation),
+        ("subimages", subimages),
+        ("incomplete", incomplete),
+    ])
+}
+
+// Text of the metadata panel while the error placeholder is shown
+pub fn load_error_panel_text(path: &Path, err: &LoadError) -> String {
+    load_error_info(path, err).to_table()
+}
+
+// Text of the in-app metadata panel: the --info table, followed by every EXIF tag grouped by
+// directory, every XMP property and the ICC tag table, which read better than the dotted keys
+// of the table
+pub fn metadata_panel_text(path: &Path, img: &LoadedImage) -> String {
+    let mut text = loaded_image_info(path, img, false).to_table();
+    if let Some(exif) = img.exif_info.as_ref().filter(|exif| !exif.tags.is_empty()) {
+        text.push('\n');
+        text += &exif.tags.to_string();
+    }
+    if let Some(xmp) = &img.xmp_info {
+        text.push('\n');
+        text += &xmp.to_string();
+    }
+    if let Some(icc) = &img.icc_info {
+        text.push('\n');
+        text += &icc.tags.to_string();
+    }
+    text
+}
+
+pub fn format_image_info(info: &JsonValue, format: InfoFormat) -> String {
+    match format {
+        InfoFormat::Json => info.to_json() + "\n",
+        InfoFormat::Table => info.to_table(),
+    }
+}
+
+// Loads every file and prints its metadata to stdout, without creating a window or a device.
+// Returns false if any file failed to load or was only decoded in part, --info exits with an
+// error code then
+pub fn print_image_info(paths: &[PathBuf], format: InfoFormat, options: &LoadOptions) -> bool {
+    let mut all_loaded = true;
+    for (i, path) in paths.iter().enumerate() {
+        let result = load_image_with_metadata(path, options);
+        all_loaded &= matches!(&result, Ok(img) if img.incomplete.is_none());
+        if format == InfoFormat::Table && i > 0 {
+            println!();
+        }
+        print!("{}", format_image_info(&image_info(path, &result), format));
+    }
+    // The process exits right after, without flushing stdout
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    all_loaded
+}
+
+#[test]
+fn test_json_output() {
+    let value = JsonValue::Object(vec![
+        ("name", JsonValue::String("a \"b\"\\\n\u{1}".to_owned())),
+        ("count", JsonValue::Int(3)),
+        ("ratio", JsonValue::Float(0.5)),
+        ("nan", JsonValue::Float(f64::NAN)),
+        ("flag", JsonValue::Bool(true)),
+        (
+            "nested",
+            JsonValue::Object(vec![("missing", JsonValue::Null)]),
+        ),
+    ]);
+    assert_eq!(
+        value.to_json(),
+        r#"{"name":"a \"b\"\\\n\u0001","count":3,"ratio":0.5,"nan":null,"flag":true,"nested":{"missing":null}}"#
+    );
+    assert_eq!(
+        value.to_table(),
+        "name   a \"b\"\\\n\u{1}\ncount  3\nratio  0.5\nnan    null\nflag   true\n"
+    );
+}
+
+#[test]
+fn test_image_info() {
+    use crate::loader::IncompleteImage;
+    use image::{DynamicImage, ImageFormat};
+
+    let mut img = LoadedImage::from_image(DynamicImage::new_luma8(3, 2));
+    img.format = Some(ImageFormat::Png);
+    img.orientation = Some(image::metadata::Orientation::Rotate90);
+    let info = image_info(Path::new("dir/a.png"), &Ok(img));
+    assert_eq!(
+        info.to_json(),
+        r#"{"path":"dir/a.png","error":null,"error_kind":null,"format":"png","width":3,"height":2,"color_type":"l8","orientation":6,"exif":null,"icc":null,"xmp":null,"animation":null,"subimages":null,"incomplete":null}"#
+    );
+    assert_eq!(
+        format_image_info(&info, InfoFormat::Table),
+        "path         dir/a.png\nformat       png\nwidth        3\nheight       2\ncolor_type   l8\norientation  6\n"
+    );
+
+    let mut img = LoadedImage::from_image(DynamicImage::new_luma8(1, 1));
+    img.incomplete = Some(IncompleteImage {
+        reason: "Truncated file".to_owned(),
+        missing_pixels: 12,
+        missing_range: 4..16,
+    });
+    let table = image_info(Path::new("c.png"), &Ok(img)).to_table();
+    assert!(table.contains("\nincomplete.reason          Truncated file\n"));
+    assert!(table.contains("\nincomplete.missing_pixels  12\n"));
+
+    // EXIF tags are grouped by directory
+    let mut img = LoadedImage::from_image(DynamicImage::new_luma8(1, 1));
+    img.exif_info = Some(ExifInfo {
+        make: Some("Canon".to_owned()),
+        tags: ExifTags {
+            entries: vec![crate::exif::ExifEntry {
+                ifd: ExifIfd::Exif,
+                tag: 0x8827,
+                value: crate::exif::ExifValue::Short(vec![200]),
+            }],
+        },
+        ..ExifInfo::default()
+    });
+    let panel_text = metadata_panel_text(Path::new("c.jpg"), &img);
+    assert!(panel_text.contains("\nexif.make   Canon\n"));
+    assert!(panel_text.ends_with("\n\n[Exif]\nISOSpeedRatings  ISO 200\n"));
+    let info = image_info(Path::new("c.jpg"), &Ok(img));
+    assert!(info
+        .to_json()
+        .contains(r#""exif":{"make":"Canon","model":null,"#));
+    assert!(info
+        .to_json()
+        .contains(r#""gps":null,"tags":{"Exif":{"ISOSpeedRatings":"ISO 200"}}}"#));
+    assert!(info
+        .to_table()
+        .contains("\nexif.tags.Exif.ISOSpeedRatings  ISO 200\n"));
+
+    // XMP arrays are JSON arrays and single table rows
+    let mut img = LoadedImage::from_image(DynamicImage::new_luma8(1, 1));
+    img.xmp_info = crate::xmp::parse_iptc(&[
+        0x1C, 2, 25, 0, 3, b's', b'e', b'a', 0x1C, 2, 25, 0, 3, b's', b'k', b'y',
+    ]);
+    let info = image_info(Path::new("d.jpg"), &Ok(img));
+    assert!(info
+        .to_json()
+        .contains(r#""keywords":["sea","sky"],"rating":null,"#));
+    assert!(info
+        .to_json()
+        .contains(r#""properties":{"dc:subject":["sea","sky"]}}"#));
+    assert!(info
+        .to_table()
+        .contains("\nxmp.keywords               sea; sky\n"));
+
+    let err = LoadError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"));
+    let info = image_info(Path::new("b.png"), &Err(err));
+    let json = info.to_json();
+    assert!(json.starts_with(
+        r#"{"path":"b.png","error":"I/O error: missing","error_kind":"io","format":null,"#
+    ));
+    // Failed loads have the same keys as successful ones
+    match info {
+        JsonValue::Object(fields) => assert_eq!(fields.len(), 14),
+        _ => panic!("expected object"),
+    }
+}
+
+#[test]
+fn test_print_image_info() {
+    use image::{ImageFormat, RgbImage};
+
+    let dir = std::env::temp_dir().join(format!("imgv_info_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut png = Vec::new();
+    RgbImage::new(16, 16)
+        .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    let good = dir.join("good.png");
+    std::fs::write(&good, &png).unwrap();
+    let truncated = dir.join("truncated.png");
+    std::fs::write(&truncated, &png[..png.len() - 20]).unwrap();
+    let missing = dir.join("missing.png");
+
+    let all_loaded =
+        |paths: &[PathBuf]| print_image_info(paths, InfoFormat::Json, &LoadOptions::default());
+    assert!(all_loaded(std::slice::from_ref(&good)));
+    assert!(!all_loaded(&[good.clone(), missing]));
+    // Shown by the viewer, but not a successful load
+    assert!(!all_loaded(&[good, truncated]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,250 @@
+use cgmath::prelude::*;
+use image::{DynamicImage, GenericImageView};
+use std::fmt;
+
+use crate::math::*;
+use crate::orientation::ViewOrientation;
+use crate::tonemap::DisplaySettings;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CopyFormat {
+    // Hex digits of the stored samples, bit patterns for floats
+    Hex,
+    // Stored samples, floats are quantized to 8 bits
+    Integer,
+    // Samples normalized to 0..1, floats as stored
+    Float,
+}
+
+impl CopyFormat {
+    pub fn next(self) -> Self {
+        match self {
+            CopyFormat::Hex => CopyFormat::Integer,
+            CopyFormat::Integer => CopyFormat::Float,
+            CopyFormat::Float => CopyFormat::Hex,
+        }
+    }
+}
+
+// Stored value of one pixel, with the channels and sample type of the decoded image
+#[derive(Clone, Debug, PartialEq)]
+pub enum PixelValue {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+impl PixelValue {
+    pub fn read(image: &DynamicImage, x: u32, y: u32) -> Self {
+        match image {
+            DynamicImage::ImageLuma8(buf) => PixelValue::U8(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageLumaA8(buf) => PixelValue::U8(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageRgb8(buf) => PixelValue::U8(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageRgba8(buf) => PixelValue::U8(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageLuma16(buf) => PixelValue::U16(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageLumaA16(buf) => PixelValue::U16(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageRgb16(buf) => PixelValue::U16(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageRgba16(buf) => PixelValue::U16(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageRgb32F(buf) => PixelValue::F32(buf.get_pixel(x, y).0.to_vec()),
+            DynamicImage::ImageRgba32F(buf) => PixelValue::F32(buf.get_pixel(x, y).0.to_vec()),
+            // Unknown future variants are read through the generic 8-bit accessor
+            _ => PixelValue::U8(image.get_pixel(x, y).0.to_vec()),
+        }
+    }
+
+    // Samples scaled to 0..1 for integer formats
+    pub fn normalized(&self) -> Vec<f32> {
+        match self {
+            PixelValue::U8(v) => v.iter().map(|&x| x as f32 / 255.0).collect(),
+            PixelValue::U16(v) => v.iter().map(|&x| x as f32 / 65535.0).collect(),
+            PixelValue::F32(v) => v.clone(),
+        }
+    }
+
+    pub fn format(&self, format: CopyFormat) -> String {
+        match (format, self) {
+            (CopyFormat::Hex, PixelValue::U8(v)) => {
+                let digits: String = v.iter().map(|x| format!("{x:02X}")).collect();
+                format!("#{digits}")
+            }
+            (CopyFormat::Hex, PixelValue::U16(v)) => {
+                let digits: String = v.iter().map(|x| format!("{x:04X}")).collect();
+                format!("#{digits}")
+            }
+            (CopyFormat::Hex, PixelValue::F32(v)) => {
+                tuple(v.iter().map(|x| format!("0x{:08X}", x.to_bits())))
+            }
+            (CopyFormat::Integer, PixelValue::U8(v)) => tuple(v.iter().map(|x| x.to_string())),
+            (CopyFormat::Integer, PixelValue::U16(v)) => tuple(v.iter().map(|x| x.to_string())),
+            (CopyFormat::Integer, PixelValue::F32(v)) => {
+                tuple(v.iter().map(|&x| unorm8(x).to_string()))
+            }
+            (CopyFormat::Float, _) => tuple(self.normalized().iter().map(|x| x.to_string())),
+        }
+    }
+}
+
+// Integer samples as stored, floats rounded for display
+impl fmt::Display for PixelValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PixelValue::F32(v) => write!(f, "{}", tuple(v.iter().map(|x| format!("{x:.4}")))),
+            _ => write!(f, "{}", self.format(CopyFormat::Integer)),
+        }
+    }
+}
+
+fn tuple(values: impl Iterator<Item = String>) -> String {
+    format!("({})", values.collect::<Vec<_>>().join(", "))
+}
+
+fn unorm8(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Color the viewer puts on screen for a pixel of the displayed image. Integer images are display
+// encoded already, float images go through the display transform like in `blit_ps`.
+pub fn displayed_srgb(value: &PixelValue, display: &DisplaySettings) -> [u8; 3] {
+    let v = value.normalized();
+    let rgb = if v.len() >= 3 {
+        float3::new(v[0], v[1], v[2])
+    } else {
+        float3::new(v[0], v[0], v[0])
+    };
+    let rgb = match value {
+        PixelValue::F32(_) => display.apply(rgb),
+        _ => rgb,
+    };
+    [unorm8(rgb.x), unorm8(rgb.y), unorm8(rgb.z)]
+}
+
+// Pixel under a window position, None outside of the image. Returns the oriented coordinates the
+// viewer reports, and the coordinates in the decoded image `dim` to read the value at. Samples at
+// the window pixel center like the shader, so it picks the pixel that is drawn there.
+pub fn window_to_pixel(
+    xfm_window_to_image: Transform2D,
+    orientation: ViewOrientation,
+    dim: (u32, u32),
+    window_pos: float2,
+) -> Option<((u32, u32), (u32, u32))> {
+    let oriented_dim = orientation.oriented_dim(dim);
+    let pos = xfm_window_to_image.transform_point(window_pos + float2::new(0.5, 0.5));
+    let uv = pos.div_element_wise(float2::new(oriented_dim.0 as f32, oriented_dim.1 as f32));
+    if uv.x < 0.0 || uv.y < 0.0 || uv.x >= 1.0 || uv.y >= 1.0 {
+        return None;
+    }
+    let oriented = (
+        (pos.x.floor() as u32).min(oriented_dim.0 - 1),
+        (pos.y.floor() as u32).min(oriented_dim.1 - 1),
+    );
+    let uv = orientation.oriented_uv_to_texture_uv(uv);
+    let texture = (
+        ((uv.x * dim.0 as f32) as u32).min(dim.0 - 1),
+        ((uv.y * dim.1 as f32) as u32).min(dim.1 - 1),
+    );
+    Some((oriented, texture))
+}
+
+#[test]
+fn test_window_to_pixel() {
+    let identity = ViewOrientation::new_identity();
+    let dim = (40, 30);
+    let xfm = Transform2D::new_identity();
+    assert_eq!(
+        window_to_pixel(xfm, identity, dim, float2::new(3.0, 7.0)),
+        Some(((3, 7), (3, 7)))
+    );
+    assert_eq!(
+        window_to_pixel(xfm, identity, dim, float2::new(40.0, 7.0)),
+        None
+    );
+    assert_eq!(
+        window_to_pixel(xfm, identity, dim, float2::new(-1.0, 7.0)),
+        None
+    );
+
+    // Magnified 4x and panned, so a block of window pixels shows the same image pixel
+    let xfm = Transform2D {
+        scale: float2::new(0.25, 0.25),
+        offset: float2::new(10.0, 5.0),
+        ..Default::default()
+    };
+    for p in [float2::new(0.0, 0.0), float2::new(3.0, 3.0)] {
+        assert_eq!(
+            window_to_pixel(xfm, identity, dim, p),
+            Some(((10, 5), (10, 5)))
+        );
+    }
+    assert_eq!(
+        window_to_pixel(xfm, identity, dim, float2::new(4.0, 0.0)),
+        Some(((11, 5), (11, 5)))
+    );
+
+    // Rotated 90 degrees clockwise: the top left on screen is reported as 0, 0 and read from the
+    // bottom left of the decoded image
+    let rotated = ViewOrientation::from_exif(image::metadata::Orientation::Rotate90);
+    let xfm = Transform2D::new_identity();
+    assert_eq!(
+        window_to_pixel(xfm, rotated, dim, float2::new(0.0, 0.0)),
+        Some(((0, 0), (0, 29)))
+    );
+    assert_eq!(
+        window_to_pixel(xfm, rotated, dim, float2::new(29.0, 39.0)),
+        Some(((29, 39), (39, 0)))
+    );
+    assert_eq!(
+        window_to_pixel(xfm, rotated, dim, float2::new(30.0, 0.0)),
+        None
+    );
+}
+
+#[test]
+fn test_pixel_value() {
+    let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        2,
+        2,
+        image::Rgba([255, 128, 0, 64]),
+    ));
+    let value = PixelValue::read(&image, 1, 1);
+    assert_eq!(value, PixelValue::U8(vec![255, 128, 0, 64]));
+    assert_eq!(value.format(CopyFormat::Hex), "#FF800040");
+    assert_eq!(value.format(CopyFormat::Integer), "(255, 128, 0, 64)");
+    assert_eq!(
+        value.format(CopyFormat::Float),
+        format!("(1, {}, 0, {})", 128.0f32 / 255.0, 64.0f32 / 255.0)
+    );
+    assert_eq!(value.to_string(), "(255, 128, 0, 64)");
+    let display = DisplaySettings::new_default();
+    assert_eq!(displayed_srgb(&value, &display), [255, 128, 0]);
+
+    let image =
+        DynamicImage::ImageLuma16(image::ImageBuffer::from_pixel(1, 1, image::Luma([4660])));
+    let value = PixelValue::read(&image, 0, 0);
+    assert_eq!(value.format(CopyFormat::Hex), "#1234");
+    assert_eq!(value.format(CopyFormat::Integer), "(4660)");
+    assert_eq!(displayed_srgb(&value, &display), [18, 18, 18]);
+
+    let image = DynamicImage::ImageRgb32F(image::ImageBuffer::from_pixel(
+        1,
+        1,
+        image::Rgb([1.0, 0.25, 4.0]),
+    ));
+    let value = PixelValue::read(&image, 0, 0);
+    assert_eq!(
+        value.format(CopyFormat::Hex),
+        "(0x3F800000, 0x3E800000, 0x40800000)"
+    );
+    assert_eq!(value.format(CopyFormat::Integer), "(255, 64, 255)");
+    assert_eq!(value.format(CopyFormat::Float), "(1, 0.25, 4)");
+    assert_eq!(value.to_string(), "(1.0000, 0.2500, 4.0000)");
+    // Linear values go through exposure, tone mapping and gamma
+    let display = DisplaySettings {
+        exposure_ev: -2.0,
+        gamma: 1.0,
+        ..DisplaySettings::new_default()
+    };
+    assert_eq!(displayed_srgb(&value, &display), [64, 16, 255]);
+
+    let cycle = (0..3).fold(CopyFormat::Hex, |format, _| format.next());
+    assert_eq!(cycle, CopyFormat::Hex);
+}
@@ -1,41 +1,1594 @@
-use icc_profile::DecodedICCProfile;
+use crate::color::{convert_to_output_profile, OutputProfile};
+use crate::dds::{decode_dds, full_header_len, DdsHeader, DDS_HEADER_LEN};
+use crate::exif::{format_decimal, parse_exif_tags, ExifIfd, ExifTags, ExifValue};
+use crate::icc::{parse_icc_tags, signature_string, IccTags};
+use crate::mips::LoadedMips;
+use crate::subimage::{SubimageInfo, SubimageKind, SubimageLayout, SubimageSet};
+use crate::xmp::{collect_xmp_info, XmpInfo};
+use anyhow::anyhow;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::imageops::FilterType;
 use image::metadata::Orientation;
-use image::{DynamicImage, ImageDecoder, ImageReader, ImageResult};
-use nom_exif::{EntryValue, Exif, ExifIter, ExifTag, MediaParser, MediaSource};
-use std::path::Path;
+use image::{
+    AnimationDecoder, ColorType, DynamicImage, ExtendedColorType, Frames, GenericImage,
+    ImageBuffer, ImageDecoder, ImageError, ImageFormat, ImageReader, Limits, Rgba, RgbaImage,
+};
+use log::warn;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Browsers treat very short GIF delays as unset and fall back to this
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+
+pub const STDIN_PATH: &str = "-";
+
+// Upper bound on image file directories followed in a TIFF
+const MAX_TIFF_PAGES: usize = 4096;
+
+// Previews are scaled down to fit this size
+const PREVIEW_MAX_SIZE: u32 = 1024;
+// Time between previews of the rows decoded so far
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(250);
+// Rows that aren't decoded yet, when there is no thumbnail to show instead
+const PREVIEW_BACKGROUND: Rgba<u8> = Rgba([32, 32, 32, 255]);
+
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    pub output_profile: OutputProfile,
+    pub limits: LoadLimits,
+    // Fail on truncated and corrupt images instead of showing the part that was decoded
+    pub strict: bool,
+    // Images with at least this many pixels publish previews while they decode, when the caller
+    // asks for them
+    pub preview_min_pixels: u64,
+    pub cancel: CancelToken,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            output_profile: OutputProfile::default(),
+            limits: LoadLimits::default(),
+            strict: false,
+            preview_min_pixels: 16 << 20,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+// Stops loads that the viewer no longer needs. A load keeps the generation it started at and
+// fails with `LoadError::Cancelled` at the next row band, animation frame or file read after
+// `cancel` is called on any clone of the token.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    generation: Arc<AtomicU64>,
+    started: u64,
+}
+
+impl CancelToken {
+    // Token for a load that starts now, calls to `cancel` before this one don't affect it
+    pub fn restart(&self) -> CancelToken {
+        CancelToken {
+            generation: self.generation.clone(),
+            started: self.generation.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.generation.load(Ordering::SeqCst) != self.started
+    }
+
+    fn check(&self) -> Result<(), LoadError> {
+        if self.is_cancelled() {
+            return Err(LoadError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+// Fails every read after the load is cancelled, which stops the decoders that stream the file
+struct CancellableReader<'a, R> {
+    inner: R,
+    cancel: &'a CancelToken,
+}
+
+impl<R: Read> Read for CancellableReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(std::io::Error::other("Loading was cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for CancellableReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// Upper bounds on decoded images. They are checked against the file header before any pixels are
+// allocated, so a small file that claims huge dimensions fails fast instead of exhausting memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    // Bytes of decoded pixels, summed over animation frames and pages
+    pub max_alloc: u64,
+}
+
+impl Default for LoadLimits {
+    fn default() -> Self {
+        LoadLimits {
+            // Larger images are drawn as several textures, the allocation limit is what keeps
+            // memory in check
+            max_width: 1 << 16,
+            max_height: 1 << 16,
+            max_alloc: 4 << 30,
+        }
+    }
+}
+
+impl LoadLimits {
+    // Dimensions are checked by `check`, which gives a better error than the decoders
+    fn to_image_limits(&self) -> Limits {
+        let mut limits = Limits::no_limits();
+        limits.max_alloc = Some(self.max_alloc);
+        limits
+    }
+
+    fn check(&self, dim: (u32, u32), total_bytes: u64) -> Result<(), LoadError> {
+        if dim.0 <= self.max_width && dim.1 <= self.max_height && total_bytes <= self.max_alloc {
+            return Ok(());
+        }
+        Err(LoadError::LimitExceeded(format!(
+            "{}x{} pixels and {} MB, the limits are {}x{} pixels and {} MB",
+            dim.0,
+            dim.1,
+            total_bytes >> 20,
+            self.max_width,
+            self.max_height,
+            self.max_alloc >> 20
+        )))
+    }
+}
+
+// Why an image couldn't be loaded, worded for the error placeholder in the viewer
+#[derive(Debug)]
+pub enum LoadError {
+    UnsupportedFormat(String),
+    // The file ends before the decoder has everything it needs
+    Truncated(String),
+    Io(std::io::Error),
+    LimitExceeded(String),
+    // Headers and metadata chunks are read before any pixels, so a decoder that fails while
+    // reading them rejects the file structure rather than the image data
+    CorruptMetadata(String),
+    CorruptData(String),
+    // The viewer moved on to another image, see `CancelToken`
+    Cancelled,
+}
+
+impl LoadError {
+    // Stable identifier for --info output
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            LoadError::UnsupportedFormat(_) => "unsupported_format",
+            LoadError::Truncated(_) => "truncated",
+            LoadError::Io(_) => "io",
+            LoadError::LimitExceeded(_) => "limit_exceeded",
+            LoadError::CorruptMetadata(_) => "corrupt_metadata",
+            LoadError::CorruptData(_) => "corrupt_data",
+            LoadError::Cancelled => "cancelled",
+        }
+    }
+
+    // Errors from creating a decoder, before any pixels are decoded
+    fn from_header_error(err: ImageError) -> Self {
+        match LoadError::from(err) {
+            LoadError::CorruptData(message) => LoadError::CorruptMetadata(message),
+            err => err,
+        }
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::UnsupportedFormat(message) => write!(f, "Unsupported format: {message}"),
+            LoadError::Truncated(message) => write!(f, "Truncated file: {message}"),
+            LoadError::Io(err) => write!(f, "I/O error: {err}"),
+            LoadError::LimitExceeded(message) => write!(f, "Image too large: {message}"),
+            LoadError::CorruptMetadata(message) => {
+                write!(f, "Corrupt header or metadata: {message}")
+            }
+            LoadError::CorruptData(message) => write!(f, "Corrupt image data: {message}"),
+            LoadError::Cancelled => write!(f, "Loading was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => LoadError::Truncated(err.to_string()),
+            _ => LoadError::Io(err),
+        }
+    }
+}
+
+impl From<ImageError> for LoadError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => LoadError::from(err),
+            ImageError::Unsupported(err) => LoadError::UnsupportedFormat(err.to_string()),
+            ImageError::Limits(err) => LoadError::LimitExceeded(err.to_string()),
+            err if is_truncation_error(&err) => LoadError::Truncated(err.to_string()),
+            err => LoadError::CorruptData(err.to_string()),
+        }
+    }
+}
+
+// Row by row decoding uses the PNG and TIFF decoders directly
+impl From<png::DecodingError> for LoadError {
+    fn from(err: png::DecodingError) -> Self {
+        match err {
+            png::DecodingError::IoError(err) => LoadError::from(err),
+            png::DecodingError::LimitsExceeded => LoadError::LimitExceeded(err.to_string()),
+            err => LoadError::CorruptData(err.to_string()),
+        }
+    }
+}
+
+impl From<tiff::TiffError> for LoadError {
+    fn from(err: tiff::TiffError) -> Self {
+        match err {
+            tiff::TiffError::IoError(err) => LoadError::from(err),
+            tiff::TiffError::UnsupportedError(err) => LoadError::UnsupportedFormat(err.to_string()),
+            tiff::TiffError::LimitsExceeded | tiff::TiffError::IntSizeError => {
+                LoadError::LimitExceeded(err.to_string())
+            }
+            err => LoadError::CorruptData(err.to_string()),
+        }
+    }
+}
+
+// Messages of decoders that ran out of data. The JPEG decoder reads from a copy of the whole file
+// in memory, so it reports reads past the end of the data instead of an I/O error.
+const TRUNCATION_MESSAGES: &[&str] = &[
+    "end of file",
+    "not enough bytes",
+    "cannot satisfy read",
+    "exhausted data",
+];
+
+// Decoders report running out of data in their own error types, which the image crate wraps in
+// decoding errors, some only in the message
+fn is_truncation_error(err: &ImageError) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = match err {
+        ImageError::Decoding(err) => Some(err),
+        _ => None,
+    };
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            if io_err.kind() == std::io::ErrorKind::UnexpectedEof {
+                return true;
+            }
+        }
+        let message = err.to_string().to_ascii_lowercase();
+        if TRUNCATION_MESSAGES.iter().any(|m| message.contains(m)) {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
 
 pub struct LoadedImage {
+    // Pixels converted to the output profile when the file has an embedded ICC profile
     pub image: DynamicImage,
+    // Decoded pixels before color management, only present when a conversion was applied
+    pub unmanaged_image: Option<DynamicImage>,
+    // Detected from the file contents, None for clipboard images
+    pub format: Option<ImageFormat>,
     pub exif: Option<Vec<u8>>,
     pub icc_profile: Option<Vec<u8>>,
     pub orientation: Option<Orientation>,
     pub exif_info: Option<ExifInfo>,
     pub icc_info: Option<IccInfo>,
+    // Descriptive metadata merged from XMP, IPTC-IIM and .xmp sidecar files
+    pub xmp_info: Option<XmpInfo>,
+    pub animation: Option<FrameSequence>,
+    // Pages of multi-page TIFFs and surfaces of DDS textures. The pixels of the first surface are
+    // in `image`, so `images` holds the remaining ones. TIFF pages are decoded with `decode_page`
+    // when they are shown instead.
+    pub subimages: Option<SubimageSet>,
+    pub tiff_pages: Option<TiffPages>,
+    // Set when decoding stopped early
+    pub incomplete: Option<IncompleteImage>,
+    // Filled in by `generate_mips` on the viewer's loader thread
+    pub mips: Option<LoadedMips>,
 }
 
-pub fn load_image_with_metadata(path: &Path) -> ImageResult<LoadedImage> {
-    let reader = ImageReader::open(path)?;
-    let reader = reader.with_guessed_format()?;
-    let mut decoder = reader.into_decoder()?;
+#[derive(Debug, Clone)]
+pub struct IncompleteImage {
+    // Error that stopped the decoder
+    pub reason: String,
+    pub missing_pixels: u64,
+    // Row order indices of the first missing pixel of `image` and of the one after the last. The
+    // viewer hatches this range, decoders stop at one point so it rarely includes decoded pixels.
+    pub missing_range: Range<u64>,
+}
+
+impl IncompleteImage {
+    // `missing` has a flag for every pixel in row order
+    fn new(reason: String, missing: impl Iterator<Item = bool>) -> Self {
+        let mut missing_pixels = 0;
+        let mut missing_range: Option<Range<u64>> = None;
+        for (index, _) in missing.enumerate().filter(|&(_, m)| m) {
+            let index = index as u64;
+            missing_pixels += 1;
+            missing_range = Some(missing_range.map_or(index, |range| range.start)..index + 1);
+        }
+        IncompleteImage {
+            reason,
+            missing_pixels,
+            missing_range: missing_range.unwrap_or(0..0),
+        }
+    }
+}
+
+impl LoadedImage {
+    pub fn from_image(image: DynamicImage) -> Self {
+        LoadedImage {
+            image,
+            unmanaged_image: None,
+            format: None,
+            exif: None,
+            icc_profile: None,
+            orientation: None,
+            exif_info: None,
+            icc_info: None,
+            xmp_info: None,
+            animation: None,
+            subimages: None,
+            tiff_pages: None,
+            incomplete: None,
+            mips: None,
+        }
+    }
+
+    // Builds the mip chains of the textures the viewer makes of this image
+    pub fn generate_mips(&mut self) {
+        self.mips = Some(LoadedMips::new(self));
+    }
+
+    // Decodes a page of a multi-page TIFF and converts it to the output profile like `image`.
+    // Every page has its own ICC profile, pages without one are shown unmanaged.
+    pub fn decode_page(
+        &self,
+        page: usize,
+        options: &LoadOptions,
+    ) -> Result<DynamicImage, LoadError> {
+        let pages = self
+            .tiff_pages
+            .as_ref()
+            .ok_or_else(|| LoadError::UnsupportedFormat("Not a multi-page TIFF".into()))?;
+        let (image, icc_profile) = pages.decode(page, options)?;
+        let managed = icc_profile.and_then(|icc| {
+            convert_to_output_profile(&image, &icc, &options.output_profile)
+                .map_err(|err| warn!("Color management failed, showing unmanaged colors: {err}"))
+                .ok()
+        });
+        Ok(managed.unwrap_or(image))
+    }
+
+    // Approximate memory used by the decoded pixels, mips and raw metadata, for the image cache
+    // budget
+    pub fn memory_size(&self) -> usize {
+        let mut size = self.image.as_bytes().len();
+        size += self
+            .unmanaged_image
+            .as_ref()
+            .map_or(0, |i| i.as_bytes().len());
+        if let Some(animation) = &self.animation {
+            size += animation
+                .frames
+                .iter()
+                .map(|f| {
+                    f.image.as_bytes().len()
+                        + f.unmanaged_image.as_ref().map_or(0, |i| i.as_bytes().len())
+                })
+                .sum::<usize>();
+        }
+        if let Some(subimages) = &self.subimages {
+            size += subimages
+                .images
+                .iter()
+                .map(|i| i.as_bytes().len())
+                .sum::<usize>();
+        }
+        size += self.tiff_pages.as_ref().map_or(0, TiffPages::memory_size);
+        size += self.exif.as_ref().map_or(0, |e| e.len());
+        size += self.icc_profile.as_ref().map_or(0, |p| p.len());
+        size += self.mips.as_ref().map_or(0, LoadedMips::memory_size);
+        size
+    }
+}
+
+// Stand-in for a large image that is still decoding. The decoded rows are scaled down into it,
+// the rest shows the EXIF thumbnail if the file has one.
+pub struct ImagePreview {
+    pub image: DynamicImage,
+    // Dimensions of the full image, which the preview is stretched over
+    pub full_dim: (u32, u32),
+    pub orientation: Option<Orientation>,
+}
+
+pub struct AnimationFrame {
+    // Converted to the output profile like `LoadedImage::image`
+    pub image: DynamicImage,
+    // Decoded pixels before color management, only present when a conversion was applied
+    pub unmanaged_image: Option<DynamicImage>,
+    pub delay: Duration,
+}
+
+// Fully composited frames of an animated image, disposal and blending are already applied
+pub struct FrameSequence {
+    pub frames: Vec<AnimationFrame>,
+}
+
+impl FrameSequence {
+    pub fn frame_delays(&self) -> Vec<Duration> {
+        self.frames.iter().map(|f| f.delay).collect()
+    }
+}
+
+// "-" in place of a file name reads the image from stdin
+pub fn is_stdin_path(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+pub fn load_image_with_metadata(
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<LoadedImage, LoadError> {
+    load_image_with_preview(path, options, None)
+}
+
+// Reads all of a stream into memory. Encoded images are smaller than their pixels, so a stream
+// longer than `LoadLimits::max_alloc` is rejected without reading the rest.
+fn read_stream<R: Read>(reader: R, options: &LoadOptions) -> Result<Vec<u8>, LoadError> {
+    let max_bytes = options.limits.max_alloc;
+    let reader = CancellableReader {
+        inner: reader,
+        cancel: &options.cancel,
+    };
+    let mut data = Vec::new();
+    let result = reader
+        .take(max_bytes.saturating_add(1))
+        .read_to_end(&mut data);
+    // Cancelled reads fail with an IO error
+    options.cancel.check()?;
+    result?;
+    if data.len() as u64 > max_bytes {
+        return Err(LoadError::LimitExceeded(format!(
+            "the stream is longer than the {} MB limit",
+            max_bytes >> 20
+        )));
+    }
+    Ok(data)
+}
+
+// Images of at least `LoadOptions::preview_min_pixels` pass previews to `on_preview` while they
+// decode, starting with the EXIF thumbnail
+pub fn load_image_with_preview(
+    path: &Path,
+    options: &LoadOptions,
+    on_preview: Option<&mut dyn FnMut(ImagePreview)>,
+) -> Result<LoadedImage, LoadError> {
+    if is_stdin_path(path) {
+        // Pipes can't seek, so the whole stream is read before decoding
+        let data = read_stream(std::io::stdin().lock(), options)?;
+        return load_image_from_reader(&mut Cursor::new(&data), None, options, on_preview);
+    }
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(CancellableReader {
+        inner: file,
+        cancel: &options.cancel,
+    });
+    let result = load_image_from_reader(&mut reader, Some(path), options, on_preview);
+    // Decoders report the failed reads in different ways, or recover part of the image from them
+    options.cancel.check()?;
+    result
+}
+
+// Same as loading from a file, with the format detected from the contents
+pub fn load_image_from_bytes(data: &[u8], options: &LoadOptions) -> Result<LoadedImage, LoadError> {
+    load_image_from_reader(&mut Cursor::new(data), None, options, None)
+}
+
+// Decodes the pixels and every kind of metadata from a single reader. Metadata comes from the
+// chunks the decoder returns, the reader is only rewound for the extra passes over animation
+// frames, TIFF pages and DDS surfaces, and to find the end marker of JPEGs. `path` is used to
+// look for .xmp sidecar files.
+pub fn load_image_from_reader<R: BufRead + Seek>(
+    reader: &mut R,
+    path: Option<&Path>,
+    options: &LoadOptions,
+    on_preview: Option<&mut dyn FnMut(ImagePreview)>,
+) -> Result<LoadedImage, LoadError> {
+    let start = reader.stream_position()?;
+    let format = ImageReader::new(&mut *reader)
+        .with_guessed_format()?
+        .format();
+    if format == Some(ImageFormat::Dds) {
+        match load_dds(reader, path, &options.limits).map_err(|err| err.downcast::<LoadError>()) {
+            Ok(loaded) => return Ok(loaded),
+            Err(Ok(err)) => return Err(err),
+            Err(Err(err)) => {
+                warn!("Failed to decode DDS surfaces, showing first surface only: {err}")
+            }
+        }
+        reader.seek(SeekFrom::Start(start))?;
+    }
+    // Creating the decoder only reads the headers
+    let mut decoder = new_decoder(reader, start, format, &options.limits)
+        .map_err(LoadError::from_header_error)?;
+    options
+        .limits
+        .check(decoder.dimensions(), decoder.total_bytes())?;
     let exif = decoder.exif_metadata().unwrap_or(None);
     let icc_profile = decoder.icc_profile().unwrap_or(None);
     let orientation = exif
         .as_deref()
         .and_then(Orientation::from_exif_chunk);
-    let exif_info = parse_exif_info(path);
+    let exif_info = exif.as_deref().and_then(parse_exif_info);
     let icc_info = icc_profile.as_deref().and_then(parse_icc_info);
-    let image = DynamicImage::from_decoder(decoder)?;
+    let xmp = decoder.xmp_metadata().unwrap_or(None);
+    let iptc = decoder.iptc_metadata().unwrap_or(None);
+    let xmp_info = collect_xmp_info(path, xmp.as_deref(), iptc.as_deref());
+    // Formats that can be animated are decoded once as a sequence of frames, the first frame is
+    // the still image. Files that fail to decode that way go through the still image decoder,
+    // which can recover the part before an error.
+    let mut frames = None;
+    if let Some(format @ (ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP)) = format {
+        drop(decoder);
+        frames = match load_animation(reader, start, format, options) {
+            Ok(frames) => frames,
+            Err(LoadError::Cancelled) => return Err(LoadError::Cancelled),
+            Err(err) => {
+                warn!("Failed to decode animation frames, decoding a still image: {err}");
+                None
+            }
+        };
+        decoder = new_decoder(reader, start, Some(format), &options.limits)
+            .map_err(LoadError::from_header_error)?;
+    }
+    let (first_frame, animation) = match frames {
+        Some(mut sequence) if sequence.frames.len() == 1 => {
+            (Some(sequence.frames.remove(0).image), None)
+        }
+        Some(sequence) => (
+            sequence.frames.first().map(|frame| frame.image.clone()),
+            Some(sequence),
+        ),
+        None => (None, None),
+    };
+    let color_type = decoder.color_type();
+    let dim = decoder.dimensions();
+    let mut preview = match on_preview {
+        Some(publish)
+            if first_frame.is_none()
+                && u64::from(dim.0) * u64::from(dim.1) >= options.preview_min_pixels =>
+        {
+            let thumbnail = match (exif.as_deref(), &exif_info) {
+                (Some(exif), Some(exif_info)) => decode_exif_thumbnail(exif, exif_info, options),
+                _ => None,
+            };
+            let mut preview = PreviewBuilder::new(dim, orientation, thumbnail.as_ref());
+            if thumbnail.is_some() {
+                publish(preview.build(icc_profile.as_deref(), &options.output_profile));
+            }
+            Some((preview, publish))
+        }
+        _ => None,
+    };
+    // Conversions such as CMYK to RGB happen after the whole image is decoded
+    let decode_rows = preview.is_some()
+        && matches!(format, Some(ImageFormat::Png) | Some(ImageFormat::Tiff))
+        && decoder.original_color_type() == ExtendedColorType::from(color_type);
+    let decoded = match (&mut preview, first_frame) {
+        (_, Some(first_frame)) => {
+            drop(decoder);
+            Ok(first_frame)
+        }
+        (Some((preview, publish)), None) if decode_rows => {
+            drop(decoder);
+            let mut on_rows = |buffer: &[u8], rows: u32| {
+                preview.add_rows(buffer, color_type, rows);
+                if preview.last_build.elapsed() >= PREVIEW_INTERVAL {
+                    publish(preview.build(icc_profile.as_deref(), &options.output_profile));
+                }
+            };
+            let decoded = decode_image_rows(
+                reader,
+                start,
+                format,
+                color_type,
+                dim,
+                options,
+                &mut on_rows,
+            );
+            match decoded {
+                Ok(Some(image)) => Ok(image),
+                // Interlaced PNGs and tiled TIFFs only decode as a whole
+                Ok(None) => new_decoder(reader, start, format, &options.limits)
+                    .and_then(DynamicImage::from_decoder)
+                    .map_err(LoadError::from),
+                Err(err) => Err(err),
+            }
+        }
+        (_, None) => DynamicImage::from_decoder(decoder).map_err(LoadError::from),
+    };
+    let (image, incomplete) = match decoded {
+        Ok(image) => (image, None),
+        Err(err) => {
+            let recovered = match err {
+                LoadError::Truncated(_) | LoadError::CorruptData(_) if !options.strict => {
+                    decode_partial(reader, start, format, &options.limits, err.to_string())?
+                }
+                _ => None,
+            };
+            match recovered {
+                Some((image, incomplete)) => (image, Some(incomplete)),
+                None => return Err(err),
+            }
+        }
+    };
+    // The JPEG decoder doesn't report missing data and fills it with gray instead. Files with
+    // other data after the end marker, like the video of motion photos, have the marker found by
+    // walking the segments. Without it, the image is only incomplete when it ends in gray.
+    let (image, incomplete) = match (format, incomplete) {
+        (Some(ImageFormat::Jpeg), None)
+            if !has_jpeg_end_marker(reader, start)? && !find_jpeg_end_marker(reader, start)? =>
+        {
+            let reason = "Truncated file: no end of image marker".to_owned();
+            let incomplete = IncompleteImage::new(reason, trailing_gray_pixels(&image));
+            if incomplete.missing_pixels == 0 {
+                (image, None)
+            } else if options.strict {
+                return Err(LoadError::Truncated(incomplete.reason));
+            } else {
+                (image, Some(incomplete))
+            }
+        }
+        (_, incomplete) => (image, incomplete),
+    };
+    if let Some(incomplete) = &incomplete {
+        warn!(
+            "Showing the decoded part of an incomplete image, {} pixels are missing: {}",
+            incomplete.missing_pixels, incomplete.reason
+        );
+    }
+    let (image, unmanaged_image) = match icc_profile.as_deref() {
+        Some(icc) => match convert_to_output_profile(&image, icc, &options.output_profile) {
+            Ok(managed_image) => (managed_image, Some(image)),
+            Err(err) => {
+                warn!("Color management failed, showing unmanaged colors: {err}");
+                (image, None)
+            }
+        },
+        None => (image, None),
+    };
+    let animation = match (animation, icc_profile.as_deref()) {
+        (Some(mut animation), Some(icc)) if unmanaged_image.is_some() => {
+            for frame in &mut animation.frames {
+                if let Ok(managed) =
+                    convert_to_output_profile(&frame.image, icc, &options.output_profile)
+                {
+                    frame.unmanaged_image = Some(std::mem::replace(&mut frame.image, managed));
+                }
+            }
+            Some(animation)
+        }
+        (animation, _) => animation,
+    };
+    let (subimages, tiff_pages) = match format {
+        Some(ImageFormat::Tiff) => load_tiff_pages(reader, start, path, &image)
+            .unwrap_or_else(|err| {
+                warn!("Failed to read TIFF pages, showing first page only: {err}");
+                None
+            })
+            .unzip(),
+        _ => (None, None),
+    };
     Ok(LoadedImage {
         image,
+        unmanaged_image,
+        format,
         exif,
         icc_profile,
         orientation,
         exif_info,
         icc_info,
+        xmp_info,
+        animation,
+        subimages,
+        tiff_pages,
+        incomplete,
+        mips: None,
     })
 }
 
-#[derive(Debug, Clone)]
+// Decodes as much of a truncated or corrupt file as the decoder manages before failing. Returns
+// the image with the pixels that are missing, or None if nothing was decoded.
+fn decode_partial<R: BufRead + Seek>(
+    reader: &mut R,
+    start: u64,
+    format: Option<ImageFormat>,
+    limits: &LoadLimits,
+    reason: String,
+) -> Result<Option<(DynamicImage, IncompleteImage)>, LoadError> {
+    if format == Some(ImageFormat::Png) {
+        if let Some(recovered) = decode_partial_png(reader, start, limits, &reason)? {
+            return Ok(Some(recovered));
+        }
+    }
+    // Decoders write rows as they go, so the output of a failed decode keeps the rows before
+    // the error. Bottom-up BMPs fill the buffer from the end. Two decodes into buffers with
+    // different fill values tell the pixels that the decoder wrote from the ones it didn't reach.
+    let mut decode_filled = |fill: u8| -> Result<_, LoadError> {
+        // Files that fail before the pixels are reported with the error of the first decode
+        let decoder = match new_decoder(reader, start, format, limits) {
+            Ok(decoder) => decoder,
+            Err(_) => return Ok(None),
+        };
+        let color_type = decoder.color_type();
+        let dim = decoder.dimensions();
+        let total_bytes = decoder.total_bytes();
+        // Both buffers are alive at once. Images that only fit once are reported as broken.
+        if limits.check(dim, total_bytes.saturating_mul(2)).is_err() {
+            return Ok(None);
+        }
+        let mut buffer = vec![fill; total_bytes as usize];
+        let _ = decoder.read_image(&mut buffer);
+        Ok(Some((color_type, dim, buffer)))
+    };
+    let (color_type, dim, buffer) = match decode_filled(0x00)? {
+        Some(decoded) => decoded,
+        None => return Ok(None),
+    };
+    let incomplete = match decode_filled(0xFF)? {
+        Some((_, _, filled)) => {
+            let bytes_per_pixel = color_type.bytes_per_pixel() as usize;
+            let missing = buffer
+                .chunks_exact(bytes_per_pixel)
+                .zip(filled.chunks_exact(bytes_per_pixel))
+                .map(|(pixel, filled)| pixel != filled);
+            IncompleteImage::new(reason, missing)
+        }
+        None => return Ok(None),
+    };
+    let pixel_count = u64::from(dim.0) * u64::from(dim.1);
+    if incomplete.missing_pixels == pixel_count {
+        return Ok(None);
+    }
+    Ok(image_from_raw(color_type, dim, buffer).map(|image| (image, incomplete)))
+}
+
+// The PNG decoder holds back the last 32 KB of inflated rows until the image data ends, so a
+// truncated file loses them, which is all of a small image. The rows that inflate are put into a
+// complete PNG with the rest of the rows zeroed, and that is decoded instead. Returns None for
+// interlaced images and when no row inflates.
+fn decode_partial_png<R: BufRead + Seek>(
+    reader: &mut R,
+    start: u64,
+    limits: &LoadLimits,
+    reason: &str,
+) -> Result<Option<(DynamicImage, IncompleteImage)>, LoadError> {
+    let (dim, total_bytes) = match new_decoder(reader, start, Some(ImageFormat::Png), limits) {
+        Ok(decoder) => (decoder.dimensions(), decoder.total_bytes()),
+        Err(_) => return Ok(None),
+    };
+    // The inflated rows and the decoded image are alive at once
+    if limits.check(dim, total_bytes.saturating_mul(2)).is_err() {
+        return Ok(None);
+    }
+    let mut data = Vec::new();
+    reader.seek(SeekFrom::Start(start))?;
+    reader.take(limits.max_alloc).read_to_end(&mut data)?;
+    let (png, decoded_rows) = match rebuild_truncated_png(&data, limits.max_alloc) {
+        Some(rebuilt) => rebuilt,
+        None => return Ok(None),
+    };
+    let image = new_decoder(&mut Cursor::new(png), 0, Some(ImageFormat::Png), limits)
+        .and_then(DynamicImage::from_decoder)?;
+    let pixel_count = u64::from(dim.0) * u64::from(dim.1);
+    let decoded_pixels = u64::from(decoded_rows) * u64::from(dim.0);
+    let incomplete = IncompleteImage {
+        reason: reason.to_owned(),
+        missing_pixels: pixel_count - decoded_pixels,
+        missing_range: decoded_pixels..pixel_count,
+    };
+    Ok(Some((image, incomplete)))
+}
+
+// Copies the chunks before the image data of a PNG and replaces the image data with the rows
+// that inflate from it. Returns the new file and the number of rows that were kept.
+fn rebuild_truncated_png(data: &[u8], max_alloc: u64) -> Option<(Vec<u8>, u32)> {
+    let mut png = data.get(..8)?.to_vec();
+    let mut header = None;
+    let mut compressed = Vec::new();
+    let mut position = 8;
+    while let Some(chunk) = data.get(position..).and_then(|rest| rest.get(..8)) {
+        let length = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        let chunk_type = &chunk[4..8];
+        let chunk_end = position.saturating_add(length).saturating_add(12);
+        if chunk_type == b"IDAT" {
+            let data_end = (chunk_end - 4).min(data.len());
+            compressed.extend_from_slice(&data[position + 8..data_end]);
+        } else if !compressed.is_empty() {
+            break;
+        } else {
+            if chunk_type == b"IHDR" {
+                header = data.get(position + 8..position + 8 + length);
+            }
+            png.extend_from_slice(data.get(position..chunk_end)?);
+        }
+        position = chunk_end;
+    }
+    let header = header.filter(|header| header.len() == 13)?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let (bit_depth, color_type, interlaced) = (header[8], header[9], header[12] != 0);
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return None,
+    };
+    if interlaced {
+        return None;
+    }
+    // Every row starts with its filter type
+    let row_bytes = 1 + (u64::from(width) * channels * u64::from(bit_depth)).div_ceil(8);
+    let image_bytes = row_bytes.checked_mul(u64::from(height))?;
+    if image_bytes > max_alloc {
+        return None;
+    }
+    let (image_bytes, row_bytes) = (image_bytes as usize, row_bytes as usize);
+    let mut rows =
+        match miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(&compressed, image_bytes) {
+            Ok(rows) => rows,
+            Err(err) => err.output,
+        };
+    let decoded_rows = (rows.len() / row_bytes).min(height as usize);
+    if decoded_rows == 0 {
+        return None;
+    }
+    rows.truncate(decoded_rows * row_bytes);
+    rows.resize(image_bytes, 0);
+    let mut push_chunk = |chunk_type: &[u8], chunk_data: &[u8]| {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(chunk_type);
+        crc.update(chunk_data);
+        png.extend_from_slice(&(chunk_data.len() as u32).to_be_bytes());
+        png.extend_from_slice(chunk_type);
+        png.extend_from_slice(chunk_data);
+        png.extend_from_slice(&crc.finalize().to_be_bytes());
+    };
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&rows, 1);
+    push_chunk(b"IDAT", &compressed);
+    push_chunk(b"IEND", &[]);
+    Some((png, decoded_rows as u32))
+}
+
+// Decoder for another pass over the image at `start`
+fn new_decoder<'a, R: BufRead + Seek>(
+    reader: &'a mut R,
+    start: u64,
+    format: Option<ImageFormat>,
+    limits: &LoadLimits,
+) -> Result<impl ImageDecoder + 'a, ImageError> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut image_reader = ImageReader::new(reader);
+    if let Some(format) = format {
+        image_reader.set_format(format);
+    }
+    image_reader.limits(limits.to_image_limits());
+    image_reader.into_decoder()
+}
+
+// Decodes PNG and strip TIFF images a band of rows at a time, straight into the output buffer,
+// and calls `on_rows` with the buffer and the number of rows finished after each band. A decode
+// that stops early returns the error, the rows it kept are recovered like for any other decode.
+// Returns None for interlaced PNGs and tiled TIFFs, which only decode as a whole.
+fn decode_image_rows<R: BufRead + Seek>(
+    reader: &mut R,
+    start: u64,
+    format: Option<ImageFormat>,
+    color_type: ColorType,
+    dim: (u32, u32),
+    options: &LoadOptions,
+    on_rows: &mut dyn FnMut(&[u8], u32),
+) -> Result<Option<DynamicImage>, LoadError> {
+    let row_bytes = dim.0 as usize * color_type.bytes_per_pixel() as usize;
+    let mut buffer = vec![0; row_bytes * dim.1 as usize];
+    reader.seek(SeekFrom::Start(start))?;
+    let decoded = match format {
+        Some(ImageFormat::Png) => read_png_rows(reader, options, &mut buffer, on_rows)?,
+        Some(ImageFormat::Tiff) => read_tiff_strips(reader, options, &mut buffer, on_rows)?,
+        _ => false,
+    };
+    if !decoded {
+        return Ok(None);
+    }
+    let image = image_from_raw(color_type, dim, buffer)
+        .ok_or_else(|| LoadError::UnsupportedFormat(format!("{color_type:?} pixels")))?;
+    Ok(Some(image))
+}
+
+// Same setup as the PNG decoder of the image crate, so that the pixels come out the same
+fn read_png_rows<R: BufRead + Seek>(
+    reader: &mut R,
+    options: &LoadOptions,
+    buffer: &mut [u8],
+    on_rows: &mut dyn FnMut(&[u8], u32),
+) -> Result<bool, LoadError> {
+    let max_bytes = options.limits.max_alloc.try_into().unwrap_or(usize::MAX);
+    let mut decoder = png::Decoder::new_with_limits(reader, png::Limits { bytes: max_bytes });
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut png_reader = decoder.read_info()?;
+    let (width, height) = (png_reader.info().width, png_reader.info().height as usize);
+    let row_bytes = match png_reader.output_line_size(width) {
+        Some(size) if size * height == buffer.len() && !png_reader.info().interlaced => size,
+        _ => return Ok(false),
+    };
+    let sixteen_bit = png_reader.output_color_type().1 == png::BitDepth::Sixteen;
+    for y in 0..height {
+        options.cancel.check()?;
+        let row = &mut buffer[y * row_bytes..(y + 1) * row_bytes];
+        png_reader.read_row(row)?;
+        // PNG samples are big endian
+        if sixteen_bit {
+            for value in row.chunks_exact_mut(2) {
+                let native = u16::from_be_bytes([value[0], value[1]]).to_ne_bytes();
+                value.copy_from_slice(&native);
+            }
+        }
+        on_rows(buffer, y as u32 + 1);
+    }
+    Ok(true)
+}
+
+fn read_tiff_strips<R: Read + Seek>(
+    reader: &mut R,
+    options: &LoadOptions,
+    buffer: &mut [u8],
+    on_rows: &mut dyn FnMut(&[u8], u32),
+) -> Result<bool, LoadError> {
+    // Same split of the allocation limit as the TIFF decoder of the image crate
+    let max_alloc: usize = options.limits.max_alloc.try_into().unwrap_or(usize::MAX);
+    let mut tiff_limits = tiff::decoder::Limits::default();
+    tiff_limits.decoding_buffer_size = buffer.len();
+    tiff_limits.intermediate_buffer_size = max_alloc.saturating_sub(buffer.len());
+    tiff_limits.ifd_value_size = tiff_limits.intermediate_buffer_size;
+    let mut decoder = tiff::decoder::Decoder::new(reader)?.with_limits(tiff_limits);
+    if decoder.get_chunk_type() != tiff::decoder::ChunkType::Strip {
+        return Ok(false);
+    }
+    let (_, height) = decoder.dimensions()?;
+    let rows_per_strip = decoder.chunk_dimensions().1;
+    let strip_count = decoder.strip_count()?;
+    if height == 0 || u64::from(strip_count) * u64::from(rows_per_strip) < u64::from(height) {
+        return Ok(false);
+    }
+    let row_bytes = buffer.len() / height as usize;
+    let mut rows = 0;
+    for strip in 0..strip_count {
+        options.cancel.check()?;
+        let end = height.min(rows + rows_per_strip);
+        let band = &mut buffer[rows as usize * row_bytes..end as usize * row_bytes];
+        decoder.read_chunk_bytes(strip, band)?;
+        rows = end;
+        on_rows(buffer, rows);
+    }
+    Ok(true)
+}
+
+// Scales the rows of an image that is still decoding down into a preview. Nearest neighbor, so
+// only the few source rows that land on a preview row are converted.
+struct PreviewBuilder {
+    image: RgbaImage,
+    full_dim: (u32, u32),
+    orientation: Option<Orientation>,
+    // Preview rows that already show decoded pixels
+    rows: u32,
+    last_build: Instant,
+}
+
+impl PreviewBuilder {
+    fn new(
+        full_dim: (u32, u32),
+        orientation: Option<Orientation>,
+        thumbnail: Option<&DynamicImage>,
+    ) -> Self {
+        let (width, height) = preview_size(full_dim);
+        let image = match thumbnail {
+            Some(thumbnail) => {
+                image::imageops::resize(&thumbnail.to_rgba8(), width, height, FilterType::Triangle)
+            }
+            None => RgbaImage::from_pixel(width, height, PREVIEW_BACKGROUND),
+        };
+        PreviewBuilder {
+            image,
+            full_dim,
+            orientation,
+            rows: 0,
+            last_build: Instant::now(),
+        }
+    }
+
+    // `buffer` holds the pixels of the full image in row order, the first `decoded_rows` are done
+    fn add_rows(&mut self, buffer: &[u8], color_type: ColorType, decoded_rows: u32) {
+        let (full_width, full_height) = self.full_dim;
+        let (width, height) = self.image.dimensions();
+        let row_bytes = full_width as usize * color_type.bytes_per_pixel() as usize;
+        // Centers of the preview pixels in the full image
+        let source = |i: u32, size: u32, full_size: u32| {
+            ((2 * u64::from(i) + 1) * u64::from(full_size) / (2 * u64::from(size))) as u32
+        };
+        while self.rows < height {
+            let y = source(self.rows, height, full_height);
+            if y >= decoded_rows {
+                break;
+            }
+            let row = buffer[y as usize * row_bytes..][..row_bytes].to_vec();
+            let row = match image_from_raw(color_type, (full_width, 1), row) {
+                Some(row) => row.to_rgba8(),
+                None => return,
+            };
+            for x in 0..width {
+                let pixel = *row.get_pixel(source(x, width, full_width), 0);
+                self.image.put_pixel(x, self.rows, pixel);
+            }
+            self.rows += 1;
+        }
+    }
+
+    fn build(
+        &mut self,
+        icc_profile: Option<&[u8]>,
+        output_profile: &OutputProfile,
+    ) -> ImagePreview {
+        self.last_build = Instant::now();
+        let image = DynamicImage::ImageRgba8(self.image.clone());
+        // Converted like the full image, so colors stay put when it replaces the preview
+        let image = match icc_profile {
+            Some(icc) => convert_to_output_profile(&image, icc, output_profile).unwrap_or(image),
+            None => image,
+        };
+        ImagePreview {
+            image,
+            full_dim: self.full_dim,
+            orientation: self.orientation,
+        }
+    }
+}
+
+// Aspect ratio of the full image, scaled down to fit PREVIEW_MAX_SIZE
+fn preview_size(full_dim: (u32, u32)) -> (u32, u32) {
+    let (width, height) = (u64::from(full_dim.0.max(1)), u64::from(full_dim.1.max(1)));
+    let max_size = u64::from(PREVIEW_MAX_SIZE);
+    let scale = |size: u64, longest: u64| (size * max_size / longest).max(1) as u32;
+    if width.max(height) <= max_size {
+        (width as u32, height as u32)
+    } else if width >= height {
+        (PREVIEW_MAX_SIZE, scale(height, width))
+    } else {
+        (scale(width, height), PREVIEW_MAX_SIZE)
+    }
+}
+
+// JPEG thumbnail that cameras store with IFD1, its offset counts from the TIFF header of the EXIF
+// block
+fn decode_exif_thumbnail(
+    exif: &[u8],
+    exif_info: &ExifInfo,
+    options: &LoadOptions,
+) -> Option<DynamicImage> {
+    let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+    let tag = |tag| {
+        let entry = exif_info.tags.get(ExifIfd::Ifd1, tag)?;
+        entry.value.as_u32().map(|value| value as usize)
+    };
+    let offset = tag(0x0201)?;
+    let data = tiff.get(offset..offset.checked_add(tag(0x0202)?)?)?;
+    let mut reader = ImageReader::with_format(Cursor::new(data), ImageFormat::Jpeg);
+    reader.limits(options.limits.to_image_limits());
+    reader
+        .decode()
+        .map_err(|err| warn!("Failed to decode EXIF thumbnail: {err}"))
+        .ok()
+}
+
+// Same as what `DynamicImage::from_decoder` builds from the bytes of `ImageDecoder::read_image`
+fn image_from_raw(color_type: ColorType, dim: (u32, u32), bytes: Vec<u8>) -> Option<DynamicImage> {
+    let (w, h) = dim;
+    let to_u16 = |bytes: &[u8]| -> Vec<u16> {
+        let values = bytes.chunks_exact(2);
+        values.map(|b| u16::from_ne_bytes([b[0], b[1]])).collect()
+    };
+    let to_f32 = |bytes: &[u8]| -> Vec<f32> {
+        let values = bytes.chunks_exact(4);
+        values
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    };
+    Some(match color_type {
+        ColorType::L8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(w, h, bytes)?),
+        ColorType::La8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(w, h, bytes)?),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, bytes)?),
+        ColorType::Rgba8 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(w, h, bytes)?),
+        ColorType::L16 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(w, h, to_u16(&bytes))?),
+        ColorType::La16 => DynamicImage::ImageLumaA16(ImageBuffer::from_raw(w, h, to_u16(&bytes))?),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(ImageBuffer::from_raw(w, h, to_u16(&bytes))?),
+        ColorType::Rgba16 => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(w, h, to_u16(&bytes))?)
+        }
+        ColorType::Rgb32F => {
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(w, h, to_f32(&bytes))?)
+        }
+        ColorType::Rgba32F => {
+            DynamicImage::ImageRgba32F(ImageBuffer::from_raw(w, h, to_f32(&bytes))?)
+        }
+        _ => return None,
+    })
+}
+
+// Missing JPEG data decodes to mid gray. The gray pixels at the end of the image, in row order,
+// are taken as the part the file didn't reach.
+fn trailing_gray_pixels(image: &DynamicImage) -> impl Iterator<Item = bool> {
+    let bytes_per_pixel = image.color().bytes_per_pixel() as usize;
+    let mut pixels = image.as_bytes().chunks_exact(bytes_per_pixel);
+    let pixel_count = pixels.len();
+    let decoded = pixels
+        .rposition(|pixel| pixel.iter().any(|&b| b != 128))
+        .map_or(0, |last| last + 1);
+    (0..pixel_count).map(move |index| index >= decoded)
+}
+
+// Every complete JPEG ends with an EOI marker, which some writers follow with zero padding. Only
+// the end of the data is read, backwards from the last byte.
+fn has_jpeg_end_marker<R: Read + Seek>(reader: &mut R, start: u64) -> Result<bool, LoadError> {
+    let mut end = reader.seek(SeekFrom::End(0))?;
+    let mut block = [0u8; 512];
+    while end > start {
+        let len = (end - start).min(block.len() as u64) as usize;
+        end -= len as u64;
+        reader.seek(SeekFrom::Start(end))?;
+        reader.read_exact(&mut block[..len])?;
+        if let Some(last) = block[..len].iter().rposition(|&b| b != 0) {
+            // The 0xFF before it can be in the previous block
+            let position = end + last as u64;
+            if block[last] != 0xD9 || position == start {
+                return Ok(false);
+            }
+            let mut marker = [0u8; 1];
+            reader.seek(SeekFrom::Start(position - 1))?;
+            reader.read_exact(&mut marker)?;
+            return Ok(marker[0] == 0xFF);
+        }
+    }
+    Ok(false)
+}
+
+// Next marker code, skipping the entropy coded data of scans with its stuffed zeros and restart
+// markers. None at the end of the data.
+fn next_jpeg_marker<R: BufRead>(reader: &mut R) -> Result<Option<u8>, LoadError> {
+    let mut after_ff = false;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+        let mut consumed = 0;
+        let mut marker = None;
+        for &byte in buffer {
+            consumed += 1;
+            match (after_ff, byte) {
+                (_, 0xFF) => after_ff = true,
+                (true, 0x00 | 0xD0..=0xD7) | (false, _) => after_ff = false,
+                (true, code) => {
+                    marker = Some(code);
+                    break;
+                }
+            }
+        }
+        reader.consume(consumed);
+        if marker.is_some() {
+            return Ok(marker);
+        }
+    }
+}
+
+// Walks the segments of the JPEG at `start` to its EOI marker. Segments are skipped by their
+// length, so markers inside of them, like the end of an EXIF thumbnail, are not mistaken for it.
+fn find_jpeg_end_marker<R: BufRead + Seek>(reader: &mut R, start: u64) -> Result<bool, LoadError> {
+    reader.seek(SeekFrom::Start(start))?;
+    while let Some(marker) = next_jpeg_marker(reader)? {
+        match marker {
+            0xD9 => return Ok(true),
+            // Markers without a segment
+            0x01 | 0xD8 => {}
+            _ => {
+                let mut length = [0u8; 2];
+                if reader.read_exact(&mut length).is_err() {
+                    return Ok(false);
+                }
+                let length = u16::from_be_bytes(length);
+                if length < 2 {
+                    return Ok(false);
+                }
+                reader.seek(SeekFrom::Current(length as i64 - 2))?;
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn load_dds<R: Read>(
+    reader: &mut R,
+    path: Option<&Path>,
+    limits: &LoadLimits,
+) -> anyhow::Result<LoadedImage> {
+    // The headers are checked against the limits before the surfaces are read
+    let mut data = vec![0; DDS_HEADER_LEN];
+    reader.read_exact(&mut data)?;
+    data.resize(full_header_len(&data), 0);
+    reader.read_exact(&mut data[DDS_HEADER_LEN..])?;
+    let header = DdsHeader::read(&data)?;
+    limits.check(header.dim(), header.decoded_bytes())?;
+    reader.take(limits.max_alloc).read_to_end(&mut data)?;
+    let mut subimages = decode_dds(&data, &header)?;
+    let image = subimages.images.remove(0);
+    let mut loaded = LoadedImage::from_image(image);
+    loaded.format = Some(ImageFormat::Dds);
+    loaded.xmp_info = collect_xmp_info(path, None, None);
+    if subimages.surfaces.len() > 1 {
+        loaded.subimages = Some(subimages);
+    }
+    Ok(loaded)
+}
+
+// Offsets of every image file directory in a classic or BigTIFF file at `start`, in page order
+fn tiff_ifd_offsets<R: Read + Seek>(reader: &mut R, start: u64) -> anyhow::Result<Vec<u64>> {
+    let truncated = || anyhow!("Truncated TIFF file");
+    let mut byte_order = [0u8; 2];
+    reader.seek(SeekFrom::Start(start))?;
+    reader
+        .read_exact(&mut byte_order)
+        .map_err(|_| truncated())?;
+    let little_endian = match &byte_order {
+        b"II" => true,
+        b"MM" => false,
+        _ => return Err(anyhow!("Not a TIFF file")),
+    };
+    let mut read = |offset: u64, size: usize| -> anyhow::Result<u64> {
+        let position = start.checked_add(offset).ok_or_else(truncated)?;
+        let mut value = [0u8; 8];
+        reader.seek(SeekFrom::Start(position))?;
+        if little_endian {
+            reader
+                .read_exact(&mut value[..size])
+                .map_err(|_| truncated())?;
+            Ok(u64::from_le_bytes(value))
+        } else {
+            reader
+                .read_exact(&mut value[8 - size..])
+                .map_err(|_| truncated())?;
+            Ok(u64::from_be_bytes(value))
+        }
+    };
+    let big_tiff = read(2, 2)? == 43;
+    // Entry count, entry and offset sizes
+    let (count_size, entry_size, offset_size) = if big_tiff { (8, 20, 8) } else { (2, 12, 4) };
+    let mut offset = read(if big_tiff { 8 } else { 4 }, offset_size)?;
+    let mut offsets = Vec::new();
+    let mut seen = HashSet::new();
+    while offset != 0 && offsets.len() < MAX_TIFF_PAGES && seen.insert(offset) {
+        offsets.push(offset);
+        let entry_count = read(offset, count_size)?;
+        let next = entry_count
+            .checked_mul(entry_size)
+            .and_then(|size| size.checked_add(offset.checked_add(count_size as u64)?))
+            .ok_or_else(truncated)?;
+        offset = read(next, offset_size)?;
+    }
+    Ok(offsets)
+}
+
+// Reader over a TIFF file at `start` whose header points at another image file directory, so
+// that the image crate decodes that page as if it were the first one
+struct TiffPageReader<R> {
+    inner: R,
+    start: u64,
+    header: Vec<u8>,
+    position: u64,
+}
+
+impl<R: Read + Seek> TiffPageReader<R> {
+    fn new(mut inner: R, start: u64, ifd_offset: u64) -> std::io::Result<Self> {
+        let mut header = vec![0u8; 8];
+        inner.seek(SeekFrom::Start(start))?;
+        inner.read_exact(&mut header)?;
+        let little_endian = header[0] == b'I';
+        if header[2..4] == [0, 43] || header[2..4] == [43, 0] {
+            header.resize(16, 0);
+            inner.read_exact(&mut header[8..])?;
+            header[8..].copy_from_slice(&if little_endian {
+                ifd_offset.to_le_bytes()
+            } else {
+                ifd_offset.to_be_bytes()
+            });
+        } else {
+            let offset = ifd_offset as u32;
+            header[4..].copy_from_slice(&if little_endian {
+                offset.to_le_bytes()
+            } else {
+                offset.to_be_bytes()
+            });
+        }
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            inner,
+            start,
+            header,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for TiffPageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let header_len = self.header.len() as u64;
+        if self.position >= header_len {
+            let read = self.inner.read(buf)?;
+            self.position += read as u64;
+            return Ok(read);
+        }
+        let header = &self.header[self.position as usize..];
+        let read = header.len().min(buf.len());
+        buf[..read].copy_from_slice(&header[..read]);
+        self.position += read as u64;
+        self.inner
+            .seek(SeekFrom::Start(self.start + self.position))?;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for TiffPageReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.start + offset),
+            pos => pos,
+        };
+        let position = self.inner.seek(pos)?;
+        self.position = position.checked_sub(self.start).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek before TIFF start")
+        })?;
+        Ok(self.position)
+    }
+}
+
+fn tiff_page_decoder<R: Read + Seek>(
+    reader: R,
+    start: u64,
+    ifd_offset: u64,
+) -> Result<TiffDecoder<BufReader<TiffPageReader<R>>>, LoadError> {
+    let reader = TiffPageReader::new(reader, start, ifd_offset)?;
+    TiffDecoder::new(BufReader::new(reader)).map_err(LoadError::from_header_error)
+}
+
+// Pixels of the page and the ICC profile from its own image file directory
+fn decode_tiff_page<R: Read + Seek>(
+    reader: R,
+    ifd_offset: u64,
+    limits: &LoadLimits,
+) -> Result<(DynamicImage, Option<Vec<u8>>), LoadError> {
+    let mut decoder = tiff_page_decoder(reader, 0, ifd_offset)?;
+    limits.check(decoder.dimensions(), decoder.total_bytes())?;
+    let icc_profile = decoder.icc_profile().unwrap_or(None);
+    Ok((DynamicImage::from_decoder(decoder)?, icc_profile))
+}
+
+// File the pages of a multi-page TIFF are decoded from when they are shown. Images that didn't
+// come from a file keep the encoded data.
+enum TiffPageSource {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
+
+// Image file directories of a multi-page TIFF, matching `LoadedImage::subimages` surfaces
+pub struct TiffPages {
+    source: TiffPageSource,
+    ifd_offsets: Vec<u64>,
+}
+
+impl TiffPages {
+    fn decode(
+        &self,
+        page: usize,
+        options: &LoadOptions,
+    ) -> Result<(DynamicImage, Option<Vec<u8>>), LoadError> {
+        let ifd_offset = *self
+            .ifd_offsets
+            .get(page)
+            .ok_or_else(|| LoadError::CorruptMetadata(format!("No TIFF page {}", page + 1)))?;
+        match &self.source {
+            TiffPageSource::File(path) => {
+                let reader = BufReader::new(CancellableReader {
+                    inner: File::open(path)?,
+                    cancel: &options.cancel,
+                });
+                let page = decode_tiff_page(reader, ifd_offset, &options.limits);
+                options.cancel.check()?;
+                page
+            }
+            TiffPageSource::Memory(data) => {
+                decode_tiff_page(Cursor::new(&data[..]), ifd_offset, &options.limits)
+            }
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        match &self.source {
+            TiffPageSource::File(_) => 0,
+            TiffPageSource::Memory(data) => data.len(),
+        }
+    }
+}
+
+// Lists the pages of a multi-page TIFF with their dimensions, the pixels of the pages after the
+// first one are decoded by `LoadedImage::decode_page` when they are shown. Pages whose directory
+// can't be read are left out of the numbering.
+fn load_tiff_pages<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    path: Option<&Path>,
+    first_page: &DynamicImage,
+) -> anyhow::Result<Option<(SubimageSet, TiffPages)>> {
+    let offsets = tiff_ifd_offsets(reader, start)?;
+    if offsets.len() < 2 {
+        return Ok(None);
+    }
+
+    let mut surfaces = vec![SubimageInfo {
+        layer: 0,
+        face: None,
+        mip_level: 0,
+        dim: (first_page.width(), first_page.height()),
+    }];
+    let mut ifd_offsets = vec![offsets[0]];
+    for (page, &offset) in offsets.iter().enumerate().skip(1) {
+        match tiff_page_decoder(&mut *reader, start, offset) {
+            Ok(decoder) => {
+                surfaces.push(SubimageInfo {
+                    layer: surfaces.len() as u32,
+                    face: None,
+                    mip_level: 0,
+                    dim: decoder.dimensions(),
+                });
+                ifd_offsets.push(offset);
+            }
+            Err(err) => warn!("Skipping TIFF page {}: {err}", page + 1),
+        }
+    }
+    if surfaces.len() < 2 {
+        return Ok(None);
+    }
+
+    let source = match path {
+        Some(path) if start == 0 => TiffPageSource::File(path.to_owned()),
+        _ => {
+            let mut data = Vec::new();
+            reader.seek(SeekFrom::Start(start))?;
+            reader.read_to_end(&mut data)?;
+            TiffPageSource::Memory(data)
+        }
+    };
+    let set = SubimageSet {
+        layout: SubimageLayout {
+            kind: SubimageKind::Pages,
+            layer_count: surfaces.len() as u32,
+            face_count: 1,
+            mip_count: 1,
+        },
+        surfaces,
+        images: Vec::new(),
+    };
+    Ok(Some((
+        set,
+        TiffPages {
+            source,
+            ifd_offsets,
+        },
+    )))
+}
+
+// Returns None for formats without animation support and for PNG and WebP files that aren't
+// animated. GIFs are always decoded as frames, so a still GIF is a single frame.
+fn load_animation<R: BufRead + Seek>(
+    reader: &mut R,
+    start: u64,
+    format: ImageFormat,
+    options: &LoadOptions,
+) -> Result<Option<FrameSequence>, LoadError> {
+    reader.seek(SeekFrom::Start(start))?;
+    let frames: Frames = match format {
+        ImageFormat::Gif => GifDecoder::new(reader)?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            decoder.apng()?.into_frames()
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(None),
+    };
+    let mut decoded = Vec::new();
+    let mut total_bytes = 0;
+    for frame in frames {
+        options.cancel.check()?;
+        let frame = frame?;
+        let buffer = frame.buffer();
+        total_bytes += buffer.as_raw().len() as u64;
+        options.limits.check(buffer.dimensions(), total_bytes)?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = Duration::from_secs_f64(numer as f64 / denom.max(1) as f64 / 1000.0);
+        decoded.push(AnimationFrame {
+            image: DynamicImage::ImageRgba8(frame.into_buffer()),
+            unmanaged_image: None,
+            delay: if delay < MIN_FRAME_DELAY {
+                DEFAULT_FRAME_DELAY
+            } else {
+                delay
+            },
+        });
+    }
+    if decoded.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(FrameSequence { frames: decoded }))
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ExifInfo {
     pub make: Option<String>,
     pub model: Option<String>,
@@ -45,6 +1598,8 @@ pub struct ExifInfo {
     pub lens_model: Option<String>,
     pub orientation: Option<u16>,
     pub gps_iso6709: Option<String>,
+    // Every decoded tag, for the metadata panel and --info output
+    pub tags: ExifTags,
 }
 
 #[derive(Debug, Clone)]
@@ -61,92 +1616,826 @@ pub struct IccInfo {
     pub manufacturer: String,
     pub model: String,
     pub rendering_intent: u32,
+    // Decoded tag table and structural problems of the profile
+    pub tags: IccTags,
 }
 
-fn parse_exif_info(path: &Path) -> Option<ExifInfo> {
-    let ms = MediaSource::file_path(path).ok()?;
-    if !ms.has_exif() {
-        return None;
-    }
-    let mut parser = MediaParser::new();
-    let iter: ExifIter = parser.parse(ms).ok()?;
-    let exif: Exif = iter.into();
-
-    let make = exif_string(&exif, ExifTag::Make);
-    let model = exif_string(&exif, ExifTag::Model);
-    let software = exif_string(&exif, ExifTag::Software);
-    let datetime = exif_value_string(&exif, ExifTag::ModifyDate);
-    let datetime_original = exif_value_string(&exif, ExifTag::DateTimeOriginal);
-    let lens_model = exif_string(&exif, ExifTag::LensModel);
-    let orientation = exif_u16(&exif, ExifTag::Orientation);
-    let gps_iso6709 = exif
-        .get_gps_info()
-        .ok()
-        .flatten()
-        .map(|gps| gps.format_iso6709());
-
+// Summary of an EXIF chunk as returned by the decoders, None if it can't be parsed
+pub fn parse_exif_info(exif: &[u8]) -> Option<ExifInfo> {
+    let tags = parse_exif_tags(exif)
+        .map_err(|err| warn!("Failed to parse EXIF tags: {err}"))
+        .ok()?;
+    let string = |ifd, tag| {
+        tags.get(ifd, tag)
+            .and_then(|e| e.value.as_str())
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+    };
+    // Offsets from UTC are stored in separate tags of the Exif directory
+    let datetime = |ifd, tag, offset_tag| {
+        let datetime = format_exif_datetime(&string(ifd, tag)?)?;
+        let offset = string(ExifIfd::Exif, offset_tag).unwrap_or_default();
+        Some(datetime + &offset)
+    };
     Some(ExifInfo {
-        make,
-        model,
-        software,
-        datetime,
-        datetime_original,
-        lens_model,
-        orientation,
-        gps_iso6709,
+        make: string(ExifIfd::Ifd0, 0x010F),
+        model: string(ExifIfd::Ifd0, 0x0110),
+        software: string(ExifIfd::Ifd0, 0x0131),
+        datetime: datetime(ExifIfd::Ifd0, 0x0132, 0x9010),
+        datetime_original: datetime(ExifIfd::Exif, 0x9003, 0x9011),
+        lens_model: string(ExifIfd::Exif, 0xA434),
+        orientation: tags
+            .get(ExifIfd::Ifd0, 0x0112)
+            .and_then(|e| e.value.as_u32())
+            .map(|o| o as u16),
+        gps_iso6709: format_gps_iso6709(&tags),
+        tags,
     })
 }
 
-fn exif_string(exif: &Exif, tag: ExifTag) -> Option<String> {
-    exif.get(tag)
-        .and_then(EntryValue::as_str)
-        .map(str::to_owned)
+// Reads only the headers of a file for its EXIF summary, e.g. to sort a folder by date taken
+pub fn read_exif_info(path: &Path) -> Option<ExifInfo> {
+    let mut decoder = ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let exif = decoder.exif_metadata().ok()??;
+    parse_exif_info(&exif)
 }
 
-fn exif_value_string(exif: &Exif, tag: ExifTag) -> Option<String> {
-    exif.get(tag).map(|v| v.to_string())
+// "2021:06:01 20:15:00" to "2021-06-01T20:15:00", None for blank and malformed dates
+fn format_exif_datetime(datetime: &str) -> Option<String> {
+    let bytes = datetime.as_bytes();
+    let valid = bytes.len() == 19
+        && bytes.iter().enumerate().all(|(i, &b)| match i {
+            4 | 7 => b == b':' || b == b'-',
+            10 => b == b' ' || b == b'T',
+            13 | 16 => b == b':',
+            _ => b.is_ascii_digit(),
+        });
+    if !valid || datetime.starts_with("0000") {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}T{}",
+        &datetime[0..4],
+        &datetime[5..7],
+        &datetime[8..10],
+        &datetime[11..]
+    ))
 }
 
-fn exif_u16(exif: &Exif, tag: ExifTag) -> Option<u16> {
-    exif.get(tag).and_then(EntryValue::as_u16)
+// Position as an ISO 6709 string such as "+46.94809+007.44744+540CRSWGS_84/"
+fn format_gps_iso6709(tags: &ExifTags) -> Option<String> {
+    let gps = |tag| tags.get(ExifIfd::Gps, tag).map(|e| &e.value);
+    let coordinate = |ref_tag, tag, negative: &str| {
+        let dms = gps(tag)?.to_f64s();
+        if dms.len() != 3 || dms.iter().any(|v| !v.is_finite()) {
+            return None;
+        }
+        let degrees = dms[0] + dms[1] / 60.0 + dms[2] / 3600.0;
+        let negative = gps(ref_tag).and_then(ExifValue::as_str) == Some(negative);
+        Some(if negative { -degrees } else { degrees })
+    };
+    let latitude = coordinate(1, 2, "S")?;
+    let longitude = coordinate(3, 4, "W")?;
+    let mut iso6709 = format!("{latitude:+09.5}{longitude:+010.5}");
+    if let Some(altitude) = gps(6).and_then(ExifValue::as_f64) {
+        // Altitude reference 1 is below sea level
+        let below = gps(5).and_then(ExifValue::as_u32) == Some(1);
+        let altitude = if below { -altitude } else { altitude };
+        let sign = if altitude < 0.0 { "-" } else { "+" };
+        iso6709 += &format!("{sign}{}CRSWGS_84", format_decimal(altitude.abs(), 3));
+    }
+    Some(iso6709 + "/")
 }
 
 fn parse_icc_info(icc: &[u8]) -> Option<IccInfo> {
-    let data = icc.to_vec();
-    let decoded = DecodedICCProfile::new(&data).ok()?;
+    let header = icc.get(0..128)?;
+    let u32_at = |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+    let u16_at = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+    let date: Vec<u16> = (0..6).map(|i| u16_at(24 + i * 2)).collect();
+    let created = if date.iter().all(|&v| v == 0) {
+        None
+    } else {
+        Some(format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            date[0], date[1], date[2], date[3], date[4], date[5]
+        ))
+    };
     Some(IccInfo {
-        size: decoded.length,
-        cmm_type: u32_to_tag(decoded.cmmid),
-        version: format_icc_version(decoded.version),
-        profile_class: u32_to_tag(decoded.device_class),
-        color_space: u32_to_tag(decoded.color_space),
-        pcs: u32_to_tag(decoded.pcs),
-        created: Some(decoded.create_date),
-        platform: u32_to_tag(decoded.platform),
-        flags: decoded.flags,
-        manufacturer: u32_to_tag(decoded.manufacturer),
-        model: u32_to_tag(decoded.model),
-        rendering_intent: decoded.rendering_intent,
+        size: u32_at(0),
+        cmm_type: signature_string(u32_at(4)),
+        version: format_icc_version(u32_at(8)),
+        profile_class: signature_string(u32_at(12)),
+        color_space: signature_string(u32_at(16)),
+        pcs: signature_string(u32_at(20)),
+        created,
+        platform: signature_string(u32_at(40)),
+        flags: u32_at(44),
+        manufacturer: signature_string(u32_at(48)),
+        model: signature_string(u32_at(52)),
+        rendering_intent: u32_at(64),
+        tags: parse_icc_tags(icc),
     })
 }
 
-fn u32_to_tag(tag: u32) -> String {
-    let bytes = tag.to_be_bytes();
-    let mut s = String::with_capacity(4);
-    for &b in &bytes {
-        let ch = if (0x20..=0x7E).contains(&b) {
-            b as char
-        } else {
-            '?'
-        };
-        s.push(ch);
-    }
-    s
-}
-
 fn format_icc_version(version_raw: u32) -> String {
     let major = (version_raw >> 24) & 0xFF;
     let minor = (version_raw >> 20) & 0x0F;
     let bugfix = (version_raw >> 16) & 0x0F;
     format!("{major}.{minor}.{bugfix}")
 }
+
+#[cfg(test)]
+fn make_gray_tiff(pages: &[(u32, u32, u8)]) -> Vec<u8> {
+    let mut data = b"II".to_vec();
+    data.extend_from_slice(&42u16.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    // Position of the offset that links to the next image file directory
+    let mut link = 4;
+    for &(width, height, value) in pages {
+        let strip_offset = data.len() as u32;
+        data.extend_from_slice(&vec![value; (width * height) as usize]);
+        let ifd_offset = data.len() as u32;
+        data[link..link + 4].copy_from_slice(&ifd_offset.to_le_bytes());
+        // Tag, type (3 = SHORT, 4 = LONG) and a single value
+        let entries: [(u16, u16, u32); 9] = [
+            (256, 4, width),
+            (257, 4, height),
+            (258, 3, 8),
+            (259, 3, 1),
+            (262, 3, 1),
+            (273, 4, strip_offset),
+            (277, 3, 1),
+            (278, 4, height),
+            (279, 4, width * height),
+        ];
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, ty, value) in entries.iter() {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&ty.to_le_bytes());
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        link = data.len();
+        data.extend_from_slice(&0u32.to_le_bytes());
+    }
+    data
+}
+
+#[test]
+fn test_tiff_pages() {
+    let data = make_gray_tiff(&[(4, 3, 10), (2, 5, 20), (3, 3, 30)]);
+    let offsets = tiff_ifd_offsets(&mut Cursor::new(&data), 0).unwrap();
+    assert_eq!(offsets.len(), 3);
+
+    // Only the first page is decoded up front, the others when they are shown
+    let options = LoadOptions::default();
+    let loaded = load_image_from_bytes(&data, &options).unwrap();
+    assert_eq!(loaded.format, Some(ImageFormat::Tiff));
+    assert_eq!((loaded.image.width(), loaded.image.height()), (4, 3));
+    let pages = loaded.subimages.as_ref().unwrap();
+    assert_eq!(pages.layout.kind, SubimageKind::Pages);
+    assert_eq!(pages.layout.layer_count, 3);
+    let dims: Vec<(u32, u32)> = pages.surfaces.iter().map(|s| s.dim).collect();
+    assert_eq!(dims, [(4, 3), (2, 5), (3, 3)]);
+    assert!(pages.images.is_empty());
+    let page = loaded.decode_page(1, &options).unwrap();
+    assert_eq!(page.to_luma8().get_pixel(1, 4).0, [20]);
+    let page = loaded.decode_page(2, &options).unwrap();
+    assert_eq!(page.to_luma8().get_pixel(2, 2).0, [30]);
+    assert!(loaded.decode_page(3, &options).is_err());
+
+    // Pages are converted with the ICC profile of their own directory
+    let icc = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+    let mut data = Vec::new();
+    {
+        let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut data)).unwrap();
+        for icc_profile in [None, Some(&icc), None] {
+            let mut page = encoder
+                .new_image::<tiff::encoder::colortype::RGB8>(1, 1)
+                .unwrap();
+            if let Some(icc) = icc_profile {
+                page.encoder()
+                    .write_tag(tiff::tags::Tag::Unknown(34675), &icc[..])
+                    .unwrap();
+            }
+            page.write_data(&[200, 60, 40]).unwrap();
+        }
+    }
+    let loaded = load_image_from_bytes(&data, &options).unwrap();
+    assert!(loaded.icc_profile.is_none());
+    let pixel = |page: usize| loaded.decode_page(page, &options).unwrap().to_rgb8()[(0, 0)].0;
+    // P3 colors are more saturated in sRGB
+    assert_ne!(pixel(1), [200, 60, 40]);
+    assert_eq!(pixel(2), [200, 60, 40]);
+
+    // Files are read again for each page instead of being kept in memory
+    let path = std::env::temp_dir().join(format!("imgv_pages_{}.tif", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    let loaded = load_image_with_metadata(&path, &options).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        loaded.tiff_pages.as_ref().unwrap().source,
+        TiffPageSource::File(_)
+    ));
+    assert!(matches!(
+        loaded.decode_page(1, &options),
+        Err(LoadError::Io(_))
+    ));
+
+    // A directory chain that loops back is cut off instead of repeating pages
+    let mut data = make_gray_tiff(&[(1, 1, 0), (1, 1, 0)]);
+    let first = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    let len = data.len();
+    data[len - 4..].copy_from_slice(&first.to_le_bytes());
+    let offsets = |data: &[u8]| tiff_ifd_offsets(&mut Cursor::new(data), 0);
+    assert_eq!(offsets(&data).unwrap().len(), 2);
+    assert!(offsets(&data[..len - 2]).is_err());
+    assert!(offsets(b"not a tiff").is_err());
+}
+
+#[test]
+fn test_load_from_reader() {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, RgbaImage};
+
+    // Animation frames are decoded in a second pass from the same reader, which doesn't have to
+    // start at the beginning of the stream
+    let mut data = b"prefix".to_vec();
+    {
+        let mut encoder = GifEncoder::new(&mut data);
+        for value in [50, 200] {
+            let frame = RgbaImage::from_pixel(3, 2, image::Rgba([value, 0, 0, 255]));
+            let delay = Delay::from_numer_denom_ms(40, 1);
+            encoder
+                .encode_frame(Frame::from_parts(frame, 0, 0, delay))
+                .unwrap();
+        }
+    }
+    let mut reader = Cursor::new(&data);
+    reader.set_position(6);
+    let loaded = load_image_from_reader(&mut reader, None, &LoadOptions::default(), None).unwrap();
+    assert_eq!(loaded.format, Some(ImageFormat::Gif));
+    let animation = loaded.animation.unwrap();
+    assert_eq!(animation.frames.len(), 2);
+    assert_eq!(animation.frame_delays(), [Duration::from_millis(40); 2]);
+    assert_eq!(
+        animation.frames[1].image.to_rgba8().get_pixel(2, 1).0,
+        [200, 0, 0, 255]
+    );
+    assert!(loaded.exif_info.is_none());
+
+    assert!(load_image_from_bytes(b"garbage", &LoadOptions::default()).is_err());
+    assert!(load_image_from_bytes(&[], &LoadOptions::default()).is_err());
+}
+
+#[test]
+fn test_animation_color_management() {
+    // APNG with a Display P3 profile, the frames keep their unmanaged pixels like still images
+    let icc = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+    let mut data = Vec::new();
+    {
+        let mut info = png::Info::with_size(2, 1);
+        info.color_type = png::ColorType::Rgba;
+        info.bit_depth = png::BitDepth::Eight;
+        info.icc_profile = Some(icc.into());
+        let mut encoder = png::Encoder::with_info(&mut data, info).unwrap();
+        encoder.set_animated(2, 0).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        for value in [200, 100] {
+            writer.set_frame_delay(40, 1000).unwrap();
+            writer
+                .write_image_data(&[value, 60, 40, 255, 0, value, 0, 255])
+                .unwrap();
+        }
+    }
+    let loaded = load_image_from_bytes(&data, &LoadOptions::default()).unwrap();
+    let animation = loaded.animation.unwrap();
+    assert_eq!(animation.frames.len(), 2);
+    // The still image is the first frame, not a separate decode
+    assert_eq!(
+        loaded.image.to_rgba8(),
+        animation.frames[0].image.to_rgba8()
+    );
+    for (frame, value) in animation.frames.iter().zip([200, 100]) {
+        let unmanaged = frame.unmanaged_image.as_ref().unwrap().to_rgba8();
+        assert_eq!(unmanaged.get_pixel(0, 0).0, [value, 60, 40, 255]);
+        // P3 colors are more saturated in sRGB
+        assert_ne!(
+            frame.image.to_rgba8().get_pixel(0, 0).0,
+            [value, 60, 40, 255]
+        );
+    }
+
+    // Still GIFs come out of the frame decoder as a single image without an animation
+    let mut data = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut data);
+        let frame = RgbaImage::from_pixel(3, 2, image::Rgba([10, 20, 30, 255]));
+        encoder.encode_frame(image::Frame::new(frame)).unwrap();
+    }
+    let loaded = load_image_from_bytes(&data, &LoadOptions::default()).unwrap();
+    assert!(loaded.animation.is_none());
+    assert_eq!(loaded.image.to_rgba8().get_pixel(2, 1).0, [10, 20, 30, 255]);
+}
+
+#[test]
+fn test_exif_info() {
+    use crate::exif::{make_test_exif, test_ascii, test_rationals, test_short, TestValue};
+
+    let exif = make_test_exif(
+        &[
+            vec![
+                (0x010F, test_ascii("Canon")),
+                (0x0112, test_short(8)),
+                (0x0132, test_ascii("2021:06:02 08:00:00")),
+                (0x8769, TestValue::Pointer(1)),
+                (0x8825, TestValue::Pointer(2)),
+            ],
+            vec![
+                (0x9003, test_ascii("2021:06:01 20:15:00")),
+                (0x9011, test_ascii("+02:00")),
+                (0xA434, test_ascii("EF 50mm  ")),
+            ],
+            vec![
+                (1, test_ascii("N")),
+                (2, test_rationals(&[(46, 1), (56, 1), (5304, 100)])),
+                (3, test_ascii("W")),
+                (4, test_rationals(&[(7, 1), (26, 1), (5077, 100)])),
+                (5, TestValue::Data(1, 1, vec![1])),
+                (6, test_rationals(&[(25, 2)])),
+            ],
+        ],
+        None,
+    );
+    let info = parse_exif_info(&exif).unwrap();
+    assert_eq!(info.make.as_deref(), Some("Canon"));
+    assert_eq!(info.model, None);
+    assert_eq!(info.orientation, Some(8));
+    assert_eq!(info.datetime.as_deref(), Some("2021-06-02T08:00:00"));
+    assert_eq!(
+        info.datetime_original.as_deref(),
+        Some("2021-06-01T20:15:00+02:00")
+    );
+    assert_eq!(info.lens_model.as_deref(), Some("EF 50mm"));
+    assert_eq!(
+        info.gps_iso6709.as_deref(),
+        Some("+46.94807-007.44744-12.5CRSWGS_84/")
+    );
+    assert_eq!(info.tags.entries.len(), 12);
+
+    assert_eq!(format_exif_datetime("0000:00:00 00:00:00"), None);
+    assert_eq!(format_exif_datetime("    :  :     :  :  "), None);
+    assert!(parse_exif_info(b"Exif\0\0garbage").is_none());
+}
+
+#[cfg(test)]
+fn encode_test_image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+    let image = image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 40) as u8, (y * 40) as u8, ((x ^ y) * 20) as u8])
+    });
+    let mut data = Vec::new();
+    image.write_to(&mut Cursor::new(&mut data), format).unwrap();
+    data
+}
+
+#[test]
+fn test_load_limits() {
+    // Headers claiming huge dimensions are rejected before any pixels are allocated
+    let mut bmp = encode_test_image(2, 2, ImageFormat::Bmp);
+    bmp[18..22].copy_from_slice(&60000i32.to_le_bytes());
+    bmp[22..26].copy_from_slice(&60000i32.to_le_bytes());
+    let result = load_image_from_bytes(&bmp, &LoadOptions::default());
+    assert!(matches!(result, Err(LoadError::LimitExceeded(_))));
+    let mut gif = encode_test_image(2, 2, ImageFormat::Gif);
+    gif[6..10].copy_from_slice(&[0xff; 4]);
+    let result = load_image_from_bytes(&gif, &LoadOptions::default());
+    assert!(matches!(result, Err(LoadError::LimitExceeded(_))));
+
+    let png = encode_test_image(8, 4, ImageFormat::Png);
+    let mut options = LoadOptions::default();
+    assert!(load_image_from_bytes(&png, &options).is_ok());
+    options.limits.max_width = 7;
+    let result = load_image_from_bytes(&png, &options);
+    assert!(matches!(result, Err(LoadError::LimitExceeded(_))));
+    options.limits = LoadLimits {
+        max_alloc: 8 * 4 * 3 - 1,
+        ..LoadLimits::default()
+    };
+    let result = load_image_from_bytes(&png, &options);
+    assert!(matches!(result, Err(LoadError::LimitExceeded(_))));
+
+    // Pages are checked when they are decoded, a page larger than the first one can fail
+    let tiff = make_gray_tiff(&[(1, 2, 10), (4, 3, 20)]);
+    options.limits.max_alloc = 4 * 3 - 1;
+    let loaded = load_image_from_bytes(&tiff, &options).unwrap();
+    let result = loaded.decode_page(1, &options);
+    assert!(matches!(result, Err(LoadError::LimitExceeded(_))));
+    options.limits.max_alloc = 4 * 3;
+    assert!(loaded.decode_page(1, &options).is_ok());
+
+    // Streams are read up to the limit
+    options.limits.max_alloc = 16;
+    let data = read_stream(&[7u8; 16][..], &options).unwrap();
+    assert_eq!(data.len(), 16);
+    let result = read_stream(&[7u8; 17][..], &options);
+    assert!(matches!(result, Err(LoadError::LimitExceeded(_))));
+    let token = CancelToken::default();
+    options.cancel = token.restart();
+    token.cancel();
+    let result = read_stream(&[7u8; 4][..], &options);
+    assert!(matches!(result, Err(LoadError::Cancelled)));
+}
+
+#[test]
+fn test_load_corrupt_files() {
+    use crate::dds::{make_dds_header, DDPF_FOURCC};
+
+    let options = LoadOptions::default();
+
+    let mut dds = make_dds_header(
+        (8, 8),
+        1,
+        [32, DDPF_FOURCC, u32::from_le_bytes(*b"DXT1"), 0, 0, 0, 0, 0],
+        0,
+    );
+    dds.extend((0..32).map(|i| i as u8 * 7));
+
+    // Rejected from the header, before the surfaces are decoded
+    let huge_dds = make_dds_header(
+        (u32::MAX, u32::MAX),
+        1,
+        [32, DDPF_FOURCC, u32::from_le_bytes(*b"DXT1"), 0, 0, 0, 0, 0],
+        0,
+    );
+    let err = load_image_from_bytes(&huge_dds, &options).err().unwrap();
+    assert_eq!(err.kind_name(), "limit_exceeded");
+    // Without reading the surfaces, which never end here
+    let mut endless = huge_dds.chain(std::io::repeat(0));
+    assert!(load_dds(&mut endless, None, &options.limits).is_err());
+
+    let samples = vec![
+        encode_test_image(5, 4, ImageFormat::Png),
+        encode_test_image(5, 4, ImageFormat::Gif),
+        encode_test_image(5, 4, ImageFormat::Bmp),
+        encode_test_image(5, 4, ImageFormat::Jpeg),
+        make_gray_tiff(&[(4, 3, 10), (2, 5, 20)]),
+        dds,
+    ];
+
+    // Truncated and bit flipped files may load or fail, but must not panic. Xorshift keeps the
+    // corruptions the same on every run.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next_random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for data in &samples {
+        assert!(load_image_from_bytes(data, &options).is_ok());
+        for len in 0..data.len() {
+            let _ = load_image_from_bytes(&data[..len], &options);
+        }
+        for _ in 0..200 {
+            let mut corrupt = data.clone();
+            for _ in 0..1 + next_random() % 4 {
+                let bit = (next_random() % (corrupt.len() as u64 * 8)) as usize;
+                corrupt[bit / 8] ^= 1 << (bit % 8);
+            }
+            let _ = load_image_from_bytes(&corrupt, &options);
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("imgv_corrupt_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("truncated.png");
+    std::fs::write(&path, &samples[0][..samples[0].len() - 16]).unwrap();
+    let loaded = load_image_with_metadata(&path, &options).unwrap();
+    assert!(loaded.incomplete.is_some());
+    std::fs::write(&path, &samples[0][..20]).unwrap();
+    assert!(load_image_with_metadata(&path, &options).is_err());
+    assert!(load_image_with_metadata(&dir.join("missing.png"), &options).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_load_errors() {
+    // Without recovery, so that truncated and corrupt files fail
+    let options = LoadOptions {
+        strict: true,
+        ..LoadOptions::default()
+    };
+    let kind = |data: &[u8]| match load_image_from_bytes(data, &options) {
+        Ok(_) => "ok",
+        Err(err) => err.kind_name(),
+    };
+    assert_eq!(kind(b"garbage"), "unsupported_format");
+
+    let png = encode_test_image(16, 16, ImageFormat::Png);
+    assert_eq!(kind(&png), "ok");
+    assert_eq!(kind(&png[..png.len() - 20]), "truncated");
+    assert_eq!(kind(&png[..20]), "truncated");
+    let gif = encode_test_image(16, 16, ImageFormat::Gif);
+    assert_eq!(kind(&gif[..gif.len() / 2]), "truncated");
+    let jpeg = encode_test_image(16, 16, ImageFormat::Jpeg);
+    assert_eq!(kind(&jpeg[..jpeg.len() / 4]), "truncated");
+
+    // The IHDR chunk fails its checksum while the decoder reads the headers, the IDAT chunk
+    // only when the pixels are decoded
+    let mut corrupt = png.clone();
+    corrupt[16] ^= 0x40;
+    assert_eq!(kind(&corrupt), "corrupt_metadata");
+    let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+    let mut corrupt = png.clone();
+    corrupt[idat + 8] ^= 0x40;
+    assert_eq!(kind(&corrupt), "corrupt_data");
+
+    let missing = std::env::temp_dir().join("imgv_missing_file.png");
+    let err = load_image_with_metadata(&missing, &options).err().unwrap();
+    assert!(matches!(err, LoadError::Io(_)));
+    assert!(err.to_string().starts_with("I/O error: "));
+}
+
+#[test]
+fn test_cancel_load() {
+    let path = std::env::temp_dir().join(format!("imgv_cancel_{}.png", std::process::id()));
+    std::fs::write(&path, encode_test_image(16, 16, ImageFormat::Png)).unwrap();
+    let token = CancelToken::default();
+    let options = LoadOptions {
+        cancel: token.restart(),
+        ..LoadOptions::default()
+    };
+    token.cancel();
+    let err = load_image_with_metadata(&path, &options).err().unwrap();
+    assert_eq!(err.kind_name(), "cancelled");
+    // A load that starts after the cancel isn't affected by it
+    let options = LoadOptions {
+        cancel: token.restart(),
+        ..options
+    };
+    assert!(load_image_with_metadata(&path, &options).is_ok());
+    std::fs::remove_file(&path).unwrap();
+
+    // Decoding stops at the next band of rows
+    let png = encode_test_image(16, 16, ImageFormat::Png);
+    let mut buffer = vec![0; 16 * 16 * 3];
+    let mut decoded_rows = 0;
+    let mut on_rows = |_: &[u8], rows: u32| {
+        decoded_rows = rows;
+        token.cancel();
+    };
+    let result = read_png_rows(&mut Cursor::new(&png), &options, &mut buffer, &mut on_rows);
+    assert!(matches!(result, Err(LoadError::Cancelled)));
+    assert_eq!(decoded_rows, 1);
+}
+
+#[test]
+fn test_partial_decode() {
+    let options = LoadOptions::default();
+    let strict = LoadOptions {
+        strict: true,
+        ..LoadOptions::default()
+    };
+    let is_missing = |incomplete: &IncompleteImage, x: u64, y: u64| {
+        incomplete.missing_range.contains(&(y * 32 + x))
+    };
+    let original = encode_test_image(32, 32, ImageFormat::Png);
+    let expected = image::load_from_memory(&original).unwrap().to_rgba8();
+
+    // Rows before the end of the data keep their pixels
+    for format in [ImageFormat::Png, ImageFormat::Gif] {
+        let data = encode_test_image(32, 32, format);
+        let truncated = &data[..data.len() * 2 / 3];
+        let loaded = load_image_from_bytes(truncated, &options).unwrap();
+        let incomplete = loaded.incomplete.unwrap();
+        assert!(incomplete.reason.starts_with("Truncated file"));
+        assert!(incomplete.missing_pixels > 0 && incomplete.missing_pixels < 32 * 32);
+        let image = loaded.image.to_rgba8();
+        if format == ImageFormat::Png {
+            assert_eq!(image.get_pixel(5, 1), expected.get_pixel(5, 1));
+        }
+        assert!(is_missing(&incomplete, 31, 31));
+        assert!(!is_missing(&incomplete, 31, 0));
+        assert_eq!(incomplete.missing_range.end, 32 * 32);
+        let err = load_image_from_bytes(truncated, &strict).err().unwrap();
+        assert!(matches!(err, LoadError::Truncated(_)));
+    }
+
+    // Bottom-up BMPs are missing their top rows
+    let bmp = encode_test_image(32, 32, ImageFormat::Bmp);
+    let loaded = load_image_from_bytes(&bmp[..bmp.len() / 2], &options).unwrap();
+    let image = loaded.image.to_rgba8();
+    let incomplete = loaded.incomplete.unwrap();
+    assert!(is_missing(&incomplete, 0, 0));
+    assert!(!is_missing(&incomplete, 7, 31));
+    assert_eq!(image.get_pixel(7, 31), expected.get_pixel(7, 31));
+    // The second decode buffer counts against the limits
+    let limited = LoadOptions {
+        limits: LoadLimits {
+            max_alloc: loaded.image.as_bytes().len() as u64 * 3 / 2,
+            ..LoadLimits::default()
+        },
+        ..LoadOptions::default()
+    };
+    assert!(load_image_from_bytes(&bmp, &limited).is_ok());
+    let result = load_image_from_bytes(&bmp[..bmp.len() / 2], &limited);
+    assert!(matches!(result, Err(LoadError::Truncated(_))));
+
+    // Decoded pixels are told apart from missing ones whatever their value
+    for value in [0x00, 0xA7, 0xFF] {
+        let rgb = image::RgbImage::from_pixel(32, 32, image::Rgb([value; 3]));
+        let mut data = Vec::new();
+        rgb.write_to(&mut Cursor::new(&mut data), ImageFormat::Bmp)
+            .unwrap();
+        let loaded = load_image_from_bytes(&data[..data.len() / 2], &options).unwrap();
+        let incomplete = loaded.incomplete.unwrap();
+        assert!(incomplete.missing_pixels > 0 && incomplete.missing_pixels < 32 * 32);
+        assert_eq!(incomplete.missing_range.start, 0);
+        assert!(!is_missing(&incomplete, 7, 31));
+        assert_eq!(loaded.image.to_rgb8().get_pixel(7, 31).0, [value; 3]);
+    }
+
+    // JPEG decoding succeeds without the end of the data, the end marker tells it's missing
+    let jpeg = encode_test_image(32, 32, ImageFormat::Jpeg);
+    let loaded = load_image_from_bytes(&jpeg, &options).unwrap();
+    assert!(loaded.incomplete.is_none());
+    let truncated = &jpeg[..jpeg.len() * 4 / 5];
+    let loaded = load_image_from_bytes(truncated, &options).unwrap();
+    let incomplete = loaded.incomplete.unwrap();
+    assert!(incomplete.missing_pixels > 0);
+    assert!(!is_missing(&incomplete, 0, 0));
+    assert!(is_missing(&incomplete, 31, 31));
+    assert!(load_image_from_bytes(truncated, &strict).is_err());
+    // Data appended after the end marker
+    let mut padded = jpeg.clone();
+    padded.extend(std::iter::repeat_n(0, 1000));
+    let loaded = load_image_from_bytes(&padded, &strict).unwrap();
+    assert!(loaded.incomplete.is_none());
+    // Other data after the end marker, the image doesn't end in gray
+    let mut trailer = jpeg.clone();
+    trailer.extend_from_slice(b"trailer data");
+    let loaded = load_image_from_bytes(&trailer, &strict).unwrap();
+    assert!(loaded.incomplete.is_none());
+    // Complete images that end in gray, with a trailer like the video of a motion photo
+    let gray_bottom = image::RgbImage::from_fn(32, 32, |x, y| match y {
+        0..=15 => image::Rgb([x as u8 * 8, 0, 255]),
+        _ => image::Rgb([128; 3]),
+    });
+    let mut motion_photo = Vec::new();
+    gray_bottom
+        .write_to(&mut Cursor::new(&mut motion_photo), ImageFormat::Jpeg)
+        .unwrap();
+    motion_photo.extend_from_slice(b"\0\0\0\x18ftypmp42\0\0\0\0mp42isom");
+    let loaded = load_image_from_bytes(&motion_photo, &strict).unwrap();
+    assert!(loaded.incomplete.is_none());
+    assert_eq!(loaded.image.to_rgb8().get_pixel(31, 31).0, [128; 3]);
+}
+
+#[test]
+fn test_progressive_decode() {
+    use crate::exif::{make_test_exif, test_short, TestValue};
+
+    let load = |data: &[u8], options: &LoadOptions| {
+        let mut previews = Vec::new();
+        let mut on_preview = |preview| previews.push(preview);
+        let loaded =
+            load_image_from_reader(&mut Cursor::new(data), None, options, Some(&mut on_preview));
+        (loaded, previews)
+    };
+    let options = LoadOptions {
+        preview_min_pixels: 0,
+        ..LoadOptions::default()
+    };
+
+    // Decoding row by row gives the same pixels as the image crate decoders
+    let png8 = encode_test_image(40, 30, ImageFormat::Png);
+    let rgb16 = ImageBuffer::from_fn(40, 30, |x, y| {
+        image::Rgb([x as u16 * 1000, y as u16 * 2000, 0x1234])
+    });
+    let mut png16 = Vec::new();
+    DynamicImage::ImageRgb16(rgb16)
+        .write_to(&mut Cursor::new(&mut png16), ImageFormat::Png)
+        .unwrap();
+    // Strips of 4 rows, the last one shorter
+    let mut striped_tiff = Vec::new();
+    {
+        let rgb = image::load_from_memory(&png8).unwrap().to_rgb8();
+        let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut striped_tiff)).unwrap();
+        let mut tiff_image = encoder
+            .new_image::<tiff::encoder::colortype::RGB8>(40, 30)
+            .unwrap();
+        tiff_image.rows_per_strip(4).unwrap();
+        tiff_image.write_data(rgb.as_raw()).unwrap();
+    }
+    let samples = vec![png8, png16, striped_tiff, make_gray_tiff(&[(7, 5, 90)])];
+    for data in &samples {
+        let expected = load_image_from_bytes(data, &LoadOptions::default()).unwrap();
+        let loaded = load(data, &options).0.unwrap();
+        assert_eq!(loaded.image.color(), expected.image.color());
+        assert_eq!(loaded.image.as_bytes(), expected.image.as_bytes());
+        assert!(loaded.incomplete.is_none());
+        for len in 0..data.len() {
+            let _ = load(&data[..len], &options);
+        }
+    }
+
+    // The rows decoded before the end of the data are kept
+    let png = &samples[0];
+    let expected = image::load_from_memory(png).unwrap().to_rgba8();
+    let truncated = &png[..png.len() * 2 / 3];
+    let loaded = load(truncated, &options).0.unwrap();
+    let incomplete = loaded.incomplete.unwrap();
+    assert!(incomplete.reason.starts_with("Truncated file"));
+    // Whole rows are missing, up to the end of the image
+    assert_eq!(incomplete.missing_range.start % 40, 0);
+    assert_eq!(incomplete.missing_range.end, 40 * 30);
+    let missing_rows = 30 - incomplete.missing_range.start / 40;
+    assert_eq!(incomplete.missing_pixels, missing_rows * 40);
+    let image = loaded.image.to_rgba8();
+    assert_eq!(image.get_pixel(5, 1), expected.get_pixel(5, 1));
+    let strict = LoadOptions {
+        strict: true,
+        ..options.clone()
+    };
+    assert!(matches!(
+        load(truncated, &strict).0,
+        Err(LoadError::Truncated(_))
+    ));
+
+    // The EXIF thumbnail is shown before any rows are decoded, here the single black pixel of a
+    // 1x1 JPEG stretched over a JPEG that isn't black
+    let thumbnail = encode_test_image(1, 1, ImageFormat::Jpeg);
+    let ifds = |offset: u32| {
+        vec![
+            vec![(0x0112, test_short(6))],
+            vec![
+                (0x0201, TestValue::Data(4, 1, offset.to_le_bytes().to_vec())),
+                (
+                    0x0202,
+                    TestValue::Data(4, 1, (thumbnail.len() as u32).to_le_bytes().to_vec()),
+                ),
+            ],
+        ]
+    };
+    let exif_len = make_test_exif(&ifds(0), Some(1)).len();
+    let mut exif = make_test_exif(&ifds(exif_len as u32), Some(1));
+    exif.extend_from_slice(&thumbnail);
+    let thumbnail_pixel = *image::load_from_memory(&thumbnail)
+        .unwrap()
+        .to_rgba8()
+        .get_pixel(0, 0);
+    let jpeg = encode_test_image(40, 30, ImageFormat::Jpeg);
+    let mut data = jpeg[..2].to_vec();
+    data.extend_from_slice(&[0xFF, 0xE1]);
+    data.extend_from_slice(&(exif.len() as u16 + 8).to_be_bytes());
+    data.extend_from_slice(b"Exif\0\0");
+    data.extend_from_slice(&exif);
+    data.extend_from_slice(&jpeg[2..]);
+    let (loaded, previews) = load(&data, &options);
+    let image = loaded.unwrap().image;
+    assert_eq!((image.width(), image.height()), (40, 30));
+    assert_eq!(previews.len(), 1);
+    assert_eq!(previews[0].full_dim, (40, 30));
+    assert_eq!(previews[0].orientation, Some(Orientation::Rotate90));
+    let preview = previews[0].image.to_rgba8();
+    assert_eq!(preview.dimensions(), (40, 30));
+    assert_eq!(*preview.get_pixel(20, 15), thumbnail_pixel);
+    assert_ne!(*image.to_rgba8().get_pixel(20, 15), thumbnail_pixel);
+
+    // Small images don't publish previews
+    assert!(load(&data, &LoadOptions::default()).1.is_empty());
+}
+
+#[test]
+fn test_preview_builder() {
+    assert_eq!(preview_size((40, 30)), (40, 30));
+    assert_eq!(preview_size((20000, 10000)), (1024, 512));
+    assert_eq!(preview_size((100, 50000)), (2, 1024));
+
+    // Preview rows sample the rows at their centers, 1, 5 and 8
+    let (width, height) = (3000, 10);
+    let buffer: Vec<u8> = (0..height)
+        .flat_map(|y| vec![y as u8 * 20; width])
+        .collect();
+    let mut builder = PreviewBuilder::new((width as u32, height as u32), None, None);
+    builder.add_rows(&buffer, ColorType::L8, 6);
+    let preview = builder
+        .build(None, &OutputProfile::default())
+        .image
+        .to_rgba8();
+    assert_eq!(preview.dimensions(), (1024, 3));
+    assert_eq!(preview.get_pixel(0, 0).0, [20, 20, 20, 255]);
+    assert_eq!(preview.get_pixel(1023, 1).0, [100, 100, 100, 255]);
+    assert_eq!(*preview.get_pixel(500, 2), PREVIEW_BACKGROUND);
+    builder.add_rows(&buffer, ColorType::L8, 10);
+    let preview = builder
+        .build(None, &OutputProfile::default())
+        .image
+        .to_rgba8();
+    assert_eq!(preview.get_pixel(500, 2).0, [160, 160, 160, 255]);
+}
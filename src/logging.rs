@@ -4,9 +4,10 @@ use log;
 use std::ptr::null_mut;
 use winapi::um::fileapi::{CreateFileW, OPEN_EXISTING};
 use winapi::um::handleapi::INVALID_HANDLE_VALUE;
-use winapi::um::processenv::SetStdHandle;
+use winapi::um::processenv::{GetStdHandle, SetStdHandle};
 use winapi::um::winbase::{STD_ERROR_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE};
 use winapi::um::consoleapi::AllocConsole;
+use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
 use winapi::um::winnt::{FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE};
 
 pub fn init_logging(verbose: bool) {
@@ -34,34 +35,51 @@ pub fn maybe_alloc_console(requested: bool) {
         if AllocConsole() == 0 {
             return;
         }
+        redirect_std_handles();
+    }
+}
 
-        let conout = to_wide_string("CONOUT$");
-        let conin = to_wide_string("CONIN$");
-        let out_handle = CreateFileW(
-            conout.as_ptr(),
-            GENERIC_READ | GENERIC_WRITE,
-            FILE_SHARE_READ | FILE_SHARE_WRITE,
-            null_mut(),
-            OPEN_EXISTING,
-            FILE_ATTRIBUTE_NORMAL,
-            null_mut(),
-        );
-        if out_handle != INVALID_HANDLE_VALUE {
-            let _ = SetStdHandle(STD_OUTPUT_HANDLE, out_handle);
-            let _ = SetStdHandle(STD_ERROR_HANDLE, out_handle);
+// Release builds use the windows subsystem, so output of command line modes only reaches the
+// terminal after attaching to the console of the parent process. Redirected output is left alone.
+pub fn attach_parent_console() {
+    unsafe {
+        if !GetStdHandle(STD_OUTPUT_HANDLE).is_null() {
+            return;
         }
-
-        let in_handle = CreateFileW(
-            conin.as_ptr(),
-            GENERIC_READ,
-            FILE_SHARE_READ | FILE_SHARE_WRITE,
-            null_mut(),
-            OPEN_EXISTING,
-            FILE_ATTRIBUTE_NORMAL,
-            null_mut(),
-        );
-        if in_handle != INVALID_HANDLE_VALUE {
-            let _ = SetStdHandle(STD_INPUT_HANDLE, in_handle);
+        if AttachConsole(ATTACH_PARENT_PROCESS) == 0 {
+            return;
         }
+        redirect_std_handles();
+    }
+}
+
+unsafe fn redirect_std_handles() {
+    let conout = to_wide_string("CONOUT$");
+    let conin = to_wide_string("CONIN$");
+    let out_handle = CreateFileW(
+        conout.as_ptr(),
+        GENERIC_READ | GENERIC_WRITE,
+        FILE_SHARE_READ | FILE_SHARE_WRITE,
+        null_mut(),
+        OPEN_EXISTING,
+        FILE_ATTRIBUTE_NORMAL,
+        null_mut(),
+    );
+    if out_handle != INVALID_HANDLE_VALUE {
+        let _ = SetStdHandle(STD_OUTPUT_HANDLE, out_handle);
+        let _ = SetStdHandle(STD_ERROR_HANDLE, out_handle);
+    }
+
+    let in_handle = CreateFileW(
+        conin.as_ptr(),
+        GENERIC_READ,
+        FILE_SHARE_READ | FILE_SHARE_WRITE,
+        null_mut(),
+        OPEN_EXISTING,
+        FILE_ATTRIBUTE_NORMAL,
+        null_mut(),
+    );
+    if in_handle != INVALID_HANDLE_VALUE {
+        let _ = SetStdHandle(STD_INPUT_HANDLE, in_handle);
     }
 }
@@ -5,9 +5,11 @@
 use cgmath::{assert_ulps_eq, prelude::*};
 use com_ptr::{hresult, ComPtr};
 use log::{debug, error, info, warn};
+use std::collections::VecDeque;
 use std::ffi::OsString;
 use std::os::windows::ffi::OsStringExt;
 use std::ptr::null_mut;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{path::Path, path::PathBuf};
 use winapi::ctypes::c_void;
@@ -30,6 +32,7 @@ use winapi::um::winuser::*;
 use winapi::Interface;
 
 use display_info::DisplayInfo;
+use image::{DynamicImage, GenericImageView};
 
 mod math;
 use math::*;
@@ -44,22 +47,158 @@ mod clipboard;
 use clipboard::*;
 
 mod logging;
-use logging::{init_logging, maybe_alloc_console};
+use logging::{attach_parent_console, init_logging, maybe_alloc_console};
 
 mod window;
 use window::*;
 
 mod loader;
-use loader::load_image_with_metadata;
+use loader::{
+    is_stdin_path, load_image_with_metadata, load_image_with_preview, CancelToken, ImagePreview,
+    LoadError, LoadOptions, LoadedImage,
+};
+
+mod color;
+use color::OutputProfile;
+
+mod orientation;
+use orientation::ViewOrientation;
+
+mod upload;
+use upload::UploadFormat;
+
+mod tonemap;
+use tonemap::DisplaySettings;
+
+mod animation;
+use animation::AnimationPlayer;
 
 mod browse;
-use browse::{get_next_file, StepDirection};
+use browse::{read_dates_taken, DirectoryIndex, SortOrder, StepDirection};
+
+mod dds;
+
+mod subimage;
+use subimage::SubimageNavigator;
+
+mod cache;
+use cache::{ImageCache, LoadQueue, LoadRequest};
+
+mod info;
+use info::{load_error_panel_text, metadata_panel_text, print_image_info, InfoFormat};
+
+mod exif;
+
+mod grid;
+use grid::PixelGrid;
+
+mod icc;
+use icc::format_xyz;
+
+mod inspect;
+use inspect::{displayed_srgb, window_to_pixel, CopyFormat, PixelValue};
+
+mod panel;
+use panel::MetadataPanel;
+
+mod placeholder;
+use placeholder::error_placeholder_image;
+
+mod mips;
+use mips::{ImageMips, LoadedMips};
+
+mod resample;
+use resample::MagFilter;
+
+mod tiles;
+
+mod xmp;
 
 const WINDOW_MIN_WIDTH: i32 = 320;
 const WINDOW_MIN_HEIGHT: i32 = 240;
 
+// Number of files skipped by PageUp and PageDown
+const BROWSE_JUMP_COUNT: usize = 10;
+
+const DEFAULT_CACHE_BUDGET_MB: usize = 1024;
+// Number of files decoded ahead in each direction of the browse order
+const DEFAULT_PREFETCH_COUNT: usize = 2;
+// Number of files whose date taken is read before the folder is sorted again
+const DATE_TAKEN_BATCH_SIZE: usize = 64;
+
+struct LoadResult {
+    image: Result<std::sync::Arc<LoadedImage>, LoadError>,
+    path: PathBuf,
+    load_begin_time: Instant,
+    from_cache: bool,
+    cache_hit_rate: f32,
+}
+
+enum LoaderMessage {
+    // Scaled down stand-in for a large image that is still decoding
+    Preview(PathBuf, ImagePreview, ImageMips),
+    Loaded(LoadResult),
+    // Page of a multi-page TIFF, decoded when it was first shown
+    Page(
+        Arc<LoadedImage>,
+        usize,
+        Result<(DynamicImage, ImageMips), LoadError>,
+    ),
+    // EXIF dates of browsed files, for sorting by date taken
+    DatesTaken(Vec<(PathBuf, Option<String>)>),
+}
+
+// Cached images are invalidated when the file is modified
+type ImageCacheKey = (PathBuf, Option<std::time::SystemTime>);
+
+fn image_cache_key(path: &Path) -> ImageCacheKey {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    (path.to_owned(), modified)
+}
+
+// Shown in the window title
+fn display_name(path: &Path) -> String {
+    if is_stdin_path(path) {
+        "stdin".to_owned()
+    } else {
+        path.to_string_lossy().into_owned()
+    }
+}
+
+// Dates taken are read on a background thread, the folder is sorted again when they arrive
+fn request_dates_taken(
+    directory: &mut DirectoryIndex,
+    date_req_tx: &std::sync::mpsc::Sender<Vec<PathBuf>>,
+) {
+    let paths = directory.take_missing_dates();
+    if !paths.is_empty() {
+        date_req_tx.send(paths).unwrap();
+    }
+}
+
+// The decode that is running on the loader thread is for an image or page the user has moved on
+// from, so it is cancelled before the new image is queued. Previews are only shown while their
+// path is the `requested_path`.
+fn request_display(
+    load_req_tx: &std::sync::mpsc::Sender<LoadRequest>,
+    cancel: &CancelToken,
+    requested_path: &std::cell::RefCell<Option<PathBuf>>,
+    path: PathBuf,
+) {
+    cancel.cancel();
+    *requested_path.borrow_mut() = Some(path.clone());
+    load_req_tx.send(LoadRequest::Display(path)).unwrap();
+}
+
+fn queue_load_request(queue: &mut LoadQueue, request: LoadRequest) {
+    if let Some(path) = queue.push(request) {
+        info!("Skipped loading image {:?}", path);
+    }
+}
+
 struct WindowCreatedData {
     hwnd: HWND,
+    metadata_panel: Option<MetadataPanel>,
 }
 
 struct NativeMessageData {
@@ -84,12 +223,15 @@ unsafe impl std::marker::Send for WindowCreatedData {}
 
 struct Window {
     message_rx: std::sync::mpsc::Receiver<WindowMessages>,
+    // Sent by the window procedure while the window was being created
+    pending_messages: VecDeque<WindowMessages>,
     hwnd: HWND,
     window_style: u32,
     window_rect: RECT,
     windowed_client_rect: RECT,
     window_dim: (i32, i32),
     full_screen: bool,
+    metadata_panel: Option<MetadataPanel>,
 }
 
 struct WindowThreadState {
@@ -111,12 +253,7 @@ unsafe extern "system" fn window_proc(
             let create_struct = lparam as *mut winapi::um::winuser::CREATESTRUCTW;
             let window_state_ptr =
                 create_struct.as_ref().unwrap().lpCreateParams as *mut WindowThreadState;
-            let window_state: &mut WindowThreadState = window_state_ptr.as_mut().unwrap();
             SetWindowLongPtrW(hwnd, GWLP_USERDATA, window_state_ptr as isize);
-            window_state
-                .message_tx
-                .send(WindowMessages::WindowCreated(WindowCreatedData { hwnd }))
-                .unwrap();
         }
         WM_DESTROY => {
             let window_state = window_state.unwrap();
@@ -245,6 +382,15 @@ impl Window {
 
                     winapi::um::shellapi::DragAcceptFiles(hwnd, 1);
 
+                    let metadata_panel = MetadataPanel::new(hwnd);
+                    window_state
+                        .message_tx
+                        .send(WindowMessages::WindowCreated(WindowCreatedData {
+                            hwnd,
+                            metadata_panel,
+                        }))
+                        .unwrap();
+
                     // Delay showing this window until D3D is ready to draw something
                     // ShowWindow(hwnd, SW_SHOW);
 
@@ -269,15 +415,28 @@ impl Window {
             })
             .unwrap();
 
-        if let WindowMessages::WindowCreated(data) = channel_receiver.recv().unwrap() {
+        // Messages such as WM_SIZE arrive while CreateWindowExW runs, they are handled after
+        // the window is set up
+        let mut pending_messages = VecDeque::new();
+        while let Ok(message) = channel_receiver.recv() {
+            let data = match message {
+                WindowMessages::WindowCreated(data) => data,
+                WindowMessages::WindowClosed => break,
+                message => {
+                    pending_messages.push_back(message);
+                    continue;
+                }
+            };
             return Ok(Window {
                 message_rx: channel_receiver,
+                pending_messages,
                 hwnd: data.hwnd,
                 window_style,
                 window_rect: get_window_rect_absolute(data.hwnd),
                 windowed_client_rect: get_client_rect(data.hwnd),
                 window_dim,
                 full_screen: false,
+                metadata_panel: data.metadata_panel,
             });
         }
 
@@ -394,11 +553,23 @@ impl Window {
     }
 }
 
-fn process_window_messages(window: &Window, should_block: bool) -> Option<WindowMessages> {
+fn process_window_messages(
+    window: &mut Window,
+    should_block: bool,
+    wake_time: Option<Instant>,
+) -> Option<WindowMessages> {
     profiling::scope!("RcvWindowMessages");
+    if let Some(x) = window.pending_messages.pop_front() {
+        return Some(x);
+    }
     if should_block {
         profiling::scope!("Block");
-        if let Ok(x) = window.message_rx.recv() {
+        if let Some(wake_time) = wake_time {
+            let timeout = wake_time.saturating_duration_since(Instant::now());
+            if let Ok(x) = window.message_rx.recv_timeout(timeout) {
+                return Some(x);
+            }
+        } else if let Ok(x) = window.message_rx.recv() {
             return Some(x);
         }
     } else if let Ok(x) = window.message_rx.try_recv() {
@@ -417,8 +588,37 @@ fn to_milliseconds(t: Duration) -> f32 {
     t.as_secs_f32() * 1000.0
 }
 
+// Pages of multi-page TIFFs are decoded on the loader thread when they are first shown
+enum PageState {
+    Loading,
+    Loaded(DynamicImage),
+    Failed(String),
+}
+
 struct ViewerState {
+    // Decoded pixels of the displayed image, read by the pixel inspector
+    loaded_image: Option<Arc<LoadedImage>>,
     texture: Option<Texture>,
+    unmanaged_texture: Option<Texture>,
+    show_unmanaged_colors: bool,
+    frame_textures: Vec<Texture>,
+    // Frames before color management, empty when no conversion was applied
+    unmanaged_frame_textures: Vec<Texture>,
+    animation: Option<AnimationPlayer>,
+    // Textures of the pages or texture surfaces after the first one, which is `texture`. TIFF
+    // pages are None until they are decoded.
+    subimage_textures: Vec<Option<Texture>>,
+    // Decoding state of the TIFF pages after the first one
+    pages: Vec<PageState>,
+    subimages: Option<SubimageNavigator>,
+    image_name: String,
+    // Reason the current file failed to load, while its placeholder is shown
+    load_error: Option<String>,
+    // Only part of the image could be decoded, the rest is hatched
+    incomplete: bool,
+    // Dimensions of the full image while `texture` holds a preview of it
+    preview_dim: Option<(u32, u32)>,
+    is_scrubbing: bool,
     frame_number: u32,
     is_resizing: bool,
     is_dragging: bool,
@@ -427,12 +627,35 @@ struct ViewerState {
     viewport_dim: float2,
     image_dim: float2,
     xfm_window_to_image: Transform2D,
+    orientation: ViewOrientation,
+    show_raw_orientation: bool,
+    display: DisplaySettings,
+    mag_filter: MagFilter,
+    pixel_grid: PixelGrid,
+    inspector: bool,
+    // Pixel under the cursor shown in the title while the inspector is enabled
+    inspector_text: Option<String>,
+    copy_format: CopyFormat,
 }
 
 impl ViewerState {
     fn new() -> Self {
         Self {
+            loaded_image: None,
             texture: None,
+            unmanaged_texture: None,
+            show_unmanaged_colors: false,
+            frame_textures: Vec::new(),
+            unmanaged_frame_textures: Vec::new(),
+            animation: None,
+            subimage_textures: Vec::new(),
+            pages: Vec::new(),
+            subimages: None,
+            image_name: String::new(),
+            load_error: None,
+            incomplete: false,
+            preview_dim: None,
+            is_scrubbing: false,
             frame_number: 0,
             is_resizing: false,
             is_dragging: false,
@@ -441,6 +664,14 @@ impl ViewerState {
             viewport_dim: FLOAT2_ZERO,
             image_dim: FLOAT2_ZERO,
             xfm_window_to_image: Transform2D::new_identity(),
+            orientation: ViewOrientation::new_identity(),
+            show_raw_orientation: false,
+            display: DisplaySettings::new_default(),
+            mag_filter: MagFilter::Nearest,
+            pixel_grid: PixelGrid::new_default(),
+            inspector: false,
+            inspector_text: None,
+            copy_format: CopyFormat::Hex,
         }
     }
 
@@ -448,6 +679,240 @@ impl ViewerState {
         self.xfm_window_to_image = Transform2D::new_identity();
         self.xfm_window_to_image.offset = 0.5 * self.image_dim - 0.5 * self.viewport_dim;
     }
+
+    // Rotates or mirrors the view on screen around the cursor
+    fn apply_view_dihedral(&mut self, dihedral: Dihedral2D) {
+        let xfm_window = Transform2D::new_dihedral_around(dihedral.inverse(), self.mouse_pos);
+        self.xfm_window_to_image = xfm_window.concatenate(self.xfm_window_to_image);
+        self.is_dragging = false;
+    }
+
+    fn step_animation(&mut self, delta: i32) {
+        if let Some(animation) = &mut self.animation {
+            animation.step(delta);
+            info!(
+                "Frame {}/{}",
+                animation.current_frame() + 1,
+                animation.frame_count()
+            );
+        }
+    }
+
+    // Horizontal mouse position across the window selects the frame
+    fn scrub_animation(&mut self, window_width: i32) {
+        if let Some(animation) = &mut self.animation {
+            animation.seek(self.mouse_pos.x / window_width.max(1) as f32);
+        }
+    }
+
+    fn displayed_texture(&self) -> Option<&Texture> {
+        if let Some(animation) = &self.animation {
+            let frame = animation.current_frame();
+            if self.show_unmanaged_colors && !self.unmanaged_frame_textures.is_empty() {
+                return self.unmanaged_frame_textures.get(frame);
+            }
+            return self.frame_textures.get(frame);
+        }
+        if let Some(subimages) = &self.subimages {
+            if subimages.current_index() > 0 {
+                return self
+                    .subimage_textures
+                    .get(subimages.current_index() - 1)
+                    .and_then(Option::as_ref);
+            }
+        }
+        if self.show_unmanaged_colors && self.unmanaged_texture.is_some() {
+            self.unmanaged_texture.as_ref()
+        } else {
+            self.texture.as_ref()
+        }
+    }
+
+    // Decoded pixels of `displayed_texture`, as (source, displayed). The source is before color
+    // management, the displayed image is what was uploaded for the current view.
+    fn displayed_pixels(&self) -> Option<(&DynamicImage, &DynamicImage)> {
+        let img = self.loaded_image.as_ref()?;
+        if let (Some(animation), Some(frames)) = (&self.animation, &img.animation) {
+            let frame = frames.frames.get(animation.current_frame())?;
+            let source = frame.unmanaged_image.as_ref().unwrap_or(&frame.image);
+            if self.show_unmanaged_colors {
+                return Some((source, source));
+            }
+            return Some((source, &frame.image));
+        }
+        if let (Some(subimages), Some(set)) = (&self.subimages, &img.subimages) {
+            let index = subimages.current_index();
+            if index > 0 {
+                let image = match self.pages.get(index - 1) {
+                    Some(PageState::Loaded(image)) => image,
+                    _ => set.images.get(index - 1)?,
+                };
+                return Some((image, image));
+            }
+        }
+        let source = img.unmanaged_image.as_ref().unwrap_or(&img.image);
+        if self.show_unmanaged_colors {
+            Some((source, source))
+        } else {
+            Some((source, &img.image))
+        }
+    }
+
+    // Dimensions of the displayed image, also while a preview or a page that is still decoding
+    // stands in for it
+    fn displayed_dim(&self) -> Option<(u32, u32)> {
+        if let Some(dim) = self.preview_dim {
+            return Some(dim);
+        }
+        if let Some(subimages) = &self.subimages {
+            return Some(subimages.current().dim);
+        }
+        self.displayed_texture().map(|texture| texture.dim)
+    }
+
+    // Part of an incomplete image that the shader hatches, as the first missing pixel and the one
+    // after the last. Other pages and surfaces are complete.
+    fn missing_pixels(&self) -> float4 {
+        let first_subimage = self
+            .subimages
+            .as_ref()
+            .is_none_or(|subimages| subimages.current_index() == 0);
+        let img = match &self.loaded_image {
+            Some(img) if first_subimage => img,
+            _ => return FLOAT4_ZERO,
+        };
+        match &img.incomplete {
+            Some(incomplete) => {
+                let width = u64::from(img.image.width().max(1));
+                let range = &incomplete.missing_range;
+                float4::new(
+                    (range.start % width) as f32,
+                    (range.start / width) as f32,
+                    (range.end % width) as f32,
+                    (range.end / width) as f32,
+                )
+            }
+            None => FLOAT4_ZERO,
+        }
+    }
+
+    fn current_page(&self) -> Option<&PageState> {
+        let index = self.subimages.as_ref()?.current_index();
+        self.pages.get(index.checked_sub(1)?)
+    }
+
+    // Window to image transform the image is drawn with. Offsets are rounded at 1:1 and above so
+    // that image pixels line up with window pixels.
+    fn displayed_xfm_window_to_image(&self) -> Transform2D {
+        if self.xfm_window_to_image.scale.x >= 1.0 || self.xfm_window_to_image.scale.y >= 1.0 {
+            Transform2D {
+                offset: float2_round(self.xfm_window_to_image.offset),
+                ..self.xfm_window_to_image
+            }
+        } else {
+            self.xfm_window_to_image
+        }
+    }
+
+    // Oriented coordinates of the image pixel under the cursor with its source and displayed values
+    fn inspect_pixel(&self) -> Option<((u32, u32), PixelValue, PixelValue)> {
+        let (source, displayed) = self.displayed_pixels()?;
+        let (oriented, pixel) = window_to_pixel(
+            self.displayed_xfm_window_to_image(),
+            self.active_orientation(),
+            source.dimensions(),
+            self.mouse_pos,
+        )?;
+        Some((
+            oriented,
+            PixelValue::read(source, pixel.0, pixel.1),
+            PixelValue::read(displayed, pixel.0, pixel.1),
+        ))
+    }
+
+    // Returns true when the title needs to be updated
+    fn update_inspector(&mut self) -> bool {
+        let text = if self.inspector {
+            self.inspect_pixel().map(|(pixel, source, displayed)| {
+                let srgb = displayed_srgb(&displayed, &self.display);
+                format!(
+                    "{}, {}: {} sRGB ({}, {}, {})",
+                    pixel.0, pixel.1, source, srgb[0], srgb[1], srgb[2]
+                )
+            })
+        } else {
+            None
+        };
+        let changed = text != self.inspector_text;
+        self.inspector_text = text;
+        changed
+    }
+
+    fn copy_pixel_value(&self) {
+        if let Some((pixel, source, _)) = self.inspect_pixel() {
+            let text = source.format(self.copy_format);
+            match write_text_to_clipboard(&text) {
+                Ok(()) => info!("Copied pixel {}, {}: {}", pixel.0, pixel.1, text),
+                Err(err) => error!("Failed to copy pixel value: {err}"),
+            }
+        }
+    }
+
+    fn is_linear_image(&self) -> bool {
+        matches!(
+            self.displayed_texture().map(|t| t.format),
+            Some(UploadFormat::Rgba32Float)
+        )
+    }
+
+    // Exposure steps are half a stop, gamma steps are 0.1
+    fn step_display_settings(&mut self, direction: f32, adjust_gamma: bool) {
+        if adjust_gamma {
+            self.display.gamma = clamp(self.display.gamma + direction * 0.1, 0.1, 10.0);
+        } else {
+            self.display.exposure_ev += direction * 0.5;
+        }
+        self.log_display_settings();
+    }
+
+    fn log_display_settings(&self) {
+        info!(
+            "Display: exposure {:+.1} EV, gamma {:.2}, tone map {:?}",
+            self.display.exposure_ev, self.display.gamma, self.display.tone_map
+        );
+    }
+
+    fn active_orientation(&self) -> ViewOrientation {
+        if self.show_raw_orientation {
+            ViewOrientation::new_identity()
+        } else {
+            self.orientation
+        }
+    }
+
+    fn window_title(&self) -> String {
+        if let Some(err) = &self.load_error {
+            return format!("{} - {}", self.image_name, err);
+        }
+        let title = match &self.subimages {
+            Some(subimages) => format!("{} [{}]", self.image_name, subimages.label()),
+            None => self.image_name.clone(),
+        };
+        let page_loading = matches!(self.current_page(), Some(PageState::Loading));
+        let title = if self.preview_dim.is_some() || page_loading {
+            format!("{title} (loading)")
+        } else if let Some(PageState::Failed(err)) = self.current_page() {
+            format!("{title} - {err}")
+        } else if self.incomplete {
+            format!("{title} (incomplete)")
+        } else {
+            title
+        };
+        match &self.inspector_text {
+            Some(text) => format!("{title} - {text}"),
+            None => title,
+        }
+    }
 }
 
 fn apply_loaded_image(
@@ -455,12 +920,329 @@ fn apply_loaded_image(
     main_window: &mut Window,
     graphics: &GraphicsD3D11,
     constants: &mut Constants,
-    img: image::DynamicImage,
+    img: &Arc<LoadedImage>,
     image_name: Option<&str>,
 ) -> (u32, u32) {
-    state.texture = Some(Texture::new(&graphics.device, img));
+    // Pasted images and error placeholders are decoded on this thread, so their mips are too
+    let generated;
+    let mips = match &img.mips {
+        Some(mips) => mips,
+        None => {
+            generated = LoadedMips::new(img);
+            &generated
+        }
+    };
+    state.loaded_image = Some(img.clone());
+    state.texture = Some(Texture::new(&graphics.device, &img.image, &mips.image));
+    state.unmanaged_texture = img
+        .unmanaged_image
+        .as_ref()
+        .zip(mips.unmanaged_image.as_ref())
+        .map(|(img, mips)| Texture::new(&graphics.device, img, mips));
+    state.orientation = img
+        .orientation
+        .map_or_else(ViewOrientation::new_identity, ViewOrientation::from_exif);
+
+    state.subimages = None;
+    state.subimage_textures.clear();
+    state.pages.clear();
+    if let Some(subimages) = &img.subimages {
+        state.subimage_textures = subimages
+            .images
+            .iter()
+            .zip(&mips.subimages)
+            .map(|(image, mips)| Some(Texture::new(&graphics.device, image, mips)))
+            .collect();
+        if img.tiff_pages.is_some() {
+            let page_count = subimages.surfaces.len() - 1;
+            state.subimage_textures.resize_with(page_count, || None);
+            state.pages.resize_with(page_count, || PageState::Loading);
+        }
+        state.subimages = Some(SubimageNavigator::new(
+            subimages.layout,
+            subimages.surfaces.clone(),
+        ));
+    }
+
+    state.animation = None;
+    state.frame_textures.clear();
+    state.unmanaged_frame_textures.clear();
+    if let Some(animation) = &img.animation {
+        state.animation = Some(AnimationPlayer::new(
+            animation.frame_delays(),
+            Instant::now(),
+        ));
+        state.frame_textures = animation
+            .frames
+            .iter()
+            .zip(&mips.frames)
+            .map(|(frame, mips)| Texture::new(&graphics.device, &frame.image, mips))
+            .collect();
+        // Only used when every frame has its unmanaged pixels, so the indices match
+        state.unmanaged_frame_textures = animation
+            .frames
+            .iter()
+            .zip(&mips.unmanaged_frames)
+            .map(|(frame, mips)| {
+                let image = frame.unmanaged_image.as_ref()?;
+                Some(Texture::new(&graphics.device, image, mips.as_ref()?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+    }
 
-    let dim = state.texture.as_ref().unwrap().dim;
+    // Same dimensions as the preview it replaces, so zoom and pan are kept
+    state.preview_dim = None;
+    let dim = apply_image_orientation(state, main_window, constants);
+
+    if let Some(image_name) = image_name {
+        state.image_name = image_name.to_owned();
+    }
+    state.load_error = None;
+    state.incomplete = img.incomplete.is_some();
+    main_window.set_window_name(&state.window_title());
+
+    dim
+}
+
+// Shows a preview in place of a large image that is still decoding. It's stretched over the
+// dimensions of the full image, so the view set up on it carries over to the image.
+fn apply_preview(
+    state: &mut ViewerState,
+    main_window: &mut Window,
+    graphics: &GraphicsD3D11,
+    constants: &mut Constants,
+    preview: &ImagePreview,
+    mips: &ImageMips,
+    image_name: &str,
+) {
+    state.loaded_image = None;
+    state.texture = Some(Texture::new(&graphics.device, &preview.image, mips));
+    state.unmanaged_texture = None;
+    state.orientation = preview
+        .orientation
+        .map_or_else(ViewOrientation::new_identity, ViewOrientation::from_exif);
+    state.subimages = None;
+    state.subimage_textures.clear();
+    state.pages.clear();
+    state.animation = None;
+    state.frame_textures.clear();
+    state.unmanaged_frame_textures.clear();
+    state.preview_dim = Some(preview.full_dim);
+    apply_image_orientation(state, main_window, constants);
+
+    state.image_name = image_name.to_owned();
+    state.load_error = None;
+    state.incomplete = false;
+    main_window.set_window_name(&state.window_title());
+}
+
+// Shows a placeholder with the file name and the reason in place of an image that failed to
+// load, so that it's clear which file is broken and browsing can continue from it
+fn apply_load_error(
+    state: &mut ViewerState,
+    main_window: &mut Window,
+    graphics: &GraphicsD3D11,
+    constants: &mut Constants,
+    err: &LoadError,
+    image_name: &str,
+) {
+    let file_name = Path::new(image_name)
+        .file_name()
+        .map_or_else(|| image_name.into(), |name| name.to_string_lossy());
+    let placeholder = error_placeholder_image(&file_name, &err.to_string());
+    let placeholder = Arc::new(LoadedImage::from_image(placeholder));
+    apply_loaded_image(
+        state,
+        main_window,
+        graphics,
+        constants,
+        &placeholder,
+        Some(image_name),
+    );
+    state.load_error = Some(err.to_string());
+    main_window.set_window_name(&state.window_title());
+}
+
+// Switches between pages, slices, faces and mip levels. The visible region of the image is kept,
+// so smaller mip levels appear magnified instead of resizing the window.
+fn step_subimage(
+    state: &mut ViewerState,
+    main_window: &mut Window,
+    constants: &mut Constants,
+    load_req_tx: &std::sync::mpsc::Sender<LoadRequest>,
+    layer_delta: i32,
+    mip_delta: i32,
+) {
+    let changed = match &mut state.subimages {
+        Some(subimages) => subimages.step_layer(layer_delta) | subimages.step_mip(mip_delta),
+        None => false,
+    };
+    if !changed {
+        return;
+    }
+
+    // Pages that aren't decoded yet are requested again, in case an earlier request was superseded
+    if let (Some(subimages), Some(img)) = (&state.subimages, &state.loaded_image) {
+        let index = subimages.current_index();
+        if let Some(PageState::Loading) = state.current_page() {
+            load_req_tx
+                .send(LoadRequest::Page(img.clone(), index))
+                .unwrap();
+        }
+    }
+
+    let orientation = state.active_orientation();
+    let dim = state
+        .displayed_dim()
+        .map(|dim| orientation.oriented_dim(dim));
+    if let Some(dim) = dim {
+        let new_image_dim = float2::new(dim.0 as f32, dim.1 as f32);
+        let ratio = new_image_dim.div_element_wise(constants.image_dim);
+        state.xfm_window_to_image = state
+            .xfm_window_to_image
+            .concatenate(Transform2D::new_scale(ratio));
+        constants.image_dim = new_image_dim;
+    }
+
+    let title = state.window_title();
+    info!("Subimage: {}", title);
+    main_window.set_window_name(&title);
+}
+
+// Shows a TIFF page that the loader thread decoded after it was navigated to
+fn apply_page(
+    state: &mut ViewerState,
+    main_window: &mut Window,
+    graphics: &GraphicsD3D11,
+    page: usize,
+    result: Result<(DynamicImage, ImageMips), LoadError>,
+) {
+    let index = page - 1;
+    if index >= state.pages.len() {
+        return;
+    }
+    match result {
+        Ok((image, mips)) => {
+            state.subimage_textures[index] = Some(Texture::new(&graphics.device, &image, &mips));
+            state.pages[index] = PageState::Loaded(image);
+        }
+        Err(err) => {
+            error!("Failed to decode page {}: {err}", page + 1);
+            state.pages[index] = PageState::Failed(err.to_string());
+        }
+    }
+    main_window.set_window_name(&state.window_title());
+}
+
+fn log_image_metadata(img: &LoadedImage) {
+    if img.exif.is_some()
+        || img.icc_profile.is_some()
+        || img.orientation.is_some()
+        || img.exif_info.is_some()
+        || img.icc_info.is_some()
+        || img.xmp_info.is_some()
+    {
+        info!(
+            "Metadata: exif={} bytes, icc={} bytes, orientation={:?}",
+            img.exif.as_ref().map_or(0, |v| v.len()),
+            img.icc_profile.as_ref().map_or(0, |v| v.len()),
+            img.orientation
+        );
+    }
+
+    if let Some(exif) = &img.exif_info {
+        info!(
+            "EXIF: make={:?}, model={:?}, software={:?}, datetime={:?}, original={:?}, lens={:?}, orientation={:?}",
+            exif.make,
+            exif.model,
+            exif.software,
+            exif.datetime,
+            exif.datetime_original,
+            exif.lens_model,
+            exif.orientation
+        );
+        if let Some(gps) = &exif.gps_iso6709 {
+            info!("EXIF GPS: {}", gps);
+        }
+    }
+
+    if let Some(icc) = &img.icc_info {
+        info!(
+            "ICC: size={} bytes, class={}, color_space={}, pcs={}, cmm={}, version={}, platform={}, manufacturer={}, model={}, intent={}, created={}",
+            icc.size,
+            icc.profile_class,
+            icc.color_space,
+            icc.pcs,
+            icc.cmm_type,
+            icc.version,
+            icc.platform,
+            icc.manufacturer,
+            icc.model,
+            icc.rendering_intent,
+            icc.created.as_deref().unwrap_or("n/a")
+        );
+        let tags = &icc.tags;
+        let trcs: Vec<String> = tags
+            .trcs()
+            .iter()
+            .filter_map(|(name, curve)| curve.map(|c| format!("{name}={c}")))
+            .collect();
+        info!(
+            "ICC tags: description={:?}, white_point={:?}, trc=[{}], luts={:?}",
+            tags.description,
+            tags.white_point.map(format_xyz),
+            trcs.join(", "),
+            tags.luts
+        );
+        for problem in &tags.problems {
+            warn!("Malformed ICC profile: {}", problem);
+        }
+    }
+
+    if let Some(xmp) = &img.xmp_info {
+        info!(
+            "XMP: title={:?}, creators={:?}, keywords={:?}, rating={:?}, label={:?}, {} properties",
+            xmp.title,
+            xmp.creators,
+            xmp.keywords,
+            xmp.rating,
+            xmp.label,
+            xmp.properties.len()
+        );
+    }
+
+    if let Some(animation) = &img.animation {
+        let duration: Duration = animation.frames.iter().map(|f| f.delay).sum();
+        info!(
+            "Animation: {} frames, {} ms",
+            animation.frames.len(),
+            to_milliseconds(duration)
+        );
+    }
+
+    if let Some(subimages) = &img.subimages {
+        info!(
+            "Subimages: {} surfaces, {:?}",
+            subimages.surfaces.len(),
+            subimages.layout
+        );
+    }
+}
+
+// Image dimensions and transforms are kept in oriented space, only the shader sees raw texels
+fn apply_image_orientation(
+    state: &mut ViewerState,
+    main_window: &mut Window,
+    constants: &mut Constants,
+) -> (u32, u32) {
+    let orientation = state.active_orientation();
+    constants.image_orientation = orientation.into();
+
+    let dim = match state.displayed_dim() {
+        Some(dim) => orientation.oriented_dim(dim),
+        None => return (0, 0),
+    };
 
     let pending_image_dim: float2 = float2::new(dim.0 as f32, dim.1 as f32);
     if constants.image_dim != pending_image_dim {
@@ -476,10 +1258,6 @@ fn apply_loaded_image(
         state.xfm_window_to_image.offset = 0.5 * constants.image_dim - 0.5 * window_dim;
     }
 
-    if let Some(image_name) = image_name {
-        main_window.set_window_name(image_name);
-    }
-
     dim
 }
 
@@ -491,11 +1269,28 @@ fn main() {
     let mut image_path: Option<PathBuf> = None;
     let mut verbose_log = false;
     let mut console_requested = false;
+    let mut output_profile_arg: Option<String> = None;
+    let mut sort_order_arg: Option<String> = None;
+    let mut shuffle_seed_arg: Option<String> = None;
+    let mut wrap_navigation = true;
+    let mut cache_budget_arg: Option<String> = None;
+    let mut prefetch_count_arg: Option<String> = None;
+    let mut max_size_arg: Option<String> = None;
+    let mut max_alloc_arg: Option<String> = None;
+    let mut strict_decoding = false;
+    let mut info_mode = false;
+    let mut info_format_arg: Option<String> = None;
+    let mut filter_arg: Option<String> = None;
+    let mut grid_zoom_arg: Option<String> = None;
+    let mut grid_color_arg: Option<String> = None;
+    let mut grid_opacity_arg: Option<String> = None;
+    let mut file_args: Vec<PathBuf> = Vec::new();
 
     let (load_req_tx, load_req_rx) = std::sync::mpsc::channel();
     let (image_tx, image_rx) = std::sync::mpsc::channel();
 
-    for arg in std::env::args().skip(1) {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
         if arg == "-v" || arg == "--verbose" {
             verbose_log = true;
             continue;
@@ -504,32 +1299,366 @@ fn main() {
             console_requested = true;
             continue;
         }
-        if image_path.is_none() {
-            let path: PathBuf = arg.into();
-            image_path = Some(path.clone());
-            load_req_tx.send(path).unwrap();
+        if arg == "--output-profile" {
+            output_profile_arg = args.next();
+            continue;
+        }
+        if arg == "--sort" {
+            sort_order_arg = args.next();
+            continue;
+        }
+        if arg == "--shuffle" {
+            shuffle_seed_arg = args.next();
+            continue;
+        }
+        if arg == "--no-wrap" {
+            wrap_navigation = false;
+            continue;
+        }
+        if arg == "--cache-mb" {
+            cache_budget_arg = args.next();
+            continue;
+        }
+        if arg == "--prefetch" {
+            prefetch_count_arg = args.next();
+            continue;
         }
+        if arg == "--max-size" {
+            max_size_arg = args.next();
+            continue;
+        }
+        if arg == "--max-alloc-mb" {
+            max_alloc_arg = args.next();
+            continue;
+        }
+        if arg == "--strict" {
+            strict_decoding = true;
+            continue;
+        }
+        if arg == "--info" {
+            info_mode = true;
+            continue;
+        }
+        if arg == "--info-format" {
+            info_format_arg = args.next();
+            continue;
+        }
+        if arg == "--filter" {
+            filter_arg = args.next();
+            continue;
+        }
+        if arg == "--grid-zoom" {
+            grid_zoom_arg = args.next();
+            continue;
+        }
+        if arg == "--grid-color" {
+            grid_color_arg = args.next();
+            continue;
+        }
+        if arg == "--grid-opacity" {
+            grid_opacity_arg = args.next();
+            continue;
+        }
+        file_args.push(arg.into());
     }
 
-    maybe_alloc_console(console_requested);
+    if info_mode {
+        attach_parent_console();
+    } else {
+        maybe_alloc_console(console_requested);
+    }
     init_logging(verbose_log);
 
+    let mut load_options = LoadOptions {
+        strict: strict_decoding,
+        ..LoadOptions::default()
+    };
+    if let Some(arg) = &output_profile_arg {
+        match OutputProfile::parse(arg) {
+            Ok(output_profile) => load_options.output_profile = output_profile,
+            Err(err) => error!("{err}"),
+        }
+    }
+    if let Some(arg) = &max_size_arg {
+        match arg.parse::<u32>() {
+            Ok(size) => {
+                load_options.limits.max_width = size;
+                load_options.limits.max_height = size;
+            }
+            Err(err) => error!("Invalid maximum image size {arg:?}: {err}"),
+        }
+    }
+    if let Some(arg) = &max_alloc_arg {
+        match arg.parse::<u64>() {
+            Ok(mb) => load_options.limits.max_alloc = mb.saturating_mul(1024 * 1024),
+            Err(err) => error!("Invalid maximum allocation {arg:?}: {err}"),
+        }
+    }
+
+    // Headless metadata dump of every file given on the command line
+    if info_mode {
+        let mut info_format = InfoFormat::Json;
+        if let Some(arg) = &info_format_arg {
+            match InfoFormat::parse(arg) {
+                Some(format) => info_format = format,
+                None => error!("Unknown info format {arg:?}, expected json or table"),
+            }
+        }
+        if file_args.is_empty() {
+            error!("--info expects one or more image files");
+            std::process::exit(1);
+        }
+        if !print_image_info(&file_args, info_format, &load_options) {
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
+    // Additional files are ignored, only the first one is opened. Images piped to stdin have no
+    // folder to browse.
+    let requested_path = std::cell::RefCell::new(None);
+    if let Some(path) = file_args.into_iter().next() {
+        if !is_stdin_path(&path) {
+            image_path = Some(path.clone());
+        }
+        request_display(&load_req_tx, &load_options.cancel, &requested_path, path);
+    }
+
+    let mut sort_order = SortOrder::Name;
+    if let Some(arg) = &sort_order_arg {
+        match SortOrder::parse(arg) {
+            Some(order) => sort_order = order,
+            None => error!("Unknown sort order {arg:?}, expected name, mtime, size or date"),
+        }
+    }
+    let mut directory = DirectoryIndex::new(sort_order);
+    directory.set_wrap(wrap_navigation);
+    // Seed used when shuffle is toggled on, logged so that an order can be reproduced
+    let mut shuffle_seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |t| t.as_nanos() as u64);
+    if let Some(arg) = &shuffle_seed_arg {
+        match arg.parse::<u64>() {
+            Ok(seed) => {
+                shuffle_seed = seed;
+                directory.set_shuffle_seed(Some(seed));
+            }
+            Err(err) => error!("Invalid shuffle seed {arg:?}: {err}"),
+        }
+    }
+
+    let mut cache_budget_mb = DEFAULT_CACHE_BUDGET_MB;
+    if let Some(arg) = &cache_budget_arg {
+        match arg.parse::<usize>() {
+            Ok(mb) => cache_budget_mb = mb,
+            Err(err) => error!("Invalid cache size {arg:?}: {err}"),
+        }
+    }
+    let mut prefetch_count = DEFAULT_PREFETCH_COUNT;
+    if let Some(arg) = &prefetch_count_arg {
+        match arg.parse::<usize>() {
+            Ok(count) => prefetch_count = count,
+            Err(err) => error!("Invalid prefetch count {arg:?}: {err}"),
+        }
+    }
+
     let mut state = ViewerState::new();
+    if let Some(arg) = &filter_arg {
+        match MagFilter::parse(arg) {
+            Some(filter) => state.mag_filter = filter,
+            None => {
+                error!("Unknown filter {arg:?}, expected nearest, bilinear, bicubic, mitchell or lanczos")
+            }
+        }
+    }
+    if let Some(arg) = &grid_zoom_arg {
+        match arg.parse::<f32>() {
+            Ok(zoom) if zoom > 0.0 => state.pixel_grid.min_magnification = zoom,
+            _ => error!("Invalid grid zoom {arg:?}, expected a positive number"),
+        }
+    }
+    if let Some(arg) = &grid_color_arg {
+        match PixelGrid::parse_color(arg) {
+            Some(color) => state.pixel_grid.color = color,
+            None => error!("Invalid grid color {arg:?}, expected RRGGBB hex digits"),
+        }
+    }
+    if let Some(arg) = &grid_opacity_arg {
+        match arg.parse::<f32>() {
+            Ok(opacity) if (0.0..=1.0).contains(&opacity) => state.pixel_grid.opacity = opacity,
+            _ => error!("Invalid grid opacity {arg:?}, expected a number from 0 to 1"),
+        }
+    }
 
     let mut main_window: Window = Window::new((500, 500)).unwrap();
     let main_window_handle = main_window.hwnd as u64;
+    // Pasted images are decoded on the main thread, navigation doesn't cancel them
+    let clipboard_load_options = LoadOptions {
+        cancel: CancelToken::default(),
+        ..load_options.clone()
+    };
+    let load_cancel = load_options.cancel.clone();
+    let (date_req_tx, date_req_rx) = std::sync::mpsc::channel::<Vec<PathBuf>>();
+    let date_tx = image_tx.clone();
+    std::thread::spawn(move || {
+        let mut cache: ImageCache<ImageCacheKey, LoadedImage> =
+            ImageCache::new(cache_budget_mb.saturating_mul(1024 * 1024));
+        let mut queue = LoadQueue::default();
+        loop {
+            // Block only when idle, then take everything that is pending so that images the user
+            // has already navigated past are dropped before they are decoded
+            if queue.is_empty() {
+                match load_req_rx.recv() {
+                    Ok(request) => queue_load_request(&mut queue, request),
+                    Err(_) => break,
+                }
+            }
+            while let Ok(request) = load_req_rx.try_recv() {
+                queue_load_request(&mut queue, request);
+            }
+            // Display requests cancel everything that started before them
+            let load_options = LoadOptions {
+                cancel: load_options.cancel.restart(),
+                ..load_options.clone()
+            };
+            match queue.pop() {
+                Some(LoadRequest::Display(path)) => {
+                    profiling::scope!("LoadImage");
+                    let load_begin_time = Instant::now();
+                    let key = image_cache_key(&path);
+                    let cached = cache.get(&key);
+                    let from_cache = cached.is_some();
+                    let img = match cached {
+                        Some(img) => Ok(img),
+                        None => {
+                            info!("Loading image {:?}", path);
+                            let mut on_preview = |preview: ImagePreview| {
+                                let mips = ImageMips::new(&preview.image);
+                                let message = LoaderMessage::Preview(path.clone(), preview, mips);
+                                let _ = image_tx.send(message);
+                                unsafe {
+                                    InvalidateRect(main_window_handle as HWND, null_mut(), 1);
+                                }
+                            };
+                            let img = load_image_with_preview(
+                                &path,
+                                &load_options,
+                                Some(&mut on_preview),
+                            )
+                            .map(|mut img| {
+                                img.generate_mips();
+                                std::sync::Arc::new(img)
+                            });
+                            if let Ok(img) = &img {
+                                let decode_time = Instant::now() - load_begin_time;
+                                info!("Time to decode image {} ms", to_milliseconds(decode_time));
+                                cache.insert(key, img.clone(), img.memory_size());
+                            }
+                            img
+                        }
+                    };
+
+                    // The image that cancelled the decode is sent next
+                    if let Err(LoadError::Cancelled) = img {
+                        info!("Cancelled loading image {:?}", path);
+                        continue;
+                    }
+
+                    // The result is dropped if another image was requested while decoding.
+                    // It stays cached in case the user comes back to it.
+                    while let Ok(request) = load_req_rx.try_recv() {
+                        queue_load_request(&mut queue, request);
+                    }
+                    if queue.has_display() {
+                        info!("Skipped displaying image {:?}", path);
+                        continue;
+                    }
+
+                    let result = LoadResult {
+                        image: img,
+                        path,
+                        load_begin_time,
+                        from_cache,
+                        cache_hit_rate: cache.hit_rate(),
+                    };
+                    let _ = image_tx.send(LoaderMessage::Loaded(result));
+                    unsafe {
+                        InvalidateRect(main_window_handle as HWND, null_mut(), 1);
+                    }
+                }
+                Some(LoadRequest::Page(img, page)) => {
+                    profiling::scope!("LoadPage");
+                    let result = img.decode_page(page, &load_options).map(|image| {
+                        let mips = ImageMips::new(&image);
+                        (image, mips)
+                    });
+                    if let Err(LoadError::Cancelled) = result {
+                        continue;
+                    }
+                    let _ = image_tx.send(LoaderMessage::Page(img, page, result));
+                    unsafe {
+                        InvalidateRect(main_window_handle as HWND, null_mut(), 1);
+                    }
+                }
+                Some(LoadRequest::Prefetch(paths)) => {
+                    for path in paths {
+                        let key = image_cache_key(&path);
+                        if cache.contains(&key) {
+                            continue;
+                        }
+                        profiling::scope!("PrefetchImage");
+                        let prefetch_begin_time = Instant::now();
+                        match load_image_with_metadata(&path, &load_options) {
+                            Ok(mut img) => {
+                                img.generate_mips();
+                                let size = img.memory_size();
+                                cache.insert(key, std::sync::Arc::new(img), size);
+                                let decode_time = Instant::now() - prefetch_begin_time;
+                                info!(
+                                    "Time to prefetch image {:?} {} ms (cache {} MB, {} images)",
+                                    path,
+                                    to_milliseconds(decode_time),
+                                    cache.used() / (1024 * 1024),
+                                    cache.len()
+                                );
+                            }
+                            // The remaining neighbors are requested again with the new image
+                            Err(LoadError::Cancelled) => {
+                                info!("Cancelled prefetching image {:?}", path);
+                                break;
+                            }
+                            Err(err) => warn!("Failed to prefetch image {:?}: {err}", path),
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        info!("Loading thread done");
+    });
+
     std::thread::spawn(move || {
-        while let Ok(x) = load_req_rx.recv() {
-            profiling::scope!("LoadImage");
-            let load_begin_time = Instant::now();
-            info!("Loading image {:?}", x);
-            let img = load_image_with_metadata(&x);
-            let _ = image_tx.send((img, load_begin_time, x));
+        let mut pending: VecDeque<PathBuf> = VecDeque::new();
+        loop {
+            if pending.is_empty() {
+                match date_req_rx.recv() {
+                    Ok(paths) => pending.extend(paths),
+                    Err(_) => break,
+                }
+            }
+            while let Ok(paths) = date_req_rx.try_recv() {
+                // Files of a folder the user has left are no longer needed
+                let dir = paths.first().and_then(|path| path.parent());
+                pending.retain(|path| path.parent() == dir);
+                pending.extend(paths);
+            }
+            let count = pending.len().min(DATE_TAKEN_BATCH_SIZE);
+            let dates = read_dates_taken(pending.drain(..count).collect());
+            let _ = date_tx.send(LoaderMessage::DatesTaken(dates));
             unsafe {
                 InvalidateRect(main_window_handle as HWND, null_mut(), 1);
             }
         }
-        info!("Loading thread done");
     });
 
     {
@@ -551,20 +1680,30 @@ fn main() {
         window_dim: FLOAT2_ZERO,
         mouse: FLOAT4_ZERO,
         xfm_viewport_to_image_uv: Transform2D::new_identity().into(),
+        xfm_viewport_to_image_uv_dihedral: Dihedral2D::IDENTITY.into(),
+        image_orientation: ViewOrientation::new_identity().into(),
+        display: DisplaySettings::new_default().to_float4(false),
+        tile_uv_rect: FLOAT4_ZERO,
+        xfm_texture_uv_to_tile_uv: Transform2D::new_identity().into(),
+        sampling: FLOAT4_ZERO,
+        pixel_grid: FLOAT4_ZERO,
+        pixel_grid_color: FLOAT4_ZERO,
+        missing_pixels: FLOAT4_ZERO,
     };
 
-    let switch_to_next_image = |current_image_path: &Path, direction: StepDirection| {
-        let file_name = get_next_file(current_image_path, direction);
-        let file_dir = current_image_path.parent();
-        if let (Some(file_name), Some(file_dir)) = (file_name, file_dir) {
-            let file_dir = file_dir.to_path_buf();
-            let path = file_dir.join(file_name);
-            load_req_tx.send(path.clone()).unwrap();
-            Some(path)
-        } else {
-            Some(current_image_path.into())
-        }
-    };
+    let switch_to_next_image =
+        |directory: &mut DirectoryIndex, current_image_path: &Path, direction: StepDirection| {
+            if let Err(err) = directory.select(current_image_path) {
+                warn!("Failed to read folder of {:?}: {err}", current_image_path);
+            }
+            request_dates_taken(directory, &date_req_tx);
+            if let Some(path) = directory.step(direction) {
+                request_display(&load_req_tx, &load_cancel, &requested_path, path.clone());
+                Some(path)
+            } else {
+                Some(current_image_path.into())
+            }
+        };
 
     let mut draw_begin_time = Instant::now();
     let mut draw_end_time = Instant::now();
@@ -576,12 +1715,22 @@ fn main() {
     let mut last_verbose_log_time = Instant::now();
     while !should_exit {
         profiling::scope!("MainLoop");
-        if let Some(x) = process_window_messages(&main_window, should_block) {
+        // Animated images wake the loop up when the next frame is due
+        let wake_time = state
+            .animation
+            .as_ref()
+            .and_then(AnimationPlayer::next_frame_time);
+        if let Some(x) = process_window_messages(&mut main_window, should_block, wake_time) {
             should_block = false;
             match x {
                 WindowMessages::OpenFile(data) => {
                     image_path = Some(data.filename.clone().into());
-                    load_req_tx.send(data.filename.into()).unwrap();
+                    request_display(
+                        &load_req_tx,
+                        &load_cancel,
+                        &requested_path,
+                        data.filename.into(),
+                    );
                 }
                 WindowMessages::WindowClosed => {
                     should_exit = true;
@@ -631,18 +1780,27 @@ fn main() {
                         WM_LBUTTONUP => {
                             state.is_dragging = false;
                         }
+                        WM_RBUTTONDOWN => {
+                            state.is_scrubbing = state.animation.is_some();
+                            state.scrub_animation(main_window.window_dim.0);
+                        }
+                        WM_RBUTTONUP => {
+                            state.is_scrubbing = false;
+                        }
                         WM_XBUTTONDOWN | WM_XBUTTONDBLCLK => {
                             let button_index = winapi::shared::minwindef::HIWORD(wparam as u32);
                             if let Some(image_path_local) = &image_path {
                                 match button_index {
                                     1 => {
                                         image_path = switch_to_next_image(
+                                            &mut directory,
                                             image_path_local,
                                             StepDirection::Backward,
                                         );
                                     }
                                     2 => {
                                         image_path = switch_to_next_image(
+                                            &mut directory,
                                             image_path_local,
                                             StepDirection::Forward,
                                         );
@@ -661,7 +1819,10 @@ fn main() {
                             let drag_delta: float2 = state.drag_origin - state.mouse_pos;
                             if state.is_dragging {
                                 state.xfm_window_to_image.offset =
-                                    drag_delta.mul_element_wise(state.xfm_window_to_image.scale);
+                                    state.xfm_window_to_image.transform_vector(drag_delta);
+                            }
+                            if state.is_scrubbing {
+                                state.scrub_animation(main_window.window_dim.0);
                             }
                             should_draw = true;
                         }
@@ -669,12 +1830,13 @@ fn main() {
                             let ctrl_down = unsafe {
                                 (GetKeyState(VK_LCONTROL) < 0) || (GetKeyState(VK_RCONTROL) < 0)
                             };
+                            let shift_down = unsafe { GetKeyState(VK_SHIFT) < 0 };
 
                             match (wparam as i32, wparam as u8 as char) {
                                 (VK_ESCAPE, _) => {
                                     should_exit = true;
                                 }
-                                (VK_HOME, _) => {
+                                (VK_HOME, _) if ctrl_down => {
                                     state.xfm_window_to_image = Transform2D::new_identity();
                                     let window_dim = float2::new(
                                         main_window.window_dim.0 as f32,
@@ -685,16 +1847,46 @@ fn main() {
                                 }
                                 (VK_LEFT, _) if image_path.is_some() => {
                                     image_path = switch_to_next_image(
+                                        &mut directory,
                                         &image_path.unwrap(),
                                         StepDirection::Backward,
                                     );
                                 }
                                 (VK_RIGHT, _) if image_path.is_some() => {
                                     image_path = switch_to_next_image(
+                                        &mut directory,
                                         &image_path.unwrap(),
                                         StepDirection::Forward,
                                     );
                                 }
+                                (VK_HOME, _) if image_path.is_some() => {
+                                    image_path = switch_to_next_image(
+                                        &mut directory,
+                                        &image_path.unwrap(),
+                                        StepDirection::First,
+                                    );
+                                }
+                                (VK_END, _) if image_path.is_some() => {
+                                    image_path = switch_to_next_image(
+                                        &mut directory,
+                                        &image_path.unwrap(),
+                                        StepDirection::Last,
+                                    );
+                                }
+                                (VK_PRIOR, _) if image_path.is_some() => {
+                                    image_path = switch_to_next_image(
+                                        &mut directory,
+                                        &image_path.unwrap(),
+                                        StepDirection::BackwardBy(BROWSE_JUMP_COUNT),
+                                    );
+                                }
+                                (VK_NEXT, _) if image_path.is_some() => {
+                                    image_path = switch_to_next_image(
+                                        &mut directory,
+                                        &image_path.unwrap(),
+                                        StepDirection::ForwardBy(BROWSE_JUMP_COUNT),
+                                    );
+                                }
                                 (VK_RETURN, _) => {
                                     main_window.set_full_screen(!main_window.full_screen);
                                 }
@@ -718,29 +1910,175 @@ fn main() {
                                     let s = 1.0 / 16.0;
                                     state.xfm_window_to_image.scale = float2::new(s, s);
                                 }
+                                (_, 'R') => {
+                                    state.apply_view_dihedral(Dihedral2D::ROTATE_90_CW);
+                                }
+                                (_, 'L') => {
+                                    state.apply_view_dihedral(Dihedral2D::ROTATE_90_CCW);
+                                }
+                                (_, 'H') => {
+                                    state.apply_view_dihedral(Dihedral2D::FLIP_X);
+                                }
+                                (VK_OEM_PLUS, _) => {
+                                    state.step_display_settings(1.0, ctrl_down);
+                                }
+                                (VK_OEM_MINUS, _) => {
+                                    state.step_display_settings(-1.0, ctrl_down);
+                                }
+                                (_, '0') => {
+                                    state.display = DisplaySettings {
+                                        tone_map: state.display.tone_map,
+                                        ..DisplaySettings::new_default()
+                                    };
+                                    state.log_display_settings();
+                                }
+                                (_, 'T') => {
+                                    state.display.tone_map = state.display.tone_map.next();
+                                    state.log_display_settings();
+                                }
+                                (_, 'F') => {
+                                    state.mag_filter = state.mag_filter.next();
+                                    info!("Magnification filter: {:?}", state.mag_filter);
+                                }
+                                (_, 'G') => {
+                                    state.pixel_grid.enabled = !state.pixel_grid.enabled;
+                                    info!("Pixel grid enabled: {}", state.pixel_grid.enabled);
+                                }
+                                (_, 'M') => {
+                                    state.show_unmanaged_colors = !state.show_unmanaged_colors;
+                                    let managed = !state.show_unmanaged_colors;
+                                    info!("Color management enabled: {}", managed);
+                                }
+                                (VK_SPACE, _) => {
+                                    if let Some(animation) = &mut state.animation {
+                                        let playing = !animation.is_playing();
+                                        animation.set_playing(playing, Instant::now());
+                                    }
+                                }
+                                (VK_OEM_COMMA, _) => {
+                                    state.step_animation(-1);
+                                }
+                                (VK_OEM_PERIOD, _) => {
+                                    state.step_animation(1);
+                                }
+                                (VK_OEM_4, _) => {
+                                    step_subimage(
+                                        &mut state,
+                                        &mut main_window,
+                                        &mut constants,
+                                        &load_req_tx,
+                                        -1,
+                                        0,
+                                    );
+                                }
+                                (VK_OEM_6, _) => {
+                                    step_subimage(
+                                        &mut state,
+                                        &mut main_window,
+                                        &mut constants,
+                                        &load_req_tx,
+                                        1,
+                                        0,
+                                    );
+                                }
+                                (VK_UP, _) => {
+                                    step_subimage(
+                                        &mut state,
+                                        &mut main_window,
+                                        &mut constants,
+                                        &load_req_tx,
+                                        0,
+                                        -1,
+                                    );
+                                }
+                                (VK_DOWN, _) => {
+                                    step_subimage(
+                                        &mut state,
+                                        &mut main_window,
+                                        &mut constants,
+                                        &load_req_tx,
+                                        0,
+                                        1,
+                                    );
+                                }
+                                (_, 'S') => {
+                                    directory.set_sort_order(directory.sort_order().next());
+                                    request_dates_taken(&mut directory, &date_req_tx);
+                                    info!("Sort order: {:?}", directory.sort_order());
+                                }
+                                (_, 'X') => {
+                                    if directory.shuffle_seed().is_some() {
+                                        directory.set_shuffle_seed(None);
+                                        info!("Shuffle disabled");
+                                    } else {
+                                        directory.set_shuffle_seed(Some(shuffle_seed));
+                                        info!("Shuffle enabled, seed {}", shuffle_seed);
+                                    }
+                                }
+                                (_, 'I') => {
+                                    if let Some(panel) = &main_window.metadata_panel {
+                                        panel.toggle();
+                                    }
+                                }
+                                (_, 'O') => {
+                                    state.show_raw_orientation = !state.show_raw_orientation;
+                                    apply_image_orientation(
+                                        &mut state,
+                                        &mut main_window,
+                                        &mut constants,
+                                    );
+                                }
                                 (_, 'V') if ctrl_down => {
                                     if let Ok(Some(path)) = get_clipboard_file_path() {
                                         image_path = Some(path.clone());
-                                        load_req_tx.send(path).unwrap();
-                                    } else if let Ok(Some(img)) = get_clipboard_image() {
+                                        request_display(
+                                            &load_req_tx,
+                                            &load_cancel,
+                                            &requested_path,
+                                            path,
+                                        );
+                                    } else if let Ok(Some(img)) =
+                                        get_clipboard_image(&clipboard_load_options)
+                                    {
                                         image_path = None;
+                                        *requested_path.borrow_mut() = None;
+                                        let img = Arc::new(img);
+                                        log_image_metadata(&img);
                                         let dim = apply_loaded_image(
                                             &mut state,
                                             &mut main_window,
                                             &graphics,
                                             &mut constants,
-                                            img,
+                                            &img,
                                             Some("Clipboard Image"),
                                         );
+                                        if let Some(panel) = &main_window.metadata_panel {
+                                            let name = Path::new("Clipboard Image");
+                                            panel.set_text(&metadata_panel_text(name, &img));
+                                        }
                                         info!(
                                             "Loaded clipboard image {:?}x{:?}",
                                             dim.0, dim.1
                                         );
                                     }
                                 }
+                                (_, 'V') => {
+                                    state.apply_view_dihedral(Dihedral2D::FLIP_Y);
+                                }
                                 (_, 'C') | (VK_INSERT, _) if ctrl_down => {
                                     main_window.clipboard_save();
                                 }
+                                (_, 'C') => {
+                                    state.copy_pixel_value();
+                                }
+                                (_, 'P') if shift_down => {
+                                    state.copy_format = state.copy_format.next();
+                                    info!("Pixel copy format: {:?}", state.copy_format);
+                                }
+                                (_, 'P') => {
+                                    state.inspector = !state.inspector;
+                                    info!("Pixel inspector enabled: {}", state.inspector);
+                                }
                                 _ => {}
                             }
                             should_draw = true;
@@ -757,7 +2095,7 @@ fn main() {
                             );
                             if edge_delta != FLOAT2_ZERO {
                                 state.xfm_window_to_image.offset +=
-                                    edge_delta.mul_element_wise(state.xfm_window_to_image.scale);
+                                    state.xfm_window_to_image.transform_vector(edge_delta);
                             }
                             main_window.window_rect = new_window_rect;
                             graphics.update_backbuffer(main_window.hwnd);
@@ -787,108 +2125,145 @@ fn main() {
             should_block = true;
         }
 
+        if let Some(animation) = &mut state.animation {
+            if animation.update(Instant::now()) {
+                should_draw = true;
+            }
+        }
+
         if !should_draw || !should_block {
             continue;
         }
 
-        let xfm_viewport_to_image_uv = Transform2D {
-            scale: 1.0 / constants.image_dim,
-            offset: FLOAT2_ZERO,
-        };
-
-        constants.window_dim.x = main_window.window_dim.0 as f32;
-        constants.window_dim.y = main_window.window_dim.1 as f32;
-
-        let xfm_window_to_image_quantized = if state.xfm_window_to_image.scale.x >= 1.0
-            || state.xfm_window_to_image.scale.y >= 1.0
-        {
-            Transform2D {
-                scale: state.xfm_window_to_image.scale,
-                offset: float2_round(state.xfm_window_to_image.offset),
-            }
-        } else {
-            state.xfm_window_to_image
-        };
-
-        constants.xfm_viewport_to_image_uv = xfm_window_to_image_quantized
-            .concatenate(xfm_viewport_to_image_uv)
-            .into();
-
-        if let Ok((img, load_begin_time, image_filename)) = image_rx.try_recv() {
-            if let Ok(img) = img {
-                // Image loaded
-                let image_name = image_filename.to_string_lossy().into_owned();
-                let dim = apply_loaded_image(
+        // Applied before the constants are filled in, so that a new image is drawn with its own
+        // dimensions and display settings from its first frame
+        match image_rx.try_recv() {
+            // Previews of an image the user has already navigated away from are dropped
+            Ok(LoaderMessage::Preview(path, preview, mips))
+                if requested_path.borrow().as_ref() == Some(&path) =>
+            {
+                apply_preview(
                     &mut state,
                     &mut main_window,
                     &graphics,
                     &mut constants,
-                    img.image,
-                    Some(&image_name),
+                    &preview,
+                    &mips,
+                    &display_name(&path),
                 );
+            }
+            Ok(LoaderMessage::Loaded(result)) => {
+                let image_name = display_name(&result.path);
+                match &result.image {
+                    Ok(img) => {
+                        // Image loaded
+                        log_image_metadata(img);
+
+                        let dim = apply_loaded_image(
+                            &mut state,
+                            &mut main_window,
+                            &graphics,
+                            &mut constants,
+                            img,
+                            Some(&image_name),
+                        );
+                        if let Some(panel) = &main_window.metadata_panel {
+                            panel.set_text(&metadata_panel_text(&result.path, img));
+                        }
 
-                if img.exif.is_some()
-                    || img.icc_profile.is_some()
-                    || img.orientation.is_some()
-                    || img.exif_info.is_some()
-                    || img.icc_info.is_some()
-                {
-                    info!(
-                        "Metadata: exif={} bytes, icc={} bytes, orientation={:?}",
-                        img.exif.as_ref().map_or(0, |v| v.len()),
-                        img.icc_profile.as_ref().map_or(0, |v| v.len()),
-                        img.orientation
-                    );
+                        let image_load_time = Instant::now() - result.load_begin_time;
+                        info!(
+                            "Time to load image {} ms ({:?}, cache {}, hit rate {:.0}%)",
+                            to_milliseconds(image_load_time),
+                            dim,
+                            if result.from_cache { "hit" } else { "miss" },
+                            result.cache_hit_rate * 100.0
+                        );
+                    }
+                    Err(err) => {
+                        error!("Failed to load image {:?}: {err}", result.path);
+                        apply_load_error(
+                            &mut state,
+                            &mut main_window,
+                            &graphics,
+                            &mut constants,
+                            err,
+                            &image_name,
+                        );
+                        if let Some(panel) = &main_window.metadata_panel {
+                            panel.set_text(&load_error_panel_text(&result.path, err));
+                        }
+                    }
                 }
 
-                if let Some(exif) = &img.exif_info {
-                    info!(
-                        "EXIF: make={:?}, model={:?}, software={:?}, datetime={:?}, original={:?}, lens={:?}, orientation={:?}",
-                        exif.make,
-                        exif.model,
-                        exif.software,
-                        exif.datetime,
-                        exif.datetime_original,
-                        exif.lens_model,
-                        exif.orientation
-                    );
-                    if let Some(gps) = &exif.gps_iso6709 {
-                        info!("EXIF GPS: {}", gps);
+                // Decode the neighbors in browse order while the user looks at this image
+                if prefetch_count > 0 && !is_stdin_path(&result.path) {
+                    if let Err(err) = directory.select(&result.path) {
+                        warn!("Failed to read folder of {:?}: {err}", result.path);
                     }
+                    request_dates_taken(&mut directory, &date_req_tx);
+                    let neighbors = directory.neighbors(prefetch_count);
+                    load_req_tx.send(LoadRequest::Prefetch(neighbors)).unwrap();
                 }
 
-                if let Some(icc) = &img.icc_info {
-                    info!(
-                        "ICC: size={} bytes, class={}, color_space={}, pcs={}, cmm={}, version={}, platform={}, manufacturer={}, model={}, intent={}, created={}",
-                        icc.size,
-                        icc.profile_class,
-                        icc.color_space,
-                        icc.pcs,
-                        icc.cmm_type,
-                        icc.version,
-                        icc.platform,
-                        icc.manufacturer,
-                        icc.model,
-                        icc.rendering_intent,
-                        icc.created.as_deref().unwrap_or("n/a")
-                    );
+                unsafe {
+                    InvalidateRect(main_window_handle as HWND, null_mut(), 1);
                 }
+            }
+            // Pages of an image the user has already navigated away from are dropped
+            Ok(LoaderMessage::Page(img, page, result)) => {
+                let is_current = state
+                    .loaded_image
+                    .as_ref()
+                    .is_some_and(|current| Arc::ptr_eq(current, &img));
+                if is_current {
+                    apply_page(&mut state, &mut main_window, &graphics, page, result);
+                }
+            }
+            Ok(LoaderMessage::DatesTaken(dates)) => {
+                directory.set_dates_taken(dates);
+            }
+            _ => {}
+        }
 
-                let image_load_time = Instant::now() - load_begin_time;
-                info!(
-                    "Time to load image {} ms ({:?})",
-                    to_milliseconds(image_load_time),
-                    dim
-                );
-            } else {
-                error!("Failed to load image: {:?}", img.err());
-            };
+        let xfm_viewport_to_image_uv = Transform2D::new_scale(1.0 / constants.image_dim);
 
-            unsafe {
-                InvalidateRect(main_window_handle as HWND, null_mut(), 1);
-            }
+        constants.display = state.display.to_float4(state.is_linear_image());
+
+        constants.window_dim.x = main_window.window_dim.0 as f32;
+        constants.window_dim.y = main_window.window_dim.1 as f32;
+
+        let xfm_window_to_image_quantized = state.displayed_xfm_window_to_image();
+
+        if state.update_inspector() {
+            main_window.set_window_name(&state.window_title());
         }
 
+        // Zoomed out images are sampled from the mip chain, magnified ones with the chosen filter
+        let minifying =
+            state.xfm_window_to_image.scale.x > 1.0 || state.xfm_window_to_image.scale.y > 1.0;
+        constants.sampling.x = minifying as u32 as f32;
+        constants.sampling.y = state.mag_filter.shader_index() as f32;
+        let srgb_texture = state
+            .displayed_texture()
+            .is_some_and(|texture| texture.format == UploadFormat::Rgba8Unorm);
+        constants.sampling.z = srgb_texture as u32 as f32;
+
+        // Previews are stretched over the full image, so they have no pixel values to label
+        let magnification = 1.0 / xfm_window_to_image_quantized.scale.x;
+        let label_format = match state.preview_dim {
+            Some(_) => None,
+            None => state.displayed_texture().map(|texture| texture.format),
+        };
+        constants.pixel_grid = state.pixel_grid.to_float4(magnification, label_format);
+        constants.pixel_grid_color = state.pixel_grid.color_float4();
+        constants.missing_pixels = state.missing_pixels();
+
+        let xfm_viewport_to_image_uv =
+            xfm_window_to_image_quantized.concatenate(xfm_viewport_to_image_uv);
+        constants.xfm_viewport_to_image_uv = xfm_viewport_to_image_uv.into();
+        constants.xfm_viewport_to_image_uv_dihedral = xfm_viewport_to_image_uv.dihedral.into();
+
         if state.frame_number == 0 {
             let init_time = Instant::now() - main_begin_time;
             info!("Init time: {:.2}ms", to_milliseconds(init_time));
@@ -914,15 +2289,6 @@ fn main() {
 
             let context = &graphics.context;
 
-            context.UpdateSubresource(
-                graphics.constants.as_ptr() as _,
-                0,
-                null_mut(),
-                &constants as *const Constants as _,
-                0,
-                0,
-            );
-
             let backbuffer = graphics
                 .backbuffer
                 .as_ref()
@@ -945,16 +2311,11 @@ fn main() {
             context.ClearRenderTargetView(backbuffer.rtv.as_ptr(), &clear_color);
 
             let cbvs: [*mut ID3D11Buffer; 1] = [graphics.constants.as_ptr()];
-            let srvs: [*mut ID3D11ShaderResourceView; 1] =
-                [if let Some(texture) = &state.texture {
-                    texture.srv.as_ptr()
-                } else {
-                    null_mut()
-                }];
-            let samplers: [*mut ID3D11SamplerState; 3] = [
-                graphics.smp_linear.as_ptr(), // g_default_sampler
-                graphics.smp_linear.as_ptr(), // g_linear_sampler
-                graphics.smp_point.as_ptr(),  // g_point_sampler
+            let samplers: [*mut ID3D11SamplerState; 4] = [
+                graphics.smp_linear.as_ptr(),      // g_default_sampler
+                graphics.smp_linear.as_ptr(),      // g_linear_sampler
+                graphics.smp_point.as_ptr(),       // g_point_sampler
+                graphics.smp_anisotropic.as_ptr(), // g_anisotropic_sampler
             ];
 
             context.PSSetConstantBuffers(0, cbvs.len() as u32, cbvs.as_ptr());
@@ -963,12 +2324,55 @@ fn main() {
             context.VSSetShader(graphics.blit_vs.as_ptr(), null_mut(), 0);
             context.PSSetShader(graphics.blit_ps.as_ptr(), null_mut(), 0);
 
-            context.VSSetShaderResources(0, srvs.len() as u32, srvs.as_ptr());
-            context.PSSetShaderResources(0, srvs.len() as u32, srvs.as_ptr());
             context.PSSetSamplers(0, samplers.len() as u32, samplers.as_ptr());
 
             context.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
-            context.Draw(3, 0);
+
+            // One pass per visible tile. Without any, a single pass still draws the background.
+            let texture = state.displayed_texture();
+            let image_pixels_per_pixel = state
+                .xfm_window_to_image
+                .scale
+                .x
+                .max(state.xfm_window_to_image.scale.y);
+            let visible_tiles = texture.map_or(Vec::new(), |texture| {
+                texture.visible_tiles(
+                    xfm_viewport_to_image_uv,
+                    state.active_orientation(),
+                    constants.window_dim,
+                    image_pixels_per_pixel,
+                )
+            });
+            let num_passes = visible_tiles.len().max(1);
+            for pass in 0..num_passes {
+                let tile =
+                    texture.and_then(|texture| Some((texture.dim, *visible_tiles.get(pass)?)));
+                let srvs: [*mut ID3D11ShaderResourceView; 1] = match tile {
+                    Some((image_dim, tile)) => {
+                        constants.tile_uv_rect = tile.tile.uv_rect(image_dim);
+                        constants.xfm_texture_uv_to_tile_uv =
+                            tile.tile.xfm_texture_uv_to_tile_uv(image_dim).into();
+                        [tile.srv.as_ptr()]
+                    }
+                    None => {
+                        constants.tile_uv_rect = FLOAT4_ZERO;
+                        [null_mut()]
+                    }
+                };
+
+                context.UpdateSubresource(
+                    graphics.constants.as_ptr() as _,
+                    0,
+                    null_mut(),
+                    &constants as *const Constants as _,
+                    0,
+                    0,
+                );
+
+                context.VSSetShaderResources(0, srvs.len() as u32, srvs.as_ptr());
+                context.PSSetShaderResources(0, srvs.len() as u32, srvs.as_ptr());
+                context.Draw(3, 0);
+            }
 
             context.ClearState();
 
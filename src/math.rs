@@ -46,10 +46,98 @@ impl Default for Box2D {
         }
     }
 }
+
+// Element of the dihedral group of the square: an optional XY swap followed by per-axis negation.
+// Covers identity, quarter turn rotations and mirrors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dihedral2D {
+    pub swap_xy: bool,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Dihedral2D {
+    pub const IDENTITY: Self = Self::new(false, false, false);
+    // Clockwise and counter-clockwise as seen on screen, where Y points down
+    pub const ROTATE_90_CW: Self = Self::new(true, true, false);
+    pub const ROTATE_180: Self = Self::new(false, true, true);
+    pub const ROTATE_90_CCW: Self = Self::new(true, false, true);
+    pub const FLIP_X: Self = Self::new(false, true, false);
+    pub const FLIP_Y: Self = Self::new(false, false, true);
+
+    pub const fn new(swap_xy: bool, flip_x: bool, flip_y: bool) -> Self {
+        Dihedral2D {
+            swap_xy,
+            flip_x,
+            flip_y,
+        }
+    }
+    pub fn apply(&self, v: float2) -> float2 {
+        let v = self.permute(v);
+        float2::new(
+            if self.flip_x { -v.x } else { v.x },
+            if self.flip_y { -v.y } else { v.y },
+        )
+    }
+    // Reorders components without changing their sign, used to carry per-axis scale through
+    pub fn permute(&self, v: float2) -> float2 {
+        if self.swap_xy {
+            float2::new(v.y, v.x)
+        } else {
+            v
+        }
+    }
+    pub fn inverse(&self) -> Self {
+        if self.swap_xy {
+            Self::new(true, self.flip_y, self.flip_x)
+        } else {
+            *self
+        }
+    }
+    // Result applies self first, then other
+    pub fn concatenate(&self, other: Self) -> Self {
+        if other.swap_xy {
+            Self::new(
+                !self.swap_xy,
+                self.flip_y ^ other.flip_x,
+                self.flip_x ^ other.flip_y,
+            )
+        } else {
+            Self::new(
+                self.swap_xy,
+                self.flip_x ^ other.flip_x,
+                self.flip_y ^ other.flip_y,
+            )
+        }
+    }
+    pub fn to_float4(self) -> float4 {
+        float4::new(
+            self.swap_xy as u32 as f32,
+            self.flip_x as u32 as f32,
+            self.flip_y as u32 as f32,
+            0.0,
+        )
+    }
+}
+
+impl From<Dihedral2D> for float4 {
+    fn from(val: Dihedral2D) -> Self {
+        val.to_float4()
+    }
+}
+
+impl Default for Dihedral2D {
+    fn default() -> Self {
+        Dihedral2D::IDENTITY
+    }
+}
+
+// Maps points as `dihedral.apply(p) * scale + offset`
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Transform2D {
     pub scale: float2,
     pub offset: float2,
+    pub dihedral: Dihedral2D,
 }
 
 impl Transform2D {
@@ -57,20 +145,36 @@ impl Transform2D {
         Transform2D {
             scale: FLOAT2_ONE,
             offset: FLOAT2_ZERO,
+            dihedral: Dihedral2D::IDENTITY,
         }
     }
     pub fn new_scale(scale: float2) -> Self {
         Transform2D {
             scale,
             offset: FLOAT2_ZERO,
+            dihedral: Dihedral2D::IDENTITY,
         }
     }
     pub fn new_translate(offset: float2) -> Self {
         Transform2D {
             scale: FLOAT2_ONE,
             offset,
+            dihedral: Dihedral2D::IDENTITY,
         }
     }
+    pub fn new_dihedral(dihedral: Dihedral2D) -> Self {
+        Transform2D {
+            scale: FLOAT2_ONE,
+            offset: FLOAT2_ZERO,
+            dihedral,
+        }
+    }
+    // Applies `dihedral` around `center`, keeping that point fixed
+    pub fn new_dihedral_around(dihedral: Dihedral2D, center: float2) -> Self {
+        Transform2D::new_translate(-center)
+            .concatenate(Transform2D::new_dihedral(dihedral))
+            .concatenate(Transform2D::new_translate(center))
+    }
     pub fn inplace_translate(&mut self, v: float2) {
         self.offset += v;
     }
@@ -80,29 +184,41 @@ impl Transform2D {
         res
     }
     pub fn transform_point(&self, v: float2) -> float2 {
-        v.mul_element_wise(self.scale) + self.offset
+        self.transform_vector(v) + self.offset
+    }
+    pub fn transform_vector(&self, v: float2) -> float2 {
+        self.dihedral.apply(v).mul_element_wise(self.scale)
     }
     pub fn transform_box(&self, r: Box2D) -> Box2D {
+        let a = self.transform_point(r.min);
+        let b = self.transform_point(r.max);
         Box2D {
-            min: self.transform_point(r.min),
-            max: self.transform_point(r.max),
+            min: float2::new(a.x.min(b.x), a.y.min(b.y)),
+            max: float2::new(a.x.max(b.x), a.y.max(b.y)),
         }
     }
     pub fn inverse(&self) -> Self {
+        let dihedral = self.dihedral.inverse();
         Self {
-            scale: 1.0 / self.scale,
-            offset: -self.offset.div_element_wise(self.scale),
+            scale: dihedral.permute(1.0 / self.scale),
+            offset: -dihedral.apply(self.offset.div_element_wise(self.scale)),
+            dihedral,
         }
     }
     pub fn concatenate(&self, other: Self) -> Self {
         Self {
-            scale: self.scale.mul_element_wise(other.scale),
-            offset: self.offset.mul_element_wise(other.scale) + other.offset,
+            scale: other
+                .dihedral
+                .permute(self.scale)
+                .mul_element_wise(other.scale),
+            offset: other.transform_vector(self.offset) + other.offset,
+            dihedral: self.dihedral.concatenate(other.dihedral),
         }
     }
     pub fn inplace_concatenate(&mut self, other: Self) {
         *self = self.concatenate(other);
     }
+    // Scale and offset only, dihedral part is passed separately via `Dihedral2D::to_float4`
     pub fn to_float4(self) -> float4 {
         float4::new(self.scale.x, self.scale.y, self.offset.x, self.offset.y)
     }
@@ -144,6 +260,7 @@ fn test_transform() {
         let t = Transform2D {
             scale: float2::new(2.0, 3.0),
             offset: float2::new(4.0, 5.0),
+            ..Default::default()
         };
         let pa = float2::new(123.0, 456.0);
         let pb = t.transform_point(pa);
@@ -154,6 +271,7 @@ fn test_transform() {
         let t = Transform2D {
             scale: float2::new(2.0, 3.0),
             offset: float2::new(4.0, 5.0),
+            ..Default::default()
         };
         let pa = float2::new(1.0, 1.0);
         let pb = t.transform_point(pa);
@@ -163,10 +281,12 @@ fn test_transform() {
         let ta = Transform2D {
             scale: float2::new(2.0, 3.0),
             offset: float2::new(4.0, 5.0),
+            ..Default::default()
         };
         let tb = Transform2D {
             scale: float2::new(3.0, 4.0),
             offset: float2::new(5.0, 6.0),
+            ..Default::default()
         };
         let tc = ta.concatenate(tb);
         let pa = float2::new(1.0, 1.0);
@@ -177,6 +297,7 @@ fn test_transform() {
         let t = Transform2D {
             scale: float2::new(1.0, 1.0),
             offset: float2::new(4.0, 5.0),
+            ..Default::default()
         };
         let b = Box2D {
             min: float2::new(0.0, 0.0),
@@ -187,3 +308,103 @@ fn test_transform() {
         assert_ulps_eq!(b2.max, float2::new(5.0, 6.0));
     }
 }
+
+#[test]
+fn test_dihedral() {
+    let all = [
+        Dihedral2D::IDENTITY,
+        Dihedral2D::ROTATE_90_CW,
+        Dihedral2D::ROTATE_180,
+        Dihedral2D::ROTATE_90_CCW,
+        Dihedral2D::FLIP_X,
+        Dihedral2D::FLIP_Y,
+        Dihedral2D::new(true, false, false),
+        Dihedral2D::new(true, true, true),
+    ];
+    let p = float2::new(2.0, 3.0);
+    for a in all {
+        assert_eq!(a.inverse().apply(a.apply(p)), p);
+        assert_eq!(a.concatenate(a.inverse()), Dihedral2D::IDENTITY);
+        for b in all {
+            assert_eq!(a.concatenate(b).apply(p), b.apply(a.apply(p)));
+        }
+    }
+
+    // +X maps to +Y (screen down) when rotating clockwise
+    assert_eq!(
+        Dihedral2D::ROTATE_90_CW.apply(float2::new(1.0, 0.0)),
+        float2::new(0.0, 1.0)
+    );
+    assert_eq!(
+        Dihedral2D::ROTATE_90_CW.concatenate(Dihedral2D::ROTATE_90_CW),
+        Dihedral2D::ROTATE_180
+    );
+    assert_eq!(
+        Dihedral2D::ROTATE_90_CW.inverse(),
+        Dihedral2D::ROTATE_90_CCW
+    );
+    assert_eq!(
+        Dihedral2D::FLIP_X.concatenate(Dihedral2D::FLIP_Y),
+        Dihedral2D::ROTATE_180
+    );
+}
+
+#[test]
+fn test_transform_dihedral() {
+    {
+        let t = Transform2D {
+            scale: float2::new(2.0, 3.0),
+            offset: float2::new(4.0, 5.0),
+            dihedral: Dihedral2D::ROTATE_90_CW,
+        };
+        let pa = float2::new(1.0, 1.0);
+        let pb = t.transform_point(pa);
+        assert_ulps_eq!(pb, float2::new(2.0, 8.0));
+        let pc = t.inverse().transform_point(pb);
+        assert_ulps_eq!(pa, pc);
+    }
+    {
+        let ta = Transform2D {
+            scale: float2::new(2.0, 3.0),
+            offset: float2::new(4.0, 5.0),
+            dihedral: Dihedral2D::FLIP_X,
+        };
+        let tb = Transform2D {
+            scale: float2::new(3.0, 4.0),
+            offset: float2::new(5.0, 6.0),
+            dihedral: Dihedral2D::ROTATE_90_CCW,
+        };
+        let tc = ta.concatenate(tb);
+        let pa = float2::new(123.0, 456.0);
+        assert_ulps_eq!(
+            tc.transform_point(pa),
+            tb.transform_point(ta.transform_point(pa))
+        );
+        assert_ulps_eq!(tc.inverse().transform_point(tc.transform_point(pa)), pa);
+        assert_eq!(tc.dihedral, Dihedral2D::new(true, false, false));
+    }
+    {
+        // Rotating around a point keeps it fixed
+        let center = float2::new(10.0, 20.0);
+        let t = Transform2D::new_dihedral_around(Dihedral2D::ROTATE_90_CW, center);
+        assert_ulps_eq!(t.transform_point(center), center);
+        assert_ulps_eq!(
+            t.transform_point(center + float2::new(1.0, 0.0)),
+            center + float2::new(0.0, 1.0)
+        );
+    }
+    {
+        let t = Transform2D {
+            scale: float2::new(1.0, 2.0),
+            offset: float2::new(4.0, 5.0),
+            dihedral: Dihedral2D::ROTATE_180,
+        };
+        let b = Box2D {
+            min: float2::new(0.0, 0.0),
+            max: float2::new(1.0, 1.0),
+        };
+        let b2 = t.transform_box(b);
+        assert_ulps_eq!(b2.min, float2::new(3.0, 3.0));
+        assert_ulps_eq!(b2.max, float2::new(4.0, 5.0));
+    }
+}
@@ -0,0 +1,513 @@
+use image::DynamicImage;
+use std::borrow::Cow;
+
+use crate::loader::LoadedImage;
+use crate::tiles::{PixelRect, TileGrid, TILE_MIP_COUNT};
+use crate::upload::{prepare_upload, UploadFormat, UploadImage, UploadPixels};
+
+// Mip levels are averaged in linear light, weighted by alpha so that transparent pixels don't
+// bleed their color into the visible ones. Integer formats hold sRGB encoded colors, float images
+// are already linear.
+
+pub fn mip_count(dim: (u32, u32)) -> u32 {
+    32 - dim.0.max(dim.1).max(1).leading_zeros()
+}
+
+fn next_mip_dim(dim: (u32, u32)) -> (u32, u32) {
+    ((dim.0 / 2).max(1), (dim.1 / 2).max(1))
+}
+
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+trait Texel: Copy {
+    // Linear value of every encoded value, indexed by the texel for integer formats
+    fn decode_table() -> Vec<f32>;
+    fn decode(self, table: &[f32]) -> f32;
+    fn decode_alpha(self) -> f32;
+    fn encode(linear: f32) -> Self;
+    fn encode_alpha(alpha: f32) -> Self;
+}
+
+impl Texel for u8 {
+    fn decode_table() -> Vec<f32> {
+        (0..=255)
+            .map(|i| srgb_to_linear(i as f32 / 255.0))
+            .collect()
+    }
+    fn decode(self, table: &[f32]) -> f32 {
+        table[self as usize]
+    }
+    fn decode_alpha(self) -> f32 {
+        self as f32 / 255.0
+    }
+    fn encode(linear: f32) -> Self {
+        Self::encode_alpha(linear_to_srgb(linear))
+    }
+    fn encode_alpha(alpha: f32) -> Self {
+        (alpha.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
+
+impl Texel for u16 {
+    fn decode_table() -> Vec<f32> {
+        (0..=65535)
+            .map(|i| srgb_to_linear(i as f32 / 65535.0))
+            .collect()
+    }
+    fn decode(self, table: &[f32]) -> f32 {
+        table[self as usize]
+    }
+    fn decode_alpha(self) -> f32 {
+        self as f32 / 65535.0
+    }
+    fn encode(linear: f32) -> Self {
+        Self::encode_alpha(linear_to_srgb(linear))
+    }
+    fn encode_alpha(alpha: f32) -> Self {
+        (alpha.clamp(0.0, 1.0) * 65535.0).round() as u16
+    }
+}
+
+impl Texel for f32 {
+    fn decode_table() -> Vec<f32> {
+        Vec::new()
+    }
+    fn decode(self, _table: &[f32]) -> f32 {
+        self
+    }
+    fn decode_alpha(self) -> f32 {
+        self
+    }
+    fn encode(linear: f32) -> Self {
+        linear
+    }
+    fn encode_alpha(alpha: f32) -> Self {
+        alpha
+    }
+}
+
+// Source pixels each destination pixel covers along one axis, with the length of the overlap.
+// Odd sizes have destination pixels that straddle a source pixel, which then contributes to both.
+fn box_filter_taps(src_size: u32, dst_size: u32) -> Vec<(usize, Vec<f32>)> {
+    let ratio = src_size as f64 / dst_size as f64;
+    (0..dst_size)
+        .map(|i| {
+            let begin = i as f64 * ratio;
+            let end = begin + ratio;
+            let first = begin.floor() as usize;
+            let last = (end.ceil() as usize).min(src_size as usize);
+            let weights = (first..last)
+                .map(|j| (end.min(j as f64 + 1.0) - begin.max(j as f64)) as f32)
+                .collect();
+            (first, weights)
+        })
+        .collect()
+}
+
+// Colors are weighted by alpha plus this bias, so fully transparent areas still average their
+// colors instead of turning black
+const ALPHA_BIAS: f32 = 1e-9;
+
+// Linear color weighted by biased alpha, and alpha
+fn decode_row<T: Texel>(src: &[T], table: &[f32], dst: &mut [f32]) {
+    for (texel, linear) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        let alpha = texel[3].decode_alpha();
+        let weight = alpha + ALPHA_BIAS;
+        linear[0] = texel[0].decode(table) * weight;
+        linear[1] = texel[1].decode(table) * weight;
+        linear[2] = texel[2].decode(table) * weight;
+        linear[3] = alpha;
+    }
+}
+
+// `src` holds rows of `pitch` components starting at the top left pixel of the level
+fn downsample<T: Texel>(
+    src: &[T],
+    pitch: usize,
+    src_dim: (u32, u32),
+    dst_dim: (u32, u32),
+    table: &[f32],
+) -> Vec<T> {
+    let taps_x = box_filter_taps(src_dim.0, dst_dim.0);
+    let taps_y = box_filter_taps(src_dim.1, dst_dim.1);
+    let row_len = src_dim.0 as usize * 4;
+    let mut decoded = vec![0.0f32; row_len];
+    let mut sums = vec![0.0f32; dst_dim.0 as usize * 4];
+    let mut dst = Vec::with_capacity(dst_dim.0 as usize * dst_dim.1 as usize * 4);
+    for (first_y, weights_y) in &taps_y {
+        sums.iter_mut().for_each(|sum| *sum = 0.0);
+        for (y, weight_y) in weights_y.iter().enumerate() {
+            let row = (first_y + y) * pitch;
+            decode_row(&src[row..row + row_len], table, &mut decoded);
+            for (sum, (first_x, weights_x)) in sums.chunks_exact_mut(4).zip(&taps_x) {
+                let texels = decoded[first_x * 4..].chunks_exact(4);
+                for (texel, weight_x) in texels.zip(weights_x) {
+                    let weight = weight_y * weight_x;
+                    for c in 0..4 {
+                        sum[c] += texel[c] * weight;
+                    }
+                }
+            }
+        }
+        let total_y: f32 = weights_y.iter().sum();
+        for (sum, (_, weights_x)) in sums.chunks_exact(4).zip(&taps_x) {
+            let total = total_y * weights_x.iter().sum::<f32>();
+            let color_weight = sum[3] + ALPHA_BIAS * total;
+            dst.push(T::encode(sum[0] / color_weight));
+            dst.push(T::encode(sum[1] / color_weight));
+            dst.push(T::encode(sum[2] / color_weight));
+            dst.push(T::encode_alpha(sum[3] / total));
+        }
+    }
+    dst
+}
+
+// Levels of `rect` downsampled to each of `dims` in turn, every level from the one before
+fn generate<T: Texel>(
+    pixels: &[T],
+    pitch: usize,
+    rect: PixelRect,
+    dims: &[(u32, u32)],
+) -> Vec<((u32, u32), Vec<T>)> {
+    let table = T::decode_table();
+    let mut levels: Vec<((u32, u32), Vec<T>)> = Vec::new();
+    let mut dim = rect.dim();
+    for &next_dim in dims {
+        let level = match levels.last() {
+            Some((_, src)) => downsample(src, dim.0 as usize * 4, dim, next_dim, &table),
+            None => {
+                let offset = rect.min.1 as usize * pitch + rect.min.0 as usize * 4;
+                downsample(&pixels[offset..], pitch, dim, next_dim, &table)
+            }
+        };
+        levels.push((next_dim, level));
+        dim = next_dim;
+    }
+    levels
+}
+
+fn into_upload_images<T>(
+    format: UploadFormat,
+    levels: Vec<((u32, u32), Vec<T>)>,
+    to_pixels: impl Fn(Vec<T>) -> UploadPixels<'static>,
+) -> Vec<UploadImage<'static>> {
+    levels
+        .into_iter()
+        .map(|(dim, pixels)| UploadImage {
+            format,
+            dim,
+            pixels: to_pixels(pixels),
+        })
+        .collect()
+}
+
+fn generate_levels(
+    image: &UploadImage,
+    rect: PixelRect,
+    dims: &[(u32, u32)],
+) -> Vec<UploadImage<'static>> {
+    let pitch = image.dim.0 as usize * 4;
+    match &image.pixels {
+        UploadPixels::U8(data) => {
+            into_upload_images(image.format, generate(data, pitch, rect, dims), |p| {
+                UploadPixels::U8(Cow::Owned(p))
+            })
+        }
+        UploadPixels::U16(data) => {
+            into_upload_images(image.format, generate(data, pitch, rect, dims), |p| {
+                UploadPixels::U16(Cow::Owned(p))
+            })
+        }
+        UploadPixels::F32(data) => {
+            into_upload_images(image.format, generate(data, pitch, rect, dims), |p| {
+                UploadPixels::F32(Cow::Owned(p))
+            })
+        }
+    }
+}
+
+// Dimensions of the levels after `dim` down to 1x1
+fn smaller_mip_dims(dim: (u32, u32)) -> Vec<(u32, u32)> {
+    std::iter::successors(Some(dim), |&dim| Some(next_mip_dim(dim)))
+        .skip(1)
+        .take(mip_count(dim) as usize - 1)
+        .collect()
+}
+
+// Levels 1 and up of the mip chain of `rect` in the image, level 0 is the image itself
+pub fn generate_mips(image: &UploadImage, rect: PixelRect) -> Vec<UploadImage<'static>> {
+    profiling::scope!("generate_mips");
+    generate_levels(image, rect, &smaller_mip_dims(rect.dim()))
+}
+
+// Mip chain of the whole image from the last level tiles have, `TILE_MIP_COUNT - 1`, down to
+// 1x1. The first level is downsampled from the image in one pass.
+pub fn generate_overview(image: &UploadImage) -> Vec<UploadImage<'static>> {
+    profiling::scope!("generate_overview");
+    let shift = TILE_MIP_COUNT - 1;
+    let first_dim = ((image.dim.0 >> shift).max(1), (image.dim.1 >> shift).max(1));
+    let mut dims = vec![first_dim];
+    dims.extend(smaller_mip_dims(first_dim));
+    let whole_image = PixelRect {
+        min: (0, 0),
+        max: image.dim,
+    };
+    generate_levels(image, whole_image, &dims)
+}
+
+// Mip chains of the tiles of an image, see `TileGrid`. They are built on the loader thread and
+// cached with the image, so creating the textures only copies pixels.
+#[derive(Default)]
+pub struct ImageMips {
+    // Levels 1 and up of every tile, in the order of `TileGrid::tiles`
+    pub tiles: Vec<Vec<UploadImage<'static>>>,
+    // Mip chain of the whole image from the last level of the tiles, only for tiled images.
+    // Views zoomed out further draw it instead of the tiles, see `TileGrid::uses_overview`.
+    pub overview: Vec<UploadImage<'static>>,
+}
+
+impl ImageMips {
+    pub fn new(image: &DynamicImage) -> Self {
+        let upload = prepare_upload(image);
+        let grid = TileGrid::new(upload.dim);
+        let tiles = grid
+            .tiles()
+            .map(|tile| {
+                let mut mips = generate_mips(&upload, tile.texture_rect);
+                if grid.border > 0 {
+                    mips.truncate(TILE_MIP_COUNT as usize - 1);
+                }
+                mips
+            })
+            .collect();
+        let overview = if grid.border > 0 {
+            generate_overview(&upload)
+        } else {
+            Vec::new()
+        };
+        Self { tiles, overview }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.tiles
+            .iter()
+            .flatten()
+            .chain(&self.overview)
+            .map(|mip| mip.row_pitch() as usize * mip.dim.1 as usize)
+            .sum()
+    }
+}
+
+// Mips of every image of a `LoadedImage` that the viewer makes a texture of
+#[derive(Default)]
+pub struct LoadedMips {
+    pub image: ImageMips,
+    pub unmanaged_image: Option<ImageMips>,
+    pub subimages: Vec<ImageMips>,
+    pub frames: Vec<ImageMips>,
+    pub unmanaged_frames: Vec<Option<ImageMips>>,
+}
+
+impl LoadedMips {
+    pub fn new(img: &LoadedImage) -> Self {
+        profiling::scope!("LoadedMips::new");
+        let frames = img.animation.as_ref().map_or(&[][..], |a| &a.frames[..]);
+        Self {
+            image: ImageMips::new(&img.image),
+            unmanaged_image: img.unmanaged_image.as_ref().map(ImageMips::new),
+            subimages: img
+                .subimages
+                .as_ref()
+                .map_or_else(Vec::new, |s| s.images.iter().map(ImageMips::new).collect()),
+            frames: frames.iter().map(|f| ImageMips::new(&f.image)).collect(),
+            unmanaged_frames: frames
+                .iter()
+                .map(|f| f.unmanaged_image.as_ref().map(ImageMips::new))
+                .collect(),
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        let optional = |mips: &Option<ImageMips>| mips.as_ref().map_or(0, ImageMips::memory_size);
+        let sum = |mips: &[ImageMips]| mips.iter().map(ImageMips::memory_size).sum::<usize>();
+        self.image.memory_size()
+            + optional(&self.unmanaged_image)
+            + sum(&self.subimages)
+            + sum(&self.frames)
+            + self.unmanaged_frames.iter().map(optional).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+fn whole_image(image: &UploadImage) -> PixelRect {
+    PixelRect {
+        min: (0, 0),
+        max: image.dim,
+    }
+}
+
+#[test]
+fn test_mip_chain() {
+    assert_eq!(mip_count((1, 1)), 1);
+    assert_eq!(mip_count((2, 1)), 2);
+    assert_eq!(mip_count((7, 3)), 3);
+    assert_eq!(mip_count((8192, 8200)), 14);
+
+    // Odd dimensions round down and every level is complete
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(7, 5, |x, y| {
+        image::Rgba([(x * 30) as u8, (y * 50) as u8, 128, 255])
+    }));
+    let upload = crate::upload::prepare_upload(&image);
+    let mips = generate_mips(&upload, whole_image(&upload));
+    let dims: Vec<(u32, u32)> = mips.iter().map(|m| m.dim).collect();
+    assert_eq!(dims, vec![(3, 2), (1, 1)]);
+    for mip in &mips {
+        assert_eq!(mip.format, UploadFormat::Rgba8Unorm);
+        match &mip.pixels {
+            UploadPixels::U8(data) => assert_eq!(data.len(), (mip.dim.0 * mip.dim.1 * 4) as usize),
+            _ => panic!("expected 8 bit pixels"),
+        }
+    }
+
+    // 7 pixels into 3 covers 7/3 pixels each, the pixels on a boundary are split between the
+    // two sides
+    let taps = box_filter_taps(7, 3);
+    assert_eq!(taps[0].0, 0);
+    assert_eq!(taps[1].0, 2);
+    assert_eq!(taps[2].0, 4);
+    let sums: Vec<f32> = taps.iter().map(|(_, w)| w.iter().sum()).collect();
+    for sum in sums {
+        assert!((sum - 7.0 / 3.0).abs() < 1e-5);
+    }
+    assert!((taps[1].1[0] - 2.0 / 3.0).abs() < 1e-5);
+
+    // 3 pixels go into one, with equal weights
+    let linear = |values: &[f32]| {
+        image::DynamicImage::ImageRgba32F(image::ImageBuffer::from_fn(
+            values.len() as u32,
+            1,
+            |x, _| image::Rgba([values[x as usize], 0.0, 0.0, 1.0]),
+        ))
+    };
+    let image = linear(&[1.0, 2.0, 4.0]);
+    let upload = crate::upload::prepare_upload(&image);
+    let mips = generate_mips(&upload, whole_image(&upload));
+    match &mips[0].pixels {
+        UploadPixels::F32(data) => assert!((data[0] - (1.0 + 2.0 + 4.0) / 3.0).abs() < 1e-5),
+        _ => panic!("expected float pixels"),
+    }
+
+    // Tiles downsample their own part of the image
+    let image = linear(&[1.0, 1.0, 5.0, 7.0]);
+    let upload = crate::upload::prepare_upload(&image);
+    let rect = PixelRect {
+        min: (2, 0),
+        max: (4, 1),
+    };
+    let mips = generate_mips(&upload, rect);
+    assert_eq!(mips.len(), 1);
+    match &mips[0].pixels {
+        UploadPixels::F32(data) => assert_eq!(&data[..], &[6.0, 0.0, 0.0, 1.0]),
+        _ => panic!("expected float pixels"),
+    }
+}
+
+#[test]
+fn test_mip_filtering() {
+    // Black and white average to middle grey in linear light, not to 128
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(2, 2, |x, _| {
+        image::Rgba([x as u8 * 255, x as u8 * 255, x as u8 * 255, 255])
+    }));
+    let upload = crate::upload::prepare_upload(&image);
+    let mips = generate_mips(&upload, whole_image(&upload));
+    match &mips[0].pixels {
+        UploadPixels::U8(data) => assert_eq!(&data[..], &[188, 188, 188, 255]),
+        _ => panic!("expected 8 bit pixels"),
+    }
+
+    // Transparent pixels don't contribute their color, but do lower the alpha
+    let image = image::DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(2, 2, |x, y| {
+        if x == 0 && y == 0 {
+            image::Rgba([65535, 0, 0, 65535])
+        } else {
+            image::Rgba([0, 65535, 0, 0])
+        }
+    }));
+    let upload = crate::upload::prepare_upload(&image);
+    let mips = generate_mips(&upload, whole_image(&upload));
+    match &mips[0].pixels {
+        UploadPixels::U16(data) => assert_eq!(&data[..], &[65535, 0, 0, 16384]),
+        _ => panic!("expected 16 bit pixels"),
+    }
+
+    // Fully transparent areas keep their color
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        3,
+        3,
+        image::Rgba([10, 200, 30, 0]),
+    ));
+    let upload = crate::upload::prepare_upload(&image);
+    let mips = generate_mips(&upload, whole_image(&upload));
+    match &mips[0].pixels {
+        UploadPixels::U8(data) => assert_eq!(&data[..], &[10, 200, 30, 0]),
+        _ => panic!("expected 8 bit pixels"),
+    }
+
+    // Partial coverage weights colors by alpha: a quarter opaque white over opaque black
+    let image = image::DynamicImage::ImageRgba32F(image::ImageBuffer::from_fn(2, 1, |x, _| {
+        if x == 0 {
+            image::Rgba([1.0, 1.0, 1.0, 0.25])
+        } else {
+            image::Rgba([0.0, 0.0, 0.0, 0.75])
+        }
+    }));
+    let upload = crate::upload::prepare_upload(&image);
+    let mips = generate_mips(&upload, whole_image(&upload));
+    match &mips[0].pixels {
+        UploadPixels::F32(data) => assert_eq!(&data[..], &[0.25, 0.25, 0.25, 0.5]),
+        _ => panic!("expected float pixels"),
+    }
+}
+
+#[test]
+fn test_image_mips() {
+    let image = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(7, 3, image::Luma([9])));
+    let mips = ImageMips::new(&image);
+    assert_eq!(mips.tiles.len(), 1);
+    assert!(mips.overview.is_empty());
+    let dims: Vec<_> = mips.tiles[0].iter().map(|mip| mip.dim).collect();
+    assert_eq!(dims, [(3, 1), (1, 1)]);
+    assert_eq!(mips.memory_size(), (3 + 1) * 4);
+
+    // Tiles stop at the last level that keeps part of their border
+    let wide = DynamicImage::ImageLuma8(image::GrayImage::new(16385, 1));
+    let mips = ImageMips::new(&wide);
+    assert_eq!(mips.tiles.len(), 3);
+    for tile in &mips.tiles {
+        assert_eq!(tile.len() as u32, TILE_MIP_COUNT - 1);
+    }
+    // The overview continues from the size of the last tile level down to 1x1
+    let dims: Vec<_> = mips.overview.iter().map(|mip| mip.dim).collect();
+    assert_eq!(dims[0], (16385 >> (TILE_MIP_COUNT - 1), 1));
+    assert_eq!(dims.len(), 9);
+    assert_eq!(dims.last(), Some(&(1, 1)));
+
+    let mut img = LoadedImage::from_image(image);
+    let size = img.memory_size();
+    img.generate_mips();
+    assert_eq!(img.memory_size(), size + 16);
+}
@@ -0,0 +1,130 @@
+use image::metadata::Orientation;
+
+use crate::math::*;
+
+// Orientation is applied when sampling the image, so texture contents always match the decoded
+// pixels. Everything outside of the shader works in oriented (displayed) image space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewOrientation {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub transpose: bool,
+}
+
+impl ViewOrientation {
+    pub fn new_identity() -> Self {
+        Self {
+            flip_x: false,
+            flip_y: false,
+            transpose: false,
+        }
+    }
+
+    // Flips are applied in oriented space first, followed by the optional transpose
+    pub fn from_exif(orientation: Orientation) -> Self {
+        let (flip_x, flip_y, transpose) = match orientation {
+            Orientation::NoTransforms => (false, false, false),
+            Orientation::FlipHorizontal => (true, false, false),
+            Orientation::Rotate180 => (true, true, false),
+            Orientation::FlipVertical => (false, true, false),
+            Orientation::Rotate90FlipH => (false, false, true), // transpose
+            Orientation::Rotate90 => (true, false, true),
+            Orientation::Rotate270FlipH => (true, true, true), // transverse
+            Orientation::Rotate270 => (false, true, true),
+        };
+        Self {
+            flip_x,
+            flip_y,
+            transpose,
+        }
+    }
+
+    pub fn oriented_dim(&self, dim: (u32, u32)) -> (u32, u32) {
+        if self.transpose {
+            (dim.1, dim.0)
+        } else {
+            dim
+        }
+    }
+
+    pub fn oriented_uv_to_texture_uv(&self, uv: float2) -> float2 {
+        let mut res = uv;
+        if self.flip_x {
+            res.x = 1.0 - res.x;
+        }
+        if self.flip_y {
+            res.y = 1.0 - res.y;
+        }
+        if self.transpose {
+            res = float2::new(res.y, res.x);
+        }
+        res
+    }
+
+    pub fn to_float4(self) -> float4 {
+        float4::new(
+            self.flip_x as u32 as f32,
+            self.flip_y as u32 as f32,
+            self.transpose as u32 as f32,
+            0.0,
+        )
+    }
+}
+
+impl From<ViewOrientation> for float4 {
+    fn from(val: ViewOrientation) -> Self {
+        val.to_float4()
+    }
+}
+
+impl Default for ViewOrientation {
+    fn default() -> Self {
+        ViewOrientation::new_identity()
+    }
+}
+
+#[test]
+fn test_view_orientation() {
+    // Sample a 3x2 raw image with distinct values at every pixel through each orientation and
+    // compare against the reference result of image::DynamicImage::apply_orientation.
+    let raw = image::RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+    let orientations = [
+        Orientation::NoTransforms,
+        Orientation::Rotate90,
+        Orientation::Rotate180,
+        Orientation::Rotate270,
+        Orientation::FlipHorizontal,
+        Orientation::FlipVertical,
+        Orientation::Rotate90FlipH,
+        Orientation::Rotate270FlipH,
+    ];
+    for orientation in orientations {
+        let mut expected = image::DynamicImage::ImageRgba8(raw.clone());
+        expected.apply_orientation(orientation);
+        let expected = expected.into_rgba8();
+
+        let view = ViewOrientation::from_exif(orientation);
+        let dim = view.oriented_dim(raw.dimensions());
+        assert_eq!(dim, expected.dimensions(), "{:?}", orientation);
+
+        for y in 0..dim.1 {
+            for x in 0..dim.0 {
+                let uv = float2::new(
+                    (x as f32 + 0.5) / dim.0 as f32,
+                    (y as f32 + 0.5) / dim.1 as f32,
+                );
+                let tex_uv = view.oriented_uv_to_texture_uv(uv);
+                let tex_x = (tex_uv.x * raw.width() as f32) as u32;
+                let tex_y = (tex_uv.y * raw.height() as f32) as u32;
+                assert_eq!(
+                    raw.get_pixel(tex_x, tex_y),
+                    expected.get_pixel(x, y),
+                    "{:?} at {},{}",
+                    orientation,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
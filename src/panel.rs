@@ -0,0 +1,155 @@
+use crate::window::to_wide_string;
+use std::ptr::null_mut;
+use winapi::shared::minwindef::{HIWORD, LOWORD, LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::ntdef::LPCWSTR;
+use winapi::shared::windef::{HBRUSH, HMENU, HWND};
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::wingdi::{
+    CreateFontW, CLEARTYPE_QUALITY, CLIP_DEFAULT_PRECIS, DEFAULT_CHARSET, FF_MODERN, FIXED_PITCH,
+    FW_NORMAL, OUT_DEFAULT_PRECIS,
+};
+use winapi::um::winuser::*;
+
+const PANEL_EDIT_ID: i32 = 1;
+
+unsafe extern "system" fn metadata_panel_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            let edit_class = to_wide_string("EDIT");
+            let edit = CreateWindowExW(
+                0,
+                edit_class.as_ptr(),
+                null_mut(),
+                WS_CHILD
+                    | WS_VISIBLE
+                    | WS_VSCROLL
+                    | WS_HSCROLL
+                    | ES_MULTILINE as u32
+                    | ES_READONLY as u32
+                    | ES_AUTOVSCROLL as u32
+                    | ES_AUTOHSCROLL as u32,
+                0,
+                0,
+                0,
+                0,
+                hwnd,
+                PANEL_EDIT_ID as HMENU,
+                GetModuleHandleW(null_mut()),
+                null_mut(),
+            );
+            let font_name = to_wide_string("Consolas");
+            let font = CreateFontW(
+                -14,
+                0,
+                0,
+                0,
+                FW_NORMAL as _,
+                0,
+                0,
+                0,
+                DEFAULT_CHARSET as _,
+                OUT_DEFAULT_PRECIS as _,
+                CLIP_DEFAULT_PRECIS as _,
+                CLEARTYPE_QUALITY as _,
+                (FIXED_PITCH | FF_MODERN) as _,
+                font_name.as_ptr(),
+            );
+            SendMessageW(edit, WM_SETFONT, font as WPARAM, 0);
+        }
+        WM_SIZE => {
+            let width = LOWORD(lparam as u32) as i32;
+            let height = HIWORD(lparam as u32) as i32;
+            MoveWindow(GetDlgItem(hwnd, PANEL_EDIT_ID), 0, 0, width, height, 1);
+            return 0;
+        }
+        // Closing only hides the panel so that it can be toggled again
+        WM_CLOSE => {
+            ShowWindow(hwnd, SW_HIDE);
+            return 0;
+        }
+        _ => {}
+    }
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+// Read-only text window listing the metadata of the current image, next to the viewer window.
+// It must be created on the window thread, which runs the message loop, but can be updated
+// from any thread.
+pub struct MetadataPanel {
+    hwnd: HWND,
+}
+
+unsafe impl std::marker::Send for MetadataPanel {}
+
+impl MetadataPanel {
+    pub unsafe fn new(owner: HWND) -> Option<Self> {
+        let hinst = GetModuleHandleW(null_mut());
+        let class_name = to_wide_string("imgv_metadata_panel_class");
+        let window_class = WNDCLASSW {
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(metadata_panel_proc),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: hinst,
+            hIcon: null_mut(),
+            hCursor: LoadCursorW(null_mut(), IDC_ARROW),
+            hbrBackground: (COLOR_WINDOW + 1) as HBRUSH,
+            lpszMenuName: 0 as LPCWSTR,
+            lpszClassName: class_name.as_ptr(),
+        };
+        if RegisterClassW(&window_class) == 0 {
+            return None;
+        }
+
+        let title = to_wide_string("Metadata");
+        let hwnd = CreateWindowExW(
+            WS_EX_TOOLWINDOW,
+            class_name.as_ptr(),
+            title.as_ptr(),
+            WS_OVERLAPPEDWINDOW,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            560,
+            720,
+            owner,
+            null_mut(),
+            hinst,
+            null_mut(),
+        );
+        if hwnd.is_null() {
+            None
+        } else {
+            Some(Self { hwnd })
+        }
+    }
+
+    pub fn set_text(&self, text: &str) {
+        // Edit controls only break lines at CRLF
+        let text = text.replace('\n', "\r\n");
+        unsafe {
+            let edit = GetDlgItem(self.hwnd, PANEL_EDIT_ID);
+            SetWindowTextW(edit, to_wide_string(&text).as_ptr());
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        unsafe { IsWindowVisible(self.hwnd) != 0 }
+    }
+
+    // Shows the panel without taking keyboard focus away from the viewer
+    pub fn toggle(&self) {
+        let command = if self.is_visible() {
+            SW_HIDE
+        } else {
+            SW_SHOWNOACTIVATE
+        };
+        unsafe {
+            ShowWindow(self.hwnd, command);
+        }
+    }
+}
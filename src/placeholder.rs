@@ -0,0 +1,129 @@
+use crate::window::to_wide_string;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::ptr::null_mut;
+use winapi::ctypes::c_void;
+use winapi::shared::windef::{HDC, HFONT, RECT};
+use winapi::um::wingdi::*;
+use winapi::um::winuser::{
+    DrawTextW, DT_CENTER, DT_NOPREFIX, DT_PATH_ELLIPSIS, DT_SINGLELINE, DT_WORDBREAK,
+};
+
+const PLACEHOLDER_WIDTH: u32 = 640;
+const PLACEHOLDER_HEIGHT: u32 = 360;
+
+// Shown instead of a file that failed to load: the file name and the reason over diagonal
+// stripes. The text is drawn with GDI since the renderer only knows how to draw images.
+pub fn error_placeholder_image(file_name: &str, reason: &str) -> DynamicImage {
+    let mut image = RgbaImage::from_fn(PLACEHOLDER_WIDTH, PLACEHOLDER_HEIGHT, |x, y| {
+        if (x + y) / 16 % 2 == 0 {
+            Rgba([56, 28, 28, 255])
+        } else {
+            Rgba([44, 22, 22, 255])
+        }
+    });
+    unsafe {
+        draw_error_text(&mut image, file_name, reason);
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+// Leaves the image untouched if any of the GDI objects can't be created
+unsafe fn draw_error_text(image: &mut RgbaImage, file_name: &str, reason: &str) {
+    let (width, height) = image.dimensions();
+    let mut info: BITMAPINFO = std::mem::zeroed();
+    info.bmiHeader.biSize = std::mem::size_of::<BITMAPINFOHEADER>() as u32;
+    info.bmiHeader.biWidth = width as i32;
+    // Negative height for top-down rows, like the image
+    info.bmiHeader.biHeight = -(height as i32);
+    info.bmiHeader.biPlanes = 1;
+    info.bmiHeader.biBitCount = 32;
+    info.bmiHeader.biCompression = BI_RGB;
+
+    let dc = CreateCompatibleDC(null_mut());
+    if dc.is_null() {
+        return;
+    }
+    let mut bits: *mut c_void = null_mut();
+    let bitmap = CreateDIBSection(dc, &info, DIB_RGB_COLORS, &mut bits, null_mut(), 0);
+    if bitmap.is_null() || bits.is_null() {
+        DeleteDC(dc);
+        return;
+    }
+    let pixels = std::slice::from_raw_parts_mut(bits as *mut u8, (width * height * 4) as usize);
+    for (bgra, rgba) in pixels.chunks_exact_mut(4).zip(image.pixels()) {
+        bgra.copy_from_slice(&[rgba[2], rgba[1], rgba[0], 255]);
+    }
+    let old_bitmap = SelectObject(dc, bitmap as _);
+
+    SetBkMode(dc, TRANSPARENT as _);
+    SetTextColor(dc, RGB(255, 255, 255));
+    let margin = 24;
+    let center = height as i32 / 2;
+    let mut name_rect = RECT {
+        left: margin,
+        top: center - 48,
+        right: width as i32 - margin,
+        bottom: center - 8,
+    };
+    let name_font = create_font(-26, FW_BOLD);
+    let old_font = SelectObject(dc, name_font as _);
+    // Long paths keep their file name and lose folders in the middle
+    draw_text(
+        dc,
+        file_name,
+        &mut name_rect,
+        DT_CENTER | DT_SINGLELINE | DT_PATH_ELLIPSIS | DT_NOPREFIX,
+    );
+    let mut reason_rect = RECT {
+        left: margin,
+        top: center + 8,
+        right: width as i32 - margin,
+        bottom: height as i32 - margin,
+    };
+    let reason_font = create_font(-18, FW_NORMAL);
+    SelectObject(dc, reason_font as _);
+    draw_text(
+        dc,
+        reason,
+        &mut reason_rect,
+        DT_CENTER | DT_WORDBREAK | DT_NOPREFIX,
+    );
+    GdiFlush();
+
+    // Text drawing clears the alpha channel of the pixels it touches
+    for (rgba, bgra) in image.pixels_mut().zip(pixels.chunks_exact(4)) {
+        *rgba = Rgba([bgra[2], bgra[1], bgra[0], 255]);
+    }
+
+    SelectObject(dc, old_font);
+    SelectObject(dc, old_bitmap);
+    DeleteObject(name_font as _);
+    DeleteObject(reason_font as _);
+    DeleteObject(bitmap as _);
+    DeleteDC(dc);
+}
+
+unsafe fn create_font(height: i32, weight: i32) -> HFONT {
+    let font_name = to_wide_string("Segoe UI");
+    CreateFontW(
+        height,
+        0,
+        0,
+        0,
+        weight,
+        0,
+        0,
+        0,
+        DEFAULT_CHARSET as _,
+        OUT_DEFAULT_PRECIS as _,
+        CLIP_DEFAULT_PRECIS as _,
+        ANTIALIASED_QUALITY as _,
+        (DEFAULT_PITCH | FF_SWISS) as _,
+        font_name.as_ptr(),
+    )
+}
+
+unsafe fn draw_text(dc: HDC, text: &str, rect: &mut RECT, format: u32) {
+    let text = to_wide_string(text);
+    DrawTextW(dc, text.as_ptr(), -1, rect, format);
+}
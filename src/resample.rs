@@ -0,0 +1,273 @@
+use cgmath::{assert_ulps_eq, prelude::*};
+use image::Rgba32FImage;
+use std::f32::consts::PI;
+
+use crate::math::*;
+use crate::mips::{linear_to_srgb, srgb_to_linear};
+
+// Reference implementation of the magnification filters in `blit_ps`. Both must be kept in sync.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MagFilter {
+    Nearest,
+    Bilinear,
+    // Catmull-Rom, which stays sharp and passes through the pixel values
+    Bicubic,
+    // Mitchell-Netravali with B = C = 1/3, softer than Catmull-Rom with less ringing
+    Mitchell,
+    Lanczos3,
+}
+
+impl MagFilter {
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg.to_ascii_lowercase().as_str() {
+            "nearest" => Some(MagFilter::Nearest),
+            "bilinear" | "linear" => Some(MagFilter::Bilinear),
+            "bicubic" | "cubic" | "catmull-rom" => Some(MagFilter::Bicubic),
+            "mitchell" | "mitchell-netravali" => Some(MagFilter::Mitchell),
+            "lanczos" | "lanczos3" => Some(MagFilter::Lanczos3),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            MagFilter::Nearest => MagFilter::Bilinear,
+            MagFilter::Bilinear => MagFilter::Bicubic,
+            MagFilter::Bicubic => MagFilter::Mitchell,
+            MagFilter::Mitchell => MagFilter::Lanczos3,
+            MagFilter::Lanczos3 => MagFilter::Nearest,
+        }
+    }
+
+    // Matches the filter switch in shaders.hlsl
+    pub fn shader_index(self) -> u32 {
+        match self {
+            MagFilter::Nearest => 0,
+            MagFilter::Bilinear => 1,
+            MagFilter::Bicubic => 2,
+            MagFilter::Lanczos3 => 3,
+            MagFilter::Mitchell => 4,
+        }
+    }
+
+    // Pixels on each side of the sample position that the kernel reaches
+    fn radius(self) -> i32 {
+        match self {
+            MagFilter::Nearest | MagFilter::Bilinear => 1,
+            MagFilter::Bicubic | MagFilter::Mitchell => 2,
+            MagFilter::Lanczos3 => 3,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            MagFilter::Nearest | MagFilter::Bilinear => (1.0 - x).max(0.0),
+            MagFilter::Bicubic => {
+                if x < 1.0 {
+                    1.5 * x * x * x - 2.5 * x * x + 1.0
+                } else if x < 2.0 {
+                    -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            MagFilter::Mitchell => {
+                if x < 1.0 {
+                    (7.0 / 6.0) * x * x * x - 2.0 * x * x + 8.0 / 9.0
+                } else if x < 2.0 {
+                    (-7.0 / 18.0) * x * x * x + 2.0 * x * x - (10.0 / 3.0) * x + 16.0 / 9.0
+                } else {
+                    0.0
+                }
+            }
+            MagFilter::Lanczos3 => {
+                if x < 1e-5 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// Samples the image at `uv` like the shader does, with clamp to edge addressing. `srgb` is set
+// for 8-bit textures, which are filtered in linear light and encoded again like `encode_texel`.
+pub fn sample(image: &Rgba32FImage, filter: MagFilter, uv: float2, srgb: bool) -> float4 {
+    let map_rgb = |c: float4, f: fn(f32) -> f32| float4::new(f(c.x), f(c.y), f(c.z), c.w);
+    let texel = |x: i32, y: i32| {
+        let x = x.clamp(0, image.width() as i32 - 1) as u32;
+        let y = y.clamp(0, image.height() as i32 - 1) as u32;
+        let c = float4::from(image.get_pixel(x, y).0);
+        if srgb {
+            map_rgb(c, srgb_to_linear)
+        } else {
+            c
+        }
+    };
+    let c = filter_texels(image, filter, uv, texel);
+    if srgb {
+        map_rgb(c, linear_to_srgb)
+    } else {
+        c
+    }
+}
+
+fn filter_texels(
+    image: &Rgba32FImage,
+    filter: MagFilter,
+    uv: float2,
+    texel: impl Fn(i32, i32) -> float4,
+) -> float4 {
+    let dim = float2::new(image.width() as f32, image.height() as f32);
+    let pos = uv.mul_element_wise(dim);
+    if filter == MagFilter::Nearest {
+        return texel(pos.x.floor() as i32, pos.y.floor() as i32);
+    }
+
+    // Kernel weights don't always add up to one, Lanczos in particular
+    let pos = pos - float2::new(0.5, 0.5);
+    let base = float2::new(pos.x.floor(), pos.y.floor());
+    let f = pos - base;
+    let radius = filter.radius();
+    let mut sum = FLOAT4_ZERO;
+    let mut total = 0.0;
+    for j in 1 - radius..=radius {
+        let weight_y = filter.weight(j as f32 - f.y);
+        for i in 1 - radius..=radius {
+            let weight = filter.weight(i as f32 - f.x) * weight_y;
+            sum += texel(base.x as i32 + i, base.y as i32 + j) * weight;
+            total += weight;
+        }
+    }
+    sum / total
+}
+
+#[cfg(test)]
+fn make_test_image(width: u32, height: u32) -> Rgba32FImage {
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let v = ((x * 7 + y * 13) % 11) as f32 / 10.0;
+        image::Rgba([0.3 + 0.4 * v, 0.8 - 0.5 * v, 0.5, 1.0])
+    })
+}
+
+#[test]
+fn test_mag_filter() {
+    let filters = [
+        MagFilter::Nearest,
+        MagFilter::Bilinear,
+        MagFilter::Bicubic,
+        MagFilter::Mitchell,
+        MagFilter::Lanczos3,
+    ];
+    let interpolating = |filter: MagFilter| filter != MagFilter::Mitchell;
+    for filter in filters {
+        assert_eq!(MagFilter::parse(&format!("{filter:?}")), Some(filter));
+    }
+    assert_eq!(MagFilter::parse("box"), None);
+    let cycle = (0..5).fold(MagFilter::Nearest, |filter, _| filter.next());
+    assert_eq!(cycle, MagFilter::Nearest);
+
+    let image = make_test_image(5, 4);
+    let uv = |x: f32, y: f32| float2::new(x / 5.0, y / 4.0);
+    for filter in filters {
+        // Every filter but Mitchell passes through the pixel values at pixel centers
+        if interpolating(filter) {
+            for y in 0..4 {
+                for x in 0..5 {
+                    let expected = float4::from(image.get_pixel(x, y).0);
+                    let c = sample(&image, filter, uv(x as f32 + 0.5, y as f32 + 0.5), false);
+                    assert_ulps_eq!(c, expected, max_ulps = 8);
+                }
+            }
+        }
+
+        // Flat areas stay flat between pixel centers and at the edges
+        let flat = Rgba32FImage::from_pixel(5, 4, image::Rgba([0.25, 0.5, 0.75, 1.0]));
+        for p in [uv(1.3, 2.7), uv(0.1, 0.2), uv(4.9, 3.6)] {
+            let c = sample(&flat, filter, p, false);
+            assert_ulps_eq!(c, float4::new(0.25, 0.5, 0.75, 1.0), max_ulps = 8);
+        }
+    }
+
+    // Nearest picks the pixel the position falls in, bilinear blends the four around it
+    let a = float4::from(image.get_pixel(1, 2).0);
+    let b = float4::from(image.get_pixel(2, 2).0);
+    assert_eq!(sample(&image, MagFilter::Nearest, uv(1.9, 2.5), false), a);
+    let c = sample(&image, MagFilter::Bilinear, uv(2.0, 2.5), false);
+    assert_ulps_eq!(c, (a + b) * 0.5, max_ulps = 8);
+
+    // Mitchell blurs slightly, with weights of 1/18, 8/9 and 1/18 at a pixel center, and rings
+    // less than Catmull-Rom next to an edge
+    let row = Rgba32FImage::from_fn(6, 1, |x, _| image::Rgba([x as f32 % 2.0, 0.5, 0.0, 1.0]));
+    let row_uv = |x: f32| float2::new(x / 6.0, 0.5);
+    let c = sample(&row, MagFilter::Mitchell, row_uv(2.5), false);
+    let expected = float4::new(1.0 / 9.0, 0.5, 0.0, 1.0);
+    assert!((c - expected).magnitude() < 1e-5, "{:?}", c);
+    let step = Rgba32FImage::from_fn(6, 1, |x, _| image::Rgba([(x / 3) as f32, 0.0, 0.0, 1.0]));
+    let undershoot = |filter| -sample(&step, filter, row_uv(2.25), false).x;
+    assert!(undershoot(MagFilter::Mitchell) > 0.0);
+    assert!(undershoot(MagFilter::Mitchell) < undershoot(MagFilter::Bicubic) * 0.5);
+
+    // 8-bit textures blend in linear light: halfway between black and white is 188 of 255, alpha
+    // is not encoded
+    let edge = Rgba32FImage::from_fn(2, 1, |x, _| {
+        let v = x as f32;
+        image::Rgba([v, v, v, 1.0 - v])
+    });
+    let c = sample(&edge, MagFilter::Bilinear, float2::new(0.5, 0.5), true);
+    assert!((c.x * 255.0 - 188.0).abs() < 0.5, "{:?}", c);
+    assert_eq!((c.x, c.y, c.z), (c.x, c.x, c.x));
+    assert_ulps_eq!(c.w, 0.5);
+    for filter in filters {
+        if !interpolating(filter) {
+            continue;
+        }
+        for x in 0..5 {
+            let expected = float4::from(image.get_pixel(x, 1).0);
+            let c = sample(&image, filter, uv(x as f32 + 0.5, 1.5), true);
+            assert!((c - expected).magnitude() < 1e-5, "{:?}: {:?}", filter, c);
+        }
+    }
+}
+
+#[test]
+fn test_resample_reference() {
+    // Upscaling with the image crate's filters matches sampling at the output pixel centers, away
+    // from the edges where the image crate drops taps instead of clamping
+    let image = make_test_image(16, 16);
+    let scale = 3;
+    let size = 16 * scale;
+    for (filter, filter_type) in [
+        (MagFilter::Bilinear, image::imageops::FilterType::Triangle),
+        (MagFilter::Bicubic, image::imageops::FilterType::CatmullRom),
+        (MagFilter::Lanczos3, image::imageops::FilterType::Lanczos3),
+    ] {
+        let expected = image::imageops::resize(&image, size, size, filter_type);
+        let margin = (filter.radius() as u32 + 1) * scale;
+        for y in margin..size - margin {
+            for x in margin..size - margin {
+                let p = float2::new(
+                    (x as f32 + 0.5) / size as f32,
+                    (y as f32 + 0.5) / size as f32,
+                );
+                let c = sample(&image, filter, p, false);
+                let e = float4::from(expected.get_pixel(x, y).0);
+                assert!(
+                    (c - e).magnitude() < 1e-4,
+                    "{:?} at {},{}: {:?} != {:?}",
+                    filter,
+                    x,
+                    y,
+                    c,
+                    e
+                );
+            }
+        }
+    }
+}
@@ -3,6 +3,15 @@ struct Constants {
 	float2 window_dim;
 	float4 mouse; // float2 xy pos, uint buttons, uint unused
 	float4 xfm_viewport_to_image_uv; // xy: scale, zw: offset
+	float4 xfm_viewport_to_image_uv_dihedral; // x: swap xy, y: negate x, z: negate y, w: unused
+	float4 image_orientation; // x: flip x, y: flip y, z: transpose, w: unused
+	float4 display; // x: exposure scale, y: 1/gamma, z: tone map operator, w: image is linear
+	float4 tile_uv_rect; // xy: texture uv min, zw: texture uv max
+	float4 xfm_texture_uv_to_tile_uv; // xy: scale, zw: offset
+	float4 sampling; // x: minifying, y: magnification filter, z: texture is sRGB, w: unused
+	float4 pixel_grid; // x: draw grid, y: draw labels, z: label max value, w: label text scale
+	float4 pixel_grid_color; // rgb: color, a: opacity
+	float4 missing_pixels; // xy: first missing pixel, zw: pixel after the last one, in row order
 };
 
 struct VSOut {
@@ -14,6 +23,7 @@ struct VSOut {
 SamplerState g_default_sampler : register(s0);
 SamplerState g_linear_sampler : register(s1);
 SamplerState g_point_sampler : register(s2);
+SamplerState g_anisotropic_sampler : register(s3);
 
 Texture2D g_image : register(t0);
 cbuffer ConstantsCB : register(b0) { Constants g_constants; }
@@ -38,9 +48,259 @@ float4 background_color(uint2 pixel_pos) {
 float2 viewport_to_image_uv(float2 viewport_pos) {
 	float2 scale  = g_constants.xfm_viewport_to_image_uv.xy;
 	float2 offset = g_constants.xfm_viewport_to_image_uv.zw;
+	float4 dihedral = g_constants.xfm_viewport_to_image_uv_dihedral;
+	viewport_pos = dihedral.x != 0 ? viewport_pos.yx : viewport_pos;
+	viewport_pos = dihedral.yz != 0 ? -viewport_pos : viewport_pos;
 	return viewport_pos * scale + offset;
 }
 
+// Maps UV in oriented (displayed) image space to UV in the source texture
+float2 image_uv_to_texture_uv(float2 uv) {
+	float4 o = g_constants.image_orientation;
+	uv = o.xy != 0 ? 1.0 - uv : uv;
+	return o.z != 0 ? uv.yx : uv;
+}
+
+// Maps UV in oriented image space back to viewport pixels, inverse of viewport_to_image_uv
+float2 image_uv_to_viewport(float2 uv) {
+	float2 scale  = g_constants.xfm_viewport_to_image_uv.xy;
+	float2 offset = g_constants.xfm_viewport_to_image_uv.zw;
+	float4 dihedral = g_constants.xfm_viewport_to_image_uv_dihedral;
+	float2 viewport_pos = (uv - offset) / scale;
+	viewport_pos = dihedral.yz != 0 ? -viewport_pos : viewport_pos;
+	return dihedral.x != 0 ? viewport_pos.yx : viewport_pos;
+}
+
+float2 texture_uv_to_tile_uv(float2 uv) {
+	float4 xfm = g_constants.xfm_texture_uv_to_tile_uv;
+	return uv * xfm.xy + xfm.zw;
+}
+
+float2 viewport_to_tile_uv(float2 viewport_pos) {
+	return texture_uv_to_tile_uv(image_uv_to_texture_uv(viewport_to_image_uv(viewport_pos)));
+}
+
+// Incomplete images are missing the pixels from missing_pixels.xy up to missing_pixels.zw
+bool is_missing_pixel(float2 texture_uv) {
+	float2 dim = g_constants.image_orientation.z != 0 ? g_constants.image_dim.yx : g_constants.image_dim;
+	float2 pixel = floor(texture_uv * dim);
+	float4 m = g_constants.missing_pixels;
+	bool from_first = pixel.y > m.y || (pixel.y == m.y && pixel.x >= m.x);
+	bool before_end = pixel.y < m.w || (pixel.y == m.w && pixel.x < m.z);
+	return from_first && before_end;
+}
+
+// Diagonal stripes in window pixels, so they show at any zoom
+float4 missing_hatch_color(uint2 pixel_pos) {
+	return ((pixel_pos.x + pixel_pos.y) / 6) & 1
+	? float4(0.16, 0, 0.16, 1)
+	: float4(1, 0, 1, 1);
+}
+
+// 8-bit textures are sampled in linear light and shown with the values they store
+float4 encode_texel(float4 color) {
+	if (g_constants.sampling.z != 0) {
+		float3 low = color.rgb * 12.92;
+		float3 high = 1.055 * pow(abs(color.rgb), 1.0 / 2.4) - 0.055;
+		color.rgb = color.rgb <= 0.0031308 ? low : high;
+	}
+	return color;
+}
+
+// Magnification filters, reference implementation is in resample.rs
+
+static const float PI = 3.14159265;
+
+float filter_weight(uint mag_filter, float x) {
+	x = abs(x);
+	if (mag_filter == 2) {
+		// Catmull-Rom
+		if (x < 1) return 1.5 * x * x * x - 2.5 * x * x + 1;
+		if (x < 2) return -0.5 * x * x * x + 2.5 * x * x - 4 * x + 2;
+		return 0;
+	}
+	if (mag_filter == 4) {
+		// Mitchell-Netravali, B = C = 1/3
+		if (x < 1) return (7.0 / 6.0) * x * x * x - 2 * x * x + 8.0 / 9.0;
+		if (x < 2) return (-7.0 / 18.0) * x * x * x + 2 * x * x - (10.0 / 3.0) * x + 16.0 / 9.0;
+		return 0;
+	}
+	// Lanczos-3
+	if (x < 1e-5) return 1;
+	if (x >= 3) return 0;
+	float px = PI * x;
+	return 3 * sin(px) * sin(px / 3) / (px * px);
+}
+
+// Kernel weights don't always add up to one, Lanczos in particular
+float4 sample_filtered(uint mag_filter, float2 uv) {
+	float2 dim;
+	g_image.GetDimensions(dim.x, dim.y);
+	int radius = mag_filter == 3 ? 3 : 2;
+	float2 pos = uv * dim - 0.5;
+	float2 base = floor(pos);
+	float2 f = pos - base;
+	float4 sum = 0;
+	float total = 0;
+	for (int j = 1 - radius; j <= radius; j++) {
+		float weight_y = filter_weight(mag_filter, j - f.y);
+		for (int i = 1 - radius; i <= radius; i++) {
+			float weight = filter_weight(mag_filter, i - f.x) * weight_y;
+			float2 tap_uv = (base + float2(i, j) + 0.5) / dim;
+			sum += g_image.SampleLevel(g_point_sampler, tap_uv, 0) * weight;
+			total += weight;
+		}
+	}
+	return sum / total;
+}
+
+// Pixel grid and pixel value labels, reference implementation is in grid.rs
+
+// Glyphs of 0-9, '.' and '-' in 3x5 pixels, bit y * 3 + x is set where the glyph is drawn
+static const uint GLYPHS[12] = {
+	0x7B6F, 0x749A, 0x73E7, 0x79A7, 0x49ED, 0x79CF, 0x7BCF, 0x2527, 0x7BEF, 0x79EF, 0x2000, 0x01C0
+};
+static const uint GLYPH_POINT = 10;
+static const uint GLYPH_MINUS = 11;
+static const float GLYPH_ADVANCE = 4;
+static const float LINE_HEIGHT = 6;
+static const float MAX_LABEL_FLOAT = 999.999;
+static const uint LABEL_MAX_CHARS = 8;
+
+bool on_grid_line(float2 viewport_pos) {
+	float2 dim = g_constants.image_dim;
+	float2 pixel = floor(viewport_to_image_uv(viewport_pos) * dim);
+	float2 deltas[2] = { float2(1, 0), float2(0, 1) };
+	for (uint i = 0; i < 2; i++) {
+		float2 uv = viewport_to_image_uv(viewport_pos - deltas[i]);
+		if (all(abs(uv - 0.5) <= 0.5) && any(floor(uv * dim) != pixel)) {
+			return true;
+		}
+	}
+	return false;
+}
+
+uint digit_count(uint n) {
+	uint count = 1;
+	for (; n >= 10; n /= 10) {
+		count++;
+	}
+	return count;
+}
+
+uint push_digits(inout uint glyphs[LABEL_MAX_CHARS], uint len, uint n, uint count) {
+	uint divisor = 1;
+	for (uint i = 1; i < count; i++) {
+		divisor *= 10;
+	}
+	for (; divisor > 0; divisor /= 10) {
+		glyphs[len++] = n / divisor % 10;
+	}
+	return len;
+}
+
+// Returns the number of glyphs written
+uint label_glyphs(float value, float max_value, out uint glyphs[LABEL_MAX_CHARS]) {
+	for (uint i = 0; i < LABEL_MAX_CHARS; i++) {
+		glyphs[i] = 0;
+	}
+	if (max_value > 0) {
+		uint n = (uint)round(saturate(value) * max_value);
+		return push_digits(glyphs, 0, n, digit_count(n));
+	}
+	uint m = (uint)round(min(abs(value), MAX_LABEL_FLOAT) * 1000);
+	uint len = 0;
+	if (value < 0 && m != 0) {
+		glyphs[len++] = GLYPH_MINUS;
+	}
+	len = push_digits(glyphs, len, m / 1000, digit_count(m / 1000));
+	glyphs[len++] = GLYPH_POINT;
+	return push_digits(glyphs, len, m % 1000, 3);
+}
+
+// `local` is relative to the center of the image pixel, channels are drawn in centered rows
+bool label_covers(float4 texel, float max_value, float s, float2 local) {
+	float top = -0.5 * (4 * LINE_HEIGHT * s - s);
+	float row = floor((local.y - top) / (LINE_HEIGHT * s));
+	if (row < 0 || row >= 4) {
+		return false;
+	}
+	uint gy = (uint)floor((local.y - top - row * LINE_HEIGHT * s) / s);
+	if (gy >= 5) {
+		return false;
+	}
+	uint glyphs[LABEL_MAX_CHARS];
+	uint len = label_glyphs(texel[(uint)row], max_value, glyphs);
+	float x = local.x + 0.5 * (len * GLYPH_ADVANCE * s - s);
+	float column = floor(x / (GLYPH_ADVANCE * s));
+	if (x < 0 || column >= len) {
+		return false;
+	}
+	uint gx = (uint)floor((x - column * GLYPH_ADVANCE * s) / s);
+	return gx < 3 && ((GLYPHS[glyphs[(uint)column]] >> (gy * 3 + gx)) & 1) != 0;
+}
+
+// Labels show the stored value of the pixel, not the filtered color
+bool on_pixel_label(float2 viewport_pos, float2 uv) {
+	float2 dim = g_constants.image_dim;
+	float2 center_uv = (floor(uv * dim) + 0.5) / dim;
+	float2 tile_uv = texture_uv_to_tile_uv(image_uv_to_texture_uv(center_uv));
+	float4 texel = encode_texel(g_image.SampleLevel(g_point_sampler, tile_uv, 0));
+	float2 local = viewport_pos - image_uv_to_viewport(center_uv);
+	float4 grid = g_constants.pixel_grid;
+	return label_covers(texel, grid.z, grid.w, local);
+}
+
+// Display transform for linear images, reference implementation is in tonemap.rs
+
+float3 tone_map_reinhard(float3 x) {
+	x = max(x, 0);
+	return x / (1 + x);
+}
+
+float3 tone_map_aces_fitted(float3 x) {
+	x = max(x, 0);
+	return saturate((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14));
+}
+
+float3 agx_contrast(float3 x) {
+	float3 x2 = x * x;
+	float3 x4 = x2 * x2;
+	return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+float3 tone_map_agx(float3 x) {
+	static const float3x3 agx_inset = {
+		0.8424791, 0.04232824, 0.04237565,
+		0.0784336, 0.8784686, 0.0784336,
+		0.07922375, 0.07916613, 0.879143
+	};
+	static const float3x3 agx_outset = {
+		1.196879, -0.05289685, -0.05297164,
+		-0.09802088, 1.151903, -0.09804345,
+		-0.09902974, -0.09896118, 1.151074
+	};
+	static const float min_ev = -12.47393;
+	static const float max_ev = 4.026069;
+	x = mul(x, agx_inset);
+	x = clamp(log2(max(x, 1e-10)), min_ev, max_ev);
+	x = agx_contrast((x - min_ev) / (max_ev - min_ev));
+	x = saturate(mul(x, agx_outset));
+	return pow(x, 2.2);
+}
+
+float3 display_transform(float3 color) {
+	float4 params = g_constants.display;
+	color *= params.x;
+	switch ((uint)params.z) {
+	case 1: color = tone_map_reinhard(color); break;
+	case 2: color = tone_map_aces_fitted(color); break;
+	case 3: color = tone_map_agx(color); break;
+	default: color = saturate(color); break;
+	}
+	return pow(color, params.y);
+}
+
 float4 blit_ps(VSOut v) : SV_TARGET {
 
 	/*
@@ -50,9 +310,51 @@ float4 blit_ps(VSOut v) : SV_TARGET {
 	*/
 
 	float2 uv = viewport_to_image_uv(v.pos.xy);
-	float4 image_color = g_image.SampleLevel(g_point_sampler, uv, 0);
 	if (any(abs(uv-0.5) > 0.5) || g_constants.image_dim.x == 0) {
-		image_color = background_color((uint2)(v.pos.xy));
+		return background_color((uint2)(v.pos.xy));
+	}
+
+	// Every tile is drawn over the whole viewport and keeps only its own part of the image
+	float2 texture_uv = image_uv_to_texture_uv(uv);
+	float4 tile_rect = g_constants.tile_uv_rect;
+	if (any(texture_uv < tile_rect.xy) || any(texture_uv >= tile_rect.zw)) {
+		discard;
+	}
+	float2 tile_uv = texture_uv_to_tile_uv(texture_uv);
+
+	float4 image_color;
+	if (g_constants.sampling.x != 0) {
+		// Gradients come from the transform, ddx and ddy are undefined next to discarded pixels
+		float2 dx = viewport_to_tile_uv(v.pos.xy + float2(1, 0)) - tile_uv;
+		float2 dy = viewport_to_tile_uv(v.pos.xy + float2(0, 1)) - tile_uv;
+		image_color = g_image.SampleGrad(g_anisotropic_sampler, tile_uv, dx, dy);
+	} else {
+		uint mag_filter = (uint)g_constants.sampling.y;
+		switch (mag_filter) {
+		case 1: image_color = g_image.SampleLevel(g_linear_sampler, tile_uv, 0); break;
+		case 2:
+		case 3:
+		case 4: image_color = sample_filtered(mag_filter, tile_uv); break;
+		default: image_color = g_image.SampleLevel(g_point_sampler, tile_uv, 0); break;
+		}
+	}
+	image_color = encode_texel(image_color);
+	if (g_constants.display.w != 0) {
+		image_color.rgb = display_transform(image_color.rgb);
+	}
+	if (is_missing_pixel(texture_uv)) {
+		image_color = missing_hatch_color((uint2)(v.pos.xy));
+	}
+
+	// Labels are black or white, whichever stands out from the pixel
+	float4 grid = g_constants.pixel_grid;
+	if (grid.y != 0 && on_pixel_label(v.pos.xy, uv)) {
+		float luminance = dot(image_color.rgb, float3(0.2126, 0.7152, 0.0722));
+		image_color.rgb = luminance > 0.5 ? 0 : 1;
+	}
+	if (grid.x != 0 && on_grid_line(v.pos.xy)) {
+		float4 color = g_constants.pixel_grid_color;
+		image_color.rgb = lerp(image_color.rgb, color.rgb, color.a);
 	}
 
 	return image_color;
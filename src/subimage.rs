@@ -0,0 +1,277 @@
+use image::DynamicImage;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    // Order of faces in DDS files and D3D texture arrays
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+}
+
+impl fmt::Display for CubeFace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CubeFace::PositiveX => "+X",
+            CubeFace::NegativeX => "-X",
+            CubeFace::PositiveY => "+Y",
+            CubeFace::NegativeY => "-Y",
+            CubeFace::PositiveZ => "+Z",
+            CubeFace::NegativeZ => "-Z",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SubimageKind {
+    // Image file directories of a multi-page TIFF
+    Pages,
+    // Array slices, cube faces and mip levels of a DDS texture
+    Texture,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SubimageLayout {
+    pub kind: SubimageKind,
+    // Number of pages or array slices
+    pub layer_count: u32,
+    // 6 for cubemaps, 1 otherwise
+    pub face_count: u32,
+    pub mip_count: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SubimageInfo {
+    // Page or array slice index
+    pub layer: u32,
+    pub face: Option<CubeFace>,
+    pub mip_level: u32,
+    pub dim: (u32, u32),
+}
+
+// Surfaces of a multi-page or texture file in file order: layers, then faces, then mip levels
+pub struct SubimageSet {
+    pub layout: SubimageLayout,
+    pub surfaces: Vec<SubimageInfo>,
+    // Pixels of the surfaces after the first one, empty for TIFF pages which are decoded when
+    // they are shown
+    pub images: Vec<DynamicImage>,
+}
+
+// Current position within the subimages of a file. Pages, slices and faces are stepped through
+// as one sequence, mip levels are a separate axis so the same level stays selected across faces.
+pub struct SubimageNavigator {
+    layout: SubimageLayout,
+    surfaces: Vec<SubimageInfo>,
+    current: usize,
+}
+
+impl SubimageNavigator {
+    pub fn new(layout: SubimageLayout, surfaces: Vec<SubimageInfo>) -> Self {
+        assert!(!surfaces.is_empty());
+        Self {
+            layout,
+            surfaces,
+            current: 0,
+        }
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> &SubimageInfo {
+        &self.surfaces[self.current]
+    }
+
+    pub fn surface_count(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    fn find(&self, layer: u32, face: Option<CubeFace>, mip_level: u32) -> Option<usize> {
+        self.surfaces
+            .iter()
+            .position(|s| s.layer == layer && s.face == face && s.mip_level == mip_level)
+    }
+
+    // Moves to the next or previous page, array slice or cube face with wrap-around.
+    // Returns true if the selection changed.
+    pub fn step_layer(&mut self, delta: i32) -> bool {
+        let face_count = self.layout.face_count.max(1) as i64;
+        let layer_face_count = self.layout.layer_count.max(1) as i64 * face_count;
+        let current = *self.current();
+        let face_index = current
+            .face
+            .and_then(|face| CubeFace::ALL.iter().position(|f| *f == face))
+            .unwrap_or(0) as i64;
+        let layer_face = current.layer as i64 * face_count + face_index;
+        let next = (layer_face + delta as i64).rem_euclid(layer_face_count);
+        if next == layer_face {
+            return false;
+        }
+        let layer = (next / face_count) as u32;
+        let face = current
+            .face
+            .map(|_| CubeFace::ALL[(next % face_count) as usize]);
+        match self.find(layer, face, current.mip_level) {
+            Some(index) => {
+                self.current = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Moves towards smaller (positive delta) or larger mip levels, stopping at either end.
+    // Returns true if the selection changed.
+    pub fn step_mip(&mut self, delta: i32) -> bool {
+        let current = *self.current();
+        let max_level = self.layout.mip_count.max(1) as i64 - 1;
+        let level = (current.mip_level as i64 + delta as i64).clamp(0, max_level) as u32;
+        match self.find(current.layer, current.face, level) {
+            Some(index) if index != self.current => {
+                self.current = index;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Title bar indicator, e.g. "page 2/5" or "slice 1/4, mip 2/10, face +X"
+    pub fn label(&self) -> String {
+        let current = self.current();
+        let mut parts = Vec::new();
+        if self.layout.layer_count > 1 {
+            let noun = match self.layout.kind {
+                SubimageKind::Pages => "page",
+                SubimageKind::Texture => "slice",
+            };
+            parts.push(format!(
+                "{noun} {}/{}",
+                current.layer + 1,
+                self.layout.layer_count
+            ));
+        }
+        if self.layout.mip_count > 1 {
+            parts.push(format!(
+                "mip {}/{}",
+                current.mip_level + 1,
+                self.layout.mip_count
+            ));
+        }
+        if let Some(face) = current.face {
+            parts.push(format!("face {face}"));
+        }
+        parts.push(format!("{}x{}", current.dim.0, current.dim.1));
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+fn make_texture_surfaces(layer_count: u32, cubemap: bool, mip_count: u32) -> Vec<SubimageInfo> {
+    let faces: Vec<Option<CubeFace>> = if cubemap {
+        CubeFace::ALL.iter().copied().map(Some).collect()
+    } else {
+        vec![None]
+    };
+    let mut surfaces = Vec::new();
+    for layer in 0..layer_count {
+        for face in &faces {
+            for mip_level in 0..mip_count {
+                surfaces.push(SubimageInfo {
+                    layer,
+                    face: *face,
+                    mip_level,
+                    dim: (64 >> mip_level, 64 >> mip_level),
+                });
+            }
+        }
+    }
+    surfaces
+}
+
+#[test]
+fn test_subimage_pages() {
+    let layout = SubimageLayout {
+        kind: SubimageKind::Pages,
+        layer_count: 3,
+        face_count: 1,
+        mip_count: 1,
+    };
+    let surfaces = (0..3)
+        .map(|layer| SubimageInfo {
+            layer,
+            face: None,
+            mip_level: 0,
+            dim: (100 + layer, 50),
+        })
+        .collect();
+    let mut nav = SubimageNavigator::new(layout, surfaces);
+    assert_eq!(nav.label(), "page 1/3, 100x50");
+    assert!(nav.step_layer(1));
+    assert_eq!(nav.current_index(), 1);
+    assert!(nav.step_layer(1));
+    assert!(nav.step_layer(1));
+    assert_eq!(nav.current_index(), 0);
+    assert!(nav.step_layer(-1));
+    assert_eq!(nav.label(), "page 3/3, 102x50");
+    assert!(!nav.step_mip(1));
+    assert!(nav.step_layer(2));
+    assert_eq!(nav.current_index(), 1);
+}
+
+#[test]
+fn test_subimage_texture() {
+    let layout = SubimageLayout {
+        kind: SubimageKind::Texture,
+        layer_count: 1,
+        face_count: 6,
+        mip_count: 4,
+    };
+    let mut nav = SubimageNavigator::new(layout, make_texture_surfaces(1, true, 4));
+    assert_eq!(nav.surface_count(), 24);
+    assert_eq!(nav.label(), "mip 1/4, face +X, 64x64");
+
+    assert!(nav.step_mip(1));
+    assert!(nav.step_mip(1));
+    assert_eq!(nav.label(), "mip 3/4, face +X, 16x16");
+    assert!(nav.step_mip(5));
+    assert!(!nav.step_mip(1));
+    assert_eq!(nav.current().mip_level, 3);
+
+    // Faces keep the selected mip level
+    assert!(nav.step_layer(1));
+    assert_eq!(nav.label(), "mip 4/4, face -X, 8x8");
+    assert!(nav.step_layer(-2));
+    assert_eq!(nav.current().face, Some(CubeFace::NegativeZ));
+    assert!(nav.step_mip(-10));
+    assert_eq!(nav.label(), "mip 1/4, face -Z, 64x64");
+
+    let layout = SubimageLayout {
+        kind: SubimageKind::Texture,
+        layer_count: 2,
+        face_count: 1,
+        mip_count: 2,
+    };
+    let mut nav = SubimageNavigator::new(layout, make_texture_surfaces(2, false, 2));
+    assert!(nav.step_mip(1));
+    assert!(nav.step_layer(1));
+    assert_eq!(nav.label(), "slice 2/2, mip 2/2, 32x32");
+    assert!(nav.step_layer(1));
+    assert_eq!(nav.current_index(), 1);
+}
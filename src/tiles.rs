@@ -0,0 +1,356 @@
+use cgmath::{assert_ulps_eq, prelude::*};
+
+use crate::math::*;
+use crate::orientation::ViewOrientation;
+
+// D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION
+pub const MAX_TEXTURE_SIZE: u32 = 16384;
+// Images that don't fit in one texture are split into tiles of this size
+pub const TILE_SIZE: u32 = 8192;
+// Pixels copied from the neighbouring tiles on every side, so filtering near a tile edge reads
+// the same pixels it would in a single texture. Every mip level halves the border, so tiles only
+// get the levels that keep at least one pixel of it, see `TILE_MIP_COUNT`.
+pub const TILE_BORDER: u32 = 64;
+// Mip levels of tiled textures including level 0. Views zoomed out past the last one draw the
+// overview of the whole image instead, see `TileGrid::uses_overview`.
+pub const TILE_MIP_COUNT: u32 = TILE_BORDER.trailing_zeros() + 1;
+// Image pixels per texel of the last tile level, where the overview starts
+pub const OVERVIEW_SCALE: f32 = (1 << (TILE_MIP_COUNT - 1)) as f32;
+
+// Rectangle in texture pixels, `min` inclusive and `max` exclusive
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelRect {
+    pub min: (u32, u32),
+    pub max: (u32, u32),
+}
+
+impl PixelRect {
+    pub fn dim(&self) -> (u32, u32) {
+        (self.max.0 - self.min.0, self.max.1 - self.min.1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    // Pixels this tile draws
+    pub rect: PixelRect,
+    // Pixels the tile texture holds, which is `rect` plus the border clamped to the image
+    pub texture_rect: PixelRect,
+}
+
+impl Tile {
+    // Part of the texture UV range this tile draws, as min xy and max zw. Tiles on the right and
+    // bottom edges extend past the image so that UV 1.0 is not dropped.
+    pub fn uv_rect(&self, image_dim: (u32, u32)) -> float4 {
+        let edge = |max: u32, dim: u32| {
+            if max >= dim {
+                f32::MAX
+            } else {
+                max as f32 / dim as f32
+            }
+        };
+        float4::new(
+            self.rect.min.0 as f32 / image_dim.0 as f32,
+            self.rect.min.1 as f32 / image_dim.1 as f32,
+            edge(self.rect.max.0, image_dim.0),
+            edge(self.rect.max.1, image_dim.1),
+        )
+    }
+
+    // Maps UV of the whole texture to UV of the tile texture
+    pub fn xfm_texture_uv_to_tile_uv(&self, image_dim: (u32, u32)) -> Transform2D {
+        let dim = self.texture_rect.dim();
+        let scale = float2::new(
+            image_dim.0 as f32 / dim.0 as f32,
+            image_dim.1 as f32 / dim.1 as f32,
+        );
+        let origin = float2::new(
+            self.texture_rect.min.0 as f32 / dim.0 as f32,
+            self.texture_rect.min.1 as f32 / dim.1 as f32,
+        );
+        Transform2D {
+            scale,
+            offset: -origin,
+            ..Default::default()
+        }
+    }
+}
+
+// Splits an image into textures the GPU can create. Images that fit are a single tile without a
+// border, so the common case draws exactly as before.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileGrid {
+    pub image_dim: (u32, u32),
+    pub tile_dim: (u32, u32),
+    pub border: u32,
+}
+
+impl TileGrid {
+    pub fn new(image_dim: (u32, u32)) -> Self {
+        if image_dim.0 <= MAX_TEXTURE_SIZE && image_dim.1 <= MAX_TEXTURE_SIZE {
+            TileGrid {
+                image_dim,
+                tile_dim: (image_dim.0.max(1), image_dim.1.max(1)),
+                border: 0,
+            }
+        } else {
+            TileGrid {
+                image_dim,
+                tile_dim: (TILE_SIZE, TILE_SIZE),
+                border: TILE_BORDER,
+            }
+        }
+    }
+
+    pub fn tile_counts(&self) -> (u32, u32) {
+        (
+            self.image_dim.0.div_ceil(self.tile_dim.0),
+            self.image_dim.1.div_ceil(self.tile_dim.1),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        let counts = self.tile_counts();
+        counts.0 as usize * counts.1 as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Tiles are numbered in row-major order
+    pub fn tile(&self, index: usize) -> Tile {
+        let columns = self.tile_counts().0 as usize;
+        let x = (index % columns) as u32 * self.tile_dim.0;
+        let y = (index / columns) as u32 * self.tile_dim.1;
+        let rect = PixelRect {
+            min: (x, y),
+            max: (
+                (x + self.tile_dim.0).min(self.image_dim.0),
+                (y + self.tile_dim.1).min(self.image_dim.1),
+            ),
+        };
+        let texture_rect = PixelRect {
+            min: (
+                rect.min.0.saturating_sub(self.border),
+                rect.min.1.saturating_sub(self.border),
+            ),
+            max: (
+                (rect.max.0 + self.border).min(self.image_dim.0),
+                (rect.max.1 + self.border).min(self.image_dim.1),
+            ),
+        };
+        Tile { rect, texture_rect }
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        (0..self.len()).map(move |index| self.tile(index))
+    }
+
+    // Whether a view showing `image_pixels_per_pixel` image pixels per viewport pixel samples
+    // past the last mip level of the tiles, and draws the overview of the whole image instead
+    pub fn uses_overview(&self, image_pixels_per_pixel: f32) -> bool {
+        self.border > 0 && image_pixels_per_pixel > OVERVIEW_SCALE
+    }
+
+    // The whole image as a single tile without a border, the overview is drawn as one
+    pub fn whole_image_tile(&self) -> Tile {
+        let rect = PixelRect {
+            min: (0, 0),
+            max: self.image_dim,
+        };
+        Tile {
+            rect,
+            texture_rect: rect,
+        }
+    }
+
+    // Indices of the tiles that cover part of the viewport. Takes the same transforms the shader
+    // uses to go from viewport pixels to texture UV.
+    pub fn visible_tiles(
+        &self,
+        xfm_viewport_to_image_uv: Transform2D,
+        orientation: ViewOrientation,
+        viewport_dim: float2,
+    ) -> Vec<usize> {
+        let viewport = Box2D {
+            min: FLOAT2_ZERO,
+            max: viewport_dim,
+        };
+        let uv = xfm_viewport_to_image_uv.transform_box(viewport);
+        // Orientation only flips and swaps axes, so the corners stay corners
+        let a = orientation.oriented_uv_to_texture_uv(uv.min);
+        let b = orientation.oriented_uv_to_texture_uv(uv.max);
+        let image_dim = float2::new(self.image_dim.0 as f32, self.image_dim.1 as f32);
+        let min = float2::new(a.x.min(b.x), a.y.min(b.y)).mul_element_wise(image_dim);
+        let max = float2::new(a.x.max(b.x), a.y.max(b.y)).mul_element_wise(image_dim);
+
+        let counts = self.tile_counts();
+        let range = |min: f32, max: f32, tile: u32, count: u32| {
+            let first = (min / tile as f32).floor().max(0.0) as u32;
+            let last = ((max / tile as f32).ceil().max(0.0) as u32).min(count);
+            first..last.max(first)
+        };
+        let columns = range(min.x, max.x, self.tile_dim.0, counts.0);
+        let rows = range(min.y, max.y, self.tile_dim.1, counts.1);
+        let mut result = Vec::new();
+        for y in rows {
+            for x in columns.clone() {
+                result.push((y * counts.0 + x) as usize);
+            }
+        }
+        result
+    }
+}
+
+#[test]
+fn test_tile_grid() {
+    // Images that fit are a single borderless tile
+    let grid = TileGrid::new((16384, 300));
+    assert_eq!(grid.len(), 1);
+    let tile = grid.tile(0);
+    assert_eq!(tile.rect, tile.texture_rect);
+    assert_eq!(tile.rect.dim(), (16384, 300));
+    assert_eq!(
+        tile.uv_rect((16384, 300)),
+        float4::new(0.0, 0.0, f32::MAX, f32::MAX)
+    );
+    assert_eq!(
+        tile.xfm_texture_uv_to_tile_uv((16384, 300)),
+        Transform2D::new_identity()
+    );
+    assert!(TileGrid::new((0, 0)).is_empty());
+
+    let dim = (20000, 9000);
+    let grid = TileGrid::new(dim);
+    assert_eq!(grid.tile_counts(), (3, 2));
+    let tiles: Vec<Tile> = grid.tiles().collect();
+    assert_eq!(tiles.len(), 6);
+    for tile in &tiles {
+        let texture_dim = tile.texture_rect.dim();
+        assert!(texture_dim.0 <= MAX_TEXTURE_SIZE && texture_dim.1 <= MAX_TEXTURE_SIZE);
+    }
+    // Tiles cover the image exactly once
+    let area: u64 = tiles
+        .iter()
+        .map(|t| t.rect.dim().0 as u64 * t.rect.dim().1 as u64)
+        .sum();
+    assert_eq!(area, dim.0 as u64 * dim.1 as u64);
+
+    let middle = tiles[4];
+    assert_eq!(middle.rect.min, (8192, 8192));
+    assert_eq!(middle.rect.max, (16384, 9000));
+    assert_eq!(middle.texture_rect.min, (8128, 8128));
+    assert_eq!(middle.texture_rect.max, (16448, 9000));
+    let last = tiles[5];
+    assert_eq!(last.rect.max, (20000, 9000));
+    assert_eq!(last.texture_rect.min, (16320, 8128));
+
+    // A texture pixel center maps to the same pixel in the tile texture
+    for tile in [tiles[0], middle, last] {
+        let xfm = tile.xfm_texture_uv_to_tile_uv(dim);
+        let pixel = (tile.rect.min.0 + 3, tile.rect.min.1 + 2);
+        let uv = float2::new(
+            (pixel.0 as f32 + 0.5) / dim.0 as f32,
+            (pixel.1 as f32 + 0.5) / dim.1 as f32,
+        );
+        let tile_uv = xfm.transform_point(uv);
+        let texture_dim = tile.texture_rect.dim();
+        let tile_pixel = (
+            (tile_uv.x * texture_dim.0 as f32) as u32,
+            (tile_uv.y * texture_dim.1 as f32) as u32,
+        );
+        assert_eq!(
+            tile_pixel,
+            (
+                pixel.0 - tile.texture_rect.min.0,
+                pixel.1 - tile.texture_rect.min.1
+            )
+        );
+    }
+
+    let uv_rect = middle.uv_rect(dim);
+    assert_ulps_eq!(uv_rect.x, 8192.0 / 20000.0);
+    assert_ulps_eq!(uv_rect.z, 16384.0 / 20000.0);
+    assert_eq!(uv_rect.w, f32::MAX);
+
+    // Zoomed out past the last tile level, the overview is drawn as a whole image tile
+    assert_eq!(OVERVIEW_SCALE, 64.0);
+    assert!(!grid.uses_overview(OVERVIEW_SCALE));
+    assert!(grid.uses_overview(OVERVIEW_SCALE * 1.5));
+    assert!(!TileGrid::new((16384, 300)).uses_overview(1000.0));
+    let overview = grid.whole_image_tile();
+    assert_eq!(
+        overview.uv_rect(dim),
+        float4::new(0.0, 0.0, f32::MAX, f32::MAX)
+    );
+    assert_eq!(
+        overview.xfm_texture_uv_to_tile_uv(dim),
+        Transform2D::new_identity()
+    );
+}
+
+#[test]
+fn test_visible_tiles() {
+    let dim = (20000, 9000);
+    let grid = TileGrid::new(dim);
+    let viewport = float2::new(1000.0, 800.0);
+    let image_dim = float2::new(dim.0 as f32, dim.1 as f32);
+    // Window pixels to image pixels, then to UV, like the renderer builds it
+    let view = |scale: f32, offset: float2, dihedral: Dihedral2D| {
+        Transform2D {
+            scale: float2::new(scale, scale),
+            offset,
+            dihedral,
+        }
+        .concatenate(Transform2D::new_scale(1.0 / image_dim))
+    };
+    let identity = ViewOrientation::new_identity();
+
+    // 1:1 around the top left corner sees one tile
+    let xfm = view(1.0, FLOAT2_ZERO, Dihedral2D::IDENTITY);
+    assert_eq!(grid.visible_tiles(xfm, identity, viewport), vec![0]);
+
+    // Straddling the corner of four tiles
+    let xfm = view(1.0, float2::new(7700.0, 8000.0), Dihedral2D::IDENTITY);
+    assert_eq!(
+        grid.visible_tiles(xfm, identity, viewport),
+        vec![0, 1, 3, 4]
+    );
+
+    // Zoomed out to fit shows everything
+    let xfm = view(25.0, float2::new(-2500.0, -5500.0), Dihedral2D::IDENTITY);
+    assert_eq!(
+        grid.visible_tiles(xfm, identity, viewport),
+        vec![0, 1, 2, 3, 4, 5]
+    );
+
+    // Panned off the image
+    let xfm = view(1.0, float2::new(-5000.0, 0.0), Dihedral2D::IDENTITY);
+    assert!(grid.visible_tiles(xfm, identity, viewport).is_empty());
+    let xfm = view(1.0, float2::new(30000.0, 0.0), Dihedral2D::IDENTITY);
+    assert!(grid.visible_tiles(xfm, identity, viewport).is_empty());
+
+    // The top left of the oriented image is the bottom left of the texture
+    let rotated = ViewOrientation::from_exif(image::metadata::Orientation::Rotate90);
+    let oriented_dim = rotated.oriented_dim(dim);
+    let oriented_dim = float2::new(oriented_dim.0 as f32, oriented_dim.1 as f32);
+    let xfm = Transform2D::new_scale(1.0 / oriented_dim);
+    let narrow = float2::new(500.0, 800.0);
+    assert_eq!(grid.visible_tiles(xfm, rotated, narrow), vec![3]);
+    assert_eq!(grid.visible_tiles(xfm, rotated, viewport), vec![0, 3]);
+
+    // Rotating the view a quarter turn swaps the extent of the viewport
+    let xfm = view(
+        1.0,
+        float2::new(8192.0 - 500.0, 0.0),
+        Dihedral2D::ROTATE_90_CW,
+    );
+    assert_eq!(grid.visible_tiles(xfm, identity, viewport), vec![0]);
+    let xfm = view(
+        1.0,
+        float2::new(8192.0 + 400.0, 0.0),
+        Dihedral2D::ROTATE_90_CW,
+    );
+    assert_eq!(grid.visible_tiles(xfm, identity, viewport), vec![0, 1]);
+}
@@ -0,0 +1,224 @@
+use cgmath::{assert_ulps_eq, prelude::*};
+
+use crate::math::*;
+
+// Reference implementation of the display transform in `blit_ps`. Both must be kept in sync.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    Clamp,
+    Reinhard,
+    AcesFitted,
+    AgX,
+}
+
+impl ToneMapOperator {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapOperator::Clamp => ToneMapOperator::Reinhard,
+            ToneMapOperator::Reinhard => ToneMapOperator::AcesFitted,
+            ToneMapOperator::AcesFitted => ToneMapOperator::AgX,
+            ToneMapOperator::AgX => ToneMapOperator::Clamp,
+        }
+    }
+
+    // Matches the operator switch in shaders.hlsl
+    pub fn shader_index(self) -> u32 {
+        match self {
+            ToneMapOperator::Clamp => 0,
+            ToneMapOperator::Reinhard => 1,
+            ToneMapOperator::AcesFitted => 2,
+            ToneMapOperator::AgX => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DisplaySettings {
+    pub exposure_ev: f32,
+    pub gamma: f32,
+    pub tone_map: ToneMapOperator,
+}
+
+impl DisplaySettings {
+    pub fn new_default() -> Self {
+        Self {
+            exposure_ev: 0.0,
+            gamma: 2.2,
+            tone_map: ToneMapOperator::Clamp,
+        }
+    }
+
+    // Linear scene referred color to display encoded color
+    pub fn apply(&self, rgb: float3) -> float3 {
+        let exposed = rgb * self.exposure_ev.exp2();
+        let mapped = tone_map(self.tone_map, exposed);
+        let inv_gamma = 1.0 / self.gamma;
+        float3::new(
+            mapped.x.powf(inv_gamma),
+            mapped.y.powf(inv_gamma),
+            mapped.z.powf(inv_gamma),
+        )
+    }
+
+    // Display transform is only applied to linear (floating point) images,
+    // integer formats are assumed to be display encoded already.
+    pub fn to_float4(self, is_linear: bool) -> float4 {
+        float4::new(
+            self.exposure_ev.exp2(),
+            1.0 / self.gamma,
+            self.tone_map.shader_index() as f32,
+            is_linear as u32 as f32,
+        )
+    }
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings::new_default()
+    }
+}
+
+fn saturate3(v: float3) -> float3 {
+    float3::new(
+        clamp(v.x, 0.0, 1.0),
+        clamp(v.y, 0.0, 1.0),
+        clamp(v.z, 0.0, 1.0),
+    )
+}
+
+pub fn tone_map(op: ToneMapOperator, rgb: float3) -> float3 {
+    match op {
+        ToneMapOperator::Clamp => saturate3(rgb),
+        ToneMapOperator::Reinhard => tone_map_reinhard(rgb),
+        ToneMapOperator::AcesFitted => tone_map_aces_fitted(rgb),
+        ToneMapOperator::AgX => tone_map_agx(rgb),
+    }
+}
+
+fn tone_map_reinhard(rgb: float3) -> float3 {
+    let rgb = rgb.map(|x| x.max(0.0));
+    rgb.div_element_wise(rgb + FLOAT3_ONE)
+}
+
+// Krzysztof Narkowicz's curve fit of the ACES filmic reference transform
+fn tone_map_aces_fitted(rgb: float3) -> float3 {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    saturate3(rgb.map(|x| {
+        let x = x.max(0.0);
+        (x * (a * x + b)) / (x * (c * x + d) + e)
+    }))
+}
+
+// Matrices are stored as rows of the row-vector convention, i.e. out = in.x * m[0] + ...
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.842_479_1, 0.042_328_24, 0.042_375_655],
+    [0.078_433_6, 0.878_468_6, 0.078_433_6],
+    [0.079_223_745, 0.079_166_13, 0.879_143],
+];
+
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196_879, -0.052_896_85, -0.052_971_635],
+    [-0.098_020_88, 1.151_903_1, -0.098_043_45],
+    [-0.099_029_74, -0.098_961_18, 1.151_073_7],
+];
+
+const AGX_MIN_EV: f32 = -12.473_93;
+const AGX_MAX_EV: f32 = 4.026_069;
+
+fn mul_row_vector(v: float3, m: &[[f32; 3]; 3]) -> float3 {
+    float3::from(m[0]) * v.x + float3::from(m[1]) * v.y + float3::from(m[2]) * v.z
+}
+
+fn agx_contrast(x: f32) -> f32 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+// Minimal AgX approximation: log encoding in a desaturated inset space with a polynomial
+// sigmoid, converted back to linear output
+fn tone_map_agx(rgb: float3) -> float3 {
+    let v = mul_row_vector(rgb, &AGX_INSET);
+    let v = v.map(|x| {
+        let ev = clamp(x.max(1e-10).log2(), AGX_MIN_EV, AGX_MAX_EV);
+        agx_contrast((ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV))
+    });
+    let v = saturate3(mul_row_vector(v, &AGX_OUTSET));
+    v.map(|x| x.powf(2.2))
+}
+
+#[test]
+fn test_tone_map() {
+    let ops = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::AcesFitted,
+        ToneMapOperator::AgX,
+    ];
+
+    // Every operator maps black to (nearly) black and any input into displayable range,
+    // and is monotonic for grey values
+    for op in ops {
+        let black = tone_map(op, FLOAT3_ZERO);
+        assert!(black.x.abs() < 1e-3, "{:?} {:?}", op, black);
+        let mut prev = -1.0;
+        for i in 0..64 {
+            let x = (i as f32 * 0.25 - 8.0).exp2();
+            let c = tone_map(op, float3::new(x, x, x));
+            assert!(c.x >= 0.0 && c.x <= 1.0, "{:?} {}: {:?}", op, x, c);
+            assert!(c.x >= prev, "{:?} {}: {:?}", op, x, c);
+            prev = c.x;
+        }
+    }
+
+    assert_eq!(
+        tone_map(ToneMapOperator::Clamp, float3::new(-1.0, 0.5, 4.0)),
+        float3::new(0.0, 0.5, 1.0)
+    );
+    assert_ulps_eq!(
+        tone_map(ToneMapOperator::Reinhard, float3::new(1.0, 3.0, 0.0)),
+        float3::new(0.5, 0.75, 0.0)
+    );
+    let bright = float3::new(100.0, 100.0, 100.0);
+    assert!(tone_map(ToneMapOperator::AcesFitted, bright).x > 0.99);
+    assert!(tone_map(ToneMapOperator::AgX, bright).x > 0.95);
+
+    // AgX desaturates bright saturated colors instead of clipping them
+    let c = tone_map(ToneMapOperator::AgX, float3::new(64.0, 0.0, 0.0));
+    assert!(c.y > 0.0 && c.x > c.y);
+
+    let ops_cycle = (0..4).fold(ToneMapOperator::Clamp, |op, _| op.next());
+    assert_eq!(ops_cycle, ToneMapOperator::Clamp);
+}
+
+#[test]
+fn test_display_settings() {
+    let settings = DisplaySettings {
+        exposure_ev: 1.0,
+        gamma: 1.0,
+        tone_map: ToneMapOperator::Clamp,
+    };
+    assert_ulps_eq!(
+        settings.apply(float3::new(0.25, 0.125, 2.0)),
+        float3::new(0.5, 0.25, 1.0)
+    );
+
+    let settings = DisplaySettings {
+        exposure_ev: -2.0,
+        gamma: 2.0,
+        tone_map: ToneMapOperator::Clamp,
+    };
+    assert_ulps_eq!(
+        settings.apply(float3::new(1.0, 4.0, 0.0)),
+        float3::new(0.5, 1.0, 0.0)
+    );
+
+    let c = DisplaySettings::new_default().to_float4(true);
+    assert_ulps_eq!(c, float4::new(1.0, 1.0 / 2.2, 0.0, 1.0));
+}
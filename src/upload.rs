@@ -0,0 +1,153 @@
+use image::DynamicImage;
+use std::borrow::Cow;
+
+// Texture formats the viewer uploads decoded images in. Everything is expanded to four channels
+// so the shader does not need to know the source layout.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UploadFormat {
+    Rgba8Unorm,
+    Rgba16Unorm,
+    Rgba32Float,
+}
+
+impl UploadFormat {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            UploadFormat::Rgba8Unorm => 4,
+            UploadFormat::Rgba16Unorm => 8,
+            UploadFormat::Rgba32Float => 16,
+        }
+    }
+}
+
+// Borrows the decoded pixels when they are already in the upload layout, so that cached images
+// are not copied again
+pub enum UploadPixels<'a> {
+    U8(Cow<'a, [u8]>),
+    U16(Cow<'a, [u16]>),
+    F32(Cow<'a, [f32]>),
+}
+
+impl UploadPixels<'_> {
+    pub fn as_bytes_ptr(&self) -> *const u8 {
+        match self {
+            UploadPixels::U8(data) => data.as_ptr(),
+            UploadPixels::U16(data) => data.as_ptr() as *const u8,
+            UploadPixels::F32(data) => data.as_ptr() as *const u8,
+        }
+    }
+}
+
+pub struct UploadImage<'a> {
+    pub format: UploadFormat,
+    pub dim: (u32, u32),
+    pub pixels: UploadPixels<'a>,
+}
+
+impl UploadImage<'_> {
+    pub fn row_pitch(&self) -> u32 {
+        self.dim.0 * self.format.bytes_per_pixel()
+    }
+}
+
+pub fn select_upload_format(image: &DynamicImage) -> UploadFormat {
+    match image {
+        DynamicImage::ImageLuma8(_)
+        | DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgb8(_)
+        | DynamicImage::ImageRgba8(_) => UploadFormat::Rgba8Unorm,
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => UploadFormat::Rgba16Unorm,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => UploadFormat::Rgba32Float,
+        // Unknown future variants go through the lossless path
+        _ => UploadFormat::Rgba32Float,
+    }
+}
+
+pub fn prepare_upload(image: &DynamicImage) -> UploadImage<'_> {
+    let format = select_upload_format(image);
+    let dim = (image.width(), image.height());
+    let pixels = match (format, image) {
+        (_, DynamicImage::ImageRgba8(buf)) => UploadPixels::U8(Cow::Borrowed(buf.as_raw())),
+        (_, DynamicImage::ImageRgba16(buf)) => UploadPixels::U16(Cow::Borrowed(buf.as_raw())),
+        (_, DynamicImage::ImageRgba32F(buf)) => UploadPixels::F32(Cow::Borrowed(buf.as_raw())),
+        (UploadFormat::Rgba8Unorm, _) => UploadPixels::U8(Cow::Owned(image.to_rgba8().into_raw())),
+        (UploadFormat::Rgba16Unorm, _) => {
+            UploadPixels::U16(Cow::Owned(image.to_rgba16().into_raw()))
+        }
+        (UploadFormat::Rgba32Float, _) => {
+            UploadPixels::F32(Cow::Owned(image.to_rgba32f().into_raw()))
+        }
+    };
+    UploadImage {
+        format,
+        dim,
+        pixels,
+    }
+}
+
+#[test]
+fn test_upload_format() {
+    {
+        let image = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(3, 2, image::Luma([7])));
+        let upload = prepare_upload(&image);
+        assert_eq!(upload.format, UploadFormat::Rgba8Unorm);
+        assert_eq!(upload.dim, (3, 2));
+        assert_eq!(upload.row_pitch(), 12);
+        match upload.pixels {
+            UploadPixels::U8(data) => {
+                assert_eq!(data.len(), 3 * 2 * 4);
+                assert_eq!(&data[0..4], &[7, 7, 7, 255]);
+            }
+            _ => panic!("expected 8 bit pixels"),
+        }
+    }
+    {
+        let image = DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(
+            5,
+            1,
+            image::Rgb([1u16, 1000, 65534]),
+        ));
+        let upload = prepare_upload(&image);
+        assert_eq!(upload.format, UploadFormat::Rgba16Unorm);
+        assert_eq!(upload.row_pitch(), 40);
+        match upload.pixels {
+            UploadPixels::U16(data) => {
+                assert_eq!(data.len(), 5 * 4);
+                assert_eq!(&data[0..4], &[1, 1000, 65534, 65535]);
+            }
+            _ => panic!("expected 16 bit pixels"),
+        }
+    }
+    {
+        let image = DynamicImage::ImageRgb32F(image::ImageBuffer::from_pixel(
+            2,
+            2,
+            image::Rgb([0.25f32, 16.0, 1000.0]),
+        ));
+        let upload = prepare_upload(&image);
+        assert_eq!(upload.format, UploadFormat::Rgba32Float);
+        assert_eq!(upload.row_pitch(), 32);
+        match upload.pixels {
+            UploadPixels::F32(data) => {
+                assert_eq!(data.len(), 2 * 2 * 4);
+                assert_eq!(&data[0..4], &[0.25, 16.0, 1000.0, 1.0]);
+            }
+            _ => panic!("expected float pixels"),
+        }
+    }
+    {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            2,
+            1,
+            image::Rgba([1, 2, 3, 4]),
+        ));
+        let upload = prepare_upload(&image);
+        match upload.pixels {
+            UploadPixels::U8(Cow::Borrowed(data)) => assert_eq!(data, &[1, 2, 3, 4, 1, 2, 3, 4]),
+            _ => panic!("expected borrowed 8 bit pixels"),
+        }
+    }
+}
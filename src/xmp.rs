@@ -0,0 +1,688 @@
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::name::{Namespace, QName, ResolveResult};
+use quick_xml::NsReader;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+const XMLNS_NS: &str = "http://www.w3.org/2000/xmlns/";
+
+// Namespaces are matched by URI, property names use the conventional prefixes regardless of the
+// prefixes declared in the packet
+const KNOWN_NAMESPACES: &[(&str, &str)] = &[
+    ("http://purl.org/dc/elements/1.1/", "dc"),
+    ("http://ns.adobe.com/xap/1.0/", "xmp"),
+    ("http://ns.adobe.com/xap/1.0/rights/", "xmpRights"),
+    ("http://ns.adobe.com/xap/1.0/mm/", "xmpMM"),
+    ("http://ns.adobe.com/photoshop/1.0/", "photoshop"),
+    ("http://ns.adobe.com/exif/1.0/", "exif"),
+    ("http://cipa.jp/exif/1.0/", "exifEX"),
+    ("http://ns.adobe.com/exif/1.0/aux/", "aux"),
+    ("http://ns.adobe.com/tiff/1.0/", "tiff"),
+    (
+        "http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/",
+        "Iptc4xmpCore",
+    ),
+    ("http://iptc.org/std/Iptc4xmpExt/2008-02-29/", "Iptc4xmpExt"),
+    ("http://ns.adobe.com/lightroom/1.0/", "lr"),
+    ("http://ns.adobe.com/camera-raw-settings/1.0/", "crs"),
+];
+
+// Upper bound on IPTC datasets, more than any real file has
+const MAX_IPTC_DATASETS: usize = 4096;
+// XML nesting accepted, the element tree is walked and dropped recursively
+const MAX_XML_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct XmpProperty {
+    // Prefixed name such as "dc:subject". Fields of structures are joined with '/'.
+    pub name: String,
+    // A single value for simple properties, every item for arrays
+    pub values: Vec<String>,
+}
+
+// Descriptive metadata from XMP packets, IPTC-IIM blocks and .xmp sidecar files
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct XmpInfo {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub creators: Vec<String>,
+    pub rights: Option<String>,
+    pub keywords: Vec<String>,
+    // -1 for rejected, 0 for unrated, 1 to 5 stars otherwise
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+    pub create_date: Option<String>,
+    pub modify_date: Option<String>,
+    pub creator_tool: Option<String>,
+    pub headline: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub country: Option<String>,
+    pub credit: Option<String>,
+    pub source: Option<String>,
+    pub date_created: Option<String>,
+    pub date_time_original: Option<String>,
+    // Every property in document order, including the ones above
+    pub properties: Vec<XmpProperty>,
+}
+
+impl XmpInfo {
+    fn from_properties(properties: Vec<XmpProperty>) -> Self {
+        let values = |name: &str| {
+            properties
+                .iter()
+                .find(|p| p.name == name)
+                .map_or_else(Vec::new, |p| p.values.clone())
+        };
+        let first = |name: &str| values(name).into_iter().next();
+        XmpInfo {
+            title: first("dc:title"),
+            description: first("dc:description"),
+            creators: values("dc:creator"),
+            rights: first("dc:rights"),
+            keywords: values("dc:subject"),
+            rating: first("xmp:Rating")
+                .and_then(|r| r.trim().parse::<f64>().ok())
+                .map(|r| r.round() as i32),
+            label: first("xmp:Label"),
+            create_date: first("xmp:CreateDate"),
+            modify_date: first("xmp:ModifyDate"),
+            creator_tool: first("xmp:CreatorTool"),
+            headline: first("photoshop:Headline"),
+            city: first("photoshop:City"),
+            state: first("photoshop:State"),
+            country: first("photoshop:Country"),
+            credit: first("photoshop:Credit"),
+            source: first("photoshop:Source"),
+            date_created: first("photoshop:DateCreated"),
+            date_time_original: first("exif:DateTimeOriginal"),
+            properties,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&XmpProperty> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    // Adds the properties of `fallback` that are missing here
+    fn merge(self, fallback: XmpInfo) -> XmpInfo {
+        let mut properties = self.properties;
+        for property in fallback.properties {
+            if !properties.iter().any(|p| p.name == property.name) {
+                properties.push(property);
+            }
+        }
+        XmpInfo::from_properties(properties)
+    }
+}
+
+// Every property in a "[XMP]" section, array items separated by semicolons
+impl fmt::Display for XmpInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.properties.iter().map(|p| p.name.len()).max();
+        let width = width.unwrap_or(0);
+        writeln!(f, "[XMP]")?;
+        for property in &self.properties {
+            writeln!(
+                f,
+                "{:width$}  {}",
+                property.name,
+                property.values.join("; ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct XmlAttribute {
+    namespace: String,
+    prefix: String,
+    local: String,
+    value: String,
+}
+
+enum XmlNode {
+    Element(XmlElement),
+    Text(String),
+}
+
+#[derive(Default)]
+struct XmlElement {
+    namespace: String,
+    prefix: String,
+    local: String,
+    attributes: Vec<XmlAttribute>,
+    children: Vec<XmlNode>,
+}
+
+impl XmlElement {
+    fn is(&self, namespace: &str, local: &str) -> bool {
+        self.namespace == namespace && self.local == local
+    }
+
+    fn attribute(&self, namespace: &str, local: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.namespace == namespace && a.local == local)
+            .map(|a| a.value.as_str())
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &XmlElement> {
+        self.children.iter().filter_map(|child| match child {
+            XmlNode::Element(element) => Some(element),
+            XmlNode::Text(_) => None,
+        })
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            if let XmlNode::Text(t) = child {
+                text += t;
+            }
+        }
+        text.trim().to_owned()
+    }
+}
+
+fn namespace_uri(namespace: ResolveResult) -> String {
+    match namespace {
+        ResolveResult::Bound(Namespace(uri)) => String::from_utf8_lossy(uri).into_owned(),
+        ResolveResult::Unbound | ResolveResult::Unknown(_) => String::new(),
+    }
+}
+
+fn name_prefix(name: QName) -> String {
+    name.prefix().map_or_else(String::new, |p| {
+        String::from_utf8_lossy(p.as_ref()).into_owned()
+    })
+}
+
+// Reads the element tree of an XML document. Returns the top level nodes as the children of an
+// unnamed element, or None for malformed documents.
+fn parse_xml(text: &str) -> Option<XmlElement> {
+    let mut reader = NsReader::from_str(text);
+    reader.config_mut().expand_empty_elements = true;
+    let mut stack = vec![XmlElement::default()];
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(start) => {
+                let (namespace, local) = reader.resolve_element(start.name());
+                let mut element = XmlElement {
+                    namespace: namespace_uri(namespace),
+                    prefix: name_prefix(start.name()),
+                    local: String::from_utf8_lossy(local.as_ref()).into_owned(),
+                    ..XmlElement::default()
+                };
+                for attribute in start.attributes() {
+                    let attribute = attribute.ok()?;
+                    let (namespace, local) = reader.resolve_attribute(attribute.key);
+                    element.attributes.push(XmlAttribute {
+                        namespace: namespace_uri(namespace),
+                        prefix: name_prefix(attribute.key),
+                        local: String::from_utf8_lossy(local.as_ref()).into_owned(),
+                        value: attribute.unescape_value().ok()?.into_owned(),
+                    });
+                }
+                stack.push(element);
+                if stack.len() > MAX_XML_DEPTH {
+                    return None;
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop()?;
+                stack.last_mut()?.children.push(XmlNode::Element(element));
+            }
+            Event::Text(text) => {
+                let text = text.decode().ok()?.into_owned();
+                stack.last_mut()?.children.push(XmlNode::Text(text));
+            }
+            Event::CData(text) => {
+                let text = text.decode().ok()?.into_owned();
+                stack.last_mut()?.children.push(XmlNode::Text(text));
+            }
+            Event::GeneralRef(reference) => {
+                let name = reference.decode().ok()?;
+                let text = match reference.resolve_char_ref().ok()? {
+                    Some(c) => c.to_string(),
+                    None => resolve_predefined_entity(&name)?.to_owned(),
+                };
+                stack.last_mut()?.children.push(XmlNode::Text(text));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if stack.len() == 1 {
+        stack.pop()
+    } else {
+        None
+    }
+}
+
+fn qualified_name(namespace: &str, prefix: &str, local: &str) -> String {
+    let prefix = KNOWN_NAMESPACES
+        .iter()
+        .find(|(uri, _)| *uri == namespace)
+        .map_or(prefix, |(_, known)| known);
+    format!("{prefix}:{local}")
+}
+
+fn push_property(properties: &mut Vec<XmpProperty>, name: String, values: Vec<String>) {
+    let values: Vec<String> = values.into_iter().filter(|v| !v.is_empty()).collect();
+    if !values.is_empty() {
+        properties.push(XmpProperty { name, values });
+    }
+}
+
+// Attributes of rdf:Description and of structure elements are properties in their own right
+fn read_property_attributes(element: &XmlElement, path: &str, properties: &mut Vec<XmpProperty>) {
+    for attribute in &element.attributes {
+        if attribute.namespace == RDF_NS
+            || attribute.namespace == XML_NS
+            || attribute.namespace == XMLNS_NS
+            || attribute.namespace.is_empty()
+        {
+            continue;
+        }
+        let name = qualified_name(&attribute.namespace, &attribute.prefix, &attribute.local);
+        push_property(
+            properties,
+            format!("{path}{name}"),
+            vec![attribute.value.trim().to_owned()],
+        );
+    }
+}
+
+fn read_property(element: &XmlElement, path: &str, properties: &mut Vec<XmpProperty>) {
+    let name = format!(
+        "{path}{}",
+        qualified_name(&element.namespace, &element.prefix, &element.local)
+    );
+    if let Some(resource) = element.attribute(RDF_NS, "resource") {
+        push_property(properties, name, vec![resource.to_owned()]);
+        return;
+    }
+    let struct_path = format!("{name}/");
+    if element.attribute(RDF_NS, "parseType") == Some("Resource") {
+        for child in element.elements() {
+            read_property(child, &struct_path, properties);
+        }
+        return;
+    }
+    // Shorthand for structures with simple fields
+    read_property_attributes(element, &struct_path, properties);
+
+    let mut has_elements = false;
+    for child in element.elements() {
+        has_elements = true;
+        if child.is(RDF_NS, "Alt") || child.is(RDF_NS, "Bag") || child.is(RDF_NS, "Seq") {
+            let mut items: Vec<&XmlElement> =
+                child.elements().filter(|li| li.is(RDF_NS, "li")).collect();
+            // Language alternatives list the default first
+            if child.is(RDF_NS, "Alt") {
+                items.sort_by_key(|li| li.attribute(XML_NS, "lang") != Some("x-default"));
+            }
+            let values = items.iter().map(|li| li.text()).collect();
+            push_property(properties, name.clone(), values);
+        } else if child.is(RDF_NS, "Description") {
+            read_property_attributes(child, &struct_path, properties);
+            for field in child.elements() {
+                read_property(field, &struct_path, properties);
+            }
+        }
+    }
+    if !has_elements {
+        push_property(properties, name, vec![element.text()]);
+    }
+}
+
+fn collect_descriptions(element: &XmlElement, properties: &mut Vec<XmpProperty>) {
+    for child in element.elements() {
+        if child.is(RDF_NS, "Description") {
+            read_property_attributes(child, "", properties);
+            for property in child.elements() {
+                read_property(property, "", properties);
+            }
+        } else {
+            collect_descriptions(child, properties);
+        }
+    }
+}
+
+// Parses an XMP packet, with or without the xpacket wrapper
+pub fn parse_xmp(data: &[u8]) -> Option<XmpInfo> {
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches('\u{feff}');
+    let root = parse_xml(text)?;
+    let mut properties = Vec::new();
+    collect_descriptions(&root, &mut properties);
+    if properties.is_empty() {
+        None
+    } else {
+        Some(XmpInfo::from_properties(properties))
+    }
+}
+
+// XMP property that holds the same information as an IPTC application record dataset
+fn iptc_property_name(dataset: u8) -> Option<&'static str> {
+    let name = match dataset {
+        5 => "dc:title",
+        15 => "photoshop:Category",
+        25 => "dc:subject",
+        40 => "photoshop:Instructions",
+        55 => "photoshop:DateCreated",
+        80 => "dc:creator",
+        85 => "photoshop:AuthorsPosition",
+        90 => "photoshop:City",
+        92 => "Iptc4xmpCore:Location",
+        95 => "photoshop:State",
+        100 => "Iptc4xmpCore:CountryCode",
+        101 => "photoshop:Country",
+        103 => "photoshop:TransmissionReference",
+        105 => "photoshop:Headline",
+        110 => "photoshop:Credit",
+        115 => "photoshop:Source",
+        116 => "dc:rights",
+        120 => "dc:description",
+        122 => "photoshop:CaptionWriter",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn decode_iptc_string(bytes: &[u8], utf8: bool) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.trim().to_owned(),
+        // Without a character set declaration the text is usually Latin-1
+        Err(_) if !utf8 => bytes.iter().map(|&b| b as char).collect::<String>(),
+        Err(_) => String::from_utf8_lossy(bytes).trim().to_owned(),
+    }
+}
+
+// IPTC-IIM datasets as raw bytes, from Photoshop image resources or ImageMagick text profiles
+fn find_iim(data: &[u8]) -> Option<std::borrow::Cow<'_, [u8]>> {
+    if data.first() == Some(&0x1C) {
+        return Some(data.into());
+    }
+    if data.starts_with(b"8BIM") {
+        // Photoshop image resource blocks: signature, id, padded Pascal name, size, data
+        let mut offset = 0;
+        while data.get(offset..offset + 4)? == b"8BIM" {
+            let id = u16::from_be_bytes([*data.get(offset + 4)?, *data.get(offset + 5)?]);
+            let name_len = *data.get(offset + 6)? as usize;
+            let size_offset = offset + 6 + ((name_len + 2) & !1);
+            let size = u32::from_be_bytes(data.get(size_offset..size_offset + 4)?.try_into().ok()?);
+            let start = size_offset + 4;
+            let end = start.checked_add(size as usize)?;
+            if id == 0x0404 {
+                return data.get(start..end).map(|iim| iim.into());
+            }
+            offset = end + (end & 1);
+        }
+        return None;
+    }
+    // ImageMagick "Raw profile type" text: name, length and hex encoded data on separate lines
+    let text = std::str::from_utf8(data).ok()?;
+    let mut lines = text.trim_start().splitn(3, '\n');
+    let _name = lines.next()?;
+    let len: usize = lines.next()?.trim().parse().ok()?;
+    let hex: Vec<u8> = lines
+        .next()?
+        .bytes()
+        .filter(u8::is_ascii_hexdigit)
+        .collect();
+    let bytes: Vec<u8> = hex
+        .chunks_exact(2)
+        .take(len)
+        .filter_map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect();
+    match find_iim(&bytes)? {
+        std::borrow::Cow::Borrowed(iim) => Some(iim.to_vec().into()),
+        std::borrow::Cow::Owned(iim) => Some(iim.into()),
+    }
+}
+
+// Parses an IPTC-IIM block into the equivalent XMP properties
+pub fn parse_iptc(data: &[u8]) -> Option<XmpInfo> {
+    let iim = find_iim(data)?;
+    let mut datasets = Vec::new();
+    let mut offset = 0;
+    let mut utf8 = false;
+    while datasets.len() < MAX_IPTC_DATASETS {
+        let header = match iim.get(offset..offset + 5) {
+            Some(header) if header[0] == 0x1C => header,
+            _ => break,
+        };
+        let (record, dataset) = (header[1], header[2]);
+        let size = u16::from_be_bytes([header[3], header[4]]);
+        // Extended datasets are only used for binary data such as previews
+        if size & 0x8000 != 0 {
+            break;
+        }
+        let start = offset + 5;
+        let value = match iim.get(start..start + size as usize) {
+            Some(value) => value,
+            None => break,
+        };
+        // Coded character set ESC % G declares UTF-8
+        if (record, dataset) == (1, 90) && value == b"\x1b%G" {
+            utf8 = true;
+        }
+        if record == 2 {
+            datasets.push((dataset, value));
+        }
+        offset = start + size as usize;
+    }
+
+    let mut properties: Vec<XmpProperty> = Vec::new();
+    for (dataset, value) in datasets {
+        let name = match iptc_property_name(dataset) {
+            Some(name) => name,
+            None => continue,
+        };
+        let mut value = decode_iptc_string(value, utf8);
+        // CCYYMMDD to the XMP date format
+        if dataset == 55 && value.len() == 8 && value.bytes().all(|b| b.is_ascii_digit()) {
+            value = format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..]);
+        }
+        if value.is_empty() {
+            continue;
+        }
+        match properties.iter_mut().find(|p| p.name == name) {
+            // Repeatable datasets such as keywords and creators
+            Some(property) => property.values.push(value),
+            None => properties.push(XmpProperty {
+                name: name.to_owned(),
+                values: vec![value],
+            }),
+        }
+    }
+    if properties.is_empty() {
+        None
+    } else {
+        Some(XmpInfo::from_properties(properties))
+    }
+}
+
+// Sidecars are named either photo.xmp or photo.jpg.xmp
+pub fn find_sidecar(image_path: &Path) -> Option<PathBuf> {
+    let mut with_suffix = image_path.as_os_str().to_owned();
+    with_suffix.push(".xmp");
+    vec![image_path.with_extension("xmp"), PathBuf::from(with_suffix)]
+        .into_iter()
+        .find(|path| path != image_path && path.is_file())
+}
+
+// Combines every source of descriptive metadata of an image. Sidecar files take precedence since
+// they hold edits made after the file was written, IPTC-IIM is the legacy fallback.
+pub fn collect_xmp_info(
+    image_path: Option<&Path>,
+    xmp: Option<&[u8]>,
+    iptc: Option<&[u8]>,
+) -> Option<XmpInfo> {
+    let sidecar = image_path
+        .and_then(find_sidecar)
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|data| parse_xmp(&data));
+    let sources = vec![sidecar, xmp.and_then(parse_xmp), iptc.and_then(parse_iptc)];
+    sources
+        .into_iter()
+        .flatten()
+        .reduce(|merged, fallback| merged.merge(fallback))
+}
+
+#[cfg(test)]
+const TEST_XMP: &str = r#"<?xpacket begin="\u{feff}" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:d="http://purl.org/dc/elements/1.1/"
+    xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/"
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:Iptc4xmpCore="http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/"
+    xmp:Rating="4" xmp:CreatorTool="Tool &amp; Co"
+    photoshop:City="Z&#252;rich" photoshop:Credit="Ann > Bob">
+   <d:title><rdf:Alt>
+     <rdf:li xml:lang="de">Sonnenuntergang</rdf:li>
+     <rdf:li xml:lang="x-default">Sunset</rdf:li>
+   </rdf:Alt></d:title>
+   <d:creator><rdf:Seq><rdf:li>Ann</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></d:creator>
+   <d:subject><rdf:Bag><rdf:li>sea</rdf:li><rdf:li>sky</rdf:li></rdf:Bag></d:subject>
+   <!-- comment <d:rights>ignored</d:rights> -> -->
+   <exif:DateTimeOriginal>2021-06-01T20:15:00</exif:DateTimeOriginal>
+   <Iptc4xmpCore:CreatorContactInfo rdf:parseType="Resource">
+    <Iptc4xmpCore:CiAdrCity>Bern</Iptc4xmpCore:CiAdrCity>
+   </Iptc4xmpCore:CreatorContactInfo>
+   <xmp:Label><![CDATA[Red <1>]]></xmp:Label>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+#[cfg(test)]
+fn make_test_iim(datasets: &[(u8, u8, &[u8])]) -> Vec<u8> {
+    let mut iim = Vec::new();
+    for (record, dataset, value) in datasets {
+        iim.extend_from_slice(&[0x1C, *record, *dataset]);
+        iim.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        iim.extend_from_slice(value);
+    }
+    iim
+}
+
+#[test]
+fn test_parse_xmp() {
+    let xmp = parse_xmp(TEST_XMP.as_bytes()).unwrap();
+    assert_eq!(xmp.title.as_deref(), Some("Sunset"));
+    assert_eq!(xmp.creators, vec!["Ann", "Bob"]);
+    assert_eq!(xmp.keywords, vec!["sea", "sky"]);
+    assert_eq!(xmp.rating, Some(4));
+    assert_eq!(xmp.creator_tool.as_deref(), Some("Tool & Co"));
+    assert_eq!(xmp.city.as_deref(), Some("Z\u{fc}rich"));
+    assert_eq!(xmp.label.as_deref(), Some("Red <1>"));
+    assert_eq!(xmp.credit.as_deref(), Some("Ann > Bob"));
+    assert_eq!(xmp.rights, None);
+    assert_eq!(
+        xmp.date_time_original.as_deref(),
+        Some("2021-06-01T20:15:00")
+    );
+    assert_eq!(
+        xmp.property("Iptc4xmpCore:CreatorContactInfo/Iptc4xmpCore:CiAdrCity")
+            .map(|p| p.values.clone()),
+        Some(vec!["Bern".to_owned()])
+    );
+    assert_eq!(
+        xmp.property("dc:title").unwrap().values,
+        vec!["Sunset", "Sonnenuntergang"]
+    );
+
+    assert!(xmp
+        .to_string()
+        .contains("\ndc:creator                                              Ann; Bob\n"));
+
+    assert!(parse_xmp(b"").is_none());
+    assert!(parse_xmp(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>").is_none());
+    // Truncated and garbled packets never panic
+    for len in 0..TEST_XMP.len() {
+        let _ = parse_xmp(&TEST_XMP.as_bytes()[..len]);
+    }
+    assert!(parse_xmp(b"<a><b></a></b>").is_none());
+
+    // Deeply nested elements are rejected instead of overflowing the stack
+    let nested = |depth| "<a>".repeat(depth) + &"</a>".repeat(depth);
+    assert!(parse_xml(&nested(MAX_XML_DEPTH - 1)).is_some());
+    assert!(parse_xml(&nested(MAX_XML_DEPTH)).is_none());
+    assert!(parse_xmp(nested(1_000_000).as_bytes()).is_none());
+}
+
+#[test]
+fn test_parse_iptc() {
+    let iim = make_test_iim(&[
+        (1, 90, b"\x1b%G"),
+        (2, 0, &[0, 4]),
+        (2, 5, "Caf\u{e9}".as_bytes()),
+        (2, 25, b"sea"),
+        (2, 25, b"sky"),
+        (2, 55, b"20210601"),
+        (2, 116, b"(c) Ann"),
+    ]);
+    let iptc = parse_iptc(&iim).unwrap();
+    assert_eq!(iptc.title.as_deref(), Some("Caf\u{e9}"));
+    assert_eq!(iptc.keywords, vec!["sea", "sky"]);
+    assert_eq!(iptc.date_created.as_deref(), Some("2021-06-01"));
+    assert_eq!(iptc.rights.as_deref(), Some("(c) Ann"));
+
+    // Photoshop image resources as stored in JPEG APP13, after an unrelated resource
+    let mut resources = b"8BIM\x03\xed\x00\x00\x00\x00\x00\x02ab".to_vec();
+    resources.extend_from_slice(b"8BIM\x04\x04\x00\x00");
+    resources.extend_from_slice(&(iim.len() as u32).to_be_bytes());
+    resources.extend_from_slice(&iim);
+    assert_eq!(parse_iptc(&resources), Some(iptc.clone()));
+
+    // ImageMagick raw profile from a PNG text chunk
+    let hex: String = iim.iter().map(|b| format!("{b:02x}")).collect();
+    let profile = format!("\niptc\n{:8}\n{}\n", iim.len(), hex);
+    assert_eq!(parse_iptc(profile.as_bytes()), Some(iptc));
+
+    // Latin-1 without a character set declaration
+    let latin1 = make_test_iim(&[(2, 120, b"Caf\xe9")]);
+    let iptc = parse_iptc(&latin1).unwrap();
+    assert_eq!(iptc.description.as_deref(), Some("Caf\u{e9}"));
+
+    assert!(parse_iptc(b"").is_none());
+    for len in 0..resources.len() {
+        let _ = parse_iptc(&resources[..len]);
+    }
+}
+
+#[test]
+fn test_xmp_sidecar() {
+    let dir = std::env::temp_dir().join(format!("imgv_xmp_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("photo.jpg");
+    std::fs::write(&image, b"").unwrap();
+    assert_eq!(find_sidecar(&image), None);
+
+    let sidecar = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF
+        xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description
+        xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="2"/></rdf:RDF></x:xmpmeta>"#;
+    std::fs::write(dir.join("photo.jpg.xmp"), sidecar).unwrap();
+    assert_eq!(find_sidecar(&image), Some(dir.join("photo.jpg.xmp")));
+
+    // Sidecar overrides the embedded packet, which overrides IPTC
+    let iim = make_test_iim(&[(2, 5, b"Old title"), (2, 105, b"Headline")]);
+    let info = collect_xmp_info(Some(&image), Some(TEST_XMP.as_bytes()), Some(&iim)).unwrap();
+    assert_eq!(info.rating, Some(2));
+    assert_eq!(info.title.as_deref(), Some("Sunset"));
+    assert_eq!(info.headline.as_deref(), Some("Headline"));
+
+    assert_eq!(collect_xmp_info(None, None, None), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}